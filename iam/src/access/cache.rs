@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::{
+    AccessApplicationService, AccessError, AuthorizationRequest, Decision, Permission,
+    PolicyDecisionPoint, PolicyRepository, Role, RoleAssignmentReport, RoleEvent, RoleRepository,
+};
+use crate::common::clock::Clock;
+
+/// Decisions are cached per tenant under the request serialized to JSON.
+type DecisionKey = (String, String);
+
+#[derive(Debug)]
+struct CachedDecision {
+    decision: Decision,
+    expires_at: DateTime<Utc>,
+}
+
+/// Decorates a [`PolicyDecisionPoint`], remembering decisions for a short
+/// time so repeated checks skip loading and evaluating the policies.
+///
+/// Errors are never cached. Callers changing a tenant's policies should call
+/// [`invalidate`](Self::invalidate) so stale decisions do not outlive the
+/// change by up to the TTL.
+pub struct CachingPolicyDecisionPoint<R, C> {
+    inner: PolicyDecisionPoint<R>,
+    clock: C,
+    ttl: Duration,
+    max_entries: usize,
+    decisions: RwLock<HashMap<DecisionKey, CachedDecision>>,
+}
+
+impl<R: PolicyRepository, C: Clock> CachingPolicyDecisionPoint<R, C> {
+    pub fn new(inner: PolicyDecisionPoint<R>, clock: C) -> Self {
+        Self {
+            inner,
+            clock,
+            ttl: Duration::minutes(1),
+            max_entries: 10_000,
+            decisions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Caps the number of cached decisions; once full, new decisions are
+    /// only cached after expired ones make room.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn decide(
+        &self,
+        tenant_id: &str,
+        request: &AuthorizationRequest,
+    ) -> Result<Decision, AccessError> {
+        let now = self.clock.now();
+        let key = (tenant_id.to_owned(), request_key(request));
+        {
+            let decisions = self.decisions.read().expect("decision cache lock poisoned");
+            if let Some(cached) = decisions.get(&key).filter(|c| now < c.expires_at) {
+                return Ok(cached.decision.clone());
+            }
+        }
+        let decision = self.inner.decide(tenant_id, request)?;
+        let mut decisions = self
+            .decisions
+            .write()
+            .expect("decision cache lock poisoned");
+        if decisions.len() >= self.max_entries {
+            decisions.retain(|_, cached| now < cached.expires_at);
+        }
+        if decisions.len() < self.max_entries {
+            decisions.insert(
+                key,
+                CachedDecision {
+                    decision: decision.clone(),
+                    expires_at: now + self.ttl,
+                },
+            );
+        }
+        Ok(decision)
+    }
+
+    /// Drops the cached decisions of the tenant, e.g. after its policies
+    /// changed.
+    pub fn invalidate(&self, tenant_id: &str) {
+        let mut decisions = self
+            .decisions
+            .write()
+            .expect("decision cache lock poisoned");
+        decisions.retain(|(tenant, _), _| tenant != tenant_id);
+    }
+
+    pub fn clear(&self) {
+        let mut decisions = self
+            .decisions
+            .write()
+            .expect("decision cache lock poisoned");
+        decisions.clear();
+    }
+}
+
+/// Attribute maps are ordered, so equal requests serialize identically.
+fn request_key(request: &AuthorizationRequest) -> String {
+    serde_json::to_string(&(&request.permission, &request.subject, &request.resource))
+        .expect("authorization requests serialize to JSON")
+}
+
+/// A question answered by [`AccessApplicationService`] about one user.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum AccessQuery {
+    Authorized(Permission),
+    InRole { role: String, scope: Option<String> },
+}
+
+/// Answers are cached per tenant and user.
+type AnswerKey = (String, String, AccessQuery);
+
+#[derive(Debug)]
+struct CachedAnswer {
+    answer: bool,
+    expires_at: DateTime<Utc>,
+}
+
+/// Decorates an [`AccessApplicationService`], remembering whether users are
+/// authorized or play a role so repeated checks skip loading their roles.
+///
+/// Changes made through the decorator drop the answers they affect. Changes
+/// made elsewhere, e.g. by another instance, reach the cache as
+/// [`RoleEvent`]s passed to [`handle`](Self::handle); until then answers are
+/// at most one TTL old. Errors are never cached.
+pub struct CachingAccessApplicationService<R, C> {
+    inner: AccessApplicationService<R>,
+    clock: C,
+    ttl: Duration,
+    max_entries: usize,
+    answers: RwLock<HashMap<AnswerKey, CachedAnswer>>,
+}
+
+impl<R: RoleRepository, C: Clock> CachingAccessApplicationService<R, C> {
+    pub fn new(inner: AccessApplicationService<R>, clock: C) -> Self {
+        Self {
+            inner,
+            clock,
+            ttl: Duration::minutes(1),
+            max_entries: 10_000,
+            answers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Caps the number of cached answers; once full, new answers are only
+    /// cached after expired ones make room.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// The decorated service, e.g. for queries that are not cached.
+    pub fn inner(&self) -> &AccessApplicationService<R> {
+        &self.inner
+    }
+
+    pub fn is_authorized(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError> {
+        self.answer(
+            tenant_id,
+            username,
+            AccessQuery::Authorized(permission.clone()),
+            || self.inner.is_authorized(tenant_id, username, permission),
+        )
+    }
+
+    pub fn is_in_role(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        role: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, AccessError> {
+        let query = AccessQuery::InRole {
+            role: role.to_owned(),
+            scope: scope.map(str::to_owned),
+        };
+        self.answer(tenant_id, username, query, || {
+            self.inner.is_in_role(tenant_id, username, role, scope)
+        })
+    }
+
+    pub fn define_role(&self, role: &Role) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.define_role(role))
+    }
+
+    pub fn remove_role(&self, tenant_id: &str, role: &str) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.remove_role(tenant_id, role))
+    }
+
+    pub fn grant(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        permission: Permission,
+    ) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.grant(tenant_id, role, permission))
+    }
+
+    pub fn revoke(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        permission: &Permission,
+    ) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.revoke(tenant_id, role, permission))
+    }
+
+    pub fn assign(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.assign(tenant_id, role, username, scope))
+    }
+
+    pub fn unassign(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<RoleEvent, AccessError> {
+        self.forward(self.inner.unassign(tenant_id, role, username, scope))
+    }
+
+    pub fn assign_users_to_role<S: AsRef<str>>(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        usernames: &[S],
+    ) -> Result<RoleAssignmentReport, AccessError> {
+        let report = self
+            .inner
+            .assign_users_to_role(tenant_id, role, usernames)?;
+        for username in report.assigned() {
+            self.invalidate_user(tenant_id, username);
+        }
+        Ok(report)
+    }
+
+    /// Drops the answers a role change may have made stale: those of the
+    /// user for assignment changes, those of the whole tenant otherwise.
+    pub fn handle(&self, event: &RoleEvent) {
+        match event.username() {
+            Some(username) => self.invalidate_user(event.tenant_id(), username),
+            None => self.invalidate(event.tenant_id()),
+        }
+    }
+
+    pub fn invalidate_user(&self, tenant_id: &str, username: &str) {
+        let mut answers = self.answers.write().expect("answer cache lock poisoned");
+        answers.retain(|(tenant, user, _), _| tenant != tenant_id || user != username);
+    }
+
+    pub fn invalidate(&self, tenant_id: &str) {
+        let mut answers = self.answers.write().expect("answer cache lock poisoned");
+        answers.retain(|(tenant, _, _), _| tenant != tenant_id);
+    }
+
+    fn forward(&self, result: Result<RoleEvent, AccessError>) -> Result<RoleEvent, AccessError> {
+        let event = result?;
+        self.handle(&event);
+        Ok(event)
+    }
+
+    fn answer(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        query: AccessQuery,
+        load: impl FnOnce() -> Result<bool, AccessError>,
+    ) -> Result<bool, AccessError> {
+        let now = self.clock.now();
+        let key = (tenant_id.to_owned(), username.to_owned(), query);
+        {
+            let answers = self.answers.read().expect("answer cache lock poisoned");
+            if let Some(cached) = answers.get(&key).filter(|c| now < c.expires_at) {
+                return Ok(cached.answer);
+            }
+        }
+        let answer = load()?;
+        let mut answers = self.answers.write().expect("answer cache lock poisoned");
+        if answers.len() >= self.max_entries {
+            answers.retain(|_, cached| now < cached.expires_at);
+        }
+        if answers.len() < self.max_entries {
+            answers.insert(
+                key,
+                CachedAnswer {
+                    answer,
+                    expires_at: now + self.ttl,
+                },
+            );
+        }
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::access::{Effect, InMemoryPolicyRepository, InMemoryRoleRepository, Policy};
+    use crate::common::clock::FixedClock;
+
+    #[derive(Default)]
+    struct CountingRepository {
+        policies: InMemoryPolicyRepository,
+        loads: AtomicUsize,
+    }
+
+    impl PolicyRepository for &CountingRepository {
+        fn policies_of(&self, tenant_id: &str) -> Result<Vec<Policy>, AccessError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.policies.policies_of(tenant_id)
+        }
+
+        fn save(&self, tenant_id: &str, policy: &Policy) -> Result<(), AccessError> {
+            self.policies.save(tenant_id, policy)
+        }
+
+        fn remove(&self, tenant_id: &str, policy_id: &str) -> Result<(), AccessError> {
+            self.policies.remove(tenant_id, policy_id)
+        }
+    }
+
+    fn request(username: &str) -> AuthorizationRequest {
+        AuthorizationRequest::new("invitation:offer".parse::<Permission>().unwrap())
+            .with_subject("username", username)
+    }
+
+    fn setup<'a>(
+        repository: &'a CountingRepository,
+        clock: &'a FixedClock,
+    ) -> CachingPolicyDecisionPoint<&'a CountingRepository, &'a FixedClock> {
+        let policy = Policy::new("offer", Effect::Allow).on("invitation:offer".parse().unwrap());
+        repository.policies.save("acme", &policy).unwrap();
+        CachingPolicyDecisionPoint::new(PolicyDecisionPoint::new(repository), clock)
+    }
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn repeated_requests_are_answered_from_the_cache_until_expiry() {
+        let (repository, clock) = (CountingRepository::default(), clock());
+        let pdp = setup(&repository, &clock);
+
+        assert!(pdp.decide("acme", &request("ada")).unwrap().is_permitted());
+        assert!(pdp.decide("acme", &request("ada")).unwrap().is_permitted());
+        assert_eq!(repository.loads.load(Ordering::SeqCst), 1);
+
+        pdp.decide("acme", &request("bob")).unwrap();
+        pdp.decide("globex", &request("ada")).unwrap();
+        assert_eq!(repository.loads.load(Ordering::SeqCst), 3);
+
+        clock.advance(Duration::minutes(1));
+        pdp.decide("acme", &request("ada")).unwrap();
+        assert_eq!(repository.loads.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn invalidation_picks_up_policy_changes() {
+        let (repository, clock) = (CountingRepository::default(), clock());
+        let pdp = setup(&repository, &clock);
+        assert!(pdp.decide("acme", &request("ada")).unwrap().is_permitted());
+
+        repository.policies.remove("acme", "offer").unwrap();
+        assert!(pdp.decide("acme", &request("ada")).unwrap().is_permitted());
+        pdp.invalidate("acme");
+        assert!(!pdp.decide("acme", &request("ada")).unwrap().is_permitted());
+    }
+
+    #[test]
+    fn full_cache_stops_growing() {
+        let (repository, clock) = (CountingRepository::default(), clock());
+        let pdp = setup(&repository, &clock).with_max_entries(1);
+        pdp.decide("acme", &request("ada")).unwrap();
+        pdp.decide("acme", &request("bob")).unwrap();
+        pdp.decide("acme", &request("bob")).unwrap();
+        assert_eq!(repository.loads.load(Ordering::SeqCst), 3);
+        pdp.decide("acme", &request("ada")).unwrap();
+        assert_eq!(repository.loads.load(Ordering::SeqCst), 3);
+    }
+
+    #[derive(Default)]
+    struct CountingRoles {
+        roles: InMemoryRoleRepository,
+        loads: AtomicUsize,
+    }
+
+    impl RoleRepository for &CountingRoles {
+        fn role_named(&self, tenant_id: &str, name: &str) -> Result<Option<Role>, AccessError> {
+            self.roles.role_named(tenant_id, name)
+        }
+
+        fn roles_in(&self, tenant_id: &str) -> Result<Vec<Role>, AccessError> {
+            self.roles.roles_in(tenant_id)
+        }
+
+        fn member_count(&self, tenant_id: &str, name: &str) -> Result<usize, AccessError> {
+            self.roles.member_count(tenant_id, name)
+        }
+
+        fn save(&self, role: &Role) -> Result<(), AccessError> {
+            self.roles.save(role)
+        }
+
+        fn remove(&self, tenant_id: &str, name: &str) -> Result<(), AccessError> {
+            self.roles.remove(tenant_id, name)
+        }
+
+        fn grant(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            permission: &Permission,
+        ) -> Result<bool, AccessError> {
+            self.roles.grant(tenant_id, name, permission)
+        }
+
+        fn revoke(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            permission: &Permission,
+        ) -> Result<bool, AccessError> {
+            self.roles.revoke(tenant_id, name, permission)
+        }
+
+        fn assign(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            username: &str,
+            scope: Option<&str>,
+        ) -> Result<(), AccessError> {
+            self.roles.assign(tenant_id, name, username, scope)
+        }
+
+        fn assign_all(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            usernames: &[String],
+        ) -> Result<Vec<bool>, AccessError> {
+            self.roles.assign_all(tenant_id, name, usernames)
+        }
+
+        fn unassign(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            username: &str,
+            scope: Option<&str>,
+        ) -> Result<(), AccessError> {
+            self.roles.unassign(tenant_id, name, username, scope)
+        }
+
+        fn roles_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Role>, AccessError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.roles.roles_of(tenant_id, username)
+        }
+
+        fn is_in_role(
+            &self,
+            tenant_id: &str,
+            name: &str,
+            username: &str,
+            scope: Option<&str>,
+        ) -> Result<bool, AccessError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            self.roles.is_in_role(tenant_id, name, username, scope)
+        }
+    }
+
+    fn access<'a>(
+        roles: &'a CountingRoles,
+        clock: &'a FixedClock,
+    ) -> CachingAccessApplicationService<&'a CountingRoles, &'a FixedClock> {
+        let service =
+            CachingAccessApplicationService::new(AccessApplicationService::new(roles), clock);
+        service.define_role(&Role::new("acme", "editor")).unwrap();
+        service.grant("acme", "editor", offer()).unwrap();
+        service.assign("acme", "editor", "ada", None).unwrap();
+        service
+    }
+
+    fn offer() -> Permission {
+        "invitation:offer".parse().unwrap()
+    }
+
+    #[test]
+    fn access_answers_are_cached_per_user_until_expiry() {
+        let (roles, clock) = (CountingRoles::default(), clock());
+        let access = access(&roles, &clock);
+
+        assert!(access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert!(access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert!(access.is_in_role("acme", "ada", "editor", None).unwrap());
+        assert!(access.is_in_role("acme", "ada", "editor", None).unwrap());
+        assert_eq!(roles.loads.load(Ordering::SeqCst), 2);
+
+        assert!(!access.is_authorized("acme", "bob", &offer()).unwrap());
+        assert_eq!(roles.loads.load(Ordering::SeqCst), 3);
+
+        clock.advance(Duration::minutes(1));
+        access.is_authorized("acme", "ada", &offer()).unwrap();
+        assert_eq!(roles.loads.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn role_changes_through_the_cache_take_effect_at_once() {
+        let (roles, clock) = (CountingRoles::default(), clock());
+        let access = access(&roles, &clock);
+        assert!(access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert!(!access.is_authorized("acme", "bob", &offer()).unwrap());
+
+        access.assign("acme", "editor", "bob", None).unwrap();
+        assert!(access.is_authorized("acme", "bob", &offer()).unwrap());
+
+        access.revoke("acme", "editor", &offer()).unwrap();
+        assert!(!access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert!(!access.is_authorized("acme", "bob", &offer()).unwrap());
+
+        access
+            .assign_users_to_role("acme", "editor", &["carl"])
+            .unwrap();
+        assert!(access.is_in_role("acme", "carl", "editor", None).unwrap());
+    }
+
+    #[test]
+    fn events_from_elsewhere_invalidate_affected_answers() {
+        let (roles, clock) = (CountingRoles::default(), clock());
+        let access = access(&roles, &clock);
+        let other = AccessApplicationService::new(&roles);
+        assert!(access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert!(!access.is_in_role("acme", "bob", "editor", None).unwrap());
+
+        let assigned = other.assign("acme", "editor", "bob", None).unwrap();
+        assert!(!access.is_in_role("acme", "bob", "editor", None).unwrap());
+        let loads = roles.loads.load(Ordering::SeqCst);
+        access.handle(&assigned);
+        assert!(access.is_in_role("acme", "bob", "editor", None).unwrap());
+        assert!(access.is_authorized("acme", "ada", &offer()).unwrap());
+        assert_eq!(roles.loads.load(Ordering::SeqCst), loads + 1, "ada kept");
+
+        let revoked = other.revoke("acme", "editor", &offer()).unwrap();
+        access.handle(&revoked);
+        assert!(!access.is_authorized("acme", "ada", &offer()).unwrap());
+    }
+}
@@ -0,0 +1,33 @@
+use thiserror::Error;
+
+mod cache;
+mod permission;
+mod policy;
+mod role;
+
+pub use cache::{CachingAccessApplicationService, CachingPolicyDecisionPoint};
+pub use permission::Permission;
+pub use policy::{
+    AttributeRef, AttributeSource, Attributes, AuthorizationRequest, Condition, Decision, Effect,
+    InMemoryPolicyRepository, Policy, PolicyDecisionPoint, PolicyRepository,
+};
+pub use role::{
+    AccessApplicationService, AssignmentOutcome, InMemoryRoleRepository, Role,
+    RoleAssignmentReport, RoleDescriptor, RoleEvent, RoleRepository,
+};
+
+/// Errors raised by access control.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AccessError {
+    #[error("invalid permission: {0}")]
+    InvalidPermission(String),
+    #[error("invalid policy: {0}")]
+    InvalidPolicy(String),
+    #[error("unknown role: {0}")]
+    UnknownRole(String),
+    #[error("role {0} is managed by the system")]
+    SystemManaged(String),
+    #[error("access control storage failure: {0}")]
+    Storage(String),
+}
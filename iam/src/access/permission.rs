@@ -0,0 +1,141 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::AccessError;
+
+/// Wildcard matching any resource or action.
+const WILDCARD: &str = "*";
+
+/// An action allowed on a kind of resource, written `resource:action`.
+///
+/// Either part may be `*` to grant every resource or every action, e.g.
+/// `invitation:*` or `*:read`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Permission {
+    resource: String,
+    action: String,
+}
+
+impl Permission {
+    pub fn new(resource: &str, action: &str) -> Result<Self, AccessError> {
+        let resource = normalize(resource)?;
+        let action = normalize(action)?;
+        Ok(Self { resource, action })
+    }
+
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    /// Whether holding this permission grants the requested one.
+    pub fn implies(&self, requested: &Permission) -> bool {
+        let covers = |granted: &str, wanted: &str| granted == WILDCARD || granted == wanted;
+        covers(&self.resource, &requested.resource) && covers(&self.action, &requested.action)
+    }
+}
+
+fn normalize(part: &str) -> Result<String, AccessError> {
+    let part = part.trim().to_lowercase();
+    let valid = part == WILDCARD
+        || (!part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')));
+    if valid {
+        Ok(part)
+    } else {
+        Err(AccessError::InvalidPermission(part))
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.resource, self.action)
+    }
+}
+
+impl FromStr for Permission {
+    type Err = AccessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (resource, action) = s
+            .split_once(':')
+            .ok_or_else(|| AccessError::InvalidPermission(s.to_owned()))?;
+        Self::new(resource, action)
+    }
+}
+
+impl TryFrom<String> for Permission {
+    type Error = AccessError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Permission> for String {
+    fn from(permission: Permission) -> Self {
+        permission.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(value: &str) -> Permission {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parts_are_normalized_and_validated() {
+        let parsed = permission(" Invitation : Offer ");
+        assert_eq!(parsed, Permission::new("invitation", "offer").unwrap());
+        assert_eq!(parsed.to_string(), "invitation:offer");
+
+        for invalid in [
+            "invitation",
+            ":offer",
+            "invitation:",
+            "invi tation:offer",
+            "a:b:c",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<Permission>(),
+                    Err(AccessError::InvalidPermission(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn wildcards_imply_every_resource_or_action() {
+        let offer = permission("invitation:offer");
+        assert!(permission("invitation:offer").implies(&offer));
+        assert!(permission("invitation:*").implies(&offer));
+        assert!(permission("*:offer").implies(&offer));
+        assert!(permission("*:*").implies(&offer));
+        assert!(!permission("invitation:withdraw").implies(&offer));
+        assert!(!permission("tenant:*").implies(&offer));
+        assert!(!offer.implies(&permission("invitation:*")));
+    }
+
+    #[test]
+    fn serializes_as_a_string() {
+        let offer = permission("invitation:offer");
+        let json = serde_json::to_string(&offer).unwrap();
+        assert_eq!(json, "\"invitation:offer\"");
+        assert_eq!(serde_json::from_str::<Permission>(&json).unwrap(), offer);
+        assert!(serde_json::from_str::<Permission>("\"invitation\"").is_err());
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{AccessError, Permission};
+
+/// Attributes describing a subject or resource, e.g. `groups` or `owner`.
+pub type Attributes = BTreeMap<String, Value>;
+
+/// Which side of a request an attribute is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttributeSource {
+    Subject,
+    Resource,
+}
+
+/// Reference to an attribute, written `subject.<name>` or
+/// `resource.<name>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AttributeRef {
+    source: AttributeSource,
+    name: String,
+}
+
+impl AttributeRef {
+    pub fn subject(name: impl Into<String>) -> Self {
+        Self {
+            source: AttributeSource::Subject,
+            name: name.into(),
+        }
+    }
+
+    pub fn resource(name: impl Into<String>) -> Self {
+        Self {
+            source: AttributeSource::Resource,
+            name: name.into(),
+        }
+    }
+
+    fn resolve<'a>(&self, request: &'a AuthorizationRequest) -> Option<&'a Value> {
+        match self.source {
+            AttributeSource::Subject => request.subject.get(&self.name),
+            AttributeSource::Resource => request.resource.get(&self.name),
+        }
+    }
+}
+
+impl fmt::Display for AttributeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            AttributeSource::Subject => write!(f, "subject.{}", self.name),
+            AttributeSource::Resource => write!(f, "resource.{}", self.name),
+        }
+    }
+}
+
+impl FromStr for AttributeRef {
+    type Err = AccessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('.') {
+            Some(("subject", name)) if !name.is_empty() => Ok(Self::subject(name)),
+            Some(("resource", name)) if !name.is_empty() => Ok(Self::resource(name)),
+            _ => Err(AccessError::InvalidPolicy(format!(
+                "invalid attribute reference: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for AttributeRef {
+    type Error = AccessError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AttributeRef> for String {
+    fn from(attribute: AttributeRef) -> Self {
+        attribute.to_string()
+    }
+}
+
+/// A predicate over the attributes of a request.
+///
+/// A condition reading a missing attribute is indeterminate rather than
+/// false, and stays indeterminate under `Not`, so negating a condition
+/// never grants access to requests lacking the attribute.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Always true.
+    Always,
+    Equals {
+        attribute: AttributeRef,
+        value: Value,
+    },
+    /// The attribute equals one of the values.
+    In {
+        attribute: AttributeRef,
+        values: Vec<Value>,
+    },
+    /// The attribute is a list holding the value.
+    Contains {
+        attribute: AttributeRef,
+        value: Value,
+    },
+    /// Two attributes hold the same value, e.g. the subject and resource
+    /// tenant.
+    Matches {
+        left: AttributeRef,
+        right: AttributeRef,
+    },
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn is_satisfied_by(&self, request: &AuthorizationRequest) -> bool {
+        self.evaluate(request) == Some(true)
+    }
+
+    /// Evaluates the condition, `None` meaning indeterminate because an
+    /// attribute it reads is missing.
+    pub fn evaluate(&self, request: &AuthorizationRequest) -> Option<bool> {
+        match self {
+            Condition::Always => Some(true),
+            Condition::Equals { attribute, value } => {
+                attribute.resolve(request).map(|actual| actual == value)
+            }
+            Condition::In { attribute, values } => attribute
+                .resolve(request)
+                .map(|actual| values.contains(actual)),
+            Condition::Contains { attribute, value } => attribute
+                .resolve(request)
+                .map(|actual| actual.as_array().is_some_and(|items| items.contains(value))),
+            Condition::Matches { left, right } => left
+                .resolve(request)
+                .zip(right.resolve(request))
+                .map(|(left, right)| left == right),
+            Condition::All(conditions) => combine(conditions, request, false),
+            Condition::Any(conditions) => combine(conditions, request, true),
+            Condition::Not(condition) => condition.evaluate(request).map(|holds| !holds),
+        }
+    }
+}
+
+/// Three-valued AND (`decisive` false) or OR (`decisive` true): a
+/// condition evaluating to `decisive` settles the result, otherwise any
+/// indeterminate condition makes it indeterminate.
+fn combine(
+    conditions: &[Condition],
+    request: &AuthorizationRequest,
+    decisive: bool,
+) -> Option<bool> {
+    let mut result = Some(!decisive);
+    for condition in conditions {
+        match condition.evaluate(request) {
+            Some(value) if value == decisive => return Some(decisive),
+            Some(_) => {}
+            None => result = None,
+        }
+    }
+    result
+}
+
+/// Whether a matching policy grants or forbids access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// A rule applying to requests for some permissions.
+///
+/// For example, "only users in group X can offer invitations":
+///
+/// ```json
+/// {
+///   "id": "offer-invitations",
+///   "effect": "allow",
+///   "permissions": ["invitation:offer"],
+///   "condition": { "contains": { "attribute": "subject.groups", "value": "X" } }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+    id: String,
+    #[serde(default)]
+    description: Option<String>,
+    effect: Effect,
+    permissions: Vec<Permission>,
+    #[serde(default = "always")]
+    condition: Condition,
+}
+
+fn always() -> Condition {
+    Condition::Always
+}
+
+impl Policy {
+    pub fn new(id: impl Into<String>, effect: Effect) -> Self {
+        Self {
+            id: id.into(),
+            description: None,
+            effect,
+            permissions: Vec::new(),
+            condition: Condition::Always,
+        }
+    }
+
+    pub fn describe(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn on(mut self, permission: Permission) -> Self {
+        self.permissions.push(permission);
+        self
+    }
+
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.condition = condition;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    /// Whether the policy targets the request and its condition holds.
+    ///
+    /// An indeterminate condition errs on the side of denying: deny
+    /// policies apply and allow policies do not.
+    pub fn applies_to(&self, request: &AuthorizationRequest) -> bool {
+        if !self
+            .permissions
+            .iter()
+            .any(|p| p.implies(&request.permission))
+        {
+            return false;
+        }
+        match self.effect {
+            Effect::Allow => self.condition.evaluate(request) == Some(true),
+            Effect::Deny => self.condition.evaluate(request) != Some(false),
+        }
+    }
+}
+
+/// What a subject wants to do to a resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorizationRequest {
+    pub permission: Permission,
+    pub subject: Attributes,
+    pub resource: Attributes,
+}
+
+impl AuthorizationRequest {
+    pub fn new(permission: Permission) -> Self {
+        Self {
+            permission,
+            subject: Attributes::new(),
+            resource: Attributes::new(),
+        }
+    }
+
+    pub fn with_subject(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.subject.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_resource(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.resource.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Outcome of a policy evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Permit {
+        policy_id: String,
+    },
+    /// Denied by the given policy, or by default when none applies.
+    Deny {
+        policy_id: Option<String>,
+    },
+}
+
+impl Decision {
+    pub fn is_permitted(&self) -> bool {
+        matches!(self, Decision::Permit { .. })
+    }
+}
+
+/// Stores the policies of each tenant.
+pub trait PolicyRepository: Send + Sync {
+    fn policies_of(&self, tenant_id: &str) -> Result<Vec<Policy>, AccessError>;
+
+    /// Inserts or replaces the tenant's policy with the same id.
+    fn save(&self, tenant_id: &str, policy: &Policy) -> Result<(), AccessError>;
+
+    fn remove(&self, tenant_id: &str, policy_id: &str) -> Result<(), AccessError>;
+}
+
+/// Repository keeping policies in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryPolicyRepository {
+    policies: RwLock<HashMap<String, Vec<Policy>>>,
+}
+
+impl InMemoryPolicyRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PolicyRepository for InMemoryPolicyRepository {
+    fn policies_of(&self, tenant_id: &str) -> Result<Vec<Policy>, AccessError> {
+        let policies = self.policies.read().expect("policies lock poisoned");
+        Ok(policies.get(tenant_id).cloned().unwrap_or_default())
+    }
+
+    fn save(&self, tenant_id: &str, policy: &Policy) -> Result<(), AccessError> {
+        let mut policies = self.policies.write().expect("policies lock poisoned");
+        let tenant = policies.entry(tenant_id.to_owned()).or_default();
+        match tenant.iter_mut().find(|p| p.id == policy.id) {
+            Some(existing) => *existing = policy.clone(),
+            None => tenant.push(policy.clone()),
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tenant_id: &str, policy_id: &str) -> Result<(), AccessError> {
+        let mut policies = self.policies.write().expect("policies lock poisoned");
+        if let Some(tenant) = policies.get_mut(tenant_id) {
+            tenant.retain(|p| p.id != policy_id);
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates requests against the policies of a tenant.
+///
+/// Deny overrides allow, and requests no policy applies to are denied.
+pub struct PolicyDecisionPoint<R> {
+    repository: R,
+}
+
+impl<R: PolicyRepository> PolicyDecisionPoint<R> {
+    pub fn new(repository: R) -> Self {
+        Self { repository }
+    }
+
+    pub fn decide(
+        &self,
+        tenant_id: &str,
+        request: &AuthorizationRequest,
+    ) -> Result<Decision, AccessError> {
+        Ok(evaluate(&self.repository.policies_of(tenant_id)?, request))
+    }
+}
+
+/// Combines the policies applying to the request, deny overriding allow.
+pub(crate) fn evaluate(policies: &[Policy], request: &AuthorizationRequest) -> Decision {
+    let mut permit = None;
+    for policy in policies.iter().filter(|p| p.applies_to(request)) {
+        match policy.effect {
+            Effect::Deny => {
+                return Decision::Deny {
+                    policy_id: Some(policy.id.clone()),
+                }
+            }
+            Effect::Allow => {
+                permit.get_or_insert_with(|| policy.id.clone());
+            }
+        }
+    }
+    match permit {
+        Some(policy_id) => Decision::Permit { policy_id },
+        None => Decision::Deny { policy_id: None },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn offer() -> Permission {
+        "invitation:offer".parse().unwrap()
+    }
+
+    fn in_group(group: &str) -> Condition {
+        Condition::Contains {
+            attribute: AttributeRef::subject("groups"),
+            value: json!(group),
+        }
+    }
+
+    fn request() -> AuthorizationRequest {
+        AuthorizationRequest::new(offer())
+    }
+
+    #[test]
+    fn requests_no_policy_applies_to_are_denied() {
+        assert_eq!(
+            evaluate(&[], &request()),
+            Decision::Deny { policy_id: None }
+        );
+
+        let other = Policy::new("read", Effect::Allow).on("tenant:read".parse().unwrap());
+        assert_eq!(
+            evaluate(&[other], &request()),
+            Decision::Deny { policy_id: None }
+        );
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let allow = Policy::new("allow", Effect::Allow).on(offer());
+        let deny = Policy::new("deny", Effect::Deny)
+            .on("invitation:*".parse().unwrap())
+            .when(in_group("suspended"));
+        let policies = [allow, deny];
+
+        let member = request().with_subject("groups", json!(["staff"]));
+        assert_eq!(
+            evaluate(&policies, &member),
+            Decision::Permit {
+                policy_id: "allow".to_owned()
+            }
+        );
+        let suspended = request().with_subject("groups", json!(["staff", "suspended"]));
+        assert_eq!(
+            evaluate(&policies, &suspended),
+            Decision::Deny {
+                policy_id: Some("deny".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn negation_of_a_missing_attribute_does_not_allow() {
+        let not_guest = Policy::new("not-guest", Effect::Allow)
+            .on(offer())
+            .when(Condition::Not(Box::new(in_group("guests"))));
+        assert!(!not_guest.applies_to(&request()));
+        assert!(not_guest.applies_to(&request().with_subject("groups", json!(["staff"]))));
+        assert!(!not_guest.applies_to(&request().with_subject("groups", json!(["guests"]))));
+
+        let any = Condition::Any(vec![Condition::Not(Box::new(in_group("guests")))]);
+        assert_eq!(any.evaluate(&request()), None);
+        let any = Condition::Any(vec![any, Condition::Always]);
+        assert_eq!(any.evaluate(&request()), Some(true));
+        let all = Condition::All(vec![in_group("guests"), Condition::Always]);
+        assert_eq!(all.evaluate(&request()), None);
+    }
+
+    #[test]
+    fn indeterminate_deny_policies_apply() {
+        let allow = Policy::new("allow", Effect::Allow).on(offer());
+        let deny_outsiders = Policy::new("deny-outsiders", Effect::Deny)
+            .on(offer())
+            .when(Condition::Not(Box::new(Condition::Matches {
+                left: AttributeRef::subject("tenant"),
+                right: AttributeRef::resource("tenant"),
+            })));
+        let policies = [allow, deny_outsiders];
+
+        let insider = request()
+            .with_subject("tenant", "acme")
+            .with_resource("tenant", "acme");
+        assert!(evaluate(&policies, &insider).is_permitted());
+        let unknown = request().with_resource("tenant", "acme");
+        assert!(!evaluate(&policies, &unknown).is_permitted());
+    }
+
+    #[test]
+    fn policies_round_trip_through_json() {
+        let json = json!({
+            "id": "offer-invitations",
+            "effect": "allow",
+            "permissions": ["invitation:offer"],
+            "condition": { "contains": { "attribute": "subject.groups", "value": "X" } }
+        });
+        let policy: Policy = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(policy.condition, in_group("X"));
+        assert_eq!(
+            serde_json::to_value(&policy).unwrap()["condition"],
+            json["condition"]
+        );
+        assert!("owner".parse::<AttributeRef>().is_err());
+        assert!("actor.owner".parse::<AttributeRef>().is_err());
+    }
+
+    #[test]
+    fn repository_replaces_policies_by_id() {
+        let repository = InMemoryPolicyRepository::new();
+        repository
+            .save("acme", &Policy::new("p", Effect::Allow).on(offer()))
+            .unwrap();
+        repository
+            .save("acme", &Policy::new("p", Effect::Deny).on(offer()))
+            .unwrap();
+        let pdp = PolicyDecisionPoint::new(repository);
+        assert_eq!(
+            pdp.decide("acme", &request()).unwrap(),
+            Decision::Deny {
+                policy_id: Some("p".to_owned())
+            }
+        );
+        assert!(!pdp.decide("globex", &request()).unwrap().is_permitted());
+    }
+}
@@ -0,0 +1,1019 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::{AccessError, Permission};
+
+/// A named set of permissions that users of a tenant can be assigned.
+///
+/// System roles are created by the IAM itself and cannot be replaced or
+/// removed; their permissions and assignments can still change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Role {
+    tenant_id: String,
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    permissions: BTreeSet<Permission>,
+    #[serde(default)]
+    system: bool,
+}
+
+impl Role {
+    pub fn new(tenant_id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            name: name.into(),
+            description: None,
+            permissions: BTreeSet::new(),
+            system: false,
+        }
+    }
+
+    /// A role managed by the system.
+    pub fn system(tenant_id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            system: true,
+            ..Self::new(tenant_id, name)
+        }
+    }
+
+    pub fn describe(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.system
+    }
+
+    pub fn permissions(&self) -> impl Iterator<Item = &Permission> {
+        self.permissions.iter()
+    }
+
+    /// Adds the permission, returning whether the role lacked it.
+    pub fn grant(&mut self, permission: Permission) -> bool {
+        self.permissions.insert(permission)
+    }
+
+    /// Removes the permission, returning whether the role had it.
+    pub fn revoke(&mut self, permission: &Permission) -> bool {
+        self.permissions.remove(permission)
+    }
+
+    /// Whether one of the role's permissions implies the requested one.
+    pub fn allows(&self, requested: &Permission) -> bool {
+        self.permissions.iter().any(|p| p.implies(requested))
+    }
+}
+
+/// What adapters show of a role, without its permissions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleDescriptor {
+    tenant_id: String,
+    name: String,
+    description: Option<String>,
+    system: bool,
+    member_count: usize,
+}
+
+impl RoleDescriptor {
+    fn new(role: Role, member_count: usize) -> Self {
+        Self {
+            tenant_id: role.tenant_id,
+            name: role.name,
+            description: role.description,
+            system: role.system,
+            member_count,
+        }
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.system
+    }
+
+    /// Users playing the role, tenant-wide or in any scope.
+    pub fn member_count(&self) -> usize {
+        self.member_count
+    }
+}
+
+/// Stores the roles of each tenant and which users play them.
+pub trait RoleRepository: Send + Sync {
+    fn role_named(&self, tenant_id: &str, name: &str) -> Result<Option<Role>, AccessError>;
+
+    fn roles_in(&self, tenant_id: &str) -> Result<Vec<Role>, AccessError>;
+
+    /// Number of users playing the role, tenant-wide or in any scope.
+    fn member_count(&self, tenant_id: &str, name: &str) -> Result<usize, AccessError>;
+
+    /// Inserts or replaces the tenant's role with the same name, failing
+    /// with [`AccessError::SystemManaged`] if that role is a system role.
+    fn save(&self, role: &Role) -> Result<(), AccessError>;
+
+    /// Removes the role along with its assignments, failing with
+    /// [`AccessError::SystemManaged`] for a system role.
+    fn remove(&self, tenant_id: &str, name: &str) -> Result<(), AccessError>;
+
+    /// Adds the permission to the stored role in a single step, returning
+    /// whether the role lacked it, so that concurrent changes to the role's
+    /// permissions are not lost.
+    fn grant(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError>;
+
+    /// Removes the permission from the stored role in a single step,
+    /// returning whether the role had it.
+    fn revoke(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError>;
+
+    /// Assigns an existing role to the user, either tenant-wide or only
+    /// within `scope` (e.g. a project id).
+    fn assign(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<(), AccessError>;
+
+    /// Assigns an existing role to each of the users tenant-wide in a single
+    /// step, returning for each whether the user lacked the role.
+    fn assign_all(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        usernames: &[String],
+    ) -> Result<Vec<bool>, AccessError>;
+
+    /// Removes the assignment made with the same scope.
+    fn unassign(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<(), AccessError>;
+
+    /// The roles assigned to the user tenant-wide.
+    fn roles_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Role>, AccessError>;
+
+    /// Whether the user plays the role within `scope`, either through an
+    /// assignment to that scope or a tenant-wide one. Without a scope only
+    /// tenant-wide assignments count.
+    fn is_in_role(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, AccessError>;
+}
+
+/// A tenant and a role or user name.
+type TenantKey = (String, String);
+
+/// A role name and the scope it was assigned in.
+type Assignment = (String, Option<String>);
+
+#[derive(Debug, Default)]
+struct Roles {
+    roles: HashMap<TenantKey, Role>,
+    /// Roles each user of a tenant plays.
+    assignments: HashMap<TenantKey, HashSet<Assignment>>,
+}
+
+impl Roles {
+    fn role_mut(&mut self, tenant_id: &str, name: &str) -> Result<&mut Role, AccessError> {
+        self.roles
+            .get_mut(&key(tenant_id, name))
+            .ok_or_else(|| AccessError::UnknownRole(name.to_owned()))
+    }
+}
+
+/// Repository keeping roles and their assignments in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryRoleRepository {
+    roles: RwLock<Roles>,
+}
+
+impl InMemoryRoleRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn key(tenant_id: &str, name: &str) -> TenantKey {
+    (tenant_id.to_owned(), name.to_owned())
+}
+
+impl RoleRepository for InMemoryRoleRepository {
+    fn role_named(&self, tenant_id: &str, name: &str) -> Result<Option<Role>, AccessError> {
+        let roles = self.roles.read().expect("roles lock poisoned");
+        Ok(roles.roles.get(&key(tenant_id, name)).cloned())
+    }
+
+    fn roles_in(&self, tenant_id: &str) -> Result<Vec<Role>, AccessError> {
+        let roles = self.roles.read().expect("roles lock poisoned");
+        Ok(roles
+            .roles
+            .iter()
+            .filter(|((tenant, _), _)| tenant == tenant_id)
+            .map(|(_, role)| role.clone())
+            .collect())
+    }
+
+    fn member_count(&self, tenant_id: &str, name: &str) -> Result<usize, AccessError> {
+        let roles = self.roles.read().expect("roles lock poisoned");
+        Ok(roles
+            .assignments
+            .iter()
+            .filter(|((tenant, _), assignments)| {
+                tenant == tenant_id && assignments.iter().any(|(role, _)| role == name)
+            })
+            .count())
+    }
+
+    fn save(&self, role: &Role) -> Result<(), AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        let key = key(&role.tenant_id, &role.name);
+        if roles.roles.get(&key).is_some_and(Role::is_system) {
+            return Err(AccessError::SystemManaged(role.name.clone()));
+        }
+        roles.roles.insert(key, role.clone());
+        Ok(())
+    }
+
+    fn remove(&self, tenant_id: &str, name: &str) -> Result<(), AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        let key = key(tenant_id, name);
+        if roles.roles.get(&key).is_some_and(Role::is_system) {
+            return Err(AccessError::SystemManaged(name.to_owned()));
+        }
+        if roles.roles.remove(&key).is_some() {
+            roles.assignments.retain(|(tenant, _), assignments| {
+                if tenant == tenant_id {
+                    assignments.retain(|(role, _)| role != name);
+                }
+                !assignments.is_empty()
+            });
+        }
+        Ok(())
+    }
+
+    fn grant(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        Ok(roles.role_mut(tenant_id, name)?.grant(permission.clone()))
+    }
+
+    fn revoke(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        Ok(roles.role_mut(tenant_id, name)?.revoke(permission))
+    }
+
+    fn assign(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<(), AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        roles.role_mut(tenant_id, name)?;
+        roles
+            .assignments
+            .entry(key(tenant_id, username))
+            .or_default()
+            .insert((name.to_owned(), scope.map(str::to_owned)));
+        Ok(())
+    }
+
+    fn assign_all(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        usernames: &[String],
+    ) -> Result<Vec<bool>, AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        roles.role_mut(tenant_id, name)?;
+        Ok(usernames
+            .iter()
+            .map(|username| {
+                roles
+                    .assignments
+                    .entry(key(tenant_id, username))
+                    .or_default()
+                    .insert((name.to_owned(), None))
+            })
+            .collect())
+    }
+
+    fn unassign(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<(), AccessError> {
+        let mut roles = self.roles.write().expect("roles lock poisoned");
+        let key = key(tenant_id, username);
+        if let Some(assignments) = roles.assignments.get_mut(&key) {
+            assignments.remove(&(name.to_owned(), scope.map(str::to_owned)));
+            if assignments.is_empty() {
+                roles.assignments.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn roles_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Role>, AccessError> {
+        let roles = self.roles.read().expect("roles lock poisoned");
+        let Some(assignments) = roles.assignments.get(&key(tenant_id, username)) else {
+            return Ok(Vec::new());
+        };
+        Ok(assignments
+            .iter()
+            .filter(|(_, scope)| scope.is_none())
+            .filter_map(|(name, _)| roles.roles.get(&key(tenant_id, name)).cloned())
+            .collect())
+    }
+
+    fn is_in_role(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, AccessError> {
+        let roles = self.roles.read().expect("roles lock poisoned");
+        Ok(roles
+            .assignments
+            .get(&key(tenant_id, username))
+            .is_some_and(|assignments| {
+                assignments.iter().any(|(role, assigned)| {
+                    role == name && (assigned.is_none() || assigned.as_deref() == scope)
+                })
+            }))
+    }
+}
+
+/// Something that changed who holds which permission.
+///
+/// Returned by the write operations of [`AccessApplicationService`] so they
+/// can be published, e.g. to invalidate a [`CachingAccessApplicationService`]
+/// of another instance.
+///
+/// [`CachingAccessApplicationService`]: super::CachingAccessApplicationService
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum RoleEvent {
+    RoleDefined {
+        tenant_id: String,
+        role: String,
+    },
+    RoleRemoved {
+        tenant_id: String,
+        role: String,
+    },
+    PermissionGranted {
+        tenant_id: String,
+        role: String,
+        permission: Permission,
+    },
+    PermissionRevoked {
+        tenant_id: String,
+        role: String,
+        permission: Permission,
+    },
+    UserAssignedToRole {
+        tenant_id: String,
+        role: String,
+        username: String,
+        scope: Option<String>,
+    },
+    UserUnassignedFromRole {
+        tenant_id: String,
+        role: String,
+        username: String,
+        scope: Option<String>,
+    },
+}
+
+impl RoleEvent {
+    pub fn tenant_id(&self) -> &str {
+        match self {
+            RoleEvent::RoleDefined { tenant_id, .. }
+            | RoleEvent::RoleRemoved { tenant_id, .. }
+            | RoleEvent::PermissionGranted { tenant_id, .. }
+            | RoleEvent::PermissionRevoked { tenant_id, .. }
+            | RoleEvent::UserAssignedToRole { tenant_id, .. }
+            | RoleEvent::UserUnassignedFromRole { tenant_id, .. } => tenant_id,
+        }
+    }
+
+    /// The only user affected, or `None` if the change affects everyone
+    /// playing the role.
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            RoleEvent::UserAssignedToRole { username, .. }
+            | RoleEvent::UserUnassignedFromRole { username, .. } => Some(username),
+            _ => None,
+        }
+    }
+}
+
+/// Users persisted by one call of [`RoleRepository::assign_all`].
+const ASSIGNMENT_CHUNK_SIZE: usize = 100;
+
+/// What happened to one user of a bulk role assignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssignmentOutcome {
+    Assigned,
+    AlreadyAssigned,
+    /// The username was empty, malformed or repeated.
+    Invalid(String),
+    /// Storing the assignment failed; the user may be retried.
+    Failed(String),
+}
+
+/// Per-user results of [`AccessApplicationService::assign_users_to_role`],
+/// in the order the users were given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoleAssignmentReport {
+    outcomes: Vec<(String, AssignmentOutcome)>,
+}
+
+impl RoleAssignmentReport {
+    pub fn outcomes(&self) -> &[(String, AssignmentOutcome)] {
+        &self.outcomes
+    }
+
+    pub fn outcome_of(&self, username: &str) -> Option<&AssignmentOutcome> {
+        self.outcomes
+            .iter()
+            .find(|(name, _)| name == username)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// Users that did not play the role before.
+    pub fn assigned(&self) -> impl Iterator<Item = &str> {
+        self.outcomes
+            .iter()
+            .filter(|(_, outcome)| *outcome == AssignmentOutcome::Assigned)
+            .map(|(username, _)| username.as_str())
+    }
+
+    /// Whether every user now plays the role.
+    pub fn is_complete(&self) -> bool {
+        self.outcomes.iter().all(|(_, outcome)| {
+            matches!(
+                outcome,
+                AssignmentOutcome::Assigned | AssignmentOutcome::AlreadyAssigned
+            )
+        })
+    }
+}
+
+fn validate_username(username: &str) -> Result<(), String> {
+    if username.is_empty() {
+        Err("username is empty".to_owned())
+    } else if username
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control())
+    {
+        Err("username contains whitespace or control characters".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
+/// Answers whether users hold a permission through the roles they play.
+pub struct AccessApplicationService<R> {
+    roles: R,
+}
+
+impl<R: RoleRepository> AccessApplicationService<R> {
+    pub fn new(roles: R) -> Self {
+        Self { roles }
+    }
+
+    /// Defines a new role of the tenant, or replaces one that is not a
+    /// system role.
+    pub fn define_role(&self, role: &Role) -> Result<RoleEvent, AccessError> {
+        self.roles.save(role)?;
+        Ok(RoleEvent::RoleDefined {
+            tenant_id: role.tenant_id.clone(),
+            role: role.name.clone(),
+        })
+    }
+
+    /// Removes the tenant's role and its assignments; system roles cannot
+    /// be removed.
+    pub fn remove_role(&self, tenant_id: &str, role: &str) -> Result<RoleEvent, AccessError> {
+        self.roles.remove(tenant_id, role)?;
+        Ok(RoleEvent::RoleRemoved {
+            tenant_id: tenant_id.to_owned(),
+            role: role.to_owned(),
+        })
+    }
+
+    /// Assigns the tenant's role to the user, tenant-wide or only within
+    /// `scope`.
+    pub fn assign(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<RoleEvent, AccessError> {
+        self.roles.assign(tenant_id, role, username, scope)?;
+        Ok(RoleEvent::UserAssignedToRole {
+            tenant_id: tenant_id.to_owned(),
+            role: role.to_owned(),
+            username: username.to_owned(),
+            scope: scope.map(str::to_owned),
+        })
+    }
+
+    /// Assigns the tenant's role to many users at once, e.g. when
+    /// onboarding a team.
+    ///
+    /// Usernames are validated first, then persisted in chunks so that a
+    /// storage failure only affects its own chunk; the report tells what
+    /// happened to each user. Fails as a whole only if the role does not
+    /// exist.
+    pub fn assign_users_to_role<S: AsRef<str>>(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        usernames: &[S],
+    ) -> Result<RoleAssignmentReport, AccessError> {
+        let mut outcomes = Vec::with_capacity(usernames.len());
+        let mut seen = HashSet::new();
+        let mut valid = Vec::new();
+        for username in usernames.iter().map(AsRef::as_ref) {
+            let outcome = match validate_username(username) {
+                Err(reason) => AssignmentOutcome::Invalid(reason),
+                Ok(()) if !seen.insert(username) => {
+                    AssignmentOutcome::Invalid("username is repeated".to_owned())
+                }
+                Ok(()) => {
+                    valid.push((outcomes.len(), username.to_owned()));
+                    AssignmentOutcome::Failed("not attempted".to_owned())
+                }
+            };
+            outcomes.push((username.to_owned(), outcome));
+        }
+        if self.roles.role_named(tenant_id, role)?.is_none() {
+            return Err(AccessError::UnknownRole(role.to_owned()));
+        }
+        for chunk in valid.chunks(ASSIGNMENT_CHUNK_SIZE) {
+            let names: Vec<String> = chunk.iter().map(|(_, name)| name.clone()).collect();
+            match self.roles.assign_all(tenant_id, role, &names) {
+                Ok(added) => {
+                    for ((index, _), added) in chunk.iter().zip(added) {
+                        outcomes[*index].1 = if added {
+                            AssignmentOutcome::Assigned
+                        } else {
+                            AssignmentOutcome::AlreadyAssigned
+                        };
+                    }
+                }
+                Err(error @ AccessError::UnknownRole(_)) => return Err(error),
+                Err(error) => {
+                    for (index, _) in chunk {
+                        outcomes[*index].1 = AssignmentOutcome::Failed(error.to_string());
+                    }
+                }
+            }
+        }
+        Ok(RoleAssignmentReport { outcomes })
+    }
+
+    /// Removes the assignment of the role made with the same scope.
+    pub fn unassign(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        username: &str,
+        scope: Option<&str>,
+    ) -> Result<RoleEvent, AccessError> {
+        self.roles.unassign(tenant_id, role, username, scope)?;
+        Ok(RoleEvent::UserUnassignedFromRole {
+            tenant_id: tenant_id.to_owned(),
+            role: role.to_owned(),
+            username: username.to_owned(),
+            scope: scope.map(str::to_owned),
+        })
+    }
+
+    /// Whether the user plays the role within `scope`, e.g. "is ada an
+    /// editor of project 42"; a tenant-wide assignment answers for every
+    /// scope.
+    pub fn is_in_role(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        role: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, AccessError> {
+        self.roles.is_in_role(tenant_id, role, username, scope)
+    }
+
+    /// Grants the permission to the tenant's role.
+    pub fn grant(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        permission: Permission,
+    ) -> Result<RoleEvent, AccessError> {
+        self.roles.grant(tenant_id, role, &permission)?;
+        Ok(RoleEvent::PermissionGranted {
+            tenant_id: tenant_id.to_owned(),
+            role: role.to_owned(),
+            permission,
+        })
+    }
+
+    /// Revokes the permission from the tenant's role.
+    pub fn revoke(
+        &self,
+        tenant_id: &str,
+        role: &str,
+        permission: &Permission,
+    ) -> Result<RoleEvent, AccessError> {
+        self.roles.revoke(tenant_id, role, permission)?;
+        Ok(RoleEvent::PermissionRevoked {
+            tenant_id: tenant_id.to_owned(),
+            role: role.to_owned(),
+            permission: permission.clone(),
+        })
+    }
+
+    /// The roles the user plays tenant-wide, ordered by name, e.g. for an admin screen
+    /// showing what the user can do.
+    pub fn roles_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Role>, AccessError> {
+        let mut roles = self.roles.roles_of(tenant_id, username)?;
+        roles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(roles)
+    }
+
+    /// Names of the roles the user plays, ordered, for the role claims of
+    /// the user's tokens (see [`TokenSubject::with_roles`]).
+    ///
+    /// [`TokenSubject::with_roles`]: crate::tokens::TokenSubject::with_roles
+    pub fn role_names_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<String>, AccessError> {
+        Ok(self
+            .roles_of(tenant_id, username)?
+            .into_iter()
+            .map(|role| role.name)
+            .collect())
+    }
+
+    /// The tenant's roles with their permissions, ordered by name, e.g. to
+    /// export a [`RoleSnapshot`] for resource servers.
+    ///
+    /// [`RoleSnapshot`]: crate::verify::RoleSnapshot
+    pub fn roles_in(&self, tenant_id: &str) -> Result<Vec<Role>, AccessError> {
+        let mut roles = self.roles.roles_in(tenant_id)?;
+        roles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(roles)
+    }
+
+    /// Descriptors of the tenant's roles, ordered by name.
+    pub fn role_descriptors(&self, tenant_id: &str) -> Result<Vec<RoleDescriptor>, AccessError> {
+        self.roles_in(tenant_id)?
+            .into_iter()
+            .map(|role| {
+                let members = self.roles.member_count(tenant_id, &role.name)?;
+                Ok(RoleDescriptor::new(role, members))
+            })
+            .collect()
+    }
+
+    pub fn role_descriptor(
+        &self,
+        tenant_id: &str,
+        role: &str,
+    ) -> Result<Option<RoleDescriptor>, AccessError> {
+        let Some(role) = self.roles.role_named(tenant_id, role)? else {
+            return Ok(None);
+        };
+        let members = self.roles.member_count(tenant_id, &role.name)?;
+        Ok(Some(RoleDescriptor::new(role, members)))
+    }
+
+    /// Whether one of the user's roles allows the permission.
+    pub fn is_authorized(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        permission: &Permission,
+    ) -> Result<bool, AccessError> {
+        Ok(self
+            .roles
+            .roles_of(tenant_id, username)?
+            .iter()
+            .any(|role| role.allows(permission)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(value: &str) -> Permission {
+        value.parse().unwrap()
+    }
+
+    fn service() -> AccessApplicationService<InMemoryRoleRepository> {
+        let roles = InMemoryRoleRepository::new();
+        roles.save(&Role::new("acme", "editor")).unwrap();
+        roles.assign("acme", "editor", "ada", None).unwrap();
+        AccessApplicationService::new(roles)
+    }
+
+    #[test]
+    fn users_hold_the_permissions_of_their_roles() {
+        let service = service();
+        service
+            .grant("acme", "editor", permission("invitation:*"))
+            .unwrap();
+
+        let offer = permission("invitation:offer");
+        assert!(service.is_authorized("acme", "ada", &offer).unwrap());
+        assert!(!service.is_authorized("acme", "bob", &offer).unwrap());
+        assert!(!service.is_authorized("globex", "ada", &offer).unwrap());
+        assert!(!service
+            .is_authorized("acme", "ada", &permission("tenant:delete"))
+            .unwrap());
+    }
+
+    #[test]
+    fn revoking_or_unassigning_removes_access() {
+        let service = service();
+        let offer = permission("invitation:offer");
+        service.grant("acme", "editor", offer.clone()).unwrap();
+        service.revoke("acme", "editor", &offer).unwrap();
+        assert!(!service.is_authorized("acme", "ada", &offer).unwrap());
+
+        service.grant("acme", "editor", offer.clone()).unwrap();
+        service.unassign("acme", "editor", "ada", None).unwrap();
+        assert!(!service.is_authorized("acme", "ada", &offer).unwrap());
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        let service = service();
+        assert!(matches!(
+            service.grant("acme", "admin", permission("*:*")),
+            Err(AccessError::UnknownRole(_))
+        ));
+        assert!(matches!(
+            service.roles.assign("acme", "admin", "ada", None),
+            Err(AccessError::UnknownRole(_))
+        ));
+    }
+
+    #[test]
+    fn concurrent_grants_are_all_kept() {
+        let service = service();
+        std::thread::scope(|scope| {
+            for action in ["offer", "withdraw", "accept", "decline"] {
+                let service = &service;
+                scope.spawn(move || {
+                    service
+                        .grant(
+                            "acme",
+                            "editor",
+                            Permission::new("invitation", action).unwrap(),
+                        )
+                        .unwrap()
+                });
+            }
+        });
+        let editor = service.roles.role_named("acme", "editor").unwrap().unwrap();
+        assert_eq!(editor.permissions().count(), 4);
+    }
+
+    #[test]
+    fn removing_a_role_drops_its_assignments() {
+        let service = service();
+        service.roles.save(&Role::new("acme", "viewer")).unwrap();
+        service.roles.assign("acme", "viewer", "ada", None).unwrap();
+        service.roles.remove("acme", "editor").unwrap();
+
+        let roles = service.roles.roles_of("acme", "ada").unwrap();
+        assert_eq!(roles.len(), 1);
+        assert_eq!(roles[0].name(), "viewer");
+
+        service.roles.save(&Role::new("acme", "editor")).unwrap();
+        assert_eq!(service.roles.roles_of("acme", "ada").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn roles_of_a_user_are_listed_by_name() {
+        let service = service();
+        service.roles.save(&Role::new("acme", "admin")).unwrap();
+        service.roles.save(&Role::new("globex", "auditor")).unwrap();
+        service.roles.assign("acme", "admin", "ada", None).unwrap();
+        service
+            .roles
+            .assign("globex", "auditor", "ada", None)
+            .unwrap();
+
+        assert_eq!(
+            service.role_names_of("acme", "ada").unwrap(),
+            ["admin", "editor"]
+        );
+        assert_eq!(service.roles_of("globex", "ada").unwrap().len(), 1);
+        assert!(service.roles_of("acme", "bob").unwrap().is_empty());
+    }
+
+    #[test]
+    fn system_roles_cannot_be_replaced_or_removed() {
+        let service = service();
+        service
+            .define_role(&Role::system("acme", "tenant-admin"))
+            .unwrap();
+        service.assign("acme", "tenant-admin", "ada", None).unwrap();
+
+        assert!(matches!(
+            service.define_role(&Role::new("acme", "tenant-admin")),
+            Err(AccessError::SystemManaged(_))
+        ));
+        assert!(matches!(
+            service.remove_role("acme", "tenant-admin"),
+            Err(AccessError::SystemManaged(_))
+        ));
+        let role = service
+            .roles
+            .role_named("acme", "tenant-admin")
+            .unwrap()
+            .unwrap();
+        assert!(role.is_system());
+        assert_eq!(service.roles_of("acme", "ada").unwrap().len(), 2);
+
+        service
+            .grant("acme", "tenant-admin", permission("tenant:*"))
+            .unwrap();
+        service.remove_role("acme", "editor").unwrap();
+        assert_eq!(
+            service.role_names_of("acme", "ada").unwrap(),
+            ["tenant-admin"]
+        );
+    }
+
+    #[test]
+    fn scoped_assignments_answer_only_for_their_scope() {
+        let service = service();
+        service.define_role(&Role::new("acme", "owner")).unwrap();
+        service
+            .assign("acme", "owner", "bob", Some("project-42"))
+            .unwrap();
+
+        assert!(service
+            .is_in_role("acme", "bob", "owner", Some("project-42"))
+            .unwrap());
+        assert!(!service
+            .is_in_role("acme", "bob", "owner", Some("project-7"))
+            .unwrap());
+        assert!(!service.is_in_role("acme", "bob", "owner", None).unwrap());
+        assert!(service.roles_of("acme", "bob").unwrap().is_empty());
+
+        assert!(service
+            .is_in_role("acme", "ada", "editor", Some("project-7"))
+            .unwrap());
+        assert!(service.is_in_role("acme", "ada", "editor", None).unwrap());
+
+        service
+            .unassign("acme", "owner", "bob", Some("project-42"))
+            .unwrap();
+        assert!(!service
+            .is_in_role("acme", "bob", "owner", Some("project-42"))
+            .unwrap());
+    }
+
+    #[test]
+    fn bulk_assignment_reports_each_user() {
+        let service = service();
+        let report = service
+            .assign_users_to_role("acme", "editor", &["ada", "bob", "", "bob", "carl lee"])
+            .unwrap();
+
+        assert_eq!(
+            report.outcome_of("ada"),
+            Some(&AssignmentOutcome::AlreadyAssigned)
+        );
+        assert_eq!(report.outcomes()[1].1, AssignmentOutcome::Assigned);
+        assert!(matches!(
+            report.outcomes()[2].1,
+            AssignmentOutcome::Invalid(_)
+        ));
+        assert!(matches!(
+            report.outcomes()[3].1,
+            AssignmentOutcome::Invalid(_)
+        ));
+        assert!(matches!(
+            report.outcome_of("carl lee"),
+            Some(AssignmentOutcome::Invalid(_))
+        ));
+        assert!(!report.is_complete());
+        assert!(service.is_in_role("acme", "bob", "editor", None).unwrap());
+    }
+
+    #[test]
+    fn bulk_assignment_spans_several_chunks() {
+        let service = service();
+        let usernames: Vec<String> = (0..250).map(|n| format!("user-{n}")).collect();
+        let report = service
+            .assign_users_to_role("acme", "editor", &usernames)
+            .unwrap();
+        assert!(report.is_complete());
+        assert!(service
+            .is_in_role("acme", "user-249", "editor", None)
+            .unwrap());
+        assert!(matches!(
+            service.assign_users_to_role("acme", "admin", &usernames),
+            Err(AccessError::UnknownRole(_))
+        ));
+    }
+
+    #[test]
+    fn descriptors_count_members_of_each_role() {
+        let service = service();
+        service
+            .define_role(&Role::system("acme", "admin").describe("Runs the tenant"))
+            .unwrap();
+        service
+            .define_role(&Role::new("globex", "auditor"))
+            .unwrap();
+        service.assign("acme", "editor", "bob", None).unwrap();
+        service
+            .assign("acme", "editor", "carl", Some("project-42"))
+            .unwrap();
+
+        let descriptors = service.role_descriptors("acme").unwrap();
+        let summary: Vec<_> = descriptors
+            .iter()
+            .map(|role| (role.name(), role.member_count(), role.is_system()))
+            .collect();
+        assert_eq!(summary, [("admin", 0, true), ("editor", 3, false)]);
+        assert_eq!(descriptors[0].description(), Some("Runs the tenant"));
+        assert_eq!(descriptors[0].tenant_id(), "acme");
+
+        let auditor = service.role_descriptor("globex", "auditor").unwrap();
+        assert_eq!(auditor.map(|role| role.member_count()), Some(0));
+        assert!(service
+            .role_descriptor("acme", "auditor")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use caseless::default_case_fold_str;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns user-supplied identifiers into the form used as a lookup key.
+///
+/// Registration and lookup must run the same canonicalizer so that values
+/// differing only in case, whitespace or Unicode composition resolve to the
+/// same identity.
+pub trait Canonicalizer: Send + Sync {
+    fn canonicalize(&self, value: &str) -> String;
+
+    /// Canonicalizes the value, keeping the original next to it.
+    fn canonical(&self, value: &str) -> Canonical {
+        Canonical {
+            original: value.to_owned(),
+            canonical: self.canonicalize(value),
+        }
+    }
+}
+
+/// An identifier as entered, together with its canonical lookup key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Canonical {
+    original: String,
+    canonical: String,
+}
+
+impl Canonical {
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+}
+
+impl fmt::Display for Canonical {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.original)
+    }
+}
+
+/// Trims, NFC-normalizes and case-folds the value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultCanonicalizer;
+
+impl Canonicalizer for DefaultCanonicalizer {
+    fn canonicalize(&self, value: &str) -> String {
+        fold(value.trim())
+    }
+}
+
+/// Canonicalizer for email addresses.
+///
+/// On top of the default rules it can drop `+tag` suffixes and dots from the
+/// local part, for providers that deliver such variants to the same mailbox.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailCanonicalizer {
+    ignore_dots: bool,
+    ignore_plus_tag: bool,
+}
+
+impl EmailCanonicalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ignore_dots(mut self, ignore: bool) -> Self {
+        self.ignore_dots = ignore;
+        self
+    }
+
+    pub fn ignore_plus_tag(mut self, ignore: bool) -> Self {
+        self.ignore_plus_tag = ignore;
+        self
+    }
+}
+
+impl Canonicalizer for EmailCanonicalizer {
+    fn canonicalize(&self, value: &str) -> String {
+        let value = fold(value.trim());
+        let Some((local, domain)) = value.rsplit_once('@') else {
+            return value;
+        };
+        let mut local = local;
+        if self.ignore_plus_tag {
+            if let Some((head, _)) = local.split_once('+') {
+                local = head;
+            }
+        }
+        let local = if self.ignore_dots {
+            local.replace('.', "")
+        } else {
+            local.to_owned()
+        };
+        format!("{}@{}", local, domain)
+    }
+}
+
+fn fold(value: &str) -> String {
+    let composed: String = value.nfc().collect();
+    default_case_fold_str(&composed).nfc().collect()
+}
@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of the current time for time-dependent domain logic.
+///
+/// Checks such as validity windows take a clock instead of calling
+/// `Utc::now()` directly, so tests can pin or move time deterministically.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock backed by the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that always reports the same instant until explicitly moved.
+///
+/// The instant sits behind a mutex so a test can keep moving a clock it has
+/// already lent to the services under test.
+#[derive(Debug)]
+pub struct FixedClock(Mutex<DateTime<Utc>>);
+
+impl FixedClock {
+    pub fn new(instant: DateTime<Utc>) -> Self {
+        Self(Mutex::new(instant))
+    }
+
+    /// Moves the clock to the given instant.
+    pub fn set(&self, instant: DateTime<Utc>) {
+        *self.0.lock().expect("clock lock poisoned") = instant;
+    }
+
+    /// Moves the clock forward (or backward, for negative durations).
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("clock lock poisoned") += duration;
+    }
+}
+
+impl Clone for FixedClock {
+    fn clone(&self) -> Self {
+        Self::new(self.now())
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().expect("clock lock poisoned")
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
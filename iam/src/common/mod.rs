@@ -0,0 +1,5 @@
+pub mod canonicalization;
+pub mod clock;
+pub mod page;
+pub(crate) mod secret;
+pub mod serialization;
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of items returned when a request does not ask for a limit.
+pub const DEFAULT_PAGE_LIMIT: u32 = 50;
+
+/// Upper bound on the number of items a single page may hold.
+pub const MAX_PAGE_LIMIT: u32 = 1000;
+
+/// Direction of a sort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Field and direction used to order a listing.
+///
+/// Field names are interpreted by each query; unknown fields should be
+/// rejected there rather than silently ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Sort {
+    field: String,
+    direction: SortDirection,
+}
+
+impl Sort {
+    pub fn new(field: impl Into<String>, direction: SortDirection) -> Self {
+        Self {
+            field: field.into(),
+            direction,
+        }
+    }
+
+    pub fn ascending(field: impl Into<String>) -> Self {
+        Self::new(field, SortDirection::Ascending)
+    }
+
+    pub fn descending(field: impl Into<String>) -> Self {
+        Self::new(field, SortDirection::Descending)
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn direction(&self) -> SortDirection {
+        self.direction
+    }
+}
+
+/// Where a page starts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PagePosition {
+    Offset(u64),
+    Cursor(String),
+}
+
+impl Default for PagePosition {
+    fn default() -> Self {
+        PagePosition::Offset(0)
+    }
+}
+
+/// A request for a bounded slice of a listing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PageRequest {
+    limit: u32,
+    position: PagePosition,
+    sort: Option<Sort>,
+}
+
+impl PageRequest {
+    /// Creates a request; the limit is clamped to `1..=MAX_PAGE_LIMIT`.
+    pub fn new(limit: u32, position: PagePosition) -> Self {
+        Self {
+            limit: limit.clamp(1, MAX_PAGE_LIMIT),
+            position,
+            sort: None,
+        }
+    }
+
+    /// Requests the first page of the listing.
+    pub fn first(limit: u32) -> Self {
+        Self::new(limit, PagePosition::default())
+    }
+
+    /// Requests the page starting at the given offset.
+    pub fn at_offset(offset: u64, limit: u32) -> Self {
+        Self::new(limit, PagePosition::Offset(offset))
+    }
+
+    /// Requests the page following the given opaque cursor.
+    pub fn after(cursor: impl Into<String>, limit: u32) -> Self {
+        Self::new(limit, PagePosition::Cursor(cursor.into()))
+    }
+
+    pub fn sorted_by(mut self, sort: Sort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn position(&self) -> &PagePosition {
+        &self.position
+    }
+
+    pub fn sort(&self) -> Option<&Sort> {
+        self.sort.as_ref()
+    }
+
+    /// The offset of the page, when it is offset-based.
+    pub fn offset(&self) -> Option<u64> {
+        match self.position {
+            PagePosition::Offset(offset) => Some(offset),
+            PagePosition::Cursor(_) => None,
+        }
+    }
+
+    /// The cursor of the page, when it is cursor-based.
+    pub fn cursor(&self) -> Option<&str> {
+        match &self.position {
+            PagePosition::Offset(_) => None,
+            PagePosition::Cursor(cursor) => Some(cursor),
+        }
+    }
+
+    fn with_position(&self, position: PagePosition) -> Self {
+        Self {
+            limit: self.limit,
+            position,
+            sort: self.sort.clone(),
+        }
+    }
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        Self::first(DEFAULT_PAGE_LIMIT)
+    }
+}
+
+/// A slice of a listing, with the request for the following slice if any.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    items: Vec<T>,
+    next: Option<PageRequest>,
+    total: Option<u64>,
+}
+
+impl<T> Page<T> {
+    /// Builds an offset-based page.
+    ///
+    /// A full page is assumed to have a successor unless `total` says
+    /// otherwise.
+    pub fn from_offset(items: Vec<T>, request: &PageRequest, total: Option<u64>) -> Self {
+        let offset = request.offset().unwrap_or_default();
+        let end = offset + items.len() as u64;
+        let has_more = match total {
+            Some(total) => end < total,
+            None => items.len() as u64 >= u64::from(request.limit()),
+        };
+        let next = has_more.then(|| request.with_position(PagePosition::Offset(end)));
+        Self { items, next, total }
+    }
+
+    /// Builds a cursor-based page; `next_cursor` is absent on the last page.
+    pub fn from_cursor(items: Vec<T>, request: &PageRequest, next_cursor: Option<String>) -> Self {
+        let next = next_cursor.map(|cursor| request.with_position(PagePosition::Cursor(cursor)));
+        Self {
+            items,
+            next,
+            total: None,
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+
+    /// The request for the following page, if there is one.
+    pub fn next(&self) -> Option<&PageRequest> {
+        self.next.as_ref()
+    }
+
+    /// The total number of items in the listing, when the query knows it.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Converts the items, e.g. from aggregates to DTOs, keeping the paging.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next: self.next,
+            total: self.total,
+        }
+    }
+}
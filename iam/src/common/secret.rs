@@ -0,0 +1,19 @@
+use data_encoding::BASE64URL_NOPAD;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+/// A random, URL-safe token carrying 256 bits of entropy.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = BASE64URL_NOPAD.encode(&bytes);
+    bytes.zeroize();
+    token
+}
+
+/// Hex SHA-256 digest under which a token is stored instead of the token
+/// itself.
+pub(crate) fn digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::str::FromStr;
+
+use prost::Message;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Errors raised while encoding or decoding a payload.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CodecError {
+    #[error("cannot encode payload as {format}: {message}")]
+    Encode {
+        format: PayloadFormat,
+        message: String,
+    },
+    #[error("cannot decode payload as {format}: {message}")]
+    Decode {
+        format: PayloadFormat,
+        message: String,
+    },
+    #[error("unknown payload format: {0}")]
+    UnknownFormat(String),
+}
+
+/// An event or token payload that can travel in every [`PayloadFormat`].
+///
+/// JSON and CBOR go through serde; protobuf goes through the `prost`
+/// message the payload converts to.
+pub trait Payload: Serialize + DeserializeOwned {
+    type Message: Message + Default;
+
+    fn to_message(&self) -> Self::Message;
+
+    /// Rebuilds the payload, failing when the message is not a valid one.
+    fn from_message(message: Self::Message) -> Result<Self, String>;
+}
+
+/// Serializes event and token payloads to and from their wire representation.
+pub trait PayloadCodec {
+    /// The format produced by this codec.
+    fn format(&self) -> PayloadFormat;
+
+    /// The media type to advertise for encoded payloads.
+    fn content_type(&self) -> &'static str {
+        self.format().content_type()
+    }
+
+    fn encode<T: Payload>(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+
+    fn decode<T: Payload>(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// Codec producing JSON documents.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl PayloadCodec for JsonCodec {
+    fn format(&self) -> PayloadFormat {
+        PayloadFormat::Json
+    }
+
+    fn encode<T: Payload>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|e| CodecError::Encode {
+            format: PayloadFormat::Json,
+            message: e.to_string(),
+        })
+    }
+
+    fn decode<T: Payload>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|e| CodecError::Decode {
+            format: PayloadFormat::Json,
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Codec producing compact CBOR documents.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+impl PayloadCodec for CborCodec {
+    fn format(&self) -> PayloadFormat {
+        PayloadFormat::Cbor
+    }
+
+    fn encode<T: Payload>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        let mut buffer = Vec::new();
+        ciborium::into_writer(value, &mut buffer).map_err(|e| CodecError::Encode {
+            format: PayloadFormat::Cbor,
+            message: e.to_string(),
+        })?;
+        Ok(buffer)
+    }
+
+    fn decode<T: Payload>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        ciborium::from_reader(bytes).map_err(|e| CodecError::Decode {
+            format: PayloadFormat::Cbor,
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Codec producing Protocol Buffers messages.
+///
+/// Payloads are encoded as the typed message they convert to, so fields
+/// travel by tag number rather than by name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtobufCodec;
+
+impl PayloadCodec for ProtobufCodec {
+    fn format(&self) -> PayloadFormat {
+        PayloadFormat::Protobuf
+    }
+
+    fn encode<T: Payload>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(value.to_message().encode_to_vec())
+    }
+
+    fn decode<T: Payload>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        let decode_error = |message: String| CodecError::Decode {
+            format: PayloadFormat::Protobuf,
+            message,
+        };
+        let message = T::Message::decode(bytes).map_err(|e| decode_error(e.to_string()))?;
+        T::from_message(message).map_err(decode_error)
+    }
+}
+
+/// The payload format selected in configuration.
+///
+/// The format itself acts as a codec, dispatching to the matching adapter, so
+/// callers can hold a single value read from configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    Cbor,
+    Protobuf,
+}
+
+impl PayloadFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PayloadFormat::Json => "application/json",
+            PayloadFormat::Cbor => "application/cbor",
+            PayloadFormat::Protobuf => "application/x-protobuf",
+        }
+    }
+}
+
+impl PayloadCodec for PayloadFormat {
+    fn format(&self) -> PayloadFormat {
+        *self
+    }
+
+    fn encode<T: Payload>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            PayloadFormat::Json => JsonCodec.encode(value),
+            PayloadFormat::Cbor => CborCodec.encode(value),
+            PayloadFormat::Protobuf => ProtobufCodec.encode(value),
+        }
+    }
+
+    fn decode<T: Payload>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            PayloadFormat::Json => JsonCodec.decode(bytes),
+            PayloadFormat::Cbor => CborCodec.decode(bytes),
+            PayloadFormat::Protobuf => ProtobufCodec.decode(bytes),
+        }
+    }
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadFormat::Json => f.write_str("json"),
+            PayloadFormat::Cbor => f.write_str("cbor"),
+            PayloadFormat::Protobuf => f.write_str("protobuf"),
+        }
+    }
+}
+
+impl FromStr for PayloadFormat {
+    type Err = CodecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "cbor" => Ok(PayloadFormat::Cbor),
+            "protobuf" | "proto" => Ok(PayloadFormat::Protobuf),
+            other => Err(CodecError::UnknownFormat(other.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        tenant_id: String,
+        version: u32,
+        occurred_at: i64,
+        ratio: f64,
+        tags: Vec<String>,
+        parent: Option<Box<Event>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct EventMessage {
+        #[prost(string, tag = "1")]
+        tenant_id: String,
+        #[prost(uint32, tag = "2")]
+        version: u32,
+        #[prost(sint64, tag = "3")]
+        occurred_at: i64,
+        #[prost(double, tag = "4")]
+        ratio: f64,
+        #[prost(string, repeated, tag = "5")]
+        tags: Vec<String>,
+        #[prost(message, optional, boxed, tag = "6")]
+        parent: Option<Box<EventMessage>>,
+    }
+
+    impl Payload for Event {
+        type Message = EventMessage;
+
+        fn to_message(&self) -> EventMessage {
+            EventMessage {
+                tenant_id: self.tenant_id.clone(),
+                version: self.version,
+                occurred_at: self.occurred_at,
+                ratio: self.ratio,
+                tags: self.tags.clone(),
+                parent: self.parent.as_ref().map(|p| Box::new(p.to_message())),
+            }
+        }
+
+        fn from_message(message: EventMessage) -> Result<Self, String> {
+            if message.tenant_id.is_empty() {
+                return Err("missing tenant".to_owned());
+            }
+            Ok(Event {
+                tenant_id: message.tenant_id,
+                version: message.version,
+                occurred_at: message.occurred_at,
+                ratio: message.ratio,
+                tags: message.tags,
+                parent: message
+                    .parent
+                    .map(|p| Event::from_message(*p).map(Box::new))
+                    .transpose()?,
+            })
+        }
+    }
+
+    fn event() -> Event {
+        Event {
+            tenant_id: "acme".to_owned(),
+            version: 3,
+            occurred_at: -1_700_000_000,
+            ratio: 0.25,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            parent: Some(Box::new(Event {
+                tenant_id: "root".to_owned(),
+                version: 0,
+                occurred_at: 0,
+                ratio: 1.0,
+                tags: Vec::new(),
+                parent: None,
+            })),
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for format in [
+            PayloadFormat::Json,
+            PayloadFormat::Cbor,
+            PayloadFormat::Protobuf,
+        ] {
+            let bytes = format.encode(&event()).unwrap();
+            assert_eq!(
+                format.decode::<Event>(&bytes).unwrap(),
+                event(),
+                "{}",
+                format
+            );
+            assert_eq!(format.to_string().parse::<PayloadFormat>().unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn protobuf_is_smaller_than_json() {
+        let protobuf = ProtobufCodec.encode(&event()).unwrap();
+        let json = JsonCodec.encode(&event()).unwrap();
+        assert!(protobuf.len() < json.len() / 2, "{} bytes", protobuf.len());
+    }
+
+    #[test]
+    fn protobuf_rejects_invalid_messages() {
+        assert!(ProtobufCodec.decode::<Event>(&[0xff]).is_err());
+        let empty = EventMessage::default().encode_to_vec();
+        assert!(matches!(
+            ProtobufCodec.decode::<Event>(&empty),
+            Err(CodecError::Decode { .. })
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+
+use super::{ExternalIdentity, FederatedIdentityRepository, FederationError, LinkedIdentity};
+
+/// A way a user can sign in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "method")]
+#[non_exhaustive]
+pub enum SignInMethod {
+    Password,
+    Passkey { credential_id: String },
+    Federated(LinkedIdentity),
+}
+
+/// Reports the sign-in methods a user holds locally, such as a password or
+/// passkeys.
+pub trait LocalSignInMethods: Send + Sync {
+    fn local_methods_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<SignInMethod>, FederationError>;
+}
+
+/// Manages the external identities linked to a user, never leaving the user
+/// without a way to sign in.
+pub struct AccountLinkingService<L, M> {
+    links: L,
+    local: M,
+}
+
+impl<L: FederatedIdentityRepository, M: LocalSignInMethods> AccountLinkingService<L, M> {
+    pub fn new(links: L, local: M) -> Self {
+        Self { links, local }
+    }
+
+    /// Every way the user can sign in, local methods first.
+    pub fn methods_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<SignInMethod>, FederationError> {
+        let mut methods = self.local.local_methods_of(tenant_id, username)?;
+        methods.extend(
+            self.links
+                .identities_of_user(tenant_id, username)?
+                .into_iter()
+                .map(SignInMethod::Federated),
+        );
+        Ok(methods)
+    }
+
+    /// Links an identity the user just signed in with to their account.
+    pub fn link(&self, username: &str, identity: &ExternalIdentity) -> Result<(), FederationError> {
+        let tenant_id = &identity.tenant_id;
+        match self
+            .links
+            .linked_username(tenant_id, &identity.provider_id, &identity.subject)?
+        {
+            Some(linked) if linked == username => Ok(()),
+            Some(_) => Err(FederationError::AlreadyLinked),
+            None => self.links.link(
+                tenant_id,
+                &identity.provider_id,
+                &identity.subject,
+                username,
+            ),
+        }
+    }
+
+    /// Unlinks an identity, refusing to remove the user's last sign-in
+    /// method.
+    pub fn unlink(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        identity: &LinkedIdentity,
+    ) -> Result<(), FederationError> {
+        let local_methods = self.local.local_methods_of(tenant_id, username)?.len();
+        self.links.unlink(
+            tenant_id,
+            &identity.provider_id,
+            &identity.subject,
+            username,
+            local_methods,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::federation::InMemoryFederatedIdentityRepository;
+
+    struct PasswordOnly;
+
+    struct FederatedOnly;
+
+    impl LocalSignInMethods for FederatedOnly {
+        fn local_methods_of(
+            &self,
+            _tenant_id: &str,
+            _username: &str,
+        ) -> Result<Vec<SignInMethod>, FederationError> {
+            Ok(Vec::new())
+        }
+    }
+
+    impl LocalSignInMethods for PasswordOnly {
+        fn local_methods_of(
+            &self,
+            _tenant_id: &str,
+            _username: &str,
+        ) -> Result<Vec<SignInMethod>, FederationError> {
+            Ok(vec![SignInMethod::Password])
+        }
+    }
+
+    fn identity() -> ExternalIdentity {
+        ExternalIdentity {
+            tenant_id: "acme".to_owned(),
+            provider_id: "google".to_owned(),
+            issuer: "https://accounts.google.com".to_owned(),
+            subject: "1234567890".to_owned(),
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn repository_refuses_to_overwrite_a_link() {
+        let links = InMemoryFederatedIdentityRepository::new();
+        links.link("acme", "google", "123", "ada").unwrap();
+        assert!(matches!(
+            links.link("acme", "google", "123", "mallory"),
+            Err(FederationError::AlreadyLinked)
+        ));
+        assert_eq!(
+            links.linked_username("acme", "google", "123").unwrap(),
+            Some("ada".to_owned())
+        );
+    }
+
+    #[test]
+    fn identity_links_to_one_user() {
+        let service =
+            AccountLinkingService::new(InMemoryFederatedIdentityRepository::new(), PasswordOnly);
+        service.link("ada", &identity()).unwrap();
+        service.link("ada", &identity()).unwrap();
+        assert!(matches!(
+            service.link("mallory", &identity()),
+            Err(FederationError::AlreadyLinked)
+        ));
+        assert_eq!(service.methods_of("acme", "ada").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn last_sign_in_method_stays() {
+        let service =
+            AccountLinkingService::new(InMemoryFederatedIdentityRepository::new(), PasswordOnly);
+        service.link("ada", &identity()).unwrap();
+        let linked = LinkedIdentity {
+            provider_id: "google".to_owned(),
+            subject: "1234567890".to_owned(),
+        };
+        assert!(matches!(
+            service.unlink("acme", "mallory", &linked),
+            Err(FederationError::NotLinked)
+        ));
+        service.unlink("acme", "ada", &linked).unwrap();
+        assert_eq!(service.methods_of("acme", "ada").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn concurrent_unlinks_keep_one_identity() {
+        let service =
+            AccountLinkingService::new(InMemoryFederatedIdentityRepository::new(), FederatedOnly);
+        let identities: Vec<LinkedIdentity> = ["google", "github", "gitlab"]
+            .into_iter()
+            .map(|provider_id| {
+                let identity = ExternalIdentity {
+                    provider_id: provider_id.to_owned(),
+                    ..identity()
+                };
+                service.link("ada", &identity).unwrap();
+                LinkedIdentity {
+                    provider_id: identity.provider_id,
+                    subject: identity.subject,
+                }
+            })
+            .collect();
+
+        let unlinked = std::thread::scope(|scope| {
+            let handles: Vec<_> = identities
+                .iter()
+                .map(|identity| {
+                    let service = &service;
+                    scope.spawn(move || service.unlink("acme", "ada", identity))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(Result::is_ok)
+                .count()
+        });
+        assert_eq!(unlinked, 2);
+        assert_eq!(service.methods_of("acme", "ada").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+mod linking;
+mod oidc;
+mod provisioning;
+
+pub use linking::{AccountLinkingService, LocalSignInMethods, SignInMethod};
+pub use oidc::{
+    FederatedLoginService, FederationRedirect, FederationStateRecord, FederationStateRepository,
+    HttpUpstreamClient, InMemoryFederationStateRepository, InMemoryLogoutReplayRepository,
+    InMemoryOidcProviderRepository, LogoutReplayRepository, OidcProvider, OidcProviderRepository,
+    UpstreamClient, UpstreamLogout,
+};
+pub use provisioning::{
+    federated_username, JitProvisioningPolicy, ProvisioningProfile, UserProvisioner,
+};
+
+/// Errors raised while signing in through an external identity provider.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FederationError {
+    #[error("unknown identity provider: {0}")]
+    UnknownProvider(String),
+    #[error("invalid or expired sign-in state")]
+    InvalidState,
+    #[error("identity provider unavailable: {0}")]
+    Upstream(String),
+    #[error("invalid token from identity provider: {0}")]
+    InvalidIdToken(String),
+    #[error("identity is already linked to another user")]
+    AlreadyLinked,
+    #[error("identity is not linked to the user")]
+    NotLinked,
+    #[error("cannot remove the last sign-in method of a user")]
+    LastSignInMethod,
+    #[error("automatic provisioning refused: {0}")]
+    ProvisioningDenied(String),
+    #[error("federation storage failure: {0}")]
+    Storage(String),
+}
+
+/// A user as asserted by an external identity provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalIdentity {
+    pub tenant_id: String,
+    pub provider_id: String,
+    pub issuer: String,
+    pub subject: String,
+    /// Local attributes mapped from the provider's claims.
+    pub attributes: BTreeMap<String, Value>,
+}
+
+impl ExternalIdentity {
+    pub fn attribute(&self, name: &str) -> Option<&Value> {
+        self.attributes.get(name)
+    }
+
+    /// The mapped `email` attribute, if the provider verified it.
+    pub fn verified_email(&self) -> Option<&str> {
+        let verified = self.attribute("email_verified") == Some(&Value::Bool(true));
+        self.attribute("email")
+            .and_then(Value::as_str)
+            .filter(|_| verified)
+    }
+}
+
+/// Outcome of a federated sign-in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FederatedSignIn {
+    pub identity: ExternalIdentity,
+    /// The local user the identity is linked to; `None` the first time.
+    pub username: Option<String>,
+}
+
+/// An external identity linked to a local user.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub provider_id: String,
+    pub subject: String,
+}
+
+/// Links external identities to local users.
+pub trait FederatedIdentityRepository: Send + Sync {
+    fn linked_username(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Option<String>, FederationError>;
+
+    fn identities_of_user(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<LinkedIdentity>, FederationError>;
+
+    /// Links the identity to the user, failing with
+    /// [`FederationError::AlreadyLinked`] if it is linked already; the check
+    /// and the insert must be atomic so that a link is never overwritten.
+    fn link(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<(), FederationError>;
+
+    /// Removes the identity's link to the user in a single step, failing
+    /// with [`FederationError::NotLinked`] if it is not linked to that user
+    /// and with [`FederationError::LastSignInMethod`] if the user holds no
+    /// `local_methods` and no other linked identity, so that concurrent
+    /// unlinks cannot remove every way to sign in.
+    fn unlink(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+        local_methods: usize,
+    ) -> Result<(), FederationError>;
+}
+
+impl<R: FederatedIdentityRepository + ?Sized> FederatedIdentityRepository for Arc<R> {
+    fn linked_username(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Option<String>, FederationError> {
+        (**self).linked_username(tenant_id, provider_id, subject)
+    }
+
+    fn identities_of_user(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<LinkedIdentity>, FederationError> {
+        (**self).identities_of_user(tenant_id, username)
+    }
+
+    fn link(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<(), FederationError> {
+        (**self).link(tenant_id, provider_id, subject, username)
+    }
+
+    fn unlink(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+        local_methods: usize,
+    ) -> Result<(), FederationError> {
+        (**self).unlink(tenant_id, provider_id, subject, username, local_methods)
+    }
+}
+
+/// Repository keeping identity links in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryFederatedIdentityRepository {
+    links: RwLock<HashMap<(String, String, String), String>>,
+}
+
+impl InMemoryFederatedIdentityRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn link_key(tenant_id: &str, provider_id: &str, subject: &str) -> (String, String, String) {
+    (
+        tenant_id.to_owned(),
+        provider_id.to_owned(),
+        subject.to_owned(),
+    )
+}
+
+impl FederatedIdentityRepository for InMemoryFederatedIdentityRepository {
+    fn linked_username(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Option<String>, FederationError> {
+        let links = self.links.read().expect("identity links lock poisoned");
+        Ok(links
+            .get(&link_key(tenant_id, provider_id, subject))
+            .cloned())
+    }
+
+    fn identities_of_user(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Vec<LinkedIdentity>, FederationError> {
+        let links = self.links.read().expect("identity links lock poisoned");
+        let mut identities: Vec<_> = links
+            .iter()
+            .filter(|((tenant, _, _), user)| tenant == tenant_id && *user == username)
+            .map(|((_, provider_id, subject), _)| LinkedIdentity {
+                provider_id: provider_id.clone(),
+                subject: subject.clone(),
+            })
+            .collect();
+        identities.sort_by(|a, b| (&a.provider_id, &a.subject).cmp(&(&b.provider_id, &b.subject)));
+        Ok(identities)
+    }
+
+    fn link(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<(), FederationError> {
+        let mut links = self.links.write().expect("identity links lock poisoned");
+        match links.entry(link_key(tenant_id, provider_id, subject)) {
+            Entry::Occupied(_) => Err(FederationError::AlreadyLinked),
+            Entry::Vacant(entry) => {
+                entry.insert(username.to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    fn unlink(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        subject: &str,
+        username: &str,
+        local_methods: usize,
+    ) -> Result<(), FederationError> {
+        let mut links = self.links.write().expect("identity links lock poisoned");
+        let key = link_key(tenant_id, provider_id, subject);
+        if links.get(&key).is_none_or(|linked| linked != username) {
+            return Err(FederationError::NotLinked);
+        }
+        let linked_identities = links
+            .iter()
+            .filter(|((tenant, _, _), user)| tenant == tenant_id && *user == username)
+            .count();
+        if local_methods + linked_identities <= 1 {
+            return Err(FederationError::LastSignInMethod);
+        }
+        links.remove(&key);
+        Ok(())
+    }
+}
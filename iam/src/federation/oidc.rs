@@ -0,0 +1,1349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::RwLock;
+use std::time::Duration as Timeout;
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::provisioning::{JitProvisioningPolicy, UserProvisioner};
+use super::{ExternalIdentity, FederatedIdentityRepository, FederatedSignIn, FederationError};
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+use crate::oauth::{code_challenge, BACKCHANNEL_LOGOUT_EVENT, PKCE_METHOD};
+
+/// An upstream OpenID Connect provider a tenant lets its users sign in with.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcProvider {
+    id: String,
+    tenant_id: String,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    scopes: Vec<String>,
+    /// Local attribute name to the claim it is read from.
+    claim_mapping: BTreeMap<String, String>,
+    /// Whether unknown users get an account on first sign-in.
+    #[serde(default)]
+    jit_provisioning: Option<JitProvisioningPolicy>,
+}
+
+impl OidcProvider {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: impl Into<String>,
+        tenant_id: impl Into<String>,
+        issuer: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        authorization_endpoint: impl Into<String>,
+        token_endpoint: impl Into<String>,
+        jwks_uri: impl Into<String>,
+    ) -> Self {
+        let claim_mapping = [
+            "email",
+            "email_verified",
+            "name",
+            "given_name",
+            "family_name",
+        ]
+        .into_iter()
+        .map(|claim| (claim.to_owned(), claim.to_owned()))
+        .collect();
+        Self {
+            id: id.into(),
+            tenant_id: tenant_id.into(),
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            authorization_endpoint: authorization_endpoint.into(),
+            token_endpoint: token_endpoint.into(),
+            jwks_uri: jwks_uri.into(),
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            claim_mapping,
+            jit_provisioning: None,
+        }
+    }
+
+    pub fn google(
+        id: impl Into<String>,
+        tenant_id: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self::new(
+            id,
+            tenant_id,
+            "https://accounts.google.com",
+            client_id,
+            client_secret,
+            "https://accounts.google.com/o/oauth2/v2/auth",
+            "https://oauth2.googleapis.com/token",
+            "https://www.googleapis.com/oauth2/v3/certs",
+        )
+    }
+
+    /// Azure AD (Entra ID) v2 endpoints of a single directory.
+    ///
+    /// `preferred_username` is chosen by users and admins and never
+    /// verified, so it is not mapped to `email`. The `email` claim only counts
+    /// as verified when the app is configured to send `email_verified`; the
+    /// immutable object id is mapped to `object_id` for matching users
+    /// across apps, since `sub` is pairwise per app.
+    pub fn azure_ad(
+        id: impl Into<String>,
+        tenant_id: impl Into<String>,
+        directory_id: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        let base = format!("https://login.microsoftonline.com/{}", directory_id);
+        Self::new(
+            id,
+            tenant_id,
+            format!("{}/v2.0", base),
+            client_id,
+            client_secret,
+            format!("{}/oauth2/v2.0/authorize", base),
+            format!("{}/oauth2/v2.0/token", base),
+            format!("{}/discovery/v2.0/keys", base),
+        )
+        .map_claim("object_id", "oid")
+    }
+
+    /// Replaces the requested scopes; `openid` is always included.
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        if !self.scopes.iter().any(|s| s == "openid") {
+            self.scopes.insert(0, "openid".to_owned());
+        }
+        self
+    }
+
+    /// Reads the local `attribute` from the given claim of the ID token.
+    pub fn map_claim(mut self, attribute: impl Into<String>, claim: impl Into<String>) -> Self {
+        self.claim_mapping.insert(attribute.into(), claim.into());
+        self
+    }
+
+    /// Creates accounts for first-time users the policy accepts.
+    pub fn with_jit_provisioning(mut self, policy: JitProvisioningPolicy) -> Self {
+        self.jit_provisioning = Some(policy);
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn client_secret(&self) -> &str {
+        &self.client_secret
+    }
+
+    pub fn token_endpoint(&self) -> &str {
+        &self.token_endpoint
+    }
+
+    pub fn jwks_uri(&self) -> &str {
+        &self.jwks_uri
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn jit_provisioning(&self) -> Option<&JitProvisioningPolicy> {
+        self.jit_provisioning.as_ref()
+    }
+
+    fn map_claims(&self, claims: &Map<String, Value>) -> BTreeMap<String, Value> {
+        self.claim_mapping
+            .iter()
+            .filter_map(|(attribute, claim)| {
+                claims
+                    .get(claim)
+                    .map(|value| (attribute.clone(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for OidcProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcProvider")
+            .field("id", &self.id)
+            .field("tenant_id", &self.tenant_id)
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stores the upstream providers of each tenant.
+pub trait OidcProviderRepository: Send + Sync {
+    fn provider_of(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+    ) -> Result<Option<OidcProvider>, FederationError>;
+
+    fn providers_of_tenant(&self, tenant_id: &str) -> Result<Vec<OidcProvider>, FederationError>;
+
+    /// Inserts or replaces the tenant's provider with the same id.
+    fn save(&self, provider: &OidcProvider) -> Result<(), FederationError>;
+
+    fn remove(&self, tenant_id: &str, provider_id: &str) -> Result<(), FederationError>;
+}
+
+/// Repository keeping upstream providers in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryOidcProviderRepository {
+    providers: RwLock<HashMap<(String, String), OidcProvider>>,
+}
+
+impl InMemoryOidcProviderRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OidcProviderRepository for InMemoryOidcProviderRepository {
+    fn provider_of(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+    ) -> Result<Option<OidcProvider>, FederationError> {
+        let providers = self.providers.read().expect("OIDC providers lock poisoned");
+        Ok(providers
+            .get(&(tenant_id.to_owned(), provider_id.to_owned()))
+            .cloned())
+    }
+
+    fn providers_of_tenant(&self, tenant_id: &str) -> Result<Vec<OidcProvider>, FederationError> {
+        let providers = self.providers.read().expect("OIDC providers lock poisoned");
+        let mut found: Vec<_> = providers
+            .values()
+            .filter(|p| p.tenant_id == tenant_id)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(found)
+    }
+
+    fn save(&self, provider: &OidcProvider) -> Result<(), FederationError> {
+        let mut providers = self
+            .providers
+            .write()
+            .expect("OIDC providers lock poisoned");
+        providers.insert(
+            (provider.tenant_id.clone(), provider.id.clone()),
+            provider.clone(),
+        );
+        Ok(())
+    }
+
+    fn remove(&self, tenant_id: &str, provider_id: &str) -> Result<(), FederationError> {
+        let mut providers = self
+            .providers
+            .write()
+            .expect("OIDC providers lock poisoned");
+        providers.remove(&(tenant_id.to_owned(), provider_id.to_owned()));
+        Ok(())
+    }
+}
+
+/// A sign-in in flight at the upstream provider; only the digest of the
+/// `state` parameter is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederationStateRecord {
+    state_hash: String,
+    tenant_id: String,
+    provider_id: String,
+    redirect_uri: String,
+    nonce: String,
+    code_verifier: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl FederationStateRecord {
+    pub fn state_hash(&self) -> &str {
+        &self.state_hash
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+/// Stores sign-ins in flight.
+pub trait FederationStateRepository: Send + Sync {
+    fn save(&self, record: &FederationStateRecord) -> Result<(), FederationError>;
+
+    /// Removes and returns the record, so each state is used once.
+    fn take(&self, state_hash: &str) -> Result<Option<FederationStateRecord>, FederationError>;
+
+    /// Deletes sign-ins abandoned past their expiry, returning how many were
+    /// removed.
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, FederationError>;
+}
+
+/// Repository keeping sign-ins in flight in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryFederationStateRepository {
+    states: RwLock<HashMap<String, FederationStateRecord>>,
+}
+
+impl InMemoryFederationStateRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FederationStateRepository for InMemoryFederationStateRepository {
+    fn save(&self, record: &FederationStateRecord) -> Result<(), FederationError> {
+        let mut states = self
+            .states
+            .write()
+            .expect("federation states lock poisoned");
+        states.insert(record.state_hash.clone(), record.clone());
+        Ok(())
+    }
+
+    fn take(&self, state_hash: &str) -> Result<Option<FederationStateRecord>, FederationError> {
+        let mut states = self
+            .states
+            .write()
+            .expect("federation states lock poisoned");
+        Ok(states.remove(state_hash))
+    }
+
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, FederationError> {
+        let mut states = self
+            .states
+            .write()
+            .expect("federation states lock poisoned");
+        let count = states.len();
+        states.retain(|_, record| now < record.expires_at);
+        Ok((count - states.len()) as u64)
+    }
+}
+
+/// Remembers the logout tokens already accepted, so that a captured token
+/// cannot be replayed.
+pub trait LogoutReplayRepository: Send + Sync {
+    /// Records the token id of the issuer until `expires_at`, returning
+    /// `false` if it was recorded already. The check and the insert must be
+    /// atomic so that concurrent deliveries of a token are not both accepted.
+    fn remember(
+        &self,
+        issuer: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, FederationError>;
+}
+
+/// Repository keeping recently accepted logout tokens in process memory.
+///
+/// Expired ids are evicted as new ones arrive. Once `capacity` live ids are
+/// held, further tokens are refused rather than evicting ids that could
+/// then be replayed.
+#[derive(Debug)]
+pub struct InMemoryLogoutReplayRepository {
+    seen: RwLock<HashMap<(String, String), DateTime<Utc>>>,
+    capacity: usize,
+}
+
+impl InMemoryLogoutReplayRepository {
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: RwLock::new(HashMap::new()),
+            capacity,
+        }
+    }
+}
+
+impl Default for InMemoryLogoutReplayRepository {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl LogoutReplayRepository for InMemoryLogoutReplayRepository {
+    fn remember(
+        &self,
+        issuer: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<bool, FederationError> {
+        let mut seen = self.seen.write().expect("logout replays lock poisoned");
+        let key = (issuer.to_owned(), jti.to_owned());
+        if seen.get(&key).is_some_and(|expiry| now < *expiry) {
+            return Ok(false);
+        }
+        if seen.len() >= self.capacity {
+            seen.retain(|_, expiry| now < *expiry);
+        }
+        if seen.len() >= self.capacity {
+            return Err(FederationError::Storage(
+                "too many logout tokens in flight".to_owned(),
+            ));
+        }
+        seen.insert(key, expires_at);
+        Ok(true)
+    }
+}
+
+/// Talks to the token and JWKS endpoints of upstream providers.
+pub trait UpstreamClient: Send + Sync {
+    /// Redeems an authorization code, returning the ID token.
+    fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<String, FederationError>;
+
+    fn jwks(&self, provider: &OidcProvider) -> Result<JwkSet, FederationError>;
+}
+
+#[derive(Debug, Deserialize)]
+struct UpstreamTokenResponse {
+    id_token: Option<String>,
+}
+
+/// Upstream client over HTTPS.
+#[derive(Debug, Clone)]
+pub struct HttpUpstreamClient {
+    agent: ureq::Agent,
+}
+
+impl HttpUpstreamClient {
+    pub fn new(timeout: Timeout) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+fn unavailable(error: impl ToString) -> FederationError {
+    FederationError::Upstream(error.to_string())
+}
+
+impl UpstreamClient for HttpUpstreamClient {
+    fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<String, FederationError> {
+        let response = self
+            .agent
+            .post(&provider.token_endpoint)
+            .send_form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+            ])
+            .map_err(unavailable)?
+            .into_string()
+            .map_err(unavailable)?;
+        let response: UpstreamTokenResponse =
+            serde_json::from_str(&response).map_err(unavailable)?;
+        response
+            .id_token
+            .ok_or_else(|| FederationError::InvalidIdToken("missing from token response".into()))
+    }
+
+    fn jwks(&self, provider: &OidcProvider) -> Result<JwkSet, FederationError> {
+        let body = self
+            .agent
+            .get(&provider.jwks_uri)
+            .call()
+            .map_err(unavailable)?
+            .into_string()
+            .map_err(unavailable)?;
+        serde_json::from_str(&body).map_err(unavailable)
+    }
+}
+
+/// Where to send the browser to sign in upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FederationRedirect {
+    pub url: String,
+    /// Value of the `state` parameter, also worth binding to the browser
+    /// session.
+    pub state: String,
+}
+
+/// A logout the upstream provider signalled through its back channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamLogout {
+    pub subject: Option<String>,
+    /// The provider's session id, if the logout is limited to one session.
+    pub session_id: Option<String>,
+    /// The linked local user, if the subject is known.
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Signs users in through the OpenID Connect providers of their tenant.
+///
+/// Uses the authorization code flow with PKCE and a nonce; ID tokens are
+/// verified against the provider's JWKS before their claims are mapped.
+/// Key sets are cached, and fetched again early only when a token names a
+/// key the cached set lacks, so that upstream key rotation is picked up.
+pub struct FederatedLoginService<P, S, L, U, R, C> {
+    providers: P,
+    states: S,
+    links: L,
+    upstream: U,
+    replays: R,
+    state_ttl: Duration,
+    leeway: Duration,
+    logout_token_max_age: Duration,
+    jwks_ttl: Duration,
+    jwks: RwLock<HashMap<String, CachedJwks>>,
+    clock: C,
+}
+
+impl<P, S, L, U, R, C> FederatedLoginService<P, S, L, U, R, C>
+where
+    P: OidcProviderRepository,
+    S: FederationStateRepository,
+    L: FederatedIdentityRepository,
+    U: UpstreamClient,
+    R: LogoutReplayRepository,
+    C: Clock,
+{
+    pub const DEFAULT_STATE_TTL_MINUTES: i64 = 10;
+    pub const DEFAULT_LOGOUT_TOKEN_MAX_AGE_MINUTES: i64 = 5;
+    pub const DEFAULT_JWKS_TTL_MINUTES: i64 = 60;
+    /// Least time between two fetches of a key set, so tokens naming
+    /// unknown keys cannot make the service hammer the provider.
+    pub const JWKS_REFRESH_COOLDOWN_SECONDS: i64 = 60;
+
+    pub fn new(providers: P, states: S, links: L, upstream: U, replays: R, clock: C) -> Self {
+        Self {
+            providers,
+            states,
+            links,
+            upstream,
+            replays,
+            state_ttl: Duration::minutes(Self::DEFAULT_STATE_TTL_MINUTES),
+            leeway: Duration::seconds(60),
+            logout_token_max_age: Duration::minutes(Self::DEFAULT_LOGOUT_TOKEN_MAX_AGE_MINUTES),
+            jwks_ttl: Duration::minutes(Self::DEFAULT_JWKS_TTL_MINUTES),
+            jwks: RwLock::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// How long the user has to complete the sign-in upstream.
+    pub fn with_state_ttl(mut self, ttl: Duration) -> Self {
+        self.state_ttl = ttl;
+        self
+    }
+
+    /// Tolerated clock skew with the provider.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Oldest logout token accepted, measured from its `iat`.
+    pub fn with_logout_token_max_age(mut self, max_age: Duration) -> Self {
+        self.logout_token_max_age = max_age;
+        self
+    }
+
+    /// How long a provider's key set is used before it is fetched again.
+    pub fn with_jwks_ttl(mut self, ttl: Duration) -> Self {
+        self.jwks_ttl = ttl;
+        self
+    }
+
+    pub fn providers(&self) -> &P {
+        &self.providers
+    }
+
+    /// Starts a sign-in, returning the provider's authorization URL.
+    pub fn begin(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        redirect_uri: &str,
+    ) -> Result<FederationRedirect, FederationError> {
+        let provider = self.provider(tenant_id, provider_id)?;
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let encode = |value: &str| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+        let separator = if provider.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}\
+             &code_challenge={}&code_challenge_method={}",
+            provider.authorization_endpoint,
+            separator,
+            encode(&provider.client_id),
+            encode(redirect_uri),
+            encode(&provider.scopes.join(" ")),
+            state,
+            nonce,
+            code_challenge(&code_verifier),
+            PKCE_METHOD,
+        );
+        self.states.save(&FederationStateRecord {
+            state_hash: digest(&state),
+            tenant_id: tenant_id.to_owned(),
+            provider_id: provider_id.to_owned(),
+            redirect_uri: redirect_uri.to_owned(),
+            nonce,
+            code_verifier,
+            expires_at: self.clock.now() + self.state_ttl,
+        })?;
+        Ok(FederationRedirect { url, state })
+    }
+
+    /// Handles the callback, verifying the ID token the code redeems for.
+    pub fn complete(&self, state: &str, code: &str) -> Result<FederatedSignIn, FederationError> {
+        let record = self
+            .states
+            .take(&digest(state))?
+            .filter(|r| self.clock.now() < r.expires_at)
+            .ok_or(FederationError::InvalidState)?;
+        let provider = self.provider(&record.tenant_id, &record.provider_id)?;
+        let id_token = self.upstream.exchange_code(
+            &provider,
+            code,
+            &record.redirect_uri,
+            &record.code_verifier,
+        )?;
+        let claims = self.verify(&provider, &id_token, &record.nonce)?;
+        let subject = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("missing sub claim"))?
+            .to_owned();
+        let username =
+            self.links
+                .linked_username(&record.tenant_id, &record.provider_id, &subject)?;
+        Ok(FederatedSignIn {
+            identity: ExternalIdentity {
+                tenant_id: record.tenant_id,
+                provider_id: record.provider_id,
+                issuer: provider.issuer.clone(),
+                subject,
+                attributes: provider.map_claims(&claims),
+            },
+            username,
+        })
+    }
+
+    /// The local user of a sign-in, provisioning and linking one just in
+    /// time if the provider allows it.
+    ///
+    /// If the identity cannot be linked to the new user, the user is
+    /// deprovisioned again; when a concurrent sign-in linked the identity to
+    /// the very same user first, that user is returned. Linking identities
+    /// to existing users goes through
+    /// [`AccountLinkingService`](super::AccountLinkingService).
+    pub fn resolve_user(
+        &self,
+        sign_in: &FederatedSignIn,
+        provisioner: &impl UserProvisioner,
+    ) -> Result<String, FederationError> {
+        if let Some(username) = &sign_in.username {
+            return Ok(username.clone());
+        }
+        let identity = &sign_in.identity;
+        let provider = self.provider(&identity.tenant_id, &identity.provider_id)?;
+        let policy = provider.jit_provisioning.as_ref().ok_or_else(|| {
+            FederationError::ProvisioningDenied("identity is not linked to a user".to_owned())
+        })?;
+        let username = provisioner.provision(&policy.evaluate(identity)?)?;
+        let linked = self.links.link(
+            &identity.tenant_id,
+            &identity.provider_id,
+            &identity.subject,
+            &username,
+        );
+        if let Err(error) = linked {
+            let raced = matches!(error, FederationError::AlreadyLinked)
+                && self
+                    .links
+                    .linked_username(
+                        &identity.tenant_id,
+                        &identity.provider_id,
+                        &identity.subject,
+                    )?
+                    .is_some_and(|linked| linked == username);
+            if !raced {
+                provisioner.deprovision(&identity.tenant_id, &username)?;
+                return Err(error);
+            }
+        }
+        Ok(username)
+    }
+
+    /// Verifies a back-channel logout token posted by the provider,
+    /// returning the local user whose sessions must end.
+    ///
+    /// The token must carry `iat` and `jti`; tokens older than the maximum
+    /// age and tokens already accepted are refused.
+    pub fn accept_logout(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+        logout_token: &str,
+    ) -> Result<UpstreamLogout, FederationError> {
+        let provider = self.provider(tenant_id, provider_id)?;
+        let claims = self.decode(&provider, logout_token, &["iss", "aud"])?;
+        let is_logout = claims
+            .get("events")
+            .and_then(Value::as_object)
+            .is_some_and(|events| events.contains_key(BACKCHANNEL_LOGOUT_EVENT));
+        if !is_logout || claims.contains_key("nonce") {
+            return Err(invalid("not a logout token"));
+        }
+        let text = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_owned);
+        let (subject, session_id) = (text("sub"), text("sid"));
+        if subject.is_none() && session_id.is_none() {
+            return Err(invalid("logout token names neither sub nor sid"));
+        }
+        let jti = text("jti").ok_or_else(|| invalid("missing jti claim"))?;
+        let issued_at = claims
+            .get("iat")
+            .and_then(Value::as_i64)
+            .and_then(|iat| DateTime::from_timestamp(iat, 0))
+            .ok_or_else(|| invalid("missing iat claim"))?;
+        let now = self.clock.now();
+        if issued_at > now + self.leeway {
+            return Err(invalid("logout token issued in the future"));
+        }
+        let stale_at = issued_at + self.logout_token_max_age + self.leeway;
+        if now > stale_at {
+            return Err(invalid("logout token too old"));
+        }
+        if !self
+            .replays
+            .remember(&provider.issuer, &jti, stale_at, now)?
+        {
+            return Err(invalid("logout token already used"));
+        }
+        let username = match &subject {
+            Some(subject) => self
+                .links
+                .linked_username(tenant_id, provider_id, subject)?,
+            None => None,
+        };
+        Ok(UpstreamLogout {
+            subject,
+            session_id,
+            username,
+        })
+    }
+
+    /// Deletes sign-ins that were never completed; meant to be called on a
+    /// schedule.
+    pub fn purge_expired_states(&self) -> Result<u64, FederationError> {
+        self.states.purge_expired(self.clock.now())
+    }
+
+    fn provider(
+        &self,
+        tenant_id: &str,
+        provider_id: &str,
+    ) -> Result<OidcProvider, FederationError> {
+        self.providers
+            .provider_of(tenant_id, provider_id)?
+            .ok_or_else(|| FederationError::UnknownProvider(provider_id.to_owned()))
+    }
+
+    fn verify(
+        &self,
+        provider: &OidcProvider,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>, FederationError> {
+        let claims = self.decode(provider, id_token, &["exp", "iss", "aud", "sub"])?;
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(invalid("nonce does not match"));
+        }
+        Ok(claims)
+    }
+
+    /// Verifies signature, issuer, audience and, when present, expiry.
+    fn decode(
+        &self,
+        provider: &OidcProvider,
+        token: &str,
+        required_claims: &[&str],
+    ) -> Result<Map<String, Value>, FederationError> {
+        let header = decode_header(token).map_err(invalid)?;
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+        ) {
+            return Err(invalid(format!("algorithm {:?} not accepted", header.alg)));
+        }
+        let jwk = self.signing_key(provider, header.kid.as_deref())?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(invalid)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_required_spec_claims(required_claims);
+        // Time checks use the injected clock instead of the system time.
+        validation.validate_exp = false;
+        let claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(invalid)?
+            .claims;
+        if let Some(exp) = claims.get("exp").and_then(Value::as_i64) {
+            if self.clock.now().timestamp() > exp + self.leeway.num_seconds() {
+                return Err(invalid("expired"));
+            }
+        }
+        Ok(claims)
+    }
+
+    /// The provider's key the token was signed with, from the cached key
+    /// set when it is fresh and has the key.
+    fn signing_key(
+        &self,
+        provider: &OidcProvider,
+        kid: Option<&str>,
+    ) -> Result<Jwk, FederationError> {
+        let now = self.clock.now();
+        let cached = self
+            .jwks
+            .read()
+            .expect("JWKS cache lock poisoned")
+            .get(&provider.jwks_uri)
+            .cloned();
+        if let Some(cached) = cached.filter(|c| now < c.fetched_at + self.jwks_ttl) {
+            if let Some(jwk) = find_key(&cached.keys, kid) {
+                return Ok(jwk.clone());
+            }
+            let cooldown = Duration::seconds(Self::JWKS_REFRESH_COOLDOWN_SECONDS);
+            if now < cached.fetched_at + cooldown {
+                return Err(invalid("signing key not found"));
+            }
+        }
+        let keys = self.upstream.jwks(provider)?;
+        let jwk = find_key(&keys, kid).cloned();
+        self.jwks.write().expect("JWKS cache lock poisoned").insert(
+            provider.jwks_uri.clone(),
+            CachedJwks {
+                keys,
+                fetched_at: now,
+            },
+        );
+        jwk.ok_or_else(|| invalid("signing key not found"))
+    }
+}
+
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+fn invalid(error: impl ToString) -> FederationError {
+    FederationError::InvalidIdToken(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::TimeZone;
+    use data_encoding::BASE64URL_NOPAD;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::federation::{
+        ExternalIdentity, FederatedSignIn, InMemoryFederatedIdentityRepository, ProvisioningProfile,
+    };
+
+    const ISSUER: &str = "https://idp.example.com";
+
+    /// Provider stand-in signing its tokens with a P-256 key.
+    struct Upstream {
+        key: EncodingKey,
+        jwks: JwkSet,
+        id_token: Mutex<Option<String>>,
+        jwks_fetches: AtomicUsize,
+    }
+
+    impl Upstream {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let alg = &ECDSA_P256_SHA256_FIXED_SIGNING;
+            let document = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+            let pair = EcdsaKeyPair::from_pkcs8(alg, document.as_ref(), &rng).unwrap();
+            let point = pair.public_key().as_ref();
+            let jwks = serde_json::from_value(json!({ "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "upstream-1",
+                "alg": "ES256",
+                "use": "sig",
+                "x": BASE64URL_NOPAD.encode(&point[1..33]),
+                "y": BASE64URL_NOPAD.encode(&point[33..]),
+            }]}))
+            .unwrap();
+            Self {
+                key: EncodingKey::from_ec_der(document.as_ref()),
+                jwks,
+                id_token: Mutex::new(None),
+                jwks_fetches: AtomicUsize::new(0),
+            }
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            self.sign_with_kid("upstream-1", claims)
+        }
+
+        fn sign_with_kid(&self, kid: &str, claims: &Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some(kid.to_owned());
+            encode(&header, claims, &self.key).unwrap()
+        }
+
+        /// Makes the next code exchange return the ID token.
+        fn respond_with(&self, id_token: String) {
+            *self.id_token.lock().unwrap() = Some(id_token);
+        }
+
+        fn jwks_fetches(&self) -> usize {
+            self.jwks_fetches.load(Ordering::SeqCst)
+        }
+    }
+
+    impl UpstreamClient for &Upstream {
+        fn exchange_code(
+            &self,
+            _provider: &OidcProvider,
+            _code: &str,
+            _redirect_uri: &str,
+            _code_verifier: &str,
+        ) -> Result<String, FederationError> {
+            self.id_token
+                .lock()
+                .unwrap()
+                .take()
+                .ok_or_else(|| FederationError::Upstream("no ID token".to_owned()))
+        }
+
+        fn jwks(&self, _provider: &OidcProvider) -> Result<JwkSet, FederationError> {
+            self.jwks_fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(self.jwks.clone())
+        }
+    }
+
+    type Service<'a> = FederatedLoginService<
+        InMemoryOidcProviderRepository,
+        InMemoryFederationStateRepository,
+        InMemoryFederatedIdentityRepository,
+        &'a Upstream,
+        InMemoryLogoutReplayRepository,
+        &'a FixedClock,
+    >;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn service<'a>(upstream: &'a Upstream, clock: &'a FixedClock) -> Service<'a> {
+        let providers = InMemoryOidcProviderRepository::new();
+        providers
+            .save(&OidcProvider::new(
+                "idp",
+                "acme",
+                ISSUER,
+                "iam",
+                "secret",
+                "https://idp.example.com/authorize",
+                "https://idp.example.com/token",
+                "https://idp.example.com/jwks",
+            ))
+            .unwrap();
+        let links = InMemoryFederatedIdentityRepository::new();
+        links.link("acme", "idp", "upstream-ada", "ada").unwrap();
+        FederatedLoginService::new(
+            providers,
+            InMemoryFederationStateRepository::new(),
+            links,
+            upstream,
+            InMemoryLogoutReplayRepository::default(),
+            clock,
+        )
+    }
+
+    fn nonce_of(redirect: &FederationRedirect) -> &str {
+        redirect
+            .url
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("nonce="))
+            .unwrap()
+    }
+
+    fn id_token_claims(clock: &FixedClock, nonce: &str) -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": "iam",
+            "sub": "upstream-ada",
+            "exp": (clock.now() + Duration::minutes(5)).timestamp(),
+            "nonce": nonce,
+        })
+    }
+
+    fn logout_claims(clock: &FixedClock, jti: &str) -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": "iam",
+            "sub": "upstream-ada",
+            "iat": clock.now().timestamp(),
+            "jti": jti,
+            "events": { BACKCHANNEL_LOGOUT_EVENT: {} },
+        })
+    }
+
+    fn rejected<T: fmt::Debug>(result: Result<T, FederationError>) -> bool {
+        matches!(result, Err(FederationError::InvalidIdToken(_)))
+    }
+
+    #[test]
+    fn linked_identity_signs_in() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        upstream.respond_with(upstream.sign(&id_token_claims(&clock, nonce_of(&redirect))));
+
+        let sign_in = service.complete(&redirect.state, "code").unwrap();
+        assert_eq!(sign_in.username.as_deref(), Some("ada"));
+        assert_eq!(sign_in.identity.subject, "upstream-ada");
+    }
+
+    #[test]
+    fn id_token_with_another_nonce_is_rejected() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        upstream.respond_with(upstream.sign(&id_token_claims(&clock, "replayed")));
+        assert!(rejected(service.complete(&redirect.state, "code")));
+    }
+
+    #[test]
+    fn id_token_of_another_issuer_is_rejected() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        let mut claims = id_token_claims(&clock, nonce_of(&redirect));
+        claims["iss"] = json!("https://evil.example.com");
+        upstream.respond_with(upstream.sign(&claims));
+        assert!(rejected(service.complete(&redirect.state, "code")));
+    }
+
+    #[test]
+    fn symmetric_algorithms_are_rejected() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        let claims = id_token_claims(&clock, nonce_of(&redirect));
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        upstream.respond_with(forged);
+        assert!(rejected(service.complete(&redirect.state, "code")));
+    }
+
+    #[test]
+    fn state_is_single_use_and_expires() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        let id_token = upstream.sign(&id_token_claims(&clock, nonce_of(&redirect)));
+        upstream.respond_with(id_token.clone());
+        service.complete(&redirect.state, "code").unwrap();
+        upstream.respond_with(id_token);
+        assert!(matches!(
+            service.complete(&redirect.state, "code"),
+            Err(FederationError::InvalidState)
+        ));
+
+        let redirect = service
+            .begin("acme", "idp", "https://iam.example.com/cb")
+            .unwrap();
+        upstream.respond_with(upstream.sign(&id_token_claims(&clock, nonce_of(&redirect))));
+        clock.advance(Duration::minutes(11));
+        assert!(matches!(
+            service.complete(&redirect.state, "code"),
+            Err(FederationError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn logout_token_is_accepted_once() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        let token = upstream.sign(&logout_claims(&clock, "logout-1"));
+
+        let logout = service.accept_logout("acme", "idp", &token).unwrap();
+        assert_eq!(logout.username.as_deref(), Some("ada"));
+        assert!(rejected(service.accept_logout("acme", "idp", &token)));
+    }
+
+    #[test]
+    fn malformed_or_stale_logout_tokens_are_rejected() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        type Edit = fn(&mut Value);
+        let edits: [(&str, Edit); 5] = [
+            ("no iat", |claims| {
+                claims.as_object_mut().unwrap().remove("iat");
+            }),
+            ("no jti", |claims| {
+                claims.as_object_mut().unwrap().remove("jti");
+            }),
+            ("no event", |claims| claims["events"] = json!({})),
+            ("nonce", |claims| claims["nonce"] = json!("n")),
+            ("stale", |claims| {
+                claims["iat"] = json!(claims["iat"].as_i64().unwrap() - 600);
+            }),
+        ];
+        for (i, (case, edit)) in edits.into_iter().enumerate() {
+            let mut claims = logout_claims(&clock, &format!("logout-{}", i));
+            edit(&mut claims);
+            let token = upstream.sign(&claims);
+            assert!(
+                rejected(service.accept_logout("acme", "idp", &token)),
+                "{}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn key_set_is_cached_until_an_unknown_key_appears() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        for jti in ["a", "b"] {
+            let token = upstream.sign(&logout_claims(&clock, jti));
+            service.accept_logout("acme", "idp", &token).unwrap();
+        }
+        assert_eq!(upstream.jwks_fetches(), 1);
+
+        let rotated = upstream.sign_with_kid("upstream-2", &logout_claims(&clock, "c"));
+        assert!(rejected(service.accept_logout("acme", "idp", &rotated)));
+        assert_eq!(upstream.jwks_fetches(), 1, "within the cooldown");
+
+        clock.advance(Duration::minutes(2));
+        let rotated = upstream.sign_with_kid("upstream-2", &logout_claims(&clock, "d"));
+        assert!(rejected(service.accept_logout("acme", "idp", &rotated)));
+        assert_eq!(upstream.jwks_fetches(), 2);
+    }
+
+    #[test]
+    fn replay_store_refuses_when_full() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let replays = InMemoryLogoutReplayRepository::new(1);
+        let expiry = now + Duration::minutes(5);
+        assert!(replays.remember(ISSUER, "a", expiry, now).unwrap());
+        assert!(!replays.remember(ISSUER, "a", expiry, now).unwrap());
+        assert!(replays.remember(ISSUER, "b", expiry, now).is_err());
+        assert!(replays.remember(ISSUER, "b", expiry, expiry).unwrap());
+    }
+
+    #[test]
+    fn azure_ad_does_not_trust_preferred_username_as_email() {
+        let provider = OidcProvider::azure_ad("entra", "acme", "directory", "app", "secret");
+        let claims = json!({
+            "sub": "pairwise-subject",
+            "oid": "00000000-0000-0000-0000-000000000001",
+            "preferred_username": "ceo@victim.example",
+        });
+        let attributes = provider.map_claims(claims.as_object().unwrap());
+        assert_eq!(attributes.get("email"), None);
+        assert_eq!(
+            attributes.get("object_id"),
+            Some(&json!("00000000-0000-0000-0000-000000000001"))
+        );
+
+        let claims = json!({ "sub": "s", "email": "ada@example.com" });
+        let identity = ExternalIdentity {
+            tenant_id: "acme".to_owned(),
+            provider_id: "entra".to_owned(),
+            issuer: provider.issuer().to_owned(),
+            subject: "s".to_owned(),
+            attributes: provider.map_claims(claims.as_object().unwrap()),
+        };
+        assert_eq!(identity.verified_email(), None);
+    }
+
+    #[test]
+    fn purge_drops_abandoned_sign_ins() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let states = InMemoryFederationStateRepository::new();
+        for (state, expires_at) in [("old", now), ("fresh", now + Duration::minutes(10))] {
+            states
+                .save(&FederationStateRecord {
+                    state_hash: digest(state),
+                    tenant_id: "acme".to_owned(),
+                    provider_id: "google".to_owned(),
+                    redirect_uri: "https://iam.example.com/callback".to_owned(),
+                    nonce: "nonce".to_owned(),
+                    code_verifier: "verifier".to_owned(),
+                    expires_at,
+                })
+                .unwrap();
+        }
+        assert_eq!(states.purge_expired(now).unwrap(), 1);
+        assert!(states.take(&digest("old")).unwrap().is_none());
+        assert!(states.take(&digest("fresh")).unwrap().is_some());
+    }
+
+    #[derive(Default)]
+    struct Directory {
+        users: Mutex<Vec<String>>,
+    }
+
+    impl UserProvisioner for Directory {
+        fn provision(&self, profile: &ProvisioningProfile) -> Result<String, FederationError> {
+            self.users.lock().unwrap().push(profile.username.clone());
+            Ok(profile.username.clone())
+        }
+
+        fn deprovision(&self, _tenant_id: &str, username: &str) -> Result<(), FederationError> {
+            self.users.lock().unwrap().retain(|user| user != username);
+            Ok(())
+        }
+    }
+
+    fn first_sign_in(subject: &str) -> FederatedSignIn {
+        let attributes = [
+            ("email".to_owned(), json!("grace@example.com")),
+            ("email_verified".to_owned(), json!(true)),
+        ];
+        FederatedSignIn {
+            identity: ExternalIdentity {
+                tenant_id: "acme".to_owned(),
+                provider_id: "idp".to_owned(),
+                issuer: ISSUER.to_owned(),
+                subject: subject.to_owned(),
+                attributes: attributes.into_iter().collect(),
+            },
+            username: None,
+        }
+    }
+
+    fn with_jit_provisioning(service: &Service<'_>) {
+        let provider = service
+            .providers
+            .provider_of("acme", "idp")
+            .unwrap()
+            .unwrap();
+        service
+            .providers
+            .save(&provider.with_jit_provisioning(JitProvisioningPolicy::new()))
+            .unwrap();
+    }
+
+    #[test]
+    fn first_sign_in_provisions_and_links_a_user() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        with_jit_provisioning(&service);
+        let directory = Directory::default();
+
+        let sign_in = first_sign_in("upstream-grace");
+        let username = service.resolve_user(&sign_in, &directory).unwrap();
+        assert_eq!(directory.users.lock().unwrap()[..], [username.as_str()]);
+        assert_eq!(
+            service
+                .links
+                .linked_username("acme", "idp", "upstream-grace")
+                .unwrap(),
+            Some(username)
+        );
+    }
+
+    #[test]
+    fn user_is_deprovisioned_when_linking_fails() {
+        let (upstream, clock) = (Upstream::new(), clock());
+        let service = service(&upstream, &clock);
+        with_jit_provisioning(&service);
+        let directory = Directory::default();
+
+        // Another sign-in linked the identity to someone else meanwhile.
+        service
+            .links
+            .link("acme", "idp", "upstream-grace", "mallory")
+            .unwrap();
+        assert!(matches!(
+            service.resolve_user(&first_sign_in("upstream-grace"), &directory),
+            Err(FederationError::AlreadyLinked)
+        ));
+        assert!(directory.users.lock().unwrap().is_empty());
+    }
+}
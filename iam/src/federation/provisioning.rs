@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{ExternalIdentity, FederationError};
+use crate::common::secret::digest;
+
+/// When a first-time federated user may get a local account automatically.
+///
+/// By default the provider must have verified the email address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JitProvisioningPolicy {
+    /// Email domains accepted; empty accepts any. Domains are only checked
+    /// against verified addresses.
+    #[serde(default)]
+    allowed_email_domains: BTreeSet<String>,
+    /// Whether the provider must have verified the email address.
+    #[serde(default = "required")]
+    require_verified_email: bool,
+}
+
+fn required() -> bool {
+    true
+}
+
+impl Default for JitProvisioningPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_email_domains: BTreeSet::new(),
+            require_verified_email: true,
+        }
+    }
+}
+
+impl JitProvisioningPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_email_domain(mut self, domain: &str) -> Self {
+        self.allowed_email_domains
+            .insert(domain.trim().to_ascii_lowercase());
+        self
+    }
+
+    pub fn require_verified_email(mut self, require: bool) -> Self {
+        self.require_verified_email = require;
+        self
+    }
+
+    /// The profile to create the user from, if the identity qualifies.
+    pub fn evaluate(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<ProvisioningProfile, FederationError> {
+        let verified_only = self.require_verified_email || !self.allowed_email_domains.is_empty();
+        let email = if verified_only {
+            identity.verified_email()
+        } else {
+            identity.attribute("email").and_then(Value::as_str)
+        };
+        if verified_only && email.is_none() {
+            return Err(FederationError::ProvisioningDenied(
+                "no verified email address".to_owned(),
+            ));
+        }
+        if let Some(email) = email.filter(|_| !self.allowed_email_domains.is_empty()) {
+            let domain = email
+                .rsplit_once('@')
+                .map(|(_, domain)| domain.to_ascii_lowercase())
+                .unwrap_or_default();
+            if !self.allowed_email_domains.contains(&domain) {
+                return Err(FederationError::ProvisioningDenied(format!(
+                    "email domain {} is not allowed",
+                    domain
+                )));
+            }
+        }
+        let text = |name: &str| {
+            identity
+                .attribute(name)
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+        };
+        Ok(ProvisioningProfile {
+            tenant_id: identity.tenant_id.clone(),
+            username: federated_username(identity),
+            email: email.map(str::to_owned),
+            given_name: text("given_name"),
+            family_name: text("family_name"),
+            display_name: text("name"),
+        })
+    }
+}
+
+/// The username proposed for a federated identity: `oidc-` followed by 32
+/// hex digits of the SHA-256 of issuer and subject.
+///
+/// Unlike email addresses, which providers let users change and recycle,
+/// the pair is stable and unique to one account at one provider.
+pub fn federated_username(identity: &ExternalIdentity) -> String {
+    let hash = digest(&format!("{}\0{}", identity.issuer, identity.subject));
+    format!("oidc-{}", &hash[..32])
+}
+
+/// Local account details mapped from a federated identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisioningProfile {
+    pub tenant_id: String,
+    /// Proposed username, derived from the issuer and subject; see
+    /// [`federated_username`].
+    pub username: String,
+    pub email: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// Creates local users for federated identities.
+///
+/// Implementations register the user and person as a normal registration
+/// would, publishing `UserRegistered`, and return the username they used.
+pub trait UserProvisioner: Send + Sync {
+    fn provision(&self, profile: &ProvisioningProfile) -> Result<String, FederationError>;
+
+    /// Removes a user provisioned moments ago whose identity could not be
+    /// linked, so that no account is left that nobody can sign in to.
+    fn deprovision(&self, tenant_id: &str, username: &str) -> Result<(), FederationError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn identity(email: Option<&str>, verified: bool) -> ExternalIdentity {
+        let mut attributes = std::collections::BTreeMap::new();
+        if let Some(email) = email {
+            attributes.insert("email".to_owned(), json!(email));
+            attributes.insert("email_verified".to_owned(), json!(verified));
+        }
+        ExternalIdentity {
+            tenant_id: "acme".to_owned(),
+            provider_id: "google".to_owned(),
+            issuer: "https://accounts.google.com".to_owned(),
+            subject: "1234567890".to_owned(),
+            attributes,
+        }
+    }
+
+    #[test]
+    fn default_policy_requires_a_verified_email() {
+        let policy = JitProvisioningPolicy::default();
+        assert!(matches!(
+            policy.evaluate(&identity(Some("ada@example.com"), false)),
+            Err(FederationError::ProvisioningDenied(_))
+        ));
+        assert!(matches!(
+            policy.evaluate(&identity(None, false)),
+            Err(FederationError::ProvisioningDenied(_))
+        ));
+        let profile = policy
+            .evaluate(&identity(Some("ada@example.com"), true))
+            .unwrap();
+        assert_eq!(profile.email.as_deref(), Some("ada@example.com"));
+    }
+
+    #[test]
+    fn policy_deserializes_secure_by_default() {
+        let policy: JitProvisioningPolicy = serde_json::from_str("{}").unwrap();
+        assert_eq!(policy, JitProvisioningPolicy::default());
+    }
+
+    #[test]
+    fn username_comes_from_issuer_and_subject() {
+        let profile = JitProvisioningPolicy::default()
+            .evaluate(&identity(Some("ada@example.com"), true))
+            .unwrap();
+        assert!(profile.username.starts_with("oidc-"));
+        assert_eq!(profile.username.len(), 37);
+        assert_ne!(profile.username, "ada@example.com");
+
+        let mut other_issuer = identity(Some("ada@example.com"), true);
+        other_issuer.issuer = "https://login.example.com".to_owned();
+        assert_ne!(federated_username(&other_issuer), profile.username);
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp::Reverse;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::common::page::{Page, PageRequest};
+
+/// Errors raised by the authentication audit trail.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AuditError {
+    #[error("invalid audit query: {0}")]
+    InvalidQuery(String),
+    #[error("audit storage failure: {0}")]
+    Storage(String),
+}
+
+/// Why an authentication attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AuthenticationFailure {
+    /// Unknown user or wrong password; the two are not told apart.
+    InvalidCredentials,
+    InvalidSecondFactor,
+    LockedOut,
+    Throttled,
+    Disabled,
+    RiskDenied,
+}
+
+/// How an authentication attempt ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "result", content = "reason", rename_all = "snake_case")]
+pub enum AuthenticationOutcome {
+    Succeeded,
+    Failed(AuthenticationFailure),
+}
+
+impl AuthenticationOutcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, AuthenticationOutcome::Succeeded)
+    }
+}
+
+/// One authentication attempt as recorded in the audit trail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthenticationAttempt {
+    pub tenant_id: String,
+    /// The username as entered, which may not exist.
+    pub username: String,
+    pub outcome: AuthenticationOutcome,
+    pub mfa_used: bool,
+    pub address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Selects audit entries of a tenant, optionally for one user and a time
+/// range; `from` is inclusive and `until` exclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticationAuditQuery {
+    tenant_id: String,
+    username: Option<String>,
+    from: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl AuthenticationAuditQuery {
+    pub fn tenant(tenant_id: impl Into<String>) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            username: None,
+            from: None,
+            until: None,
+        }
+    }
+
+    pub fn with_username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    pub fn with_from(mut self, from: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    pub fn with_until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn from(&self) -> Option<DateTime<Utc>> {
+        self.from
+    }
+
+    pub fn until(&self) -> Option<DateTime<Utc>> {
+        self.until
+    }
+
+    pub fn matches(&self, attempt: &AuthenticationAttempt) -> bool {
+        attempt.tenant_id == self.tenant_id
+            && self
+                .username
+                .as_ref()
+                .is_none_or(|username| attempt.username == *username)
+            && self.from.is_none_or(|from| attempt.occurred_at >= from)
+            && self.until.is_none_or(|until| attempt.occurred_at < until)
+    }
+}
+
+/// Append-only store of authentication attempts.
+pub trait AuthenticationAuditRepository: Send + Sync {
+    fn record(&self, attempt: &AuthenticationAttempt) -> Result<(), AuditError>;
+
+    /// Matching attempts, newest first.
+    fn attempts(
+        &self,
+        query: &AuthenticationAuditQuery,
+        page: &PageRequest,
+    ) -> Result<Page<AuthenticationAttempt>, AuditError>;
+
+    /// Deletes attempts older than the retention cut-off, returning how many
+    /// were removed.
+    fn purge_before(&self, before: DateTime<Utc>) -> Result<u64, AuditError>;
+}
+
+/// Audit trail kept in process memory, with offset-based paging.
+#[derive(Debug, Default)]
+pub struct InMemoryAuthenticationAuditRepository {
+    attempts: RwLock<Vec<AuthenticationAttempt>>,
+}
+
+impl InMemoryAuthenticationAuditRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AuthenticationAuditRepository for InMemoryAuthenticationAuditRepository {
+    fn record(&self, attempt: &AuthenticationAttempt) -> Result<(), AuditError> {
+        let mut attempts = self.attempts.write().expect("audit trail lock poisoned");
+        attempts.push(attempt.clone());
+        Ok(())
+    }
+
+    fn attempts(
+        &self,
+        query: &AuthenticationAuditQuery,
+        page: &PageRequest,
+    ) -> Result<Page<AuthenticationAttempt>, AuditError> {
+        let offset = page
+            .offset()
+            .ok_or_else(|| AuditError::InvalidQuery("cursor paging is not supported".to_owned()))?;
+        let attempts = self.attempts.read().expect("audit trail lock poisoned");
+        let mut matching: Vec<&AuthenticationAttempt> =
+            attempts.iter().filter(|a| query.matches(a)).collect();
+        matching.sort_by_key(|a| Reverse(a.occurred_at));
+        let total = matching.len() as u64;
+        let items = matching
+            .into_iter()
+            .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+            .take(page.limit() as usize)
+            .cloned()
+            .collect();
+        Ok(Page::from_offset(items, page, Some(total)))
+    }
+
+    fn purge_before(&self, before: DateTime<Utc>) -> Result<u64, AuditError> {
+        let mut attempts = self.attempts.write().expect("audit trail lock poisoned");
+        let count = attempts.len();
+        attempts.retain(|attempt| attempt.occurred_at >= before);
+        Ok((count - attempts.len()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn attempt(
+        username: &str,
+        minutes: i64,
+        outcome: AuthenticationOutcome,
+    ) -> AuthenticationAttempt {
+        AuthenticationAttempt {
+            tenant_id: "acme".to_owned(),
+            username: username.to_owned(),
+            outcome,
+            mfa_used: false,
+            address: Some("192.0.2.1".parse().unwrap()),
+            user_agent: Some("curl/8.0".to_owned()),
+            occurred_at: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+                + Duration::minutes(minutes),
+        }
+    }
+
+    fn repository() -> InMemoryAuthenticationAuditRepository {
+        let repository = InMemoryAuthenticationAuditRepository::new();
+        let failed = AuthenticationOutcome::Failed(AuthenticationFailure::InvalidCredentials);
+        for (username, minutes, outcome) in [
+            ("ada", 0, failed),
+            ("ada", 1, AuthenticationOutcome::Succeeded),
+            ("bob", 2, failed),
+            ("ada", 3, AuthenticationOutcome::Succeeded),
+        ] {
+            repository
+                .record(&attempt(username, minutes, outcome))
+                .unwrap();
+        }
+        let mut other_tenant = attempt("ada", 4, AuthenticationOutcome::Succeeded);
+        other_tenant.tenant_id = "globex".to_owned();
+        repository.record(&other_tenant).unwrap();
+        repository
+    }
+
+    #[test]
+    fn filters_by_user_and_time_range_newest_first() {
+        let repository = repository();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let query = AuthenticationAuditQuery::tenant("acme")
+            .with_username("ada")
+            .with_from(start + Duration::minutes(1))
+            .with_until(start + Duration::minutes(4));
+        let page = repository
+            .attempts(&query, &PageRequest::default())
+            .unwrap();
+        let minutes: Vec<i64> = page
+            .items()
+            .iter()
+            .map(|a| (a.occurred_at - start).num_minutes())
+            .collect();
+        assert_eq!(minutes, [3, 1]);
+    }
+
+    #[test]
+    fn pages_through_a_tenant() {
+        let repository = repository();
+        let query = AuthenticationAuditQuery::tenant("acme");
+        let first = repository.attempts(&query, &PageRequest::first(3)).unwrap();
+        assert_eq!((first.items().len(), first.total()), (3, Some(4)));
+        let second = repository.attempts(&query, first.next().unwrap()).unwrap();
+        assert_eq!(second.items().len(), 1);
+        assert!(second.next().is_none());
+    }
+
+    #[test]
+    fn purges_old_attempts() {
+        let repository = repository();
+        let cut_off = Utc.with_ymd_and_hms(2024, 1, 1, 0, 2, 0).unwrap();
+        assert_eq!(repository.purge_before(cut_off).unwrap(), 2);
+        let page = repository
+            .attempts(
+                &AuthenticationAuditQuery::tenant("acme"),
+                &PageRequest::default(),
+            )
+            .unwrap();
+        assert_eq!(page.total(), Some(2));
+    }
+}
@@ -0,0 +1,342 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::audit::AuthenticationFailure;
+use super::lockout::{LoginAttemptError, LoginAttemptKey, LoginAttemptTracker};
+use super::password::{
+    PasswordAuthentication, PasswordChangeReason, PasswordCredential, PasswordError,
+    PasswordHashingStrategy, PasswordPolicy, PlainPassword,
+};
+use super::risk::{RiskDecision, RiskError, SignInContext, SignInRiskEvaluator};
+use crate::common::clock::Clock;
+
+/// How strongly a session was authenticated, from weakest to strongest.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthenticationStrength {
+    /// Authenticated through a link or federated assertion only.
+    #[default]
+    SingleFactor,
+    Password,
+    MultiFactor,
+}
+
+/// Authentication an operation demands before it may proceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepUpRequirement {
+    min_strength: AuthenticationStrength,
+    max_age: Option<Duration>,
+}
+
+impl StepUpRequirement {
+    pub fn new(min_strength: AuthenticationStrength) -> Self {
+        Self {
+            min_strength,
+            max_age: None,
+        }
+    }
+
+    /// Also requires the authentication to have happened recently.
+    pub fn within(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn min_strength(&self) -> AuthenticationStrength {
+        self.min_strength
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Whether an authentication of the given strength and time satisfies the
+    /// requirement.
+    pub fn is_satisfied_by(
+        &self,
+        strength: AuthenticationStrength,
+        authenticated_at: DateTime<Utc>,
+        clock: &impl Clock,
+    ) -> bool {
+        strength >= self.min_strength
+            && self
+                .max_age
+                .is_none_or(|max_age| clock.now() - authenticated_at <= max_age)
+    }
+}
+
+/// Errors raised while signing users in.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AuthenticationError {
+    #[error(transparent)]
+    Password(#[from] PasswordError),
+    #[error(transparent)]
+    Lockout(#[from] LoginAttemptError),
+    #[error(transparent)]
+    Risk(#[from] RiskError),
+    #[error("authentication storage failure: {0}")]
+    Storage(String),
+}
+
+/// Looks up the password credentials of the users signing in.
+pub trait SignInAccounts: Send + Sync {
+    fn credential_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<PasswordCredential>, AuthenticationError>;
+}
+
+/// How a password sign-in ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SignInOutcome {
+    /// The user is signed in; `needs_rehash` asks the caller to store a
+    /// fresh hash while the plaintext is at hand.
+    Authenticated {
+        needs_rehash: bool,
+    },
+    /// The password matches, but the sign-in looks risky enough to need a
+    /// second factor; report it through
+    /// [`AuthenticationService::record_sign_in`] once that is verified.
+    MfaRequired,
+    MustChangePassword(PasswordChangeReason),
+    Failed(AuthenticationFailure),
+}
+
+/// Signs users in with their password, applying lockout and sign-in risk
+/// evaluation.
+///
+/// Unknown users and wrong passwords fail alike and count towards the
+/// user's lockout. The risk evaluator sees every sign-in whose password
+/// matched and learns from those that completed.
+pub struct AuthenticationService<A, L, E, H, C> {
+    accounts: A,
+    attempts: L,
+    risk: E,
+    hashing: H,
+    clock: C,
+    policy: PasswordPolicy,
+}
+
+impl<A, L, E, H, C> AuthenticationService<A, L, E, H, C>
+where
+    A: SignInAccounts,
+    L: LoginAttemptTracker,
+    E: SignInRiskEvaluator,
+    H: PasswordHashingStrategy,
+    C: Clock,
+{
+    pub fn new(accounts: A, attempts: L, risk: E, hashing: H, clock: C) -> Self {
+        Self {
+            accounts,
+            attempts,
+            risk,
+            hashing,
+            clock,
+            policy: PasswordPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Checks the password of the user described by `context`, which also
+    /// carries the address, location and device the risk evaluator needs.
+    pub fn authenticate(
+        &self,
+        context: &SignInContext,
+        password: &PlainPassword,
+    ) -> Result<SignInOutcome, AuthenticationError> {
+        let key = LoginAttemptKey::User {
+            tenant_id: context.tenant_id.clone(),
+            username: context.username.clone(),
+        };
+        if self.attempts.status(&key)?.is_locked() {
+            return Ok(SignInOutcome::Failed(AuthenticationFailure::LockedOut));
+        }
+        let authentication = match self
+            .accounts
+            .credential_of(&context.tenant_id, &context.username)?
+        {
+            Some(credential) => {
+                credential.authenticate(password, &self.hashing, &self.policy, &self.clock)?
+            }
+            None => PasswordAuthentication::Rejected,
+        };
+        if authentication == PasswordAuthentication::Rejected {
+            self.attempts.record_failure(&key)?;
+            return Ok(SignInOutcome::Failed(
+                AuthenticationFailure::InvalidCredentials,
+            ));
+        }
+        self.attempts.record_success(&key)?;
+        match self.risk.evaluate(context)? {
+            RiskDecision::Deny => {
+                return Ok(SignInOutcome::Failed(AuthenticationFailure::RiskDenied))
+            }
+            RiskDecision::RequireMfa => return Ok(SignInOutcome::MfaRequired),
+            RiskDecision::Allow => {}
+        }
+        Ok(match authentication {
+            PasswordAuthentication::MustChangePassword(reason) => {
+                SignInOutcome::MustChangePassword(reason)
+            }
+            PasswordAuthentication::Authenticated { needs_rehash } => {
+                self.record_sign_in(context)?;
+                SignInOutcome::Authenticated { needs_rehash }
+            }
+            _ => SignInOutcome::Failed(AuthenticationFailure::InvalidCredentials),
+        })
+    }
+
+    /// Lets the risk evaluator learn from a sign-in that completed, e.g.
+    /// after the second factor asked for by [`SignInOutcome::MfaRequired`].
+    pub fn record_sign_in(&self, context: &SignInContext) -> Result<(), AuthenticationError> {
+        Ok(self.risk.record_sign_in(context)?)
+    }
+
+    /// The time sign-ins are evaluated at, for building contexts.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::lockout::{InMemoryLoginAttemptTracker, LockoutPolicy};
+    use crate::identity::password::Argon2Strategy;
+    use crate::identity::risk::{GeoLocation, GeoVelocityEvaluator};
+
+    struct Accounts(HashMap<String, PasswordCredential>);
+
+    impl SignInAccounts for Accounts {
+        fn credential_of(
+            &self,
+            _tenant_id: &str,
+            username: &str,
+        ) -> Result<Option<PasswordCredential>, AuthenticationError> {
+            Ok(self.0.get(username).cloned())
+        }
+    }
+
+    type Service<'a> = AuthenticationService<
+        Accounts,
+        InMemoryLoginAttemptTracker<&'a FixedClock>,
+        GeoVelocityEvaluator,
+        Argon2Strategy,
+        &'a FixedClock,
+    >;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn service(clock: &FixedClock) -> Service<'_> {
+        let hashing = Argon2Strategy::new(8, 1, 1).unwrap();
+        let hash = PlainPassword::new("hunter22").encrypt(&hashing).unwrap();
+        let accounts = Accounts(HashMap::from([(
+            "ada".to_owned(),
+            PasswordCredential::new(hash, clock.now()),
+        )]));
+        let attempts = InMemoryLoginAttemptTracker::new(
+            LockoutPolicy::new(3, Duration::minutes(10), Duration::minutes(5)),
+            clock,
+        );
+        AuthenticationService::new(
+            accounts,
+            attempts,
+            GeoVelocityEvaluator::new(),
+            hashing,
+            clock,
+        )
+    }
+
+    fn context(service: &Service<'_>, username: &str, location: GeoLocation) -> SignInContext {
+        SignInContext::new(
+            "acme",
+            username,
+            Ipv4Addr::new(192, 0, 2, 1).into(),
+            service.now(),
+        )
+        .located_at(location)
+    }
+
+    fn paris() -> GeoLocation {
+        GeoLocation::new("fr", 48.8566, 2.3522)
+    }
+
+    fn sydney() -> GeoLocation {
+        GeoLocation::new("au", -33.8688, 151.2093)
+    }
+
+    #[test]
+    fn risk_is_evaluated_after_the_password_matches() {
+        let clock = clock();
+        let service = service(&clock);
+        let password = PlainPassword::new("hunter22");
+
+        let home = context(&service, "ada", paris());
+        assert_eq!(
+            service.authenticate(&home, &password).unwrap(),
+            SignInOutcome::Authenticated {
+                needs_rehash: false
+            }
+        );
+
+        clock.advance(Duration::hours(1));
+        let teleport = context(&service, "ada", sydney());
+        assert_eq!(
+            service.authenticate(&teleport, &password).unwrap(),
+            SignInOutcome::Failed(AuthenticationFailure::RiskDenied)
+        );
+
+        clock.advance(Duration::days(1));
+        let trip = context(&service, "ada", sydney());
+        assert_eq!(
+            service.authenticate(&trip, &password).unwrap(),
+            SignInOutcome::MfaRequired
+        );
+        service.record_sign_in(&trip).unwrap();
+        clock.advance(Duration::hours(1));
+        assert!(matches!(
+            service.authenticate(&context(&service, "ada", sydney()), &password),
+            Ok(SignInOutcome::Authenticated { .. })
+        ));
+    }
+
+    #[test]
+    fn failures_lock_the_user_out_whether_or_not_it_exists() {
+        let clock = clock();
+        let service = service(&clock);
+        let wrong = PlainPassword::new("wrong password");
+        for username in ["ada", "nobody"] {
+            let attempt = context(&service, username, paris());
+            for _ in 0..3 {
+                assert_eq!(
+                    service.authenticate(&attempt, &wrong).unwrap(),
+                    SignInOutcome::Failed(AuthenticationFailure::InvalidCredentials)
+                );
+            }
+            assert_eq!(
+                service
+                    .authenticate(&attempt, &PlainPassword::new("hunter22"))
+                    .unwrap(),
+                SignInOutcome::Failed(AuthenticationFailure::LockedOut)
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::common::clock::Clock;
+
+/// Errors raised while registering or resolving tenant email domains.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DiscoveryError {
+    #[error("invalid email domain: {0}")]
+    InvalidDomain(String),
+    #[error("email domain {0} is already claimed by another tenant")]
+    DomainClaimed(String),
+    #[error("email domain {0} is not registered")]
+    UnknownDomain(String),
+    #[error("tenant email domain storage failure: {0}")]
+    Storage(String),
+}
+
+/// An email domain claimed by a tenant.
+///
+/// Only verified domains route sign-ins; verification proves control of the
+/// domain, typically by publishing the token in a DNS TXT record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantEmailDomain {
+    domain: String,
+    tenant_id: String,
+    identity_provider: Option<String>,
+    verification_token: String,
+    verified_at: Option<DateTime<Utc>>,
+}
+
+impl TenantEmailDomain {
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    /// The identity provider users of the domain sign in with, if not the
+    /// tenant's local credentials.
+    pub fn identity_provider(&self) -> Option<&str> {
+        self.identity_provider.as_deref()
+    }
+
+    pub fn verification_token(&self) -> &str {
+        &self.verification_token
+    }
+
+    pub fn verified_at(&self) -> Option<DateTime<Utc>> {
+        self.verified_at
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+}
+
+/// Where a sign-in UI should send a user before asking for a password.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TenantRoute {
+    pub tenant_id: String,
+    pub identity_provider: Option<String>,
+}
+
+/// Stores the email domains claimed by tenants.
+pub trait TenantEmailDomainRepository: Send + Sync {
+    fn domain_of(&self, domain: &str) -> Result<Option<TenantEmailDomain>, DiscoveryError>;
+
+    fn domains_of_tenant(&self, tenant_id: &str) -> Result<Vec<TenantEmailDomain>, DiscoveryError>;
+
+    /// Inserts or replaces the entry of its domain.
+    fn save(&self, domain: &TenantEmailDomain) -> Result<(), DiscoveryError>;
+
+    fn remove(&self, domain: &str) -> Result<(), DiscoveryError>;
+}
+
+/// Repository keeping tenant email domains in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryTenantEmailDomainRepository {
+    domains: RwLock<HashMap<String, TenantEmailDomain>>,
+}
+
+impl InMemoryTenantEmailDomainRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TenantEmailDomainRepository for InMemoryTenantEmailDomainRepository {
+    fn domain_of(&self, domain: &str) -> Result<Option<TenantEmailDomain>, DiscoveryError> {
+        let domains = self.domains.read().expect("email domains lock poisoned");
+        Ok(domains.get(domain).cloned())
+    }
+
+    fn domains_of_tenant(&self, tenant_id: &str) -> Result<Vec<TenantEmailDomain>, DiscoveryError> {
+        let domains = self.domains.read().expect("email domains lock poisoned");
+        let mut found: Vec<_> = domains
+            .values()
+            .filter(|d| d.tenant_id == tenant_id)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.domain.cmp(&b.domain));
+        Ok(found)
+    }
+
+    fn save(&self, domain: &TenantEmailDomain) -> Result<(), DiscoveryError> {
+        let mut domains = self.domains.write().expect("email domains lock poisoned");
+        domains.insert(domain.domain.clone(), domain.clone());
+        Ok(())
+    }
+
+    fn remove(&self, domain: &str) -> Result<(), DiscoveryError> {
+        let mut domains = self.domains.write().expect("email domains lock poisoned");
+        domains.remove(domain);
+        Ok(())
+    }
+}
+
+/// Routes users to their tenant from the domain of their email address.
+pub struct TenantDiscoveryService<R, C> {
+    repository: R,
+    clock: C,
+}
+
+impl<R: TenantEmailDomainRepository, C: Clock> TenantDiscoveryService<R, C> {
+    pub fn new(repository: R, clock: C) -> Self {
+        Self { repository, clock }
+    }
+
+    /// Claims a domain for the tenant, pending verification.
+    ///
+    /// Claiming again from the same tenant updates the identity provider and
+    /// keeps the verification state.
+    pub fn register_domain(
+        &self,
+        tenant_id: &str,
+        domain: &str,
+        identity_provider: Option<&str>,
+    ) -> Result<TenantEmailDomain, DiscoveryError> {
+        let domain = normalize_domain(domain)?;
+        let entry = match self.repository.domain_of(&domain)? {
+            Some(existing) if existing.tenant_id != tenant_id && existing.is_verified() => {
+                return Err(DiscoveryError::DomainClaimed(domain));
+            }
+            Some(existing) if existing.tenant_id == tenant_id => TenantEmailDomain {
+                identity_provider: identity_provider.map(str::to_owned),
+                ..existing
+            },
+            _ => TenantEmailDomain {
+                domain,
+                tenant_id: tenant_id.to_owned(),
+                identity_provider: identity_provider.map(str::to_owned),
+                verification_token: verification_token(),
+                verified_at: None,
+            },
+        };
+        self.repository.save(&entry)?;
+        Ok(entry)
+    }
+
+    /// Marks the domain verified once the caller has confirmed the
+    /// verification token was published.
+    pub fn mark_verified(&self, domain: &str) -> Result<TenantEmailDomain, DiscoveryError> {
+        let domain = normalize_domain(domain)?;
+        let mut entry = self
+            .repository
+            .domain_of(&domain)?
+            .ok_or(DiscoveryError::UnknownDomain(domain))?;
+        entry.verified_at.get_or_insert(self.clock.now());
+        self.repository.save(&entry)?;
+        Ok(entry)
+    }
+
+    pub fn remove_domain(&self, tenant_id: &str, domain: &str) -> Result<(), DiscoveryError> {
+        let domain = normalize_domain(domain)?;
+        match self.repository.domain_of(&domain)? {
+            Some(entry) if entry.tenant_id == tenant_id => self.repository.remove(&domain),
+            _ => Err(DiscoveryError::UnknownDomain(domain)),
+        }
+    }
+
+    pub fn domains_of_tenant(
+        &self,
+        tenant_id: &str,
+    ) -> Result<Vec<TenantEmailDomain>, DiscoveryError> {
+        self.repository.domains_of_tenant(tenant_id)
+    }
+
+    /// The tenant owning the verified domain of the email address, if any.
+    pub fn discover(&self, email: &str) -> Result<Option<TenantRoute>, DiscoveryError> {
+        let Some((_, domain)) = email.trim().rsplit_once('@') else {
+            return Ok(None);
+        };
+        let Ok(domain) = normalize_domain(domain) else {
+            return Ok(None);
+        };
+        Ok(self
+            .repository
+            .domain_of(&domain)?
+            .filter(TenantEmailDomain::is_verified)
+            .map(|entry| TenantRoute {
+                tenant_id: entry.tenant_id,
+                identity_provider: entry.identity_provider,
+            }))
+    }
+}
+
+fn normalize_domain(domain: &str) -> Result<String, DiscoveryError> {
+    let normalized = domain.trim().trim_end_matches('.').to_lowercase();
+    let valid = normalized.contains('.')
+        && normalized.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    if valid {
+        Ok(normalized)
+    } else {
+        Err(DiscoveryError::InvalidDomain(domain.to_owned()))
+    }
+}
+
+fn verification_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    format!("iam-domain-verification={}", hex::encode(bytes))
+}
@@ -0,0 +1,289 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+use crate::common::clock::Clock;
+
+/// Errors raised by login attempt trackers.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LoginAttemptError {
+    #[error("login attempt storage failure: {0}")]
+    Storage(String),
+}
+
+/// What failed sign-ins are counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LoginAttemptKey {
+    User { tenant_id: String, username: String },
+    Address(IpAddr),
+}
+
+/// Thresholds after which further sign-ins are refused for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    max_failures: u32,
+    window: Duration,
+    cooldown: Duration,
+}
+
+impl LockoutPolicy {
+    /// Locks for `cooldown` once `max_failures` failures happen within
+    /// `window`.
+    pub fn new(max_failures: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            window,
+            cooldown,
+        }
+    }
+
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::minutes(15), Duration::minutes(15))
+    }
+}
+
+/// Whether sign-ins are currently allowed for a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockoutStatus {
+    Open { remaining_attempts: u32 },
+    Locked { until: DateTime<Utc> },
+}
+
+impl LockoutStatus {
+    pub fn is_locked(&self) -> bool {
+        matches!(self, LockoutStatus::Locked { .. })
+    }
+}
+
+/// Records failed sign-ins and decides when to lock.
+///
+/// The authentication service records every failure and emits a
+/// `UserLockedOut` event when a failure returns a locked status.
+pub trait LoginAttemptTracker: Send + Sync {
+    fn status(&self, key: &LoginAttemptKey) -> Result<LockoutStatus, LoginAttemptError>;
+
+    fn record_failure(&self, key: &LoginAttemptKey) -> Result<LockoutStatus, LoginAttemptError>;
+
+    /// Forgets past failures after a successful sign-in.
+    fn record_success(&self, key: &LoginAttemptKey) -> Result<(), LoginAttemptError>;
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: VecDeque<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl Attempts {
+    /// Whether the entry no longer affects any decision.
+    fn is_idle(&self, now: DateTime<Utc>, window: Duration) -> bool {
+        self.locked_until.is_none_or(|until| now >= until)
+            && self.failures.back().is_none_or(|at| now - *at >= window)
+    }
+}
+
+#[derive(Debug, Default)]
+struct AttemptLog {
+    entries: HashMap<LoginAttemptKey, Attempts>,
+    pruned_at: Option<DateTime<Utc>>,
+}
+
+/// Tracker keeping attempts in process memory.
+///
+/// Suitable for single-instance deployments and tests; state is lost on
+/// restart and not shared between instances. Entries whose failures have
+/// left the window and whose lock has expired are dropped at most once per
+/// window, so keys that stop failing do not accumulate.
+pub struct InMemoryLoginAttemptTracker<C> {
+    policy: LockoutPolicy,
+    clock: C,
+    attempts: Mutex<AttemptLog>,
+}
+
+impl<C: Clock> InMemoryLoginAttemptTracker<C> {
+    pub fn new(policy: LockoutPolicy, clock: C) -> Self {
+        Self {
+            policy,
+            clock,
+            attempts: Mutex::new(AttemptLog::default()),
+        }
+    }
+
+    fn prune(&self, log: &mut AttemptLog, now: DateTime<Utc>) {
+        if log
+            .pruned_at
+            .is_some_and(|at| now - at < self.policy.window)
+        {
+            return;
+        }
+        let window = self.policy.window;
+        log.entries.retain(|_, entry| !entry.is_idle(now, window));
+        log.pruned_at = Some(now);
+    }
+
+    fn evaluate(&self, attempts: &mut Attempts, now: DateTime<Utc>) -> LockoutStatus {
+        if let Some(until) = attempts.locked_until {
+            if now < until {
+                return LockoutStatus::Locked { until };
+            }
+            attempts.locked_until = None;
+        }
+        while attempts
+            .failures
+            .front()
+            .is_some_and(|at| now - *at >= self.policy.window)
+        {
+            attempts.failures.pop_front();
+        }
+        let failures = u32::try_from(attempts.failures.len()).unwrap_or(u32::MAX);
+        LockoutStatus::Open {
+            remaining_attempts: self.policy.max_failures.saturating_sub(failures),
+        }
+    }
+}
+
+impl<C: Clock> LoginAttemptTracker for InMemoryLoginAttemptTracker<C> {
+    fn status(&self, key: &LoginAttemptKey) -> Result<LockoutStatus, LoginAttemptError> {
+        let now = self.clock.now();
+        let mut log = self.attempts.lock().expect("login attempts lock poisoned");
+        let Some(entry) = log.entries.get_mut(key) else {
+            return Ok(LockoutStatus::Open {
+                remaining_attempts: self.policy.max_failures,
+            });
+        };
+        let status = self.evaluate(entry, now);
+        if entry.is_idle(now, self.policy.window) {
+            log.entries.remove(key);
+        }
+        Ok(status)
+    }
+
+    fn record_failure(&self, key: &LoginAttemptKey) -> Result<LockoutStatus, LoginAttemptError> {
+        let now = self.clock.now();
+        let mut log = self.attempts.lock().expect("login attempts lock poisoned");
+        self.prune(&mut log, now);
+        let entry = log.entries.entry(key.clone()).or_default();
+        if let status @ LockoutStatus::Locked { .. } = self.evaluate(entry, now) {
+            return Ok(status);
+        }
+        entry.failures.push_back(now);
+        Ok(match self.evaluate(entry, now) {
+            LockoutStatus::Open {
+                remaining_attempts: 0,
+            } => {
+                let until = now + self.policy.cooldown;
+                entry.failures.clear();
+                entry.locked_until = Some(until);
+                LockoutStatus::Locked { until }
+            }
+            status => status,
+        })
+    }
+
+    fn record_success(&self, key: &LoginAttemptKey) -> Result<(), LoginAttemptError> {
+        self.attempts
+            .lock()
+            .expect("login attempts lock poisoned")
+            .entries
+            .remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn tracker(clock: &FixedClock) -> InMemoryLoginAttemptTracker<&FixedClock> {
+        let policy = LockoutPolicy::new(3, Duration::minutes(10), Duration::minutes(5));
+        InMemoryLoginAttemptTracker::new(policy, clock)
+    }
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn address(last: u8) -> LoginAttemptKey {
+        LoginAttemptKey::Address(Ipv4Addr::new(192, 0, 2, last).into())
+    }
+
+    fn entries(tracker: &InMemoryLoginAttemptTracker<&FixedClock>) -> usize {
+        tracker.attempts.lock().unwrap().entries.len()
+    }
+
+    #[test]
+    fn locks_after_max_failures_then_reopens() {
+        let clock = clock();
+        let tracker = tracker(&clock);
+        let key = address(1);
+        tracker.record_failure(&key).unwrap();
+        tracker.record_failure(&key).unwrap();
+        assert!(tracker.record_failure(&key).unwrap().is_locked());
+        clock.advance(Duration::minutes(5));
+        assert_eq!(
+            tracker.status(&key).unwrap(),
+            LockoutStatus::Open {
+                remaining_attempts: 3
+            }
+        );
+    }
+
+    #[test]
+    fn elapsed_entries_are_pruned() {
+        let clock = clock();
+        let tracker = tracker(&clock);
+        for last in 0..100 {
+            tracker.record_failure(&address(last)).unwrap();
+        }
+        assert_eq!(entries(&tracker), 100);
+
+        clock.advance(Duration::minutes(10));
+        tracker.record_failure(&address(200)).unwrap();
+        assert_eq!(entries(&tracker), 1);
+    }
+
+    #[test]
+    fn locked_entries_survive_pruning() {
+        let clock = clock();
+        let policy = LockoutPolicy::new(1, Duration::minutes(1), Duration::hours(1));
+        let tracker = InMemoryLoginAttemptTracker::new(policy, &clock);
+        assert!(tracker.record_failure(&address(1)).unwrap().is_locked());
+
+        clock.advance(Duration::minutes(30));
+        tracker.record_failure(&address(2)).unwrap();
+        assert!(tracker.status(&address(1)).unwrap().is_locked());
+    }
+
+    #[test]
+    fn status_drops_idle_entries() {
+        let clock = clock();
+        let tracker = tracker(&clock);
+        tracker.record_failure(&address(1)).unwrap();
+        clock.advance(Duration::minutes(10));
+        tracker.status(&address(1)).unwrap();
+        assert_eq!(entries(&tracker), 0);
+    }
+}
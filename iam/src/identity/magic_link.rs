@@ -0,0 +1,248 @@
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+use super::authentication::AuthenticationStrength;
+use super::recovery::{MessageSender, RecoveryDestination};
+use super::single_use::{
+    SingleUseToken, SingleUseTokenError, SingleUseTokenRepository, TokenPurpose,
+};
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+
+/// Errors of passwordless sign-in.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MagicLinkError {
+    #[error("sign-in link is invalid or expired")]
+    InvalidToken,
+    #[error("sign-in link storage failure: {0}")]
+    Storage(String),
+}
+
+impl From<SingleUseTokenError> for MagicLinkError {
+    fn from(error: SingleUseTokenError) -> Self {
+        Self::Storage(error.0)
+    }
+}
+
+/// Finds the users sign-in links are sent to.
+pub trait MagicLinkAccounts: Send + Sync {
+    /// The enabled user owning the email address in the tenant, if any.
+    fn username_by_email(
+        &self,
+        tenant_id: &str,
+        email: &str,
+    ) -> Result<Option<String>, MagicLinkError>;
+}
+
+/// A user signed in through a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagicLinkSignIn {
+    pub tenant_id: String,
+    pub username: String,
+    pub strength: AuthenticationStrength,
+    pub authenticated_at: DateTime<Utc>,
+}
+
+/// Passwordless sign-in through short-lived, single-use links mailed to the
+/// user.
+///
+/// Requesting a link answers the same way whether or not the address has an
+/// account, and failures to deliver the link are not reported. The work
+/// done still differs, so callers that must not leak which addresses exist
+/// through response times should request links in the background.
+pub struct MagicLinkService<R, A, S, C> {
+    tokens: R,
+    accounts: A,
+    sender: S,
+    clock: C,
+    link_base: String,
+    token_ttl: Duration,
+}
+
+impl<R, A, S, C> MagicLinkService<R, A, S, C>
+where
+    R: SingleUseTokenRepository,
+    A: MagicLinkAccounts,
+    S: MessageSender,
+    C: Clock,
+{
+    /// Creates the service; links are `link_base` followed by the token,
+    /// e.g. `https://app.example.com/sign-in?token=`.
+    pub fn new(tokens: R, accounts: A, sender: S, clock: C, link_base: impl Into<String>) -> Self {
+        Self {
+            tokens,
+            accounts,
+            sender,
+            clock,
+            link_base: link_base.into(),
+            token_ttl: Duration::minutes(10),
+        }
+    }
+
+    pub fn with_token_ttl(mut self, token_ttl: Duration) -> Self {
+        self.token_ttl = token_ttl;
+        self
+    }
+
+    /// Mails a sign-in link to the address if it belongs to a user; a link
+    /// that cannot be delivered is dropped.
+    pub fn request_magic_link(&self, tenant_id: &str, email: &str) -> Result<(), MagicLinkError> {
+        let Some(username) = self.accounts.username_by_email(tenant_id, email)? else {
+            return Ok(());
+        };
+        let token = random_token();
+        self.tokens.save(&SingleUseToken {
+            purpose: TokenPurpose::MagicLink,
+            tenant_id: tenant_id.to_owned(),
+            username,
+            token_hash: digest(&token),
+            expires_at: self.clock.now() + self.token_ttl,
+        })?;
+        let minutes = self.token_ttl.num_minutes();
+        let body = format!(
+            "Sign in with this link:\n\n{}{token}\n\nIt expires in {minutes} minutes.",
+            self.link_base
+        );
+        // Reporting the failure would tell the caller the address is known.
+        let _ = self.sender.send(
+            &RecoveryDestination::Email(email.to_owned()),
+            "Your sign-in link",
+            &body,
+        );
+        Ok(())
+    }
+
+    /// Redeems the link's token, signing its user in.
+    pub fn redeem_magic_link(&self, token: &str) -> Result<MagicLinkSignIn, MagicLinkError> {
+        let now = self.clock.now();
+        let record = self
+            .tokens
+            .take(TokenPurpose::MagicLink, &digest(token))?
+            .filter(|record| now < record.expires_at)
+            .ok_or(MagicLinkError::InvalidToken)?;
+        Ok(MagicLinkSignIn {
+            tenant_id: record.tenant_id,
+            username: record.username,
+            strength: AuthenticationStrength::SingleFactor,
+            authenticated_at: now,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::single_use::InMemorySingleUseTokenRepository;
+    use crate::identity::testing::{Outbox, Unreachable};
+
+    const LINK_BASE: &str = "https://app.example.com/sign-in?token=";
+
+    fn last_token(outbox: &Outbox) -> String {
+        let body = outbox.last();
+        let (_, rest) = body.split_once(LINK_BASE).unwrap();
+        rest.split_whitespace().next().unwrap().to_owned()
+    }
+
+    struct Accounts;
+
+    impl MagicLinkAccounts for Accounts {
+        fn username_by_email(
+            &self,
+            _tenant_id: &str,
+            email: &str,
+        ) -> Result<Option<String>, MagicLinkError> {
+            Ok((email == "ada@example.com").then(|| "ada".to_owned()))
+        }
+    }
+
+    fn service(
+        outbox: &Outbox,
+    ) -> MagicLinkService<InMemorySingleUseTokenRepository, Accounts, &Outbox, FixedClock> {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        MagicLinkService::new(
+            InMemorySingleUseTokenRepository::new(),
+            Accounts,
+            outbox,
+            FixedClock::new(now),
+            LINK_BASE,
+        )
+    }
+
+    #[test]
+    fn unknown_address_gets_no_link() {
+        let outbox = Outbox::default();
+        service(&outbox)
+            .request_magic_link("acme", "nobody@example.com")
+            .unwrap();
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn delivery_failure_is_not_reported() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let service = MagicLinkService::new(
+            InMemorySingleUseTokenRepository::new(),
+            Accounts,
+            Unreachable,
+            FixedClock::new(now),
+            LINK_BASE,
+        );
+        assert!(service
+            .request_magic_link("acme", "ada@example.com")
+            .is_ok());
+    }
+
+    #[test]
+    fn link_signs_in_once() {
+        let outbox = Outbox::default();
+        let service = service(&outbox);
+        service
+            .request_magic_link("acme", "ada@example.com")
+            .unwrap();
+        let token = last_token(&outbox);
+
+        let sign_in = service.redeem_magic_link(&token).unwrap();
+        assert_eq!(
+            (sign_in.tenant_id.as_str(), sign_in.username.as_str()),
+            ("acme", "ada")
+        );
+        assert_eq!(sign_in.strength, AuthenticationStrength::SingleFactor);
+        assert!(matches!(
+            service.redeem_magic_link(&token),
+            Err(MagicLinkError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn new_link_replaces_the_previous_one() {
+        let outbox = Outbox::default();
+        let service = service(&outbox);
+        service
+            .request_magic_link("acme", "ada@example.com")
+            .unwrap();
+        let first = last_token(&outbox);
+        service
+            .request_magic_link("acme", "ada@example.com")
+            .unwrap();
+
+        assert!(service.redeem_magic_link(&first).is_err());
+        assert!(service.redeem_magic_link(&last_token(&outbox)).is_ok());
+    }
+
+    #[test]
+    fn expired_link_is_rejected() {
+        let outbox = Outbox::default();
+        let service = service(&outbox).with_token_ttl(Duration::zero());
+        service
+            .request_magic_link("acme", "ada@example.com")
+            .unwrap();
+        assert!(matches!(
+            service.redeem_magic_link(&last_token(&outbox)),
+            Err(MagicLinkError::InvalidToken)
+        ));
+    }
+}
@@ -0,0 +1,14 @@
+pub mod audit;
+pub mod authentication;
+pub mod captcha;
+pub mod discovery;
+pub mod lockout;
+pub mod magic_link;
+pub mod password;
+pub mod recovery;
+pub mod reset;
+pub mod risk;
+pub mod single_use;
+#[cfg(test)]
+mod testing;
+pub mod throttling;
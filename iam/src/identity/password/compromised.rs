@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::PlainPassword;
+
+/// Errors raised while checking a password against known breaches.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CompromisedCheckError {
+    #[error("breached password source unavailable: {0}")]
+    Unavailable(String),
+    #[error("malformed breached password data: {0}")]
+    Malformed(String),
+}
+
+/// Tells whether a password appears in known data breaches.
+pub trait CompromisedPasswordChecker: Send + Sync {
+    fn is_compromised(&self, password: &PlainPassword) -> Result<bool, CompromisedCheckError>;
+}
+
+/// Checker backed by the Have I Been Pwned range API.
+///
+/// Only the first five hex characters of the password's SHA-1 digest leave
+/// the process (k-anonymity); the match is done locally on the returned
+/// suffixes.
+#[derive(Debug, Clone)]
+pub struct HibpChecker {
+    agent: ureq::Agent,
+    endpoint: String,
+    min_occurrences: u64,
+}
+
+impl HibpChecker {
+    pub const DEFAULT_ENDPOINT: &'static str = "https://api.pwnedpasswords.com/range";
+    const PREFIX_LENGTH: usize = 5;
+
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            endpoint: Self::DEFAULT_ENDPOINT.to_owned(),
+            min_occurrences: 1,
+        }
+    }
+
+    /// Uses another range endpoint, e.g. a self-hosted mirror.
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Only reports passwords seen in at least this many breaches.
+    pub fn with_min_occurrences(mut self, min_occurrences: u64) -> Self {
+        self.min_occurrences = min_occurrences.max(1);
+        self
+    }
+}
+
+impl CompromisedPasswordChecker for HibpChecker {
+    fn is_compromised(&self, password: &PlainPassword) -> Result<bool, CompromisedCheckError> {
+        let digest = Zeroizing::new(hex::encode_upper(Sha1::digest(
+            password.as_str().as_bytes(),
+        )));
+        let (prefix, suffix) = digest.split_at(Self::PREFIX_LENGTH);
+        let body = self
+            .agent
+            .get(&format!("{}/{}", self.endpoint, prefix))
+            .set("Add-Padding", "true")
+            .call()
+            .map_err(|e| CompromisedCheckError::Unavailable(e.to_string()))?
+            .into_string()
+            .map_err(|e| CompromisedCheckError::Unavailable(e.to_string()))?;
+        for line in body.lines() {
+            let (candidate, count) = line
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| CompromisedCheckError::Malformed(line.to_owned()))?;
+            if candidate.eq_ignore_ascii_case(suffix) {
+                let count: u64 = count
+                    .parse()
+                    .map_err(|_| CompromisedCheckError::Malformed(line.to_owned()))?;
+                return Ok(count >= self.min_occurrences);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Offline checker backed by a Bloom filter of breached password digests.
+///
+/// Entries are SHA-1 digests, so the filter can be built from the published
+/// breach corpus without handling plaintext. False positives are possible at
+/// the configured rate; false negatives are not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilterChecker {
+    bits: Vec<u64>,
+    len: u64,
+    hashes: u32,
+}
+
+impl BloomFilterChecker {
+    /// Most hash functions a filter may use; optimal filters for any
+    /// realistic false positive rate need far fewer.
+    pub const MAX_HASHES: u32 = 32;
+    const HEADER_LENGTH: usize = 12;
+
+    /// Creates an empty filter sized for the expected number of entries.
+    pub fn with_capacity(expected_entries: usize, false_positive_rate: f64) -> Self {
+        let entries = expected_entries.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-entries * rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / entries) * ln2)
+            .round()
+            .clamp(1.0, f64::from(Self::MAX_HASHES)) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            len: bits as u64,
+            hashes,
+        }
+    }
+
+    pub fn insert(&mut self, password: &PlainPassword) {
+        self.insert_digest(&Sha1::digest(password.as_str().as_bytes()));
+    }
+
+    /// Inserts a hex-encoded SHA-1 digest as found in breach corpora.
+    pub fn insert_sha1(&mut self, hex_digest: &str) -> Result<(), CompromisedCheckError> {
+        let digest = hex::decode(hex_digest.trim())
+            .ok()
+            .filter(|digest| digest.len() == 20)
+            .ok_or_else(|| CompromisedCheckError::Malformed(hex_digest.to_owned()))?;
+        self.insert_digest(&digest);
+        Ok(())
+    }
+
+    /// Restores a filter previously produced by [`Self::to_bytes`].
+    ///
+    /// The encoding is the number of hash functions (`u32`), the length in
+    /// bits (`u64`) and the bit words (`u64` each), all little endian.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CompromisedCheckError> {
+        let malformed = |reason: &str| {
+            CompromisedCheckError::Malformed(format!("invalid bloom filter: {reason}"))
+        };
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(malformed("truncated header"));
+        }
+        let (header, words) = bytes.split_at(Self::HEADER_LENGTH);
+        let hashes = u32::from_le_bytes(header[..4].try_into().expect("4 bytes"));
+        let len = u64::from_le_bytes(header[4..].try_into().expect("8 bytes"));
+        if hashes == 0 || hashes > Self::MAX_HASHES {
+            return Err(malformed("hash count out of range"));
+        }
+        if len == 0 {
+            return Err(malformed("empty bit array"));
+        }
+        if words.len() % 8 != 0 || (words.len() / 8) as u64 != len.div_ceil(64) {
+            return Err(malformed("bit length does not match the data"));
+        }
+        let bits = words
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().expect("chunk of 8 bytes")))
+            .collect();
+        Ok(Self { bits, len, hashes })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_LENGTH + self.bits.len() * 8);
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        for word in &self.bits {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn insert_digest(&mut self, digest: &[u8]) {
+        for index in self.indexes(digest).collect::<Vec<_>>() {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+
+    fn contains_digest(&self, digest: &[u8]) -> bool {
+        self.indexes(digest)
+            .all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+
+    /// Bit positions for a digest, using double hashing over its two halves.
+    fn indexes(&self, digest: &[u8]) -> impl Iterator<Item = usize> + '_ {
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().expect("SHA-1 digest"));
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().expect("SHA-1 digest")) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % self.len) as usize)
+    }
+}
+
+impl CompromisedPasswordChecker for BloomFilterChecker {
+    fn is_compromised(&self, password: &PlainPassword) -> Result<bool, CompromisedCheckError> {
+        Ok(self.contains_digest(&Sha1::digest(password.as_str().as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(hashes: u32, len: u64) -> Vec<u8> {
+        let mut bytes = hashes.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn filter_round_trips_through_bytes() {
+        let mut filter = BloomFilterChecker::with_capacity(1_000, 0.001);
+        filter.insert(&PlainPassword::new("password1"));
+        filter
+            .insert_sha1("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8")
+            .unwrap();
+
+        let restored = BloomFilterChecker::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(restored, filter);
+        for password in ["password1", "password"] {
+            let password = PlainPassword::new(password);
+            assert!(restored.is_compromised(&password).unwrap());
+        }
+        let password = PlainPassword::new("correct horse battery staple");
+        assert!(!restored.is_compromised(&password).unwrap());
+    }
+
+    #[test]
+    fn tiny_rates_cap_the_hash_count() {
+        let filter = BloomFilterChecker::with_capacity(10, 0.0);
+        assert!(BloomFilterChecker::from_bytes(&filter.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        let word = [0u8; 8];
+        let cases = [
+            Vec::new(),
+            header(1, 64)[..11].to_vec(),
+            [header(0, 64), word.to_vec()].concat(),
+            [
+                header(BloomFilterChecker::MAX_HASHES + 1, 64),
+                word.to_vec(),
+            ]
+            .concat(),
+            [header(u32::MAX, 64), word.to_vec()].concat(),
+            [header(3, 0), word.to_vec()].concat(),
+            header(3, 64),
+            [header(3, 65), word.to_vec()].concat(),
+            [header(3, 64), word.to_vec(), word.to_vec()].concat(),
+            [header(3, 64), word[..5].to_vec()].concat(),
+        ];
+        for bytes in cases {
+            assert!(BloomFilterChecker::from_bytes(&bytes).is_err(), "{bytes:?}");
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    EncryptedPassword, PasswordError, PasswordHashingStrategy, PasswordPolicy, PlainPassword,
+};
+use crate::common::clock::Clock;
+
+/// Why a user has to choose a new password before signing in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum PasswordChangeReason {
+    /// The password is older than the policy's maximum age.
+    Expired,
+    /// An administrator asked for the password to be changed.
+    Forced,
+}
+
+/// Outcome of signing in with a password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PasswordAuthentication {
+    Rejected,
+    /// The password matches; `needs_rehash` asks the caller to store a fresh
+    /// hash while the plaintext is at hand.
+    Authenticated {
+        needs_rehash: bool,
+    },
+    /// The password matches, but the user must change it before getting a
+    /// session.
+    MustChangePassword(PasswordChangeReason),
+}
+
+/// A user's password hash along with when it was set and whether an
+/// administrator requires it to be changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordCredential {
+    password: EncryptedPassword,
+    password_changed_at: DateTime<Utc>,
+    #[serde(default)]
+    force_change: bool,
+}
+
+impl PasswordCredential {
+    pub fn new(password: EncryptedPassword, password_changed_at: DateTime<Utc>) -> Self {
+        Self {
+            password,
+            password_changed_at,
+            force_change: false,
+        }
+    }
+
+    pub fn password(&self) -> &EncryptedPassword {
+        &self.password
+    }
+
+    pub fn password_changed_at(&self) -> DateTime<Utc> {
+        self.password_changed_at
+    }
+
+    pub fn force_change(&self) -> bool {
+        self.force_change
+    }
+
+    /// Makes the user choose a new password at their next sign-in.
+    pub fn require_change(&mut self) {
+        self.force_change = true;
+    }
+
+    /// Replaces the password, clearing any pending forced change.
+    pub fn change(&mut self, password: EncryptedPassword, clock: &impl Clock) {
+        self.password = password;
+        self.password_changed_at = clock.now();
+        self.force_change = false;
+    }
+
+    /// Checks the password, then whether the user may keep using it.
+    pub fn authenticate(
+        &self,
+        password: &PlainPassword,
+        strategy: &dyn PasswordHashingStrategy,
+        policy: &PasswordPolicy,
+        clock: &impl Clock,
+    ) -> Result<PasswordAuthentication, PasswordError> {
+        let verification = self.password.verify(password, strategy)?;
+        Ok(if !verification.is_match() {
+            PasswordAuthentication::Rejected
+        } else if self.force_change {
+            PasswordAuthentication::MustChangePassword(PasswordChangeReason::Forced)
+        } else if policy.is_expired(self.password_changed_at, clock) {
+            PasswordAuthentication::MustChangePassword(PasswordChangeReason::Expired)
+        } else {
+            PasswordAuthentication::Authenticated {
+                needs_rehash: verification.needs_rehash(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::password::Pbkdf2Strategy;
+
+    fn setup() -> (PasswordCredential, Pbkdf2Strategy, FixedClock) {
+        let strategy = Pbkdf2Strategy::new(1).unwrap();
+        let clock = FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+        let hash = PlainPassword::new("hunter22").encrypt(&strategy).unwrap();
+        (PasswordCredential::new(hash, clock.now()), strategy, clock)
+    }
+
+    #[test]
+    fn wrong_password_is_rejected_even_when_expired() {
+        let (credential, strategy, clock) = setup();
+        let policy = PasswordPolicy::default().expire_after_days(30);
+        clock.advance(Duration::days(31));
+        let outcome = credential
+            .authenticate(&PlainPassword::new("wrong"), &strategy, &policy, &clock)
+            .unwrap();
+        assert_eq!(outcome, PasswordAuthentication::Rejected);
+    }
+
+    #[test]
+    fn expired_password_must_be_changed() {
+        let (mut credential, strategy, clock) = setup();
+        let policy = PasswordPolicy::default().expire_after_days(30);
+        let password = PlainPassword::new("hunter22");
+        clock.advance(Duration::days(29));
+        assert_eq!(
+            credential
+                .authenticate(&password, &strategy, &policy, &clock)
+                .unwrap(),
+            PasswordAuthentication::Authenticated {
+                needs_rehash: false
+            }
+        );
+        clock.advance(Duration::days(1));
+        assert_eq!(
+            credential
+                .authenticate(&password, &strategy, &policy, &clock)
+                .unwrap(),
+            PasswordAuthentication::MustChangePassword(PasswordChangeReason::Expired)
+        );
+
+        credential.change(password.encrypt(&strategy).unwrap(), &clock);
+        assert!(matches!(
+            credential
+                .authenticate(&password, &strategy, &policy, &clock)
+                .unwrap(),
+            PasswordAuthentication::Authenticated { .. }
+        ));
+    }
+
+    #[test]
+    fn forced_change_lasts_until_the_password_changes() {
+        let (mut credential, strategy, clock) = setup();
+        let policy = PasswordPolicy::default();
+        let password = PlainPassword::new("hunter22");
+        credential.require_change();
+        assert_eq!(
+            credential
+                .authenticate(&password, &strategy, &policy, &clock)
+                .unwrap(),
+            PasswordAuthentication::MustChangePassword(PasswordChangeReason::Forced)
+        );
+        credential.change(password.encrypt(&strategy).unwrap(), &clock);
+        assert!(!credential.force_change());
+    }
+}
@@ -0,0 +1,549 @@
+use argon2::{Algorithm as Argon2Algorithm, Argon2, Params as Argon2Params, Version};
+use password_hash::rand_core::OsRng;
+use password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use pbkdf2::{Algorithm as Pbkdf2Algorithm, Params as Pbkdf2Params, Pbkdf2};
+use scrypt::{Params as ScryptParams, Scrypt};
+
+use super::{EncryptedPassword, PasswordError, PlainPassword};
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordVerification {
+    Mismatch,
+    Match,
+    /// The password matches, but the hash uses an outdated scheme or
+    /// parameters and should be replaced with a fresh one.
+    MatchNeedsRehash,
+}
+
+impl PasswordVerification {
+    pub fn is_match(&self) -> bool {
+        !matches!(self, PasswordVerification::Mismatch)
+    }
+
+    pub fn needs_rehash(&self) -> bool {
+        matches!(self, PasswordVerification::MatchNeedsRehash)
+    }
+}
+
+/// Algorithm used to hash and verify passwords.
+pub trait PasswordHashingStrategy: Send + Sync {
+    /// Whether the hash was produced by the scheme this strategy implements.
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool;
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError>;
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError>;
+}
+
+/// Argon2 hashing, the recommended strategy.
+#[derive(Debug, Clone)]
+pub struct Argon2Strategy {
+    algorithm: Argon2Algorithm,
+    params: Argon2Params,
+}
+
+impl Argon2Strategy {
+    /// Creates an Argon2id strategy with the given memory (KiB), iteration and
+    /// parallelism costs.
+    pub fn new(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Self, PasswordError> {
+        let params = Argon2Params::new(m_cost, t_cost, p_cost, None)
+            .map_err(|e| PasswordError::InvalidParameters(e.to_string()))?;
+        Ok(Self {
+            algorithm: Argon2Algorithm::Argon2id,
+            params,
+        })
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(self.algorithm, Version::V0x13, self.params.clone())
+    }
+
+    fn is_current(&self, hash: &PasswordHash<'_>) -> bool {
+        let Ok(params) = Argon2Params::try_from(hash) else {
+            return false;
+        };
+        hash.algorithm == self.algorithm.ident()
+            && hash.version == Some(Version::V0x13.into())
+            && params.m_cost() == self.params.m_cost()
+            && params.t_cost() == self.params.t_cost()
+            && params.p_cost() == self.params.p_cost()
+    }
+}
+
+impl Default for Argon2Strategy {
+    fn default() -> Self {
+        Self {
+            algorithm: Argon2Algorithm::Argon2id,
+            params: Argon2Params::DEFAULT,
+        }
+    }
+}
+
+impl PasswordHashingStrategy for Argon2Strategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        PasswordHash::new(encrypted.as_str())
+            .map(|hash| Argon2Algorithm::try_from(hash.algorithm).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.hasher()
+            .hash_password(password.hashable()?, &salt)
+            .map(|hash| EncryptedPassword::new(hash.to_string()))
+            .map_err(|e| PasswordError::Hashing(e.to_string()))
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let hash = parse(encrypted)?;
+        verify_phc(&Argon2::default(), password, &hash, self.is_current(&hash))
+    }
+}
+
+/// scrypt hashing.
+#[derive(Debug, Clone, Copy)]
+pub struct ScryptStrategy {
+    params: ScryptParams,
+}
+
+impl ScryptStrategy {
+    /// Creates a strategy with the given CPU/memory cost (as a power of two),
+    /// block size and parallelism.
+    pub fn new(log_n: u8, r: u32, p: u32) -> Result<Self, PasswordError> {
+        let params = ScryptParams::new(log_n, r, p, ScryptParams::RECOMMENDED_LEN)
+            .map_err(|e| PasswordError::InvalidParameters(e.to_string()))?;
+        Ok(Self { params })
+    }
+
+    fn is_current(&self, hash: &PasswordHash<'_>) -> bool {
+        ScryptParams::try_from(hash)
+            .map(|params| {
+                params.log_n() == self.params.log_n()
+                    && params.r() == self.params.r()
+                    && params.p() == self.params.p()
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl Default for ScryptStrategy {
+    fn default() -> Self {
+        Self {
+            params: ScryptParams::recommended(),
+        }
+    }
+}
+
+impl PasswordHashingStrategy for ScryptStrategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        PasswordHash::new(encrypted.as_str())
+            .map(|hash| hash.algorithm == scrypt::ALG_ID)
+            .unwrap_or(false)
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Scrypt
+            .hash_password_customized(password.hashable()?, None, None, self.params, &salt)
+            .map(|hash| EncryptedPassword::new(hash.to_string()))
+            .map_err(|e| PasswordError::Hashing(e.to_string()))
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let hash = parse(encrypted)?;
+        verify_phc(&Scrypt, password, &hash, self.is_current(&hash))
+    }
+}
+
+/// PBKDF2 hashing, mainly for hashes imported from legacy systems.
+#[derive(Debug, Clone, Copy)]
+pub struct Pbkdf2Strategy {
+    algorithm: Pbkdf2Algorithm,
+    rounds: u32,
+}
+
+impl Pbkdf2Strategy {
+    /// Creates a PBKDF2-HMAC-SHA256 strategy with the given number of rounds.
+    pub fn new(rounds: u32) -> Result<Self, PasswordError> {
+        if rounds == 0 {
+            return Err(PasswordError::InvalidParameters(
+                "rounds must be positive".to_owned(),
+            ));
+        }
+        Ok(Self {
+            algorithm: Pbkdf2Algorithm::Pbkdf2Sha256,
+            rounds,
+        })
+    }
+
+    fn params(&self) -> Pbkdf2Params {
+        Pbkdf2Params {
+            rounds: self.rounds,
+            ..Pbkdf2Params::default()
+        }
+    }
+
+    fn is_current(&self, hash: &PasswordHash<'_>) -> bool {
+        hash.algorithm == self.algorithm.ident()
+            && Pbkdf2Params::try_from(hash)
+                .map(|params| params.rounds == self.rounds)
+                .unwrap_or(false)
+    }
+}
+
+impl Default for Pbkdf2Strategy {
+    fn default() -> Self {
+        Self {
+            algorithm: Pbkdf2Algorithm::Pbkdf2Sha256,
+            rounds: Pbkdf2Params::RECOMMENDED_ROUNDS as u32,
+        }
+    }
+}
+
+impl PasswordHashingStrategy for Pbkdf2Strategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        PasswordHash::new(encrypted.as_str())
+            .map(|hash| Pbkdf2Algorithm::try_from(hash.algorithm).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Pbkdf2
+            .hash_password_customized(
+                password.hashable()?,
+                Some(self.algorithm.ident()),
+                None,
+                self.params(),
+                &salt,
+            )
+            .map(|hash| EncryptedPassword::new(hash.to_string()))
+            .map_err(|e| PasswordError::Hashing(e.to_string()))
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let hash = parse(encrypted)?;
+        verify_phc(&Pbkdf2, password, &hash, self.is_current(&hash))
+    }
+}
+
+/// bcrypt hashing, mainly for hashes imported from legacy systems.
+///
+/// Imported bcrypt hashes are stored as is; their `$2?$` prefix already
+/// identifies the scheme. bcrypt only reads the first 72 bytes of a
+/// password, so longer ones are refused rather than silently truncated.
+#[derive(Debug, Clone, Copy)]
+pub struct BcryptStrategy {
+    cost: u32,
+}
+
+impl BcryptStrategy {
+    const PREFIXES: [&'static str; 4] = ["$2a$", "$2b$", "$2x$", "$2y$"];
+
+    /// Longest password, in bytes, bcrypt takes into account.
+    pub const MAX_LENGTH: usize = 72;
+
+    pub fn new(cost: u32) -> Result<Self, PasswordError> {
+        if !(4..=31).contains(&cost) {
+            return Err(PasswordError::InvalidParameters(format!(
+                "bcrypt cost must be between 4 and 31, got {}",
+                cost
+            )));
+        }
+        Ok(Self { cost })
+    }
+
+    fn is_current(&self, encrypted: &EncryptedPassword) -> bool {
+        let hash = encrypted.as_str();
+        hash.starts_with("$2b$") && hash.get(4..6) == Some(format!("{:02}", self.cost).as_str())
+    }
+}
+
+impl Default for BcryptStrategy {
+    fn default() -> Self {
+        Self {
+            cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl PasswordHashingStrategy for BcryptStrategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        Self::PREFIXES
+            .iter()
+            .any(|prefix| encrypted.as_str().starts_with(prefix))
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        bcrypt::hash(password.bytes_up_to(Self::MAX_LENGTH)?, self.cost)
+            .map(EncryptedPassword::new)
+            .map_err(|e| PasswordError::Hashing(e.to_string()))
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let password = password.bytes_up_to(Self::MAX_LENGTH)?;
+        let matches = bcrypt::verify(password, encrypted.as_str())
+            .map_err(|e| PasswordError::MalformedHash(e.to_string()))?;
+        Ok(outcome(matches, self.is_current(encrypted)))
+    }
+}
+
+/// Hashes with a preferred strategy while still verifying hashes produced by
+/// legacy ones.
+///
+/// Any match against a legacy hash is reported as needing a rehash, so
+/// credentials migrate to the preferred scheme as users sign in.
+pub struct CompositeStrategy {
+    preferred: Box<dyn PasswordHashingStrategy>,
+    legacy: Vec<Box<dyn PasswordHashingStrategy>>,
+}
+
+impl CompositeStrategy {
+    pub fn new(preferred: impl PasswordHashingStrategy + 'static) -> Self {
+        Self {
+            preferred: Box::new(preferred),
+            legacy: Vec::new(),
+        }
+    }
+
+    pub fn with_legacy(mut self, strategy: impl PasswordHashingStrategy + 'static) -> Self {
+        self.legacy.push(Box::new(strategy));
+        self
+    }
+}
+
+impl Default for CompositeStrategy {
+    fn default() -> Self {
+        Self::new(Argon2Strategy::default())
+            .with_legacy(ScryptStrategy::default())
+            .with_legacy(Pbkdf2Strategy::default())
+            .with_legacy(BcryptStrategy::default())
+    }
+}
+
+impl PasswordHashingStrategy for CompositeStrategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        self.preferred.supports(encrypted) || self.legacy.iter().any(|s| s.supports(encrypted))
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        self.preferred.hash(password)
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        if self.preferred.supports(encrypted) {
+            return self.preferred.verify(password, encrypted);
+        }
+        let legacy = self
+            .legacy
+            .iter()
+            .find(|s| s.supports(encrypted))
+            .ok_or(PasswordError::UnsupportedScheme)?;
+        Ok(match legacy.verify(password, encrypted)? {
+            PasswordVerification::Mismatch => PasswordVerification::Mismatch,
+            _ => PasswordVerification::MatchNeedsRehash,
+        })
+    }
+}
+
+fn parse(encrypted: &EncryptedPassword) -> Result<PasswordHash<'_>, PasswordError> {
+    PasswordHash::new(encrypted.as_str()).map_err(|e| PasswordError::MalformedHash(e.to_string()))
+}
+
+fn verify_phc(
+    verifier: &dyn PasswordVerifier,
+    password: &PlainPassword,
+    hash: &PasswordHash<'_>,
+    current: bool,
+) -> Result<PasswordVerification, PasswordError> {
+    match verifier.verify_password(password.hashable()?, hash) {
+        Ok(()) => Ok(outcome(true, current)),
+        Err(password_hash::Error::Password) => Ok(PasswordVerification::Mismatch),
+        Err(e) => Err(PasswordError::MalformedHash(e.to_string())),
+    }
+}
+
+fn outcome(matches: bool, current: bool) -> PasswordVerification {
+    match (matches, current) {
+        (false, _) => PasswordVerification::Mismatch,
+        (true, true) => PasswordVerification::Match,
+        (true, false) => PasswordVerification::MatchNeedsRehash,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategies() -> Vec<Box<dyn PasswordHashingStrategy>> {
+        vec![
+            Box::new(Argon2Strategy::new(8, 1, 1).unwrap()),
+            Box::new(ScryptStrategy::new(4, 8, 1).unwrap()),
+            Box::new(Pbkdf2Strategy::new(1).unwrap()),
+            Box::new(BcryptStrategy::new(4).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn every_strategy_round_trips() {
+        let password = PlainPassword::new("correct horse battery staple");
+        for strategy in strategies() {
+            let hash = strategy.hash(&password).unwrap();
+            assert!(strategy.supports(&hash), "{}", hash.as_str());
+            assert_eq!(
+                strategy.verify(&password, &hash).unwrap(),
+                PasswordVerification::Match
+            );
+            assert_eq!(
+                strategy
+                    .verify(&PlainPassword::new("wrong"), &hash)
+                    .unwrap(),
+                PasswordVerification::Mismatch
+            );
+            assert_ne!(strategy.hash(&password).unwrap(), hash, "salts differ");
+        }
+    }
+
+    #[test]
+    fn each_strategy_only_supports_its_own_hashes() {
+        let password = PlainPassword::new("hunter2");
+        let strategies = strategies();
+        for (i, hasher) in strategies.iter().enumerate() {
+            let hash = hasher.hash(&password).unwrap();
+            for (j, other) in strategies.iter().enumerate() {
+                assert_eq!(other.supports(&hash), i == j, "{}", hash.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn changed_parameters_ask_for_a_rehash() {
+        let password = PlainPassword::new("hunter2");
+        let cases: [(
+            Box<dyn PasswordHashingStrategy>,
+            Box<dyn PasswordHashingStrategy>,
+        ); 4] = [
+            (
+                Box::new(Argon2Strategy::new(8, 1, 1).unwrap()),
+                Box::new(Argon2Strategy::new(16, 1, 1).unwrap()),
+            ),
+            (
+                Box::new(ScryptStrategy::new(4, 8, 1).unwrap()),
+                Box::new(ScryptStrategy::new(5, 8, 1).unwrap()),
+            ),
+            (
+                Box::new(Pbkdf2Strategy::new(1).unwrap()),
+                Box::new(Pbkdf2Strategy::new(2).unwrap()),
+            ),
+            (
+                Box::new(BcryptStrategy::new(4).unwrap()),
+                Box::new(BcryptStrategy::new(5).unwrap()),
+            ),
+        ];
+        for (old, new) in cases {
+            let hash = old.hash(&password).unwrap();
+            assert_eq!(
+                new.verify(&password, &hash).unwrap(),
+                PasswordVerification::MatchNeedsRehash,
+                "{}",
+                hash.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn composite_migrates_legacy_hashes() {
+        let password = PlainPassword::new("hunter2");
+        let composite = CompositeStrategy::new(Argon2Strategy::new(8, 1, 1).unwrap())
+            .with_legacy(BcryptStrategy::new(4).unwrap());
+        let legacy = BcryptStrategy::new(4).unwrap().hash(&password).unwrap();
+        assert_eq!(
+            composite.verify(&password, &legacy).unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+        let fresh = composite.hash(&password).unwrap();
+        assert!(fresh.as_str().starts_with("$argon2id$"));
+        assert_eq!(
+            composite.verify(&password, &fresh).unwrap(),
+            PasswordVerification::Match
+        );
+
+        let scrypt = ScryptStrategy::new(4, 8, 1)
+            .unwrap()
+            .hash(&password)
+            .unwrap();
+        assert!(matches!(
+            composite.verify(&password, &scrypt),
+            Err(PasswordError::UnsupportedScheme)
+        ));
+    }
+
+    #[test]
+    fn oversized_passwords_are_refused() {
+        let longest = PlainPassword::new("a".repeat(PlainPassword::MAX_LENGTH));
+        let oversized = PlainPassword::new("a".repeat(PlainPassword::MAX_LENGTH + 1));
+        for strategy in &strategies()[..3] {
+            let hash = strategy.hash(&longest).unwrap();
+            assert!(strategy.verify(&longest, &hash).unwrap().is_match());
+            assert!(matches!(
+                strategy.hash(&oversized),
+                Err(PasswordError::TooLong { max: 1024 })
+            ));
+            assert!(matches!(
+                strategy.verify(&oversized, &hash),
+                Err(PasswordError::TooLong { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn bcrypt_refuses_passwords_it_would_truncate() {
+        let bcrypt = BcryptStrategy::new(4).unwrap();
+        let longest = PlainPassword::new("a".repeat(BcryptStrategy::MAX_LENGTH));
+        let hash = bcrypt.hash(&longest).unwrap();
+        assert!(bcrypt.verify(&longest, &hash).unwrap().is_match());
+
+        let longer = PlainPassword::new(format!("{}b", longest.as_str()));
+        assert!(matches!(
+            bcrypt.hash(&longer),
+            Err(PasswordError::TooLong { max: 72 })
+        ));
+        assert!(matches!(
+            bcrypt.verify(&longer, &hash),
+            Err(PasswordError::TooLong { max: 72 })
+        ));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(Pbkdf2Strategy::new(0).is_err());
+        assert!(BcryptStrategy::new(3).is_err());
+        assert!(BcryptStrategy::new(32).is_err());
+        assert!(Argon2Strategy::new(1, 1, 1).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use data_encoding::{BASE64, HEXLOWER_PERMISSIVE};
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+use super::hashing::{PasswordHashingStrategy, PasswordVerification};
+use super::{EncryptedPassword, PasswordError, PlainPassword};
+
+const SHA1_LENGTH: usize = 20;
+
+/// Where a legacy system put the salt relative to the password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaltPosition {
+    /// `sha1(salt || password)`
+    Prefix,
+    /// `sha1(password || salt)`
+    Suffix,
+}
+
+impl SaltPosition {
+    fn tag(&self) -> &'static str {
+        match self {
+            SaltPosition::Prefix => "prefix",
+            SaltPosition::Suffix => "suffix",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "prefix" => Some(SaltPosition::Prefix),
+            "suffix" => Some(SaltPosition::Suffix),
+            _ => None,
+        }
+    }
+}
+
+/// Verifies salted SHA-1 hashes imported from legacy user stores.
+///
+/// Imported hashes are tagged as `$sha1$<position>$<hex salt>$<hex digest>`.
+/// The scheme is verify-only: matches always ask for a rehash so the
+/// credential moves to the preferred strategy at first sign-in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SaltedSha1Strategy;
+
+impl SaltedSha1Strategy {
+    const PREFIX: &'static str = "$sha1$";
+
+    /// Tags a hash exported from a legacy system for storage.
+    pub fn import(
+        salt: &[u8],
+        hex_digest: &str,
+        position: SaltPosition,
+    ) -> Result<EncryptedPassword, PasswordError> {
+        let digest = HEXLOWER_PERMISSIVE
+            .decode(hex_digest.trim().as_bytes())
+            .map_err(|e| PasswordError::MalformedHash(e.to_string()))?;
+        if digest.len() != SHA1_LENGTH {
+            return Err(PasswordError::MalformedHash(format!(
+                "SHA-1 digest must be {} bytes, got {}",
+                SHA1_LENGTH,
+                digest.len()
+            )));
+        }
+        Ok(EncryptedPassword::new(format!(
+            "{}{}${}${}",
+            Self::PREFIX,
+            position.tag(),
+            hex::encode(salt),
+            hex::encode(digest)
+        )))
+    }
+
+    fn parse(
+        encrypted: &EncryptedPassword,
+    ) -> Result<(SaltPosition, Vec<u8>, Vec<u8>), PasswordError> {
+        let malformed = || PasswordError::MalformedHash("invalid salted SHA-1 hash".to_owned());
+        let mut parts = encrypted
+            .as_str()
+            .strip_prefix(Self::PREFIX)
+            .ok_or_else(malformed)?
+            .split('$');
+        let (Some(position), Some(salt), Some(digest), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(malformed());
+        };
+        let position = SaltPosition::from_tag(position).ok_or_else(malformed)?;
+        let salt = hex::decode(salt).map_err(|_| malformed())?;
+        let digest = hex::decode(digest).map_err(|_| malformed())?;
+        Ok((position, salt, digest))
+    }
+}
+
+impl PasswordHashingStrategy for SaltedSha1Strategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        encrypted.as_str().starts_with(Self::PREFIX)
+    }
+
+    fn hash(&self, _password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        Err(PasswordError::UnsupportedScheme)
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let (position, salt, digest) = Self::parse(encrypted)?;
+        let mut hasher = Sha1::new();
+        match position {
+            SaltPosition::Prefix => {
+                hasher.update(&salt);
+                hasher.update(password.hashable()?);
+            }
+            SaltPosition::Suffix => {
+                hasher.update(password.hashable()?);
+                hasher.update(&salt);
+            }
+        }
+        Ok(legacy_outcome(&hasher.finalize(), &digest))
+    }
+}
+
+/// Verifies LDAP `{SSHA}` and `{SHA}` userPassword values.
+///
+/// Values are stored as exported from the directory. The scheme is
+/// verify-only, like [`SaltedSha1Strategy`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LdapSshaStrategy;
+
+impl LdapSshaStrategy {
+    const SALTED: &'static str = "{SSHA}";
+    const UNSALTED: &'static str = "{SHA}";
+
+    fn strip_scheme(value: &str) -> Option<(&str, bool)> {
+        let scheme = value.get(..Self::SALTED.len());
+        if scheme.is_some_and(|s| s.eq_ignore_ascii_case(Self::SALTED)) {
+            return Some((&value[Self::SALTED.len()..], true));
+        }
+        let scheme = value.get(..Self::UNSALTED.len());
+        if scheme.is_some_and(|s| s.eq_ignore_ascii_case(Self::UNSALTED)) {
+            return Some((&value[Self::UNSALTED.len()..], false));
+        }
+        None
+    }
+}
+
+impl PasswordHashingStrategy for LdapSshaStrategy {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        Self::strip_scheme(encrypted.as_str()).is_some()
+    }
+
+    fn hash(&self, _password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        Err(PasswordError::UnsupportedScheme)
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let (encoded, salted) =
+            Self::strip_scheme(encrypted.as_str()).ok_or(PasswordError::UnsupportedScheme)?;
+        let decoded = BASE64
+            .decode(encoded.trim().as_bytes())
+            .map_err(|e| PasswordError::MalformedHash(e.to_string()))?;
+        if decoded.len() < SHA1_LENGTH || (!salted && decoded.len() != SHA1_LENGTH) {
+            return Err(PasswordError::MalformedHash(
+                "LDAP SHA value too short".to_owned(),
+            ));
+        }
+        let (digest, salt) = decoded.split_at(SHA1_LENGTH);
+        let mut hasher = Sha1::new();
+        hasher.update(password.hashable()?);
+        hasher.update(salt);
+        Ok(legacy_outcome(&hasher.finalize(), digest))
+    }
+}
+
+fn legacy_outcome(computed: &[u8], stored: &[u8]) -> PasswordVerification {
+    if bool::from(computed.ct_eq(stored)) {
+        PasswordVerification::MatchNeedsRehash
+    } else {
+        PasswordVerification::Mismatch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD_SHA1: &str = "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8";
+
+    fn salted_sha1(salt: &[u8], password: &str, position: SaltPosition) -> String {
+        let mut hasher = Sha1::new();
+        match position {
+            SaltPosition::Prefix => {
+                hasher.update(salt);
+                hasher.update(password.as_bytes());
+            }
+            SaltPosition::Suffix => {
+                hasher.update(password.as_bytes());
+                hasher.update(salt);
+            }
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    #[test]
+    fn imported_sha1_hashes_verify_and_ask_for_a_rehash() {
+        let strategy = SaltedSha1Strategy;
+        let password = PlainPassword::new("password");
+        let unsalted =
+            SaltedSha1Strategy::import(b"", PASSWORD_SHA1, SaltPosition::Prefix).unwrap();
+        assert!(strategy.supports(&unsalted));
+        assert_eq!(
+            strategy.verify(&password, &unsalted).unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+
+        for position in [SaltPosition::Prefix, SaltPosition::Suffix] {
+            let digest = salted_sha1(b"pepper", "password", position);
+            let imported = SaltedSha1Strategy::import(b"pepper", &digest, position).unwrap();
+            assert_eq!(
+                strategy.verify(&password, &imported).unwrap(),
+                PasswordVerification::MatchNeedsRehash
+            );
+            assert_eq!(
+                strategy
+                    .verify(&PlainPassword::new("wrong"), &imported)
+                    .unwrap(),
+                PasswordVerification::Mismatch
+            );
+        }
+    }
+
+    #[test]
+    fn salt_position_matters() {
+        let digest = salted_sha1(b"salt", "password", SaltPosition::Prefix);
+        let imported = SaltedSha1Strategy::import(b"salt", &digest, SaltPosition::Suffix).unwrap();
+        assert_eq!(
+            SaltedSha1Strategy
+                .verify(&PlainPassword::new("password"), &imported)
+                .unwrap(),
+            PasswordVerification::Mismatch
+        );
+    }
+
+    #[test]
+    fn import_rejects_bad_digests() {
+        for digest in ["not hex", "5baa61e4", &format!("{PASSWORD_SHA1}00")] {
+            assert!(matches!(
+                SaltedSha1Strategy::import(b"", digest, SaltPosition::Prefix),
+                Err(PasswordError::MalformedHash(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn legacy_schemes_do_not_hash() {
+        let password = PlainPassword::new("password");
+        assert!(matches!(
+            SaltedSha1Strategy.hash(&password),
+            Err(PasswordError::UnsupportedScheme)
+        ));
+        assert!(matches!(
+            LdapSshaStrategy.hash(&password),
+            Err(PasswordError::UnsupportedScheme)
+        ));
+    }
+
+    #[test]
+    fn ldap_sha_verifies_and_asks_for_a_rehash() {
+        let strategy = LdapSshaStrategy;
+        let value = EncryptedPassword::new("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=");
+        assert!(strategy.supports(&value));
+        assert_eq!(
+            strategy
+                .verify(&PlainPassword::new("password"), &value)
+                .unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+        assert_eq!(
+            strategy
+                .verify(&PlainPassword::new("wrong"), &value)
+                .unwrap(),
+            PasswordVerification::Mismatch
+        );
+    }
+
+    #[test]
+    fn ldap_ssha_verifies_with_the_trailing_salt() {
+        let salt = b"\x01\x02\x03\x04";
+        let mut value = hex::decode(salted_sha1(salt, "password", SaltPosition::Suffix)).unwrap();
+        value.extend_from_slice(salt);
+        let value = EncryptedPassword::new(format!("{{ssha}}{}", BASE64.encode(&value)));
+        assert!(LdapSshaStrategy.supports(&value));
+        assert_eq!(
+            LdapSshaStrategy
+                .verify(&PlainPassword::new("password"), &value)
+                .unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+    }
+
+    #[test]
+    fn ldap_rejects_malformed_values() {
+        let password = PlainPassword::new("password");
+        for value in [
+            "{SHA}not base64!",
+            "{SSHA}AAAA",
+            "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9gA",
+        ] {
+            assert!(matches!(
+                LdapSshaStrategy.verify(&password, &EncryptedPassword::new(value)),
+                Err(PasswordError::MalformedHash(_))
+            ));
+        }
+        assert!(!LdapSshaStrategy.supports(&EncryptedPassword::new("{MD5}abc")));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+mod compromised;
+mod credential;
+mod hashing;
+mod legacy;
+mod pepper;
+mod policy;
+mod strength;
+
+pub use compromised::{
+    BloomFilterChecker, CompromisedCheckError, CompromisedPasswordChecker, HibpChecker,
+};
+pub use credential::{PasswordAuthentication, PasswordChangeReason, PasswordCredential};
+pub use hashing::{
+    Argon2Strategy, BcryptStrategy, CompositeStrategy, PasswordHashingStrategy,
+    PasswordVerification, Pbkdf2Strategy, ScryptStrategy,
+};
+pub use legacy::{LdapSshaStrategy, SaltPosition, SaltedSha1Strategy};
+pub use pepper::{Pepper, PepperedStrategy};
+pub use policy::{CharacterClass, PasswordPolicy, PasswordPolicyError, PasswordPolicyViolation};
+pub use strength::StrengthReport;
+
+/// Errors raised while hashing or verifying passwords.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PasswordError {
+    #[error("invalid hashing parameters: {0}")]
+    InvalidParameters(String),
+    #[error("cannot hash password: {0}")]
+    Hashing(String),
+    #[error("malformed password hash: {0}")]
+    MalformedHash(String),
+    #[error("unsupported password hashing scheme")]
+    UnsupportedScheme,
+    #[error("unknown pepper key id: {0}")]
+    UnknownPepper(String),
+    #[error("password is longer than {max} bytes")]
+    TooLong { max: usize },
+}
+
+/// A password as typed by the user.
+///
+/// The plaintext is wiped from memory when the value is dropped and never
+/// shows up in `Debug` output. Strategies refuse to hash passwords longer
+/// than [`MAX_LENGTH`](Self::MAX_LENGTH) bytes, so that a huge input cannot
+/// tie up a slow hash.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct PlainPassword(String);
+
+impl PlainPassword {
+    /// Longest password, in bytes, that strategies will hash.
+    pub const MAX_LENGTH: usize = 1024;
+
+    pub fn new(password: impl Into<String>) -> Self {
+        Self(password.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The bytes to feed a hash, refusing passwords over `max` bytes.
+    pub(crate) fn bytes_up_to(&self, max: usize) -> Result<&[u8], PasswordError> {
+        if self.0.len() > max {
+            return Err(PasswordError::TooLong { max });
+        }
+        Ok(self.0.as_bytes())
+    }
+
+    /// The bytes to feed a hash, refusing passwords over
+    /// [`MAX_LENGTH`](Self::MAX_LENGTH) bytes.
+    pub(crate) fn hashable(&self) -> Result<&[u8], PasswordError> {
+        self.bytes_up_to(Self::MAX_LENGTH)
+    }
+
+    /// Hashes the password with the given strategy.
+    pub fn encrypt(
+        &self,
+        strategy: &dyn PasswordHashingStrategy,
+    ) -> Result<EncryptedPassword, PasswordError> {
+        strategy.hash(self)
+    }
+
+    /// Estimates how hard the password is to guess, with suggestions for
+    /// making it stronger.
+    pub fn strength_report(&self) -> StrengthReport {
+        StrengthReport::of(&self.0)
+    }
+}
+
+impl fmt::Debug for PlainPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PlainPassword(***)")
+    }
+}
+
+/// A password hash as persisted, in PHC or modular crypt format.
+///
+/// Hashes are wiped on drop and redacted in `Debug` output as well, since a
+/// leaked hash enables offline guessing.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+#[serde(transparent)]
+pub struct EncryptedPassword(String);
+
+impl EncryptedPassword {
+    pub fn new(hash: impl Into<String>) -> Self {
+        Self(hash.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Checks the password against this hash with the given strategy.
+    pub fn verify(
+        &self,
+        password: &PlainPassword,
+        strategy: &dyn PasswordHashingStrategy,
+    ) -> Result<PasswordVerification, PasswordError> {
+        strategy.verify(password, self)
+    }
+}
+
+impl fmt::Debug for EncryptedPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptedPassword(***)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zeroize::Zeroize;
+
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_in_debug_output() {
+        let password = PlainPassword::new("hunter2");
+        let hash = EncryptedPassword::new("$2b$04$secret-hash");
+        assert_eq!(format!("{password:?}"), "PlainPassword(***)");
+        assert_eq!(format!("{hash:?}"), "EncryptedPassword(***)");
+        assert!(!format!("{:?}", Some(&password)).contains("hunter2"));
+    }
+
+    #[test]
+    fn zeroize_wipes_the_material() {
+        let mut password = PlainPassword::new("hunter2");
+        password.zeroize();
+        assert!(password.as_str().is_empty());
+
+        let mut hash = EncryptedPassword::new("$2b$04$secret-hash");
+        hash.zeroize();
+        assert!(hash.as_str().is_empty());
+    }
+
+    #[test]
+    fn encrypted_password_serializes_as_the_bare_hash() {
+        let hash = EncryptedPassword::new("$2b$04$abc");
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, "\"$2b$04$abc\"");
+        assert_eq!(
+            serde_json::from_str::<EncryptedPassword>(&json).unwrap(),
+            hash
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::{
+    EncryptedPassword, PasswordError, PasswordHashingStrategy, PasswordVerification, PlainPassword,
+};
+
+const TAG: &str = "$pepper$";
+
+/// An application secret mixed into password hashes, identified by a key id.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct Pepper {
+    key_id: String,
+    secret: Vec<u8>,
+}
+
+impl Pepper {
+    /// Creates a pepper; key ids may only contain ASCII letters, digits, `-`
+    /// and `_` since they are embedded in stored hashes.
+    pub fn new(
+        key_id: impl Into<String>,
+        secret: impl Into<Vec<u8>>,
+    ) -> Result<Self, PasswordError> {
+        let key_id = key_id.into();
+        let secret = secret.into();
+        let valid_id = !key_id.is_empty()
+            && key_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_id {
+            return Err(PasswordError::InvalidParameters(format!(
+                "invalid pepper key id: {}",
+                key_id
+            )));
+        }
+        if secret.is_empty() {
+            return Err(PasswordError::InvalidParameters(
+                "pepper secret must not be empty".to_owned(),
+            ));
+        }
+        Ok(Self { key_id, secret })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn apply(&self, password: &PlainPassword) -> Result<PlainPassword, PasswordError> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(password.hashable()?);
+        let mut keyed = mac.finalize().into_bytes();
+        let password = PlainPassword::new(hex::encode(keyed));
+        keyed.zeroize();
+        Ok(password)
+    }
+}
+
+impl fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pepper")
+            .field("key_id", &self.key_id)
+            .field("secret", &"***")
+            .finish()
+    }
+}
+
+/// Decorates a strategy so passwords are keyed with a pepper before hashing.
+///
+/// Stored hashes are tagged with the pepper's key id, so retired peppers can
+/// still verify existing credentials while new hashes use the active one.
+/// Hashes made with a retired pepper, or before peppering was enabled, are
+/// reported as needing a rehash.
+pub struct PepperedStrategy<S> {
+    inner: S,
+    active: Pepper,
+    retired: HashMap<String, Pepper>,
+}
+
+impl<S: PasswordHashingStrategy> PepperedStrategy<S> {
+    pub fn new(inner: S, active: Pepper) -> Self {
+        Self {
+            inner,
+            active,
+            retired: HashMap::new(),
+        }
+    }
+
+    /// Keeps a previous pepper available for verifying existing hashes.
+    pub fn with_retired(mut self, pepper: Pepper) -> Self {
+        if pepper.key_id != self.active.key_id {
+            self.retired.insert(pepper.key_id.clone(), pepper);
+        }
+        self
+    }
+
+    fn pepper(&self, key_id: &str) -> Option<&Pepper> {
+        if key_id == self.active.key_id {
+            Some(&self.active)
+        } else {
+            self.retired.get(key_id)
+        }
+    }
+}
+
+impl<S: PasswordHashingStrategy> PasswordHashingStrategy for PepperedStrategy<S> {
+    fn supports(&self, encrypted: &EncryptedPassword) -> bool {
+        match untag(encrypted) {
+            Some((_, inner)) => self.inner.supports(&inner),
+            None => self.inner.supports(encrypted),
+        }
+    }
+
+    fn hash(&self, password: &PlainPassword) -> Result<EncryptedPassword, PasswordError> {
+        let inner = self.inner.hash(&self.active.apply(password)?)?;
+        Ok(EncryptedPassword::new(format!(
+            "{}{}{}",
+            TAG,
+            self.active.key_id,
+            inner.as_str()
+        )))
+    }
+
+    fn verify(
+        &self,
+        password: &PlainPassword,
+        encrypted: &EncryptedPassword,
+    ) -> Result<PasswordVerification, PasswordError> {
+        let Some((key_id, inner)) = untag(encrypted) else {
+            return Ok(match self.inner.verify(password, encrypted)? {
+                PasswordVerification::Mismatch => PasswordVerification::Mismatch,
+                _ => PasswordVerification::MatchNeedsRehash,
+            });
+        };
+        let pepper = self
+            .pepper(key_id)
+            .ok_or_else(|| PasswordError::UnknownPepper(key_id.to_owned()))?;
+        Ok(match self.inner.verify(&pepper.apply(password)?, &inner)? {
+            PasswordVerification::Match if key_id == self.active.key_id => {
+                PasswordVerification::Match
+            }
+            PasswordVerification::Mismatch => PasswordVerification::Mismatch,
+            _ => PasswordVerification::MatchNeedsRehash,
+        })
+    }
+}
+
+/// Splits a tagged hash into its pepper key id and the inner hash.
+fn untag(encrypted: &EncryptedPassword) -> Option<(&str, EncryptedPassword)> {
+    let rest = encrypted.as_str().strip_prefix(TAG)?;
+    let split = rest.find('$')?;
+    let (key_id, inner) = rest.split_at(split);
+    Some((key_id, EncryptedPassword::new(inner)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::password::Pbkdf2Strategy;
+
+    fn pepper(key_id: &str) -> Pepper {
+        Pepper::new(key_id, format!("secret-{key_id}")).unwrap()
+    }
+
+    fn strategy(active: &str) -> PepperedStrategy<Pbkdf2Strategy> {
+        PepperedStrategy::new(Pbkdf2Strategy::new(1).unwrap(), pepper(active))
+    }
+
+    #[test]
+    fn peppered_hash_round_trips() {
+        let password = PlainPassword::new("hunter2");
+        let strategy = strategy("k1");
+        let hash = strategy.hash(&password).unwrap();
+        assert!(hash.as_str().starts_with("$pepper$k1$pbkdf2-sha256$"));
+        assert!(strategy.supports(&hash));
+        assert_eq!(
+            strategy.verify(&password, &hash).unwrap(),
+            PasswordVerification::Match
+        );
+        assert_eq!(
+            strategy
+                .verify(&PlainPassword::new("hunter3"), &hash)
+                .unwrap(),
+            PasswordVerification::Mismatch
+        );
+    }
+
+    #[test]
+    fn hash_is_useless_without_the_pepper() {
+        let password = PlainPassword::new("hunter2");
+        let hash = strategy("k1").hash(&password).unwrap();
+        let (_, inner) = untag(&hash).unwrap();
+        let unpeppered = Pbkdf2Strategy::new(1).unwrap();
+        assert_eq!(
+            unpeppered.verify(&password, &inner).unwrap(),
+            PasswordVerification::Mismatch
+        );
+    }
+
+    #[test]
+    fn retired_and_missing_peppers_ask_for_a_rehash() {
+        let password = PlainPassword::new("hunter2");
+        let old = strategy("k1").hash(&password).unwrap();
+        let rotated = strategy("k2").with_retired(pepper("k1"));
+        assert_eq!(
+            rotated.verify(&password, &old).unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+
+        let plain = Pbkdf2Strategy::new(1).unwrap().hash(&password).unwrap();
+        assert_eq!(
+            rotated.verify(&password, &plain).unwrap(),
+            PasswordVerification::MatchNeedsRehash
+        );
+
+        assert!(matches!(
+            strategy("k2").verify(&password, &old),
+            Err(PasswordError::UnknownPepper(id)) if id == "k1"
+        ));
+    }
+
+    #[test]
+    fn invalid_peppers_are_rejected() {
+        assert!(Pepper::new("", "secret").is_err());
+        assert!(Pepper::new("k$1", "secret").is_err());
+        assert!(Pepper::new("k1", Vec::new()).is_err());
+        assert!(!format!("{:?}", pepper("k1")).contains("secret-k1"));
+    }
+
+    #[test]
+    fn oversized_password_is_refused_before_peppering() {
+        let oversized = PlainPassword::new("a".repeat(PlainPassword::MAX_LENGTH + 1));
+        assert!(matches!(
+            strategy("k1").hash(&oversized),
+            Err(PasswordError::TooLong { .. })
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{PlainPassword, StrengthReport};
+use crate::common::clock::Clock;
+
+/// Shortest personal identifier considered when looking for it in passwords.
+const MIN_PERSONAL_FRAGMENT_LENGTH: usize = 3;
+
+/// A class of characters a policy may require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            CharacterClass::Lowercase => c.is_lowercase(),
+            CharacterClass::Uppercase => c.is_uppercase(),
+            CharacterClass::Digit => c.is_numeric(),
+            CharacterClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+}
+
+impl fmt::Display for CharacterClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CharacterClass::Lowercase => f.write_str("lowercase letter"),
+            CharacterClass::Uppercase => f.write_str("uppercase letter"),
+            CharacterClass::Digit => f.write_str("digit"),
+            CharacterClass::Symbol => f.write_str("symbol"),
+        }
+    }
+}
+
+/// A single rule of a policy that a password breaks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "rule")]
+#[non_exhaustive]
+pub enum PasswordPolicyViolation {
+    TooShort { min_length: usize },
+    MissingCharacterClass { class: CharacterClass },
+    ContainsPersonalInformation,
+    TooWeak { min_score: u8, score: u8 },
+}
+
+impl fmt::Display for PasswordPolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordPolicyViolation::TooShort { min_length } => {
+                write!(f, "must be at least {} characters long", min_length)
+            }
+            PasswordPolicyViolation::MissingCharacterClass { class } => {
+                write!(f, "must contain at least one {}", class)
+            }
+            PasswordPolicyViolation::ContainsPersonalInformation => {
+                f.write_str("must not contain the username or email address")
+            }
+            PasswordPolicyViolation::TooWeak { min_score, score } => {
+                write!(
+                    f,
+                    "is too easy to guess (strength {} of {}, {} required)",
+                    score,
+                    StrengthReport::MAX_SCORE,
+                    min_score
+                )
+            }
+        }
+    }
+}
+
+/// Every rule of a policy that a password breaks.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("password {}", .0.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "))]
+pub struct PasswordPolicyError(Vec<PasswordPolicyViolation>);
+
+impl PasswordPolicyError {
+    pub fn violations(&self) -> &[PasswordPolicyViolation] {
+        &self.0
+    }
+}
+
+/// Rules a tenant imposes on the passwords of its users.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    required_classes: BTreeSet<CharacterClass>,
+    deny_personal_information: bool,
+    #[serde(default)]
+    max_age_days: Option<u32>,
+    #[serde(default)]
+    min_strength: Option<u8>,
+}
+
+impl PasswordPolicy {
+    pub const DEFAULT_MIN_LENGTH: usize = 8;
+
+    pub fn new(min_length: usize) -> Self {
+        Self {
+            min_length,
+            required_classes: BTreeSet::new(),
+            deny_personal_information: true,
+            max_age_days: None,
+            min_strength: None,
+        }
+    }
+
+    /// Requires at least one character of the given class.
+    pub fn require(mut self, class: CharacterClass) -> Self {
+        self.required_classes.insert(class);
+        self
+    }
+
+    /// Whether passwords may not contain the username or email address.
+    pub fn deny_personal_information(mut self, deny: bool) -> Self {
+        self.deny_personal_information = deny;
+        self
+    }
+
+    /// Makes passwords expire the given number of days after they were set.
+    pub fn expire_after_days(mut self, days: u32) -> Self {
+        self.max_age_days = Some(days);
+        self
+    }
+
+    /// Requires a strength score of at least `score`, from 0 to 4.
+    pub fn require_strength(mut self, score: u8) -> Self {
+        self.min_strength = Some(score.min(StrengthReport::MAX_SCORE));
+        self
+    }
+
+    pub fn min_length(&self) -> usize {
+        self.min_length
+    }
+
+    pub fn required_classes(&self) -> impl Iterator<Item = CharacterClass> + '_ {
+        self.required_classes.iter().copied()
+    }
+
+    pub fn min_strength(&self) -> Option<u8> {
+        self.min_strength
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_days
+            .map(|days| Duration::days(i64::from(days)))
+    }
+
+    /// Whether a password set at `changed_at` is too old to be used.
+    pub fn is_expired(&self, changed_at: DateTime<Utc>, clock: &impl Clock) -> bool {
+        self.max_age()
+            .is_some_and(|max_age| clock.now() - changed_at >= max_age)
+    }
+
+    /// Checks the password, reporting every rule it breaks.
+    ///
+    /// `personal_information` holds identifiers such as the username and email
+    /// address; for email addresses the local part is checked as well.
+    pub fn check(
+        &self,
+        password: &PlainPassword,
+        personal_information: &[&str],
+    ) -> Result<(), PasswordPolicyError> {
+        let strength = self
+            .min_strength
+            .map(|min_score| (min_score, password.strength_report().score()));
+        let password = password.as_str();
+        let mut violations = Vec::new();
+        if password.chars().count() < self.min_length {
+            violations.push(PasswordPolicyViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        for class in &self.required_classes {
+            if !password.chars().any(|c| class.contains(c)) {
+                violations.push(PasswordPolicyViolation::MissingCharacterClass { class: *class });
+            }
+        }
+        if self.deny_personal_information && contains_any(password, personal_information) {
+            violations.push(PasswordPolicyViolation::ContainsPersonalInformation);
+        }
+        if let Some((min_score, score)) = strength.filter(|(min, score)| score < min) {
+            violations.push(PasswordPolicyViolation::TooWeak { min_score, score });
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordPolicyError(violations))
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MIN_LENGTH)
+    }
+}
+
+fn contains_any(password: &str, personal_information: &[&str]) -> bool {
+    let password = password.to_lowercase();
+    personal_information
+        .iter()
+        .flat_map(|value| {
+            let local_part = value.split_once('@').map(|(local, _)| local);
+            std::iter::once(*value).chain(local_part)
+        })
+        .map(|fragment| fragment.trim().to_lowercase())
+        .filter(|fragment| fragment.chars().count() >= MIN_PERSONAL_FRAGMENT_LENGTH)
+        .any(|fragment| password.contains(&fragment))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn violations(policy: &PasswordPolicy, password: &str) -> Vec<PasswordPolicyViolation> {
+        policy
+            .check(
+                &PlainPassword::new(password),
+                &["ada", "ada.lovelace@example.com"],
+            )
+            .err()
+            .map(|error| error.violations().to_vec())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn default_policy_only_checks_length_and_personal_information() {
+        let policy = PasswordPolicy::default();
+        assert!(violations(&policy, "plain words here").is_empty());
+        assert_eq!(
+            violations(&policy, "short"),
+            [PasswordPolicyViolation::TooShort { min_length: 8 }]
+        );
+        assert_eq!(
+            violations(&policy, "i am ADA really"),
+            [PasswordPolicyViolation::ContainsPersonalInformation]
+        );
+        assert_eq!(
+            violations(&policy, "xx-lovelace-xx"),
+            [],
+            "fragments of the local part other than the whole are allowed"
+        );
+        assert_eq!(
+            violations(&policy, "ada.lovelace!!"),
+            [PasswordPolicyViolation::ContainsPersonalInformation]
+        );
+    }
+
+    #[test]
+    fn every_broken_rule_is_reported() {
+        let policy = PasswordPolicy::new(12)
+            .require(CharacterClass::Uppercase)
+            .require(CharacterClass::Digit)
+            .require(CharacterClass::Symbol)
+            .require_strength(3);
+        assert_eq!(
+            violations(&policy, "password"),
+            [
+                PasswordPolicyViolation::TooShort { min_length: 12 },
+                PasswordPolicyViolation::MissingCharacterClass {
+                    class: CharacterClass::Uppercase
+                },
+                PasswordPolicyViolation::MissingCharacterClass {
+                    class: CharacterClass::Digit
+                },
+                PasswordPolicyViolation::MissingCharacterClass {
+                    class: CharacterClass::Symbol
+                },
+                PasswordPolicyViolation::TooWeak {
+                    min_score: 3,
+                    score: 0
+                },
+            ]
+        );
+        assert!(violations(&policy, "Tr0ub4dour&3x").is_empty());
+
+        let error = policy
+            .check(&PlainPassword::new("Password1!"), &[])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "password must be at least 12 characters long, \
+             is too easy to guess (strength 0 of 4, 3 required)"
+        );
+    }
+
+    #[test]
+    fn personal_information_check_can_be_disabled() {
+        let policy = PasswordPolicy::default().deny_personal_information(false);
+        assert!(violations(&policy, "i am ADA really").is_empty());
+    }
+
+    #[test]
+    fn strength_requirement_is_capped_at_the_maximum_score() {
+        let policy = PasswordPolicy::default().require_strength(9);
+        assert_eq!(policy.min_strength(), Some(StrengthReport::MAX_SCORE));
+    }
+
+    #[test]
+    fn passwords_expire_after_the_maximum_age() {
+        let changed_at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let clock = FixedClock::new(changed_at);
+        let policy = PasswordPolicy::default().expire_after_days(90);
+        assert_eq!(policy.max_age(), Some(Duration::days(90)));
+
+        clock.advance(Duration::days(90) - Duration::seconds(1));
+        assert!(!policy.is_expired(changed_at, &clock));
+        clock.advance(Duration::seconds(1));
+        assert!(policy.is_expired(changed_at, &clock));
+
+        assert!(!PasswordPolicy::default().is_expired(changed_at, &clock));
+    }
+}
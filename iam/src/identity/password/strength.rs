@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+/// Longest prefix of a password that is analysed; characters beyond it are
+/// counted as brute force.
+const MAX_ANALYSED_LENGTH: usize = 64;
+
+/// Guesses per character for parts of a password matching no pattern.
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+
+/// Guess count thresholds (log10) separating the scores 0 to 4.
+const SCORE_THRESHOLDS: [f64; 4] = [3.0, 6.0, 8.0, 10.0];
+
+/// Common passwords and words, most frequent first.
+#[rustfmt::skip]
+const DICTIONARY: &[&str] = &[
+    "password", "123456", "12345678", "qwerty", "123456789", "12345", "1234", "111111", "1234567",
+    "dragon", "123123", "baseball", "abc123", "football", "monkey", "letmein", "696969", "shadow",
+    "master", "666666", "qwertyuiop", "123321", "mustang", "1234567890", "michael", "654321",
+    "pussy", "superman", "1qaz2wsx", "7777777", "fuckyou", "121212", "000000", "qazwsx", "123qwe",
+    "killer", "trustno1", "jordan", "jennifer", "zxcvbnm", "asdfgh", "hunter", "buster", "soccer",
+    "harley", "batman", "andrew", "tigger", "sunshine", "iloveyou", "fuckme", "2000", "charlie",
+    "robert", "thomas", "hockey", "ranger", "daniel", "starwars", "klaster", "112233", "george",
+    "asshole", "computer", "michelle", "jessica", "pepper", "1111", "zxcvbn", "555555", "11111111",
+    "131313", "freedom", "777777", "pass", "fuck", "maggie", "159753", "aaaaaa", "ginger",
+    "princess", "joshua", "cheese", "amanda", "summer", "love", "ashley", "6969", "nicole",
+    "chelsea", "biteme", "matthew", "access", "yankees", "987654321", "dallas", "austin", "thunder",
+    "taylor", "matrix", "welcome", "admin", "login", "passw0rd", "hello", "secret", "changeme",
+    "qwerty123", "dragon1", "monkey1", "football1", "baseball1", "letmein1", "winter", "spring",
+    "autumn", "flower", "orange", "banana", "apple", "purple", "yellow", "silver", "golden",
+    "diamond", "angel", "lovely", "family", "friend", "friends", "school", "summer1", "money",
+    "internet", "service", "server", "company", "office", "house", "world", "dream", "heart",
+    "happy", "music", "people", "water", "light", "night", "green", "black", "white", "little",
+    "super", "magic", "blue", "red", "cat", "dog", "god", "sex", "star", "king", "queen", "boss",
+    "test", "guest", "user", "root", "default", "system", "database", "oracle", "manager",
+    "support", "temp", "demo",
+];
+
+/// Keyboard rows of the US QWERTY layout, unshifted; each row is offset half
+/// a key to the right of the one above.
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"];
+const SHIFTED_ROWS: [&str; 4] = ["!@#$%^&*()_+", "QWERTYUIOP{}", "ASDFGHJKL:\"", "ZXCVBNM<>?"];
+
+/// Substitutions commonly used to disguise dictionary words.
+const LEET: [(char, char); 9] = [
+    ('4', 'a'),
+    ('@', 'a'),
+    ('3', 'e'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+];
+
+/// How hard a password is to guess, with advice for improving it.
+///
+/// Scores range from 0 (trivially guessable) to 4 (very unguessable), in the
+/// style of zxcvbn: the password is split into the cheapest sequence of
+/// dictionary words, keyboard walks, repeats, sequences, years and brute
+/// forced characters, and the score follows from the estimated guess count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrengthReport {
+    score: u8,
+    guesses_log10: f64,
+    suggestions: Vec<String>,
+}
+
+impl StrengthReport {
+    pub const MAX_SCORE: u8 = 4;
+
+    pub(crate) fn of(password: &str) -> Self {
+        let chars: Vec<char> = password.chars().collect();
+        let analysed = &chars[..chars.len().min(MAX_ANALYSED_LENGTH)];
+        let (mut guesses_log10, patterns) = most_guessable(analysed);
+        guesses_log10 += (chars.len() - analysed.len()) as f64 * BRUTEFORCE_CARDINALITY.log10();
+        let score = SCORE_THRESHOLDS
+            .iter()
+            .take_while(|threshold| guesses_log10 >= **threshold)
+            .count() as u8;
+        Self {
+            score,
+            guesses_log10,
+            suggestions: suggestions(score, &patterns, chars.is_empty()),
+        }
+    }
+
+    pub fn score(&self) -> u8 {
+        self.score
+    }
+
+    /// Base-10 logarithm of the estimated number of guesses.
+    pub fn guesses_log10(&self) -> f64 {
+        self.guesses_log10
+    }
+
+    /// Human-readable advice, empty for strong passwords.
+    pub fn suggestions(&self) -> &[String] {
+        &self.suggestions
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pattern {
+    Dictionary {
+        rank: usize,
+        capitalized: bool,
+        leet: bool,
+    },
+    Keyboard,
+    Repeat,
+    Sequence,
+    Year,
+    BruteForce,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    pattern: Pattern,
+    guesses_log10: f64,
+}
+
+fn dictionary() -> &'static HashMap<&'static str, usize> {
+    static RANKS: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    RANKS.get_or_init(|| {
+        let mut ranks = HashMap::new();
+        for (index, word) in DICTIONARY.iter().enumerate() {
+            ranks.entry(*word).or_insert(index + 1);
+        }
+        ranks
+    })
+}
+
+/// Finds the sequence of matches covering the password with the fewest
+/// guesses, returning its guess count and the patterns involved.
+fn most_guessable(chars: &[char]) -> (f64, Vec<Pattern>) {
+    let n = chars.len();
+    if n == 0 {
+        return (0.0, Vec::new());
+    }
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n + 1];
+    for found in find_matches(chars) {
+        by_end[found.end].push(found);
+    }
+    // best[k][i]: cheapest cover of the first i characters with k matches,
+    // remembering the last match; consecutive brute force runs are merged.
+    let mut best: Vec<Vec<Option<(f64, Match)>>> = vec![vec![None; n + 1]; n + 1];
+    for end in 1..=n {
+        let bruteforce = (0..end).map(|start| Match {
+            start,
+            end,
+            pattern: Pattern::BruteForce,
+            guesses_log10: (end - start) as f64 * BRUTEFORCE_CARDINALITY.log10(),
+        });
+        for candidate in by_end[end].iter().copied().chain(bruteforce) {
+            if candidate.start == 0 {
+                relax(&mut best[1][end], 0.0, candidate);
+                continue;
+            }
+            for k in 1..n {
+                let Some((cost, previous)) = best[k][candidate.start] else {
+                    continue;
+                };
+                if candidate.pattern == Pattern::BruteForce
+                    && previous.pattern == Pattern::BruteForce
+                {
+                    continue;
+                }
+                relax(&mut best[k + 1][end], cost, candidate);
+            }
+        }
+    }
+    let (k, total) = (1..=n)
+        .filter_map(|k| best[k][n].map(|(cost, _)| (k, cost + log10_factorial(k))))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .expect("brute force always covers the password");
+    let mut patterns = Vec::with_capacity(k);
+    let (mut k, mut end) = (k, n);
+    while k > 0 {
+        let (_, last) = best[k][end].expect("back pointer to a reached state");
+        patterns.push(last.pattern);
+        end = last.start;
+        k -= 1;
+    }
+    patterns.reverse();
+    (total, patterns)
+}
+
+fn relax(slot: &mut Option<(f64, Match)>, cost: f64, candidate: Match) {
+    let cost = cost + candidate.guesses_log10;
+    if slot.is_none_or(|(current, _)| cost < current) {
+        *slot = Some((cost, candidate));
+    }
+}
+
+fn log10_factorial(k: usize) -> f64 {
+    (2..=k).map(|i| (i as f64).log10()).sum()
+}
+
+fn find_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    dictionary_matches(chars, &mut matches);
+    keyboard_matches(chars, &mut matches);
+    repeat_matches(chars, &mut matches);
+    sequence_matches(chars, &mut matches);
+    year_matches(chars, &mut matches);
+    matches
+}
+
+fn dictionary_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    if lower.len() != chars.len() {
+        return;
+    }
+    let unleeted: Vec<char> = lower
+        .iter()
+        .map(|c| {
+            LEET.iter()
+                .find(|(from, _)| from == c)
+                .map_or(*c, |(_, to)| *to)
+        })
+        .collect();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len() {
+            let plain: String = lower[start..end].iter().collect();
+            let (rank, leet) = match dictionary().get(plain.as_str()) {
+                Some(rank) => (*rank, false),
+                None => {
+                    let unleeted: String = unleeted[start..end].iter().collect();
+                    match dictionary().get(unleeted.as_str()) {
+                        Some(rank) => (*rank, true),
+                        None => continue,
+                    }
+                }
+            };
+            let capitalized = chars[start..end].iter().any(|c| c.is_uppercase());
+            let mut guesses = rank as f64;
+            if capitalized {
+                guesses *= uppercase_variations(&chars[start..end]);
+            }
+            if leet {
+                guesses *= 2.0;
+            }
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Dictionary {
+                    rank,
+                    capitalized,
+                    leet,
+                },
+                guesses_log10: guesses.log10(),
+            });
+        }
+    }
+}
+
+/// Extra guesses for the capitalization of a dictionary word: first letter
+/// or all caps are tried early, arbitrary mixes are not.
+fn uppercase_variations(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let first_only = upper == 1 && word[0].is_uppercase();
+    if first_only || upper == word.len() {
+        2.0
+    } else {
+        2f64.powi(upper.min(word.len() - upper).max(1) as i32 + 1)
+    }
+}
+
+fn key_position(c: char) -> Option<(usize, usize)> {
+    KEYBOARD_ROWS
+        .iter()
+        .chain(SHIFTED_ROWS.iter())
+        .enumerate()
+        .find_map(|(row, keys)| keys.chars().position(|k| k == c).map(|col| (row % 4, col)))
+}
+
+/// Direction from one key to an adjacent one, if they are adjacent.
+fn key_step(from: (usize, usize), to: (usize, usize)) -> Option<(i8, i8)> {
+    let row = to.0 as i64 - from.0 as i64;
+    let col = to.1 as i64 - from.1 as i64;
+    let adjacent = match row {
+        0 => col.abs() == 1,
+        1 => col == 0 || col == -1,
+        -1 => col == 0 || col == 1,
+        _ => false,
+    };
+    adjacent.then_some((row as i8, col as i8))
+}
+
+fn keyboard_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut direction = None;
+        while end < chars.len() {
+            let step = key_position(chars[end - 1])
+                .zip(key_position(chars[end]))
+                .and_then(|(from, to)| key_step(from, to));
+            let Some(step) = step else { break };
+            if direction.is_some_and(|d| d != step) {
+                turns += 1;
+            }
+            direction = Some(step);
+            end += 1;
+        }
+        if end - start >= 3 {
+            let guesses = 47.0 * (end - start) as f64 * 4f64.powi(turns);
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Keyboard,
+                guesses_log10: guesses.log10(),
+            });
+        }
+        start = end.max(start + 1);
+    }
+}
+
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        for block in 1..=4 {
+            let mut end = start + block;
+            while end + block <= chars.len()
+                && chars[end..end + block] == chars[start..start + block]
+            {
+                end += block;
+            }
+            let repeats = (end - start) / block;
+            if repeats >= 2 && end - start >= 3 {
+                let guesses = BRUTEFORCE_CARDINALITY.powi(block as i32) * repeats as f64;
+                matches.push(Match {
+                    start,
+                    end,
+                    pattern: Pattern::Repeat,
+                    guesses_log10: guesses.log10(),
+                });
+            }
+        }
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let same_kind = |a: char, b: char| {
+            (a.is_ascii_digit() && b.is_ascii_digit())
+                || (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+                || (a.is_ascii_uppercase() && b.is_ascii_uppercase())
+        };
+        let mut end = start + 1;
+        while end < chars.len()
+            && delta.abs() == 1
+            && chars[end] as i64 - chars[end - 1] as i64 == delta
+            && same_kind(chars[end - 1], chars[end])
+        {
+            end += 1;
+        }
+        if end - start >= 3 {
+            let first = chars[start];
+            let base = if matches!(first, 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9') {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if delta < 0 { 2.0 } else { 1.0 };
+            let guesses = base * (end - start) as f64 * direction;
+            matches.push(Match {
+                start,
+                end,
+                pattern: Pattern::Sequence,
+                guesses_log10: guesses.log10(),
+            });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn year_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len().saturating_sub(3) {
+        let candidate: String = chars[start..start + 4].iter().collect();
+        if candidate
+            .parse::<u32>()
+            .is_ok_and(|year| (1900..=2099).contains(&year))
+        {
+            matches.push(Match {
+                start,
+                end: start + 4,
+                pattern: Pattern::Year,
+                guesses_log10: 2.0,
+            });
+        }
+    }
+}
+
+fn suggestions(score: u8, patterns: &[Pattern], empty: bool) -> Vec<String> {
+    let mut suggestions: Vec<String> = Vec::new();
+    if empty {
+        suggestions.push("Use a few words, avoid common phrases.".to_owned());
+        suggestions.push("No need for symbols, digits, or uppercase letters.".to_owned());
+        return suggestions;
+    }
+    if score > 2 {
+        return suggestions;
+    }
+    let mut push = |suggestion: &str| {
+        if !suggestions.iter().any(|s| s == suggestion) {
+            suggestions.push(suggestion.to_owned());
+        }
+    };
+    for pattern in patterns {
+        match pattern {
+            Pattern::Dictionary {
+                rank,
+                capitalized,
+                leet,
+            } => {
+                if *rank <= 10 {
+                    push("This is a top-10 common password.");
+                } else if *rank <= 100 {
+                    push("This is a very common password.");
+                } else {
+                    push("Avoid common words and names.");
+                }
+                if *capitalized {
+                    push("Capitalization doesn't help very much.");
+                }
+                if *leet {
+                    push("Predictable substitutions like '@' instead of 'a' don't help very much.");
+                }
+            }
+            Pattern::Keyboard => push("Avoid keyboard patterns like qwerty or asdf."),
+            Pattern::Repeat => push("Avoid repeated words and characters."),
+            Pattern::Sequence => push("Avoid sequences like abc or 6543."),
+            Pattern::Year => push("Avoid years that are associated with you."),
+            Pattern::BruteForce => {}
+        }
+    }
+    push("Add another word or two. Uncommon words are better.");
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggests(password: &str, advice: &str) -> bool {
+        StrengthReport::of(password)
+            .suggestions()
+            .iter()
+            .any(|s| s.contains(advice))
+    }
+
+    #[test]
+    fn common_passwords_score_zero() {
+        for password in ["", "password", "123456", "qwerty", "letmein"] {
+            assert_eq!(StrengthReport::of(password).score(), 0, "{password:?}");
+        }
+        assert!(suggests("password", "top-10 common password"));
+        assert!(suggests("", "Use a few words"));
+    }
+
+    #[test]
+    fn disguised_dictionary_words_stay_weak() {
+        let plain = StrengthReport::of("password");
+        let disguised = StrengthReport::of("P@ssw0rd");
+        assert_eq!(disguised.score(), 0);
+        assert!(disguised.guesses_log10() > plain.guesses_log10());
+        assert!(suggests("Password", "Capitalization doesn't help"));
+        assert!(suggests("P@ssw0rd", "Predictable substitutions"));
+    }
+
+    #[test]
+    fn patterns_are_recognized() {
+        assert!(suggests("ghjkl;", "keyboard patterns"));
+        assert!(suggests("aaaaaaaaaa", "repeated words and characters"));
+        assert!(suggests("abcdefgh", "sequences"));
+        assert!(suggests("98765", "sequences"));
+        assert!(suggests("ada1987", "years"));
+        for password in ["ghjkl;", "aaaaaaaaaa", "abcdefgh"] {
+            assert_eq!(StrengthReport::of(password).score(), 0, "{password:?}");
+        }
+    }
+
+    #[test]
+    fn passphrases_and_random_strings_score_four() {
+        for password in ["correct horse battery staple", "kj8#Qm2!xZ9v"] {
+            let report = StrengthReport::of(password);
+            assert_eq!(report.score(), StrengthReport::MAX_SCORE, "{password:?}");
+            assert!(report.suggestions().is_empty());
+        }
+    }
+
+    #[test]
+    fn characters_beyond_the_analysed_prefix_count_as_brute_force() {
+        let analysed = StrengthReport::of(&"a".repeat(MAX_ANALYSED_LENGTH));
+        let longer = StrengthReport::of(&"a".repeat(MAX_ANALYSED_LENGTH + 6));
+        let extra = 6.0 * BRUTEFORCE_CARDINALITY.log10();
+        assert!((longer.guesses_log10() - analysed.guesses_log10() - extra).abs() < 1e-9);
+        assert!(longer.score() > analysed.score());
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use crate::common::clock::Clock;
+
+/// Errors raised while setting up or using a recovery channel.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RecoveryError {
+    #[error("no recovery channel configured for {0}")]
+    NotConfigured(String),
+    #[error("recovery channel of {0} is not verified")]
+    NotVerified(String),
+    #[error("recovery channel {0} is not enabled")]
+    ChannelDisabled(RecoveryChannelKind),
+    #[error("a code was sent recently, retry in {} seconds", retry_after.num_seconds())]
+    Throttled { retry_after: Duration },
+    #[error("message delivery failed: {0}")]
+    Delivery(String),
+    #[error("recovery channel storage failure: {0}")]
+    Storage(String),
+}
+
+/// The kinds of secondary recovery channel a deployment can offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryChannelKind {
+    Email,
+    Sms,
+}
+
+impl fmt::Display for RecoveryChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RecoveryChannelKind::Email => "email",
+            RecoveryChannelKind::Sms => "sms",
+        })
+    }
+}
+
+/// Where recovery codes are sent when the primary email is inaccessible.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "address", rename_all = "snake_case")]
+pub enum RecoveryDestination {
+    Email(String),
+    /// Phone number in E.164 format.
+    Sms(String),
+}
+
+impl RecoveryDestination {
+    pub fn kind(&self) -> RecoveryChannelKind {
+        match self {
+            RecoveryDestination::Email(_) => RecoveryChannelKind::Email,
+            RecoveryDestination::Sms(_) => RecoveryChannelKind::Sms,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        match self {
+            RecoveryDestination::Email(address) | RecoveryDestination::Sms(address) => address,
+        }
+    }
+}
+
+/// Delivers messages through email, SMS or any other transport.
+pub trait MessageSender: Send + Sync {
+    fn send(
+        &self,
+        destination: &RecoveryDestination,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), RecoveryError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PendingCode {
+    hash: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// A user's secondary recovery channel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryChannel {
+    tenant_id: String,
+    username: String,
+    destination: RecoveryDestination,
+    verified_at: Option<DateTime<Utc>>,
+    pending: Option<PendingCode>,
+    #[serde(default)]
+    last_sent_at: Option<DateTime<Utc>>,
+    /// Wrong codes entered since `failures_since`, across resends.
+    #[serde(default)]
+    failed_attempts: u32,
+    #[serde(default)]
+    failures_since: Option<DateTime<Utc>>,
+}
+
+impl RecoveryChannel {
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn destination(&self) -> &RecoveryDestination {
+        &self.destination
+    }
+
+    pub fn verified_at(&self) -> Option<DateTime<Utc>> {
+        self.verified_at
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+}
+
+/// Stores users' recovery channels.
+pub trait RecoveryChannelRepository: Send + Sync {
+    fn channel_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<RecoveryChannel>, RecoveryError>;
+
+    /// Inserts or replaces the channel of its user.
+    fn save(&self, channel: &RecoveryChannel) -> Result<(), RecoveryError>;
+
+    fn remove(&self, tenant_id: &str, username: &str) -> Result<(), RecoveryError>;
+}
+
+/// Repository keeping recovery channels in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryRecoveryChannelRepository {
+    channels: RwLock<HashMap<(String, String), RecoveryChannel>>,
+}
+
+impl InMemoryRecoveryChannelRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RecoveryChannelRepository for InMemoryRecoveryChannelRepository {
+    fn channel_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<RecoveryChannel>, RecoveryError> {
+        let channels = self
+            .channels
+            .read()
+            .expect("recovery channels lock poisoned");
+        Ok(channels
+            .get(&(tenant_id.to_owned(), username.to_owned()))
+            .cloned())
+    }
+
+    fn save(&self, channel: &RecoveryChannel) -> Result<(), RecoveryError> {
+        let mut channels = self
+            .channels
+            .write()
+            .expect("recovery channels lock poisoned");
+        channels.insert(
+            (channel.tenant_id.clone(), channel.username.clone()),
+            channel.clone(),
+        );
+        Ok(())
+    }
+
+    fn remove(&self, tenant_id: &str, username: &str) -> Result<(), RecoveryError> {
+        let mut channels = self
+            .channels
+            .write()
+            .expect("recovery channels lock poisoned");
+        channels.remove(&(tenant_id.to_owned(), username.to_owned()));
+        Ok(())
+    }
+}
+
+/// Sets up, verifies and uses secondary recovery channels.
+///
+/// A destination only becomes usable for recovery once the user has entered
+/// the code sent to it at setup time. Codes are six digits, single use and
+/// short lived. Wrong guesses are counted per channel rather than per code,
+/// so requesting a new code does not grant new guesses: once `max_attempts`
+/// is reached, codes are refused until `code_ttl` has passed since the
+/// first failure. Codes can be sent at most once per `resend_interval`.
+pub struct RecoveryChannelService<R, S, C> {
+    repository: R,
+    sender: S,
+    clock: C,
+    enabled: Vec<RecoveryChannelKind>,
+    code_ttl: Duration,
+    max_attempts: u32,
+    resend_interval: Duration,
+}
+
+impl<R: RecoveryChannelRepository, S: MessageSender, C: Clock> RecoveryChannelService<R, S, C> {
+    pub fn new(repository: R, sender: S, clock: C) -> Self {
+        Self {
+            repository,
+            sender,
+            clock,
+            enabled: vec![RecoveryChannelKind::Email, RecoveryChannelKind::Sms],
+            code_ttl: Duration::minutes(10),
+            max_attempts: 5,
+            resend_interval: Duration::minutes(1),
+        }
+    }
+
+    /// Restricts the channel kinds users may set up.
+    pub fn with_enabled(mut self, kinds: impl IntoIterator<Item = RecoveryChannelKind>) -> Self {
+        self.enabled = kinds.into_iter().collect();
+        self
+    }
+
+    pub fn with_code_ttl(mut self, code_ttl: Duration) -> Self {
+        self.code_ttl = code_ttl;
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_resend_interval(mut self, resend_interval: Duration) -> Self {
+        self.resend_interval = resend_interval;
+        self
+    }
+
+    /// Registers a new, unverified destination and sends it a verification
+    /// code, replacing any previous channel.
+    pub fn set_up(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        destination: RecoveryDestination,
+    ) -> Result<(), RecoveryError> {
+        if !self.enabled.contains(&destination.kind()) {
+            return Err(RecoveryError::ChannelDisabled(destination.kind()));
+        }
+        // Throttling state carries over so that replacing the channel does
+        // not reset it.
+        let previous = self.repository.channel_of(tenant_id, username)?;
+        let mut channel = RecoveryChannel {
+            tenant_id: tenant_id.to_owned(),
+            username: username.to_owned(),
+            destination,
+            verified_at: None,
+            pending: None,
+            last_sent_at: previous.as_ref().and_then(|c| c.last_sent_at),
+            failed_attempts: previous.as_ref().map_or(0, |c| c.failed_attempts),
+            failures_since: previous.and_then(|c| c.failures_since),
+        };
+        self.send_code(
+            &mut channel,
+            "Verify your recovery contact",
+            "Your verification code is",
+        )
+    }
+
+    /// Marks the destination verified if the code matches.
+    pub fn confirm(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        code: &str,
+    ) -> Result<bool, RecoveryError> {
+        let mut channel = self.channel(tenant_id, username)?;
+        let matched = self.check_code(&mut channel, code);
+        if matched {
+            channel.verified_at = Some(self.clock.now());
+        }
+        self.repository.save(&channel)?;
+        Ok(matched)
+    }
+
+    /// Sends a recovery code to the verified destination.
+    pub fn send_recovery_code(&self, tenant_id: &str, username: &str) -> Result<(), RecoveryError> {
+        let mut channel = self.verified_channel(tenant_id, username)?;
+        self.send_code(
+            &mut channel,
+            "Account recovery",
+            "Your account recovery code is",
+        )
+    }
+
+    /// Consumes the recovery code; on success the caller may let the user
+    /// reset their password or primary email.
+    pub fn redeem_recovery_code(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        code: &str,
+    ) -> Result<bool, RecoveryError> {
+        let mut channel = self.verified_channel(tenant_id, username)?;
+        let matched = self.check_code(&mut channel, code);
+        self.repository.save(&channel)?;
+        Ok(matched)
+    }
+
+    pub fn channel_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<RecoveryChannel>, RecoveryError> {
+        self.repository.channel_of(tenant_id, username)
+    }
+
+    pub fn remove(&self, tenant_id: &str, username: &str) -> Result<(), RecoveryError> {
+        self.repository.remove(tenant_id, username)
+    }
+
+    fn channel(&self, tenant_id: &str, username: &str) -> Result<RecoveryChannel, RecoveryError> {
+        self.repository
+            .channel_of(tenant_id, username)?
+            .ok_or_else(|| RecoveryError::NotConfigured(username.to_owned()))
+    }
+
+    fn verified_channel(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<RecoveryChannel, RecoveryError> {
+        let channel = self.channel(tenant_id, username)?;
+        if !channel.is_verified() {
+            return Err(RecoveryError::NotVerified(username.to_owned()));
+        }
+        if !self.enabled.contains(&channel.destination.kind()) {
+            return Err(RecoveryError::ChannelDisabled(channel.destination.kind()));
+        }
+        Ok(channel)
+    }
+
+    fn send_code(
+        &self,
+        channel: &mut RecoveryChannel,
+        subject: &str,
+        lead: &str,
+    ) -> Result<(), RecoveryError> {
+        let now = self.clock.now();
+        if let Some(next) = channel
+            .last_sent_at
+            .map(|at| at + self.resend_interval)
+            .filter(|next| now < *next)
+        {
+            return Err(RecoveryError::Throttled {
+                retry_after: next - now,
+            });
+        }
+        let code = format!("{:06}", OsRng.next_u32() % 1_000_000);
+        channel.pending = Some(PendingCode {
+            hash: hash(&code),
+            expires_at: now + self.code_ttl,
+        });
+        channel.last_sent_at = Some(now);
+        self.repository.save(channel)?;
+        let minutes = self.code_ttl.num_minutes();
+        let body = format!("{lead} {code}. It expires in {minutes} minutes.");
+        self.sender.send(&channel.destination, subject, &body)
+    }
+
+    /// Checks and, when it matches, consumes the pending code.
+    fn check_code(&self, channel: &mut RecoveryChannel, code: &str) -> bool {
+        let now = self.clock.now();
+        if channel
+            .failures_since
+            .is_some_and(|since| now - since >= self.code_ttl)
+        {
+            channel.failed_attempts = 0;
+            channel.failures_since = None;
+        }
+        if channel.failed_attempts >= self.max_attempts {
+            channel.pending = None;
+            return false;
+        }
+        let Some(pending) = channel.pending.as_ref() else {
+            return false;
+        };
+        if now >= pending.expires_at {
+            channel.pending = None;
+            return false;
+        }
+        if bool::from(hash(code.trim()).as_bytes().ct_eq(pending.hash.as_bytes())) {
+            channel.pending = None;
+            channel.failed_attempts = 0;
+            channel.failures_since = None;
+            return true;
+        }
+        channel.failed_attempts += 1;
+        channel.failures_since.get_or_insert(now);
+        if channel.failed_attempts >= self.max_attempts {
+            channel.pending = None;
+        }
+        false
+    }
+}
+
+fn hash(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::testing::Outbox;
+
+    fn last_code(outbox: &Outbox) -> String {
+        outbox
+            .last()
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|part| part.len() == 6)
+            .unwrap()
+            .to_owned()
+    }
+
+    type Service<'a> =
+        RecoveryChannelService<InMemoryRecoveryChannelRepository, &'a Outbox, &'a FixedClock>;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn verified<'a>(outbox: &'a Outbox, clock: &'a FixedClock) -> Service<'a> {
+        let service =
+            RecoveryChannelService::new(InMemoryRecoveryChannelRepository::new(), outbox, clock)
+                .with_max_attempts(3);
+        let destination = RecoveryDestination::Sms("+15555550100".to_owned());
+        service.set_up("acme", "ada", destination).unwrap();
+        assert!(service.confirm("acme", "ada", &last_code(outbox)).unwrap());
+        clock.advance(Duration::minutes(1));
+        service
+    }
+
+    fn wrong(code: &str) -> String {
+        format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000)
+    }
+
+    #[test]
+    fn recovery_code_works_once() {
+        let (outbox, clock) = (Outbox::default(), clock());
+        let service = verified(&outbox, &clock);
+        service.send_recovery_code("acme", "ada").unwrap();
+        let code = last_code(&outbox);
+        assert!(service.redeem_recovery_code("acme", "ada", &code).unwrap());
+        assert!(!service.redeem_recovery_code("acme", "ada", &code).unwrap());
+    }
+
+    #[test]
+    fn sends_are_throttled() {
+        let (outbox, clock) = (Outbox::default(), clock());
+        let service = verified(&outbox, &clock);
+        service.send_recovery_code("acme", "ada").unwrap();
+        assert!(matches!(
+            service.send_recovery_code("acme", "ada"),
+            Err(RecoveryError::Throttled { .. })
+        ));
+        clock.advance(Duration::minutes(1));
+        service.send_recovery_code("acme", "ada").unwrap();
+    }
+
+    #[test]
+    fn failed_attempts_survive_resends() {
+        let (outbox, clock) = (Outbox::default(), clock());
+        let service = verified(&outbox, &clock);
+        for _ in 0..3 {
+            service.send_recovery_code("acme", "ada").unwrap();
+            let code = last_code(&outbox);
+            assert!(!service
+                .redeem_recovery_code("acme", "ada", &wrong(&code))
+                .unwrap());
+            clock.advance(Duration::minutes(1));
+        }
+        service.send_recovery_code("acme", "ada").unwrap();
+        let code = last_code(&outbox);
+        assert!(!service.redeem_recovery_code("acme", "ada", &code).unwrap());
+
+        clock.advance(Duration::minutes(10));
+        service.send_recovery_code("acme", "ada").unwrap();
+        let code = last_code(&outbox);
+        assert!(service.redeem_recovery_code("acme", "ada", &code).unwrap());
+    }
+
+    #[test]
+    fn replacing_the_channel_keeps_the_throttle() {
+        let (outbox, clock) = (Outbox::default(), clock());
+        let service = verified(&outbox, &clock);
+        service.send_recovery_code("acme", "ada").unwrap();
+        let destination = RecoveryDestination::Email("ada@example.com".to_owned());
+        assert!(matches!(
+            service.set_up("acme", "ada", destination),
+            Err(RecoveryError::Throttled { .. })
+        ));
+    }
+}
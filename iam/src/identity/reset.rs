@@ -0,0 +1,308 @@
+use chrono::Duration;
+use thiserror::Error;
+
+use super::password::{
+    EncryptedPassword, PasswordError, PasswordHashingStrategy, PasswordPolicy, PasswordPolicyError,
+    PlainPassword,
+};
+use super::recovery::{MessageSender, RecoveryDestination};
+use super::single_use::{
+    SingleUseToken, SingleUseTokenError, SingleUseTokenRepository, TokenPurpose,
+};
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+
+/// Errors of the password reset flow.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PasswordResetError {
+    #[error("reset token is invalid or expired")]
+    InvalidToken,
+    #[error(transparent)]
+    Policy(#[from] PasswordPolicyError),
+    #[error(transparent)]
+    Password(#[from] PasswordError),
+    #[error("password reset storage failure: {0}")]
+    Storage(String),
+}
+
+impl From<SingleUseTokenError> for PasswordResetError {
+    fn from(error: SingleUseTokenError) -> Self {
+        Self::Storage(error.0)
+    }
+}
+
+/// Looks up and updates the accounts whose password is being reset.
+pub trait PasswordResetAccounts: Send + Sync {
+    /// The user owning the email address in the tenant, if any.
+    fn username_by_email(
+        &self,
+        tenant_id: &str,
+        email: &str,
+    ) -> Result<Option<String>, PasswordResetError>;
+
+    /// Replaces the user's password without asking for the current one.
+    fn set_password(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        password: &EncryptedPassword,
+    ) -> Result<(), PasswordResetError>;
+}
+
+/// Lets users who forgot their password choose a new one through a
+/// single-use token mailed to them.
+///
+/// Initiating a reset answers the same way whether or not the address has an
+/// account, and failures to deliver the message are not reported. The work
+/// done still differs, so callers that must not leak which addresses exist
+/// through response times should initiate resets in the background.
+pub struct PasswordResetService<R, A, S, H, C> {
+    tokens: R,
+    accounts: A,
+    sender: S,
+    hashing: H,
+    clock: C,
+    policy: PasswordPolicy,
+    token_ttl: Duration,
+}
+
+impl<R, A, S, H, C> PasswordResetService<R, A, S, H, C>
+where
+    R: SingleUseTokenRepository,
+    A: PasswordResetAccounts,
+    S: MessageSender,
+    H: PasswordHashingStrategy,
+    C: Clock,
+{
+    pub fn new(tokens: R, accounts: A, sender: S, hashing: H, clock: C) -> Self {
+        Self {
+            tokens,
+            accounts,
+            sender,
+            hashing,
+            clock,
+            policy: PasswordPolicy::default(),
+            token_ttl: Duration::minutes(30),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: PasswordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_token_ttl(mut self, token_ttl: Duration) -> Self {
+        self.token_ttl = token_ttl;
+        self
+    }
+
+    /// Mails a reset token to the address if it belongs to a user; a message
+    /// that cannot be delivered is dropped.
+    pub fn initiate(&self, tenant_id: &str, email: &str) -> Result<(), PasswordResetError> {
+        let Some(username) = self.accounts.username_by_email(tenant_id, email)? else {
+            return Ok(());
+        };
+        let token = random_token();
+        self.tokens.save(&SingleUseToken {
+            purpose: TokenPurpose::PasswordReset,
+            tenant_id: tenant_id.to_owned(),
+            username,
+            token_hash: digest(&token),
+            expires_at: self.clock.now() + self.token_ttl,
+        })?;
+        let minutes = self.token_ttl.num_minutes();
+        let body =
+            format!("Your password reset token is {token}. It expires in {minutes} minutes.");
+        // Reporting the failure would tell the caller the address is known.
+        let _ = self.sender.send(
+            &RecoveryDestination::Email(email.to_owned()),
+            "Reset your password",
+            &body,
+        );
+        Ok(())
+    }
+
+    /// Redeems the token and sets the new password, returning the user it
+    /// belonged to.
+    ///
+    /// A password the policy rejects leaves the token usable for another
+    /// attempt.
+    pub fn complete(
+        &self,
+        token: &str,
+        new_password: &PlainPassword,
+    ) -> Result<String, PasswordResetError> {
+        let hash = digest(token);
+        let record = self
+            .tokens
+            .token_of_hash(TokenPurpose::PasswordReset, &hash)?
+            .filter(|record| self.clock.now() < record.expires_at)
+            .ok_or(PasswordResetError::InvalidToken)?;
+        self.policy.check(new_password, &[&record.username])?;
+        // Taking the token only once the password is acceptable means a
+        // rejected attempt never has to put it back.
+        let record = self
+            .tokens
+            .take(TokenPurpose::PasswordReset, &hash)?
+            .filter(|taken| taken == &record)
+            .ok_or(PasswordResetError::InvalidToken)?;
+        let encrypted = new_password.encrypt(&self.hashing)?;
+        self.accounts
+            .set_password(&record.tenant_id, &record.username, &encrypted)?;
+        Ok(record.username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::password::Pbkdf2Strategy;
+    use crate::identity::single_use::InMemorySingleUseTokenRepository;
+    use crate::identity::testing::{Outbox, Unreachable};
+
+    fn last_token(outbox: &Outbox) -> String {
+        outbox
+            .last()
+            .split(' ')
+            .nth(5)
+            .unwrap()
+            .trim_end_matches('.')
+            .to_owned()
+    }
+
+    #[derive(Default)]
+    struct Accounts(Mutex<HashMap<String, EncryptedPassword>>);
+
+    impl PasswordResetAccounts for &Accounts {
+        fn username_by_email(
+            &self,
+            _tenant_id: &str,
+            email: &str,
+        ) -> Result<Option<String>, PasswordResetError> {
+            Ok((email == "ada@example.com").then(|| "ada".to_owned()))
+        }
+
+        fn set_password(
+            &self,
+            _tenant_id: &str,
+            username: &str,
+            password: &EncryptedPassword,
+        ) -> Result<(), PasswordResetError> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(username.to_owned(), password.clone());
+            Ok(())
+        }
+    }
+
+    fn service<'a>(
+        outbox: &'a Outbox,
+        accounts: &'a Accounts,
+    ) -> PasswordResetService<
+        InMemorySingleUseTokenRepository,
+        &'a Accounts,
+        &'a Outbox,
+        Pbkdf2Strategy,
+        FixedClock,
+    > {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        PasswordResetService::new(
+            InMemorySingleUseTokenRepository::new(),
+            accounts,
+            outbox,
+            Pbkdf2Strategy::new(1).unwrap(),
+            FixedClock::new(now),
+        )
+    }
+
+    #[test]
+    fn unknown_address_gets_no_message() {
+        let (outbox, accounts) = (Outbox::default(), Accounts::default());
+        service(&outbox, &accounts)
+            .initiate("acme", "nobody@example.com")
+            .unwrap();
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn delivery_failure_is_not_reported() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let accounts = Accounts::default();
+        let service = PasswordResetService::new(
+            InMemorySingleUseTokenRepository::new(),
+            &accounts,
+            Unreachable,
+            Pbkdf2Strategy::new(1).unwrap(),
+            FixedClock::new(now),
+        );
+        assert!(service.initiate("acme", "ada@example.com").is_ok());
+    }
+
+    #[test]
+    fn token_sets_the_password_once() {
+        let (outbox, accounts) = (Outbox::default(), Accounts::default());
+        let service = service(&outbox, &accounts);
+        service.initiate("acme", "ada@example.com").unwrap();
+        let token = last_token(&outbox);
+        let password = PlainPassword::new("correct horse battery staple");
+
+        assert_eq!(service.complete(&token, &password).unwrap(), "ada");
+        let stored = accounts.0.lock().unwrap()["ada"].clone();
+        let hashing = Pbkdf2Strategy::new(1).unwrap();
+        assert!(stored.verify(&password, &hashing).unwrap().is_match());
+        assert!(matches!(
+            service.complete(&token, &password),
+            Err(PasswordResetError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn rejected_password_keeps_the_token() {
+        let (outbox, accounts) = (Outbox::default(), Accounts::default());
+        let service = service(&outbox, &accounts);
+        service.initiate("acme", "ada@example.com").unwrap();
+        let token = last_token(&outbox);
+
+        assert!(matches!(
+            service.complete(&token, &PlainPassword::new("short")),
+            Err(PasswordResetError::Policy(_))
+        ));
+        let password = PlainPassword::new("correct horse battery staple");
+        assert!(service.complete(&token, &password).is_ok());
+    }
+
+    #[test]
+    fn new_token_replaces_the_previous_one() {
+        let (outbox, accounts) = (Outbox::default(), Accounts::default());
+        let service = service(&outbox, &accounts);
+        service.initiate("acme", "ada@example.com").unwrap();
+        let first = last_token(&outbox);
+        service.initiate("acme", "ada@example.com").unwrap();
+
+        let password = PlainPassword::new("correct horse battery staple");
+        assert!(service.complete(&first, &password).is_err());
+        assert!(service.complete(&last_token(&outbox), &password).is_ok());
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let (outbox, accounts) = (Outbox::default(), Accounts::default());
+        let service = service(&outbox, &accounts).with_token_ttl(Duration::zero());
+        service.initiate("acme", "ada@example.com").unwrap();
+
+        let password = PlainPassword::new("correct horse battery staple");
+        assert!(matches!(
+            service.complete(&last_token(&outbox), &password),
+            Err(PasswordResetError::InvalidToken)
+        ));
+        assert!(accounts.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+/// Errors raised by sign-in risk evaluators.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RiskError {
+    #[error("risk engine failure: {0}")]
+    Engine(String),
+    #[error("risk history storage failure: {0}")]
+    Storage(String),
+}
+
+/// Where a sign-in came from, as resolved from its address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoLocation {
+    /// ISO 3166-1 alpha-2 country code.
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoLocation {
+    pub fn new(country: impl Into<String>, latitude: f64, longitude: f64) -> Self {
+        Self {
+            country: country.into().to_ascii_uppercase(),
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Great-circle distance in kilometres.
+    pub fn distance_km(&self, other: &GeoLocation) -> f64 {
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// What is known about a sign-in attempt whose credentials checked out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignInContext {
+    pub tenant_id: String,
+    pub username: String,
+    pub address: IpAddr,
+    pub location: Option<GeoLocation>,
+    pub device_fingerprint: Option<String>,
+    /// Sign-ins by the same user within the last hour.
+    pub recent_attempts: u32,
+    pub at: DateTime<Utc>,
+}
+
+impl SignInContext {
+    pub fn new(
+        tenant_id: impl Into<String>,
+        username: impl Into<String>,
+        address: IpAddr,
+        at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            username: username.into(),
+            address,
+            location: None,
+            device_fingerprint: None,
+            recent_attempts: 0,
+            at,
+        }
+    }
+
+    pub fn located_at(mut self, location: GeoLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn from_device(mut self, fingerprint: impl Into<String>) -> Self {
+        self.device_fingerprint = Some(fingerprint.into());
+        self
+    }
+
+    pub fn with_recent_attempts(mut self, attempts: u32) -> Self {
+        self.recent_attempts = attempts;
+        self
+    }
+}
+
+/// Outcome of a risk evaluation, ordered from least to most restrictive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RiskDecision {
+    Allow,
+    RequireMfa,
+    Deny,
+}
+
+/// Decides whether a sign-in proceeds, needs a second factor, or is refused.
+///
+/// [`AuthenticationService`] evaluates every sign-in after the password
+/// check and reports the ones that completed through `record_sign_in`.
+///
+/// [`AuthenticationService`]: super::authentication::AuthenticationService
+/// Deployments plug external risk engines in by implementing this trait.
+pub trait SignInRiskEvaluator: Send + Sync {
+    fn evaluate(&self, context: &SignInContext) -> Result<RiskDecision, RiskError>;
+
+    /// Learns from a completed sign-in.
+    fn record_sign_in(&self, _context: &SignInContext) -> Result<(), RiskError> {
+        Ok(())
+    }
+}
+
+/// Runs several evaluators and keeps the most restrictive decision.
+#[derive(Default)]
+pub struct CompositeRiskEvaluator {
+    evaluators: Vec<Box<dyn SignInRiskEvaluator>>,
+}
+
+impl CompositeRiskEvaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, evaluator: impl SignInRiskEvaluator + 'static) -> Self {
+        self.evaluators.push(Box::new(evaluator));
+        self
+    }
+}
+
+impl SignInRiskEvaluator for CompositeRiskEvaluator {
+    fn evaluate(&self, context: &SignInContext) -> Result<RiskDecision, RiskError> {
+        let mut decision = RiskDecision::Allow;
+        for evaluator in &self.evaluators {
+            decision = decision.max(evaluator.evaluate(context)?);
+            if decision == RiskDecision::Deny {
+                break;
+            }
+        }
+        Ok(decision)
+    }
+
+    fn record_sign_in(&self, context: &SignInContext) -> Result<(), RiskError> {
+        self.evaluators
+            .iter()
+            .try_for_each(|evaluator| evaluator.record_sign_in(context))
+    }
+}
+
+#[derive(Debug)]
+struct History {
+    countries: HashSet<String>,
+    last: GeoLocation,
+    at: DateTime<Utc>,
+}
+
+/// Built-in evaluator flagging sign-ins from new countries and travel faster
+/// than an airliner since the previous sign-in.
+///
+/// A user's first located sign-in is allowed. A new country requires MFA and
+/// impossible travel is denied. History lives in process memory: users not
+/// seen for the retention period are forgotten, and once `capacity` users
+/// are tracked the one seen least recently makes room for a new one.
+pub struct GeoVelocityEvaluator {
+    max_speed_kmh: f64,
+    retention: Duration,
+    capacity: usize,
+    history: Mutex<HashMap<(String, String), History>>,
+}
+
+impl GeoVelocityEvaluator {
+    pub const DEFAULT_CAPACITY: usize = 100_000;
+
+    pub fn new() -> Self {
+        Self {
+            max_speed_kmh: 1000.0,
+            retention: Duration::days(180),
+            capacity: Self::DEFAULT_CAPACITY,
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Overrides the fastest plausible travel speed.
+    pub fn with_max_speed_kmh(mut self, max_speed_kmh: f64) -> Self {
+        self.max_speed_kmh = max_speed_kmh;
+        self
+    }
+
+    /// How long a user's history is kept after their last sign-in.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Most users whose history is kept.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    fn key(context: &SignInContext) -> (String, String) {
+        (context.tenant_id.clone(), context.username.clone())
+    }
+
+    fn make_room(&self, history: &mut HashMap<(String, String), History>, now: DateTime<Utc>) {
+        history.retain(|_, entry| now - entry.at < self.retention);
+        while history.len() >= self.capacity {
+            let Some(oldest) = history
+                .iter()
+                .min_by_key(|(_, entry)| entry.at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            history.remove(&oldest);
+        }
+    }
+}
+
+impl Default for GeoVelocityEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SignInRiskEvaluator for GeoVelocityEvaluator {
+    fn evaluate(&self, context: &SignInContext) -> Result<RiskDecision, RiskError> {
+        let Some(location) = &context.location else {
+            return Ok(RiskDecision::Allow);
+        };
+        let history = self.history.lock().expect("risk history lock poisoned");
+        let Some(history) = history
+            .get(&Self::key(context))
+            .filter(|entry| context.at - entry.at < self.retention)
+        else {
+            return Ok(RiskDecision::Allow);
+        };
+        let hours = (context.at - history.at).num_seconds().max(1) as f64 / 3600.0;
+        if history.last.distance_km(location) / hours > self.max_speed_kmh {
+            return Ok(RiskDecision::Deny);
+        }
+        if !history.countries.contains(&location.country) {
+            return Ok(RiskDecision::RequireMfa);
+        }
+        Ok(RiskDecision::Allow)
+    }
+
+    fn record_sign_in(&self, context: &SignInContext) -> Result<(), RiskError> {
+        let Some(location) = &context.location else {
+            return Ok(());
+        };
+        let mut history = self.history.lock().expect("risk history lock poisoned");
+        let key = Self::key(context);
+        if !history.contains_key(&key) {
+            self.make_room(&mut history, context.at);
+        }
+        let entry = history.entry(key).or_insert_with(|| History {
+            countries: HashSet::new(),
+            last: location.clone(),
+            at: context.at,
+        });
+        if context.at - entry.at >= self.retention {
+            entry.countries.clear();
+        }
+        entry.countries.insert(location.country.clone());
+        entry.last = location.clone();
+        entry.at = context.at;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    fn paris() -> GeoLocation {
+        GeoLocation::new("fr", 48.8566, 2.3522)
+    }
+
+    fn berlin() -> GeoLocation {
+        GeoLocation::new("de", 52.52, 13.405)
+    }
+
+    fn sydney() -> GeoLocation {
+        GeoLocation::new("au", -33.8688, 151.2093)
+    }
+
+    fn sign_in(username: &str, location: GeoLocation, at: DateTime<Utc>) -> SignInContext {
+        SignInContext::new("acme", username, Ipv4Addr::new(192, 0, 2, 1).into(), at)
+            .located_at(location)
+    }
+
+    #[test]
+    fn first_sign_in_and_known_countries_are_allowed() {
+        let evaluator = GeoVelocityEvaluator::new();
+        let first = sign_in("ada", paris(), start());
+        assert_eq!(evaluator.evaluate(&first).unwrap(), RiskDecision::Allow);
+        evaluator.record_sign_in(&first).unwrap();
+
+        let again = sign_in("ada", paris(), start() + Duration::hours(1));
+        assert_eq!(evaluator.evaluate(&again).unwrap(), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn new_country_requires_mfa() {
+        let evaluator = GeoVelocityEvaluator::new();
+        evaluator
+            .record_sign_in(&sign_in("ada", paris(), start()))
+            .unwrap();
+        let trip = sign_in("ada", berlin(), start() + Duration::hours(3));
+        assert_eq!(evaluator.evaluate(&trip).unwrap(), RiskDecision::RequireMfa);
+
+        evaluator.record_sign_in(&trip).unwrap();
+        let back = sign_in("ada", paris(), start() + Duration::hours(6));
+        assert_eq!(evaluator.evaluate(&back).unwrap(), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn impossible_travel_is_denied() {
+        let evaluator = GeoVelocityEvaluator::new();
+        evaluator
+            .record_sign_in(&sign_in("ada", paris(), start()))
+            .unwrap();
+        let teleport = sign_in("ada", sydney(), start() + Duration::hours(2));
+        assert_eq!(evaluator.evaluate(&teleport).unwrap(), RiskDecision::Deny);
+        let flight = sign_in("ada", sydney(), start() + Duration::hours(24));
+        assert_eq!(
+            evaluator.evaluate(&flight).unwrap(),
+            RiskDecision::RequireMfa
+        );
+        let other_user = sign_in("bob", sydney(), start() + Duration::hours(2));
+        assert_eq!(
+            evaluator.evaluate(&other_user).unwrap(),
+            RiskDecision::Allow
+        );
+    }
+
+    #[test]
+    fn history_is_bounded_and_expires() {
+        let evaluator = GeoVelocityEvaluator::new()
+            .with_capacity(2)
+            .with_retention(Duration::days(30));
+        for (minute, username) in ["ada", "bob", "carl"].into_iter().enumerate() {
+            let at = start() + Duration::minutes(minute as i64);
+            evaluator
+                .record_sign_in(&sign_in(username, paris(), at))
+                .unwrap();
+        }
+        assert_eq!(evaluator.history.lock().unwrap().len(), 2);
+        let later = start() + Duration::hours(1);
+        assert_eq!(
+            evaluator
+                .evaluate(&sign_in("ada", sydney(), later))
+                .unwrap(),
+            RiskDecision::Allow,
+            "least recently seen user was evicted"
+        );
+        assert_eq!(
+            evaluator
+                .evaluate(&sign_in("bob", sydney(), later))
+                .unwrap(),
+            RiskDecision::Deny
+        );
+
+        let much_later = start() + Duration::days(31);
+        assert_eq!(
+            evaluator
+                .evaluate(&sign_in("bob", berlin(), much_later))
+                .unwrap(),
+            RiskDecision::Allow,
+            "history older than the retention is ignored"
+        );
+    }
+
+    #[test]
+    fn composite_keeps_the_most_restrictive_decision() {
+        struct Fixed(RiskDecision);
+
+        impl SignInRiskEvaluator for Fixed {
+            fn evaluate(&self, _context: &SignInContext) -> Result<RiskDecision, RiskError> {
+                Ok(self.0)
+            }
+        }
+
+        let evaluator = CompositeRiskEvaluator::new()
+            .with(Fixed(RiskDecision::RequireMfa))
+            .with(Fixed(RiskDecision::Allow));
+        let context = sign_in("ada", paris(), start());
+        assert_eq!(
+            evaluator.evaluate(&context).unwrap(),
+            RiskDecision::RequireMfa
+        );
+        let evaluator = evaluator.with(Fixed(RiskDecision::Deny));
+        assert_eq!(evaluator.evaluate(&context).unwrap(), RiskDecision::Deny);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Failure of the store holding single-use tokens.
+#[derive(Debug, Error)]
+#[error("single-use token storage failure: {0}")]
+pub struct SingleUseTokenError(pub String);
+
+/// What a single-use token lets its bearer do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    MagicLink,
+}
+
+/// An outstanding token mailed to a user; only its SHA-256 digest is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SingleUseToken {
+    pub purpose: TokenPurpose,
+    pub tenant_id: String,
+    pub username: String,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Stores outstanding single-use tokens.
+///
+/// Lookups name the purpose, so a token issued for one flow is never
+/// accepted by another.
+pub trait SingleUseTokenRepository: Send + Sync {
+    /// Stores the token, replacing any other token of the same user issued
+    /// for the same purpose.
+    fn save(&self, token: &SingleUseToken) -> Result<(), SingleUseTokenError>;
+
+    fn token_of_hash(
+        &self,
+        purpose: TokenPurpose,
+        token_hash: &str,
+    ) -> Result<Option<SingleUseToken>, SingleUseTokenError>;
+
+    /// Removes and returns the token with the digest, so that concurrent
+    /// redemptions cannot both succeed.
+    fn take(
+        &self,
+        purpose: TokenPurpose,
+        token_hash: &str,
+    ) -> Result<Option<SingleUseToken>, SingleUseTokenError>;
+}
+
+/// Repository keeping single-use tokens in process memory.
+#[derive(Debug, Default)]
+pub struct InMemorySingleUseTokenRepository {
+    tokens: RwLock<HashMap<String, SingleUseToken>>,
+}
+
+impl InMemorySingleUseTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SingleUseTokenRepository for InMemorySingleUseTokenRepository {
+    fn save(&self, token: &SingleUseToken) -> Result<(), SingleUseTokenError> {
+        let mut tokens = self
+            .tokens
+            .write()
+            .expect("single-use tokens lock poisoned");
+        tokens.retain(|_, t| {
+            t.purpose != token.purpose
+                || t.tenant_id != token.tenant_id
+                || t.username != token.username
+        });
+        tokens.insert(token.token_hash.clone(), token.clone());
+        Ok(())
+    }
+
+    fn token_of_hash(
+        &self,
+        purpose: TokenPurpose,
+        token_hash: &str,
+    ) -> Result<Option<SingleUseToken>, SingleUseTokenError> {
+        let tokens = self.tokens.read().expect("single-use tokens lock poisoned");
+        Ok(tokens
+            .get(token_hash)
+            .filter(|token| token.purpose == purpose)
+            .cloned())
+    }
+
+    fn take(
+        &self,
+        purpose: TokenPurpose,
+        token_hash: &str,
+    ) -> Result<Option<SingleUseToken>, SingleUseTokenError> {
+        let mut tokens = self
+            .tokens
+            .write()
+            .expect("single-use tokens lock poisoned");
+        if tokens.get(token_hash).map(|token| token.purpose) != Some(purpose) {
+            return Ok(None);
+        }
+        Ok(tokens.remove(token_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn token(purpose: TokenPurpose, hash: &str) -> SingleUseToken {
+        SingleUseToken {
+            purpose,
+            tenant_id: "acme".to_owned(),
+            username: "ada".to_owned(),
+            token_hash: hash.to_owned(),
+            expires_at: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn tokens_are_only_redeemed_for_their_purpose() {
+        let repository = InMemorySingleUseTokenRepository::new();
+        repository
+            .save(&token(TokenPurpose::PasswordReset, "reset"))
+            .unwrap();
+        repository
+            .save(&token(TokenPurpose::MagicLink, "link"))
+            .unwrap();
+
+        assert!(repository
+            .take(TokenPurpose::MagicLink, "reset")
+            .unwrap()
+            .is_none());
+        assert!(repository
+            .take(TokenPurpose::PasswordReset, "reset")
+            .unwrap()
+            .is_some());
+        assert!(repository
+            .token_of_hash(TokenPurpose::MagicLink, "link")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn new_token_only_replaces_the_same_purpose() {
+        let repository = InMemorySingleUseTokenRepository::new();
+        repository
+            .save(&token(TokenPurpose::PasswordReset, "first"))
+            .unwrap();
+        repository
+            .save(&token(TokenPurpose::MagicLink, "link"))
+            .unwrap();
+        repository
+            .save(&token(TokenPurpose::PasswordReset, "second"))
+            .unwrap();
+
+        let reset = TokenPurpose::PasswordReset;
+        assert!(repository.token_of_hash(reset, "first").unwrap().is_none());
+        assert!(repository.token_of_hash(reset, "second").unwrap().is_some());
+        assert!(repository
+            .token_of_hash(TokenPurpose::MagicLink, "link")
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,51 @@
+//! Fixtures shared by the identity tests.
+
+use std::sync::Mutex;
+
+use super::recovery::{MessageSender, RecoveryDestination, RecoveryError};
+
+/// Records the body of every message sent through it.
+#[derive(Debug, Default)]
+pub(crate) struct Outbox(Mutex<Vec<String>>);
+
+impl Outbox {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    /// The body of the most recent message.
+    pub(crate) fn last(&self) -> String {
+        self.0
+            .lock()
+            .unwrap()
+            .last()
+            .cloned()
+            .expect("no message sent")
+    }
+}
+
+impl MessageSender for &Outbox {
+    fn send(
+        &self,
+        _destination: &RecoveryDestination,
+        _subject: &str,
+        body: &str,
+    ) -> Result<(), RecoveryError> {
+        self.0.lock().unwrap().push(body.to_owned());
+        Ok(())
+    }
+}
+
+/// Fails every delivery.
+pub(crate) struct Unreachable;
+
+impl MessageSender for Unreachable {
+    fn send(
+        &self,
+        _destination: &RecoveryDestination,
+        _subject: &str,
+        _body: &str,
+    ) -> Result<(), RecoveryError> {
+        Err(RecoveryError::Delivery("mail server down".to_owned()))
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+use crate::common::clock::Clock;
+use crate::common::secret::digest;
+
+/// Errors raised by rate limiters.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RateLimitError {
+    #[error("rate limiter storage failure: {0}")]
+    Storage(String),
+}
+
+/// Operation exposed to guessing attacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThrottledOperation {
+    SignIn,
+    PasswordReset,
+}
+
+impl ThrottledOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ThrottledOperation::SignIn => "sign_in",
+            ThrottledOperation::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// Who requests are counted against.
+///
+/// IPv6 addresses are counted per /64, the block usually assigned to a
+/// single host, so that rotating through it does not escape the limit;
+/// IPv4-mapped IPv6 addresses count as their IPv4 address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitSubject {
+    Principal(String),
+    Address(IpAddr),
+}
+
+impl RateLimitSubject {
+    fn aggregated(self) -> Self {
+        match self {
+            RateLimitSubject::Address(IpAddr::V6(address)) => {
+                RateLimitSubject::Address(match address.to_ipv4_mapped() {
+                    Some(mapped) => IpAddr::V4(mapped),
+                    None => IpAddr::V6(Ipv6Addr::from(u128::from(address) & !(u64::MAX as u128))),
+                })
+            }
+            subject => subject,
+        }
+    }
+}
+
+/// Bucket a request draws from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RateLimitKey {
+    tenant_id: String,
+    operation: ThrottledOperation,
+    subject: RateLimitSubject,
+}
+
+impl RateLimitKey {
+    pub fn new(
+        tenant_id: impl Into<String>,
+        operation: ThrottledOperation,
+        subject: RateLimitSubject,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            operation,
+            subject: subject.aggregated(),
+        }
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn operation(&self) -> ThrottledOperation {
+        self.operation
+    }
+
+    pub fn subject(&self) -> &RateLimitSubject {
+        &self.subject
+    }
+}
+
+/// Token bucket parameters with exponential backoff once the bucket is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    capacity: u32,
+    refill_interval: Duration,
+    backoff_base: Duration,
+    backoff_max: Duration,
+}
+
+impl RateLimitPolicy {
+    /// Allows bursts of `capacity` requests, regaining one every
+    /// `refill_interval`. Each consecutive refusal doubles the wait, starting
+    /// at `backoff_base` and capped at `backoff_max`.
+    pub fn new(
+        capacity: u32,
+        refill_interval: Duration,
+        backoff_base: Duration,
+        backoff_max: Duration,
+    ) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            refill_interval,
+            backoff_base,
+            backoff_max: backoff_max.max(backoff_base),
+        }
+    }
+
+    fn backoff(&self, violations: u32) -> Duration {
+        let factor = 2i32.saturating_pow(violations.saturating_sub(1).min(30));
+        self.backoff_base
+            .checked_mul(factor)
+            .map_or(self.backoff_max, |backoff| backoff.min(self.backoff_max))
+    }
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        Self::new(
+            10,
+            Duration::seconds(6),
+            Duration::seconds(1),
+            Duration::minutes(15),
+        )
+    }
+}
+
+/// Policies by tenant, falling back to a default.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitPolicies {
+    default: RateLimitPolicy,
+    tenants: HashMap<String, RateLimitPolicy>,
+}
+
+impl RateLimitPolicies {
+    pub fn new(default: RateLimitPolicy) -> Self {
+        Self {
+            default,
+            tenants: HashMap::new(),
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant_id: impl Into<String>, policy: RateLimitPolicy) -> Self {
+        self.tenants.insert(tenant_id.into(), policy);
+        self
+    }
+
+    pub fn policy_for(&self, tenant_id: &str) -> &RateLimitPolicy {
+        self.tenants.get(tenant_id).unwrap_or(&self.default)
+    }
+}
+
+/// Whether a request may proceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    Allowed { remaining: u32 },
+    Throttled { retry_after: Duration },
+}
+
+impl RateLimitDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, RateLimitDecision::Allowed { .. })
+    }
+}
+
+/// Throttles requests to sensitive operations.
+pub trait RateLimiter: Send + Sync {
+    /// Takes a token from the key's bucket if one is available.
+    fn acquire(&self, key: &RateLimitKey) -> Result<RateLimitDecision, RateLimitError>;
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+    violations: u32,
+    blocked_until: Option<DateTime<Utc>>,
+}
+
+impl Bucket {
+    /// Whether the bucket has refilled and is not blocked, so dropping it is
+    /// the same as starting afresh.
+    fn is_idle(&self, policy: &RateLimitPolicy, now: DateTime<Utc>) -> bool {
+        let capacity = i32::try_from(policy.capacity).unwrap_or(i32::MAX);
+        self.blocked_until.is_none_or(|until| now >= until)
+            && policy
+                .refill_interval
+                .checked_mul(capacity)
+                .is_some_and(|refill| now - self.updated_at >= refill)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    entries: HashMap<RateLimitKey, Bucket>,
+    swept_at: Option<DateTime<Utc>>,
+}
+
+/// Token bucket limiter keeping its buckets in process memory.
+///
+/// Buckets that have refilled and are not blocked are dropped, at most once
+/// per sweep interval, so keys that stop sending requests do not accumulate.
+pub struct InMemoryRateLimiter<C> {
+    policies: RateLimitPolicies,
+    clock: C,
+    sweep_interval: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+impl<C: Clock> InMemoryRateLimiter<C> {
+    pub fn new(policies: RateLimitPolicies, clock: C) -> Self {
+        Self {
+            policies,
+            clock,
+            sweep_interval: Duration::minutes(1),
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+
+    pub fn with_sweep_interval(mut self, sweep_interval: Duration) -> Self {
+        self.sweep_interval = sweep_interval;
+        self
+    }
+
+    fn sweep(&self, buckets: &mut Buckets, now: DateTime<Utc>) {
+        if buckets
+            .swept_at
+            .is_some_and(|at| now - at < self.sweep_interval)
+        {
+            return;
+        }
+        buckets
+            .entries
+            .retain(|key, bucket| !bucket.is_idle(self.policies.policy_for(&key.tenant_id), now));
+        buckets.swept_at = Some(now);
+    }
+}
+
+impl<C: Clock> RateLimiter for InMemoryRateLimiter<C> {
+    fn acquire(&self, key: &RateLimitKey) -> Result<RateLimitDecision, RateLimitError> {
+        let policy = self.policies.policy_for(&key.tenant_id);
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        self.sweep(&mut buckets, now);
+        let bucket = buckets
+            .entries
+            .entry(key.clone())
+            .or_insert_with(|| Bucket {
+                tokens: f64::from(policy.capacity),
+                updated_at: now,
+                violations: 0,
+                blocked_until: None,
+            });
+        if let Some(until) = bucket.blocked_until.filter(|until| now < *until) {
+            // Retrying before the wait is over escalates the backoff.
+            bucket.violations = bucket.violations.saturating_add(1);
+            let until = until.max(now + policy.backoff(bucket.violations));
+            bucket.blocked_until = Some(until);
+            return Ok(RateLimitDecision::Throttled {
+                retry_after: until - now,
+            });
+        }
+        let interval = policy.refill_interval.num_milliseconds().max(1) as f64;
+        let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64;
+        bucket.tokens = (bucket.tokens + elapsed / interval).min(f64::from(policy.capacity));
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.violations = 0;
+            bucket.blocked_until = None;
+            return Ok(RateLimitDecision::Allowed {
+                remaining: bucket.tokens as u32,
+            });
+        }
+        bucket.violations = bucket.violations.saturating_add(1);
+        let until_refill = Duration::milliseconds(((1.0 - bucket.tokens) * interval).ceil() as i64);
+        let retry_after = policy.backoff(bucket.violations).max(until_refill);
+        bucket.blocked_until = Some(now + retry_after);
+        Ok(RateLimitDecision::Throttled { retry_after })
+    }
+}
+
+/// Runs Lua scripts on a Redis server, implemented by deployments on top of
+/// their Redis client (e.g. with `EVALSHA`, falling back to `EVAL`).
+pub trait RedisScripting: Send + Sync {
+    /// Evaluates the script atomically, returning its integer array reply.
+    fn eval(&self, script: &str, keys: &[String], args: &[i64])
+        -> Result<Vec<i64>, RateLimitError>;
+}
+
+/// Token bucket matching [`InMemoryRateLimiter`], kept in a Redis hash per
+/// key and updated atomically by the script. Keys expire once the bucket
+/// would have refilled and is no longer blocked.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local now, capacity, refill, base, max =
+  tonumber(ARGV[1]), tonumber(ARGV[2]), tonumber(ARGV[3]), tonumber(ARGV[4]), tonumber(ARGV[5])
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at', 'violations', 'blocked_until')
+local tokens = tonumber(state[1]) or capacity
+local updated_at = tonumber(state[2]) or now
+local violations = tonumber(state[3]) or 0
+local blocked_until = tonumber(state[4]) or 0
+local function backoff(count)
+  return math.min(base * 2 ^ math.min(count - 1, 30), max)
+end
+local allowed, retry_after = 0, 0
+if now < blocked_until then
+  violations = violations + 1
+  blocked_until = math.max(blocked_until, now + backoff(violations))
+  retry_after = blocked_until - now
+else
+  tokens = math.min(capacity, tokens + math.max(now - updated_at, 0) / refill)
+  updated_at = now
+  if tokens >= 1 then
+    tokens, violations, blocked_until, allowed = tokens - 1, 0, 0, 1
+  else
+    violations = violations + 1
+    retry_after = math.max(backoff(violations), math.ceil((1 - tokens) * refill))
+    blocked_until = now + retry_after
+  end
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', updated_at,
+  'violations', violations, 'blocked_until', blocked_until)
+redis.call('PEXPIRE', KEYS[1], math.ceil(math.max(capacity * refill, blocked_until - now)))
+return {allowed, math.floor(tokens), retry_after}
+"#;
+
+/// Token bucket limiter keeping its buckets in Redis, so that every instance
+/// of the IAM draws from the same buckets.
+///
+/// Bucket keys hash the tenant, operation and subject, keeping usernames and
+/// addresses out of Redis. Instances are expected to have synchronized
+/// clocks, as the injected clock's time is passed to the script.
+pub struct RedisRateLimiter<R, C> {
+    redis: R,
+    policies: RateLimitPolicies,
+    clock: C,
+    key_prefix: String,
+}
+
+impl<R: RedisScripting, C: Clock> RedisRateLimiter<R, C> {
+    pub fn new(redis: R, policies: RateLimitPolicies, clock: C) -> Self {
+        Self {
+            redis,
+            policies,
+            clock,
+            key_prefix: "iam:rate-limit:".to_owned(),
+        }
+    }
+
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    fn redis_key(&self, key: &RateLimitKey) -> String {
+        let subject = match &key.subject {
+            RateLimitSubject::Principal(principal) => format!("principal\0{}", principal),
+            RateLimitSubject::Address(address) => format!("address\0{}", address),
+        };
+        let id = format!("{}\0{}\0{}", key.tenant_id, key.operation.as_str(), subject);
+        format!("{}{}", self.key_prefix, digest(&id))
+    }
+}
+
+impl<R: RedisScripting, C: Clock> RateLimiter for RedisRateLimiter<R, C> {
+    fn acquire(&self, key: &RateLimitKey) -> Result<RateLimitDecision, RateLimitError> {
+        let policy = self.policies.policy_for(&key.tenant_id);
+        let args = [
+            self.clock.now().timestamp_millis(),
+            i64::from(policy.capacity),
+            policy.refill_interval.num_milliseconds().max(1),
+            policy.backoff_base.num_milliseconds(),
+            policy.backoff_max.num_milliseconds(),
+        ];
+        let reply = self
+            .redis
+            .eval(TOKEN_BUCKET_SCRIPT, &[self.redis_key(key)], &args)?;
+        match reply[..] {
+            [1, remaining, _] => Ok(RateLimitDecision::Allowed {
+                remaining: u32::try_from(remaining).unwrap_or(0),
+            }),
+            [0, _, retry_after] => Ok(RateLimitDecision::Throttled {
+                retry_after: Duration::milliseconds(retry_after),
+            }),
+            _ => Err(RateLimitError::Storage(format!(
+                "unexpected token bucket reply: {:?}",
+                reply
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn limiter(clock: &FixedClock) -> InMemoryRateLimiter<&FixedClock> {
+        let policy = RateLimitPolicy::new(
+            2,
+            Duration::seconds(10),
+            Duration::seconds(1),
+            Duration::minutes(10),
+        );
+        InMemoryRateLimiter::new(RateLimitPolicies::new(policy), clock)
+    }
+
+    fn key(principal: &str) -> RateLimitKey {
+        RateLimitKey::new(
+            "acme",
+            ThrottledOperation::SignIn,
+            RateLimitSubject::Principal(principal.to_owned()),
+        )
+    }
+
+    fn buckets(limiter: &InMemoryRateLimiter<&FixedClock>) -> usize {
+        limiter.buckets.lock().unwrap().entries.len()
+    }
+
+    #[test]
+    fn empty_bucket_throttles_with_growing_backoff() {
+        let clock = clock();
+        let limiter = limiter(&clock);
+        assert!(limiter.acquire(&key("ada")).unwrap().is_allowed());
+        assert!(limiter.acquire(&key("ada")).unwrap().is_allowed());
+        let RateLimitDecision::Throttled { retry_after: first } =
+            limiter.acquire(&key("ada")).unwrap()
+        else {
+            panic!("expected throttling");
+        };
+        let RateLimitDecision::Throttled {
+            retry_after: second,
+        } = limiter.acquire(&key("ada")).unwrap()
+        else {
+            panic!("expected throttling");
+        };
+        assert!(second >= first);
+        assert!(limiter.acquire(&key("bob")).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn refilled_buckets_are_evicted() {
+        let clock = clock();
+        let limiter = limiter(&clock);
+        for principal in 0..50 {
+            limiter.acquire(&key(&principal.to_string())).unwrap();
+        }
+        assert_eq!(buckets(&limiter), 50);
+
+        clock.advance(Duration::seconds(20));
+        limiter.acquire(&key("ada")).unwrap();
+        assert_eq!(buckets(&limiter), 51, "swept at most once per interval");
+
+        clock.advance(Duration::seconds(40));
+        limiter.acquire(&key("ada")).unwrap();
+        assert_eq!(buckets(&limiter), 1);
+    }
+
+    #[test]
+    fn blocked_buckets_are_kept() {
+        let clock = clock();
+        let limiter = limiter(&clock);
+        for _ in 0..10 {
+            limiter.acquire(&key("ada")).unwrap();
+        }
+        clock.advance(Duration::seconds(61));
+        limiter.acquire(&key("bob")).unwrap();
+        assert_eq!(buckets(&limiter), 2);
+        assert!(!limiter.acquire(&key("ada")).unwrap().is_allowed());
+    }
+
+    #[test]
+    fn ipv6_addresses_share_their_slash_64() {
+        let address = |value: &str| {
+            RateLimitKey::new(
+                "acme",
+                ThrottledOperation::SignIn,
+                RateLimitSubject::Address(value.parse().unwrap()),
+            )
+        };
+        assert_eq!(
+            address("2001:db8:1:2:aaaa::1"),
+            address("2001:db8:1:2:ffff:ffff:ffff:ffff")
+        );
+        assert_ne!(address("2001:db8:1:2::1"), address("2001:db8:1:3::1"));
+        assert_eq!(address("::ffff:192.0.2.1"), address("192.0.2.1"));
+        assert_ne!(address("192.0.2.1"), address("192.0.2.2"));
+
+        let clock = clock();
+        let limiter = limiter(&clock);
+        limiter.acquire(&address("2001:db8::1")).unwrap();
+        limiter.acquire(&address("2001:db8::2")).unwrap();
+        assert!(!limiter
+            .acquire(&address("2001:db8::3"))
+            .unwrap()
+            .is_allowed());
+    }
+
+    /// Stands in for Redis, replying with canned results.
+    struct Script {
+        reply: Vec<i64>,
+        calls: Mutex<Vec<(Vec<String>, Vec<i64>)>>,
+    }
+
+    impl RedisScripting for &Script {
+        fn eval(
+            &self,
+            script: &str,
+            keys: &[String],
+            args: &[i64],
+        ) -> Result<Vec<i64>, RateLimitError> {
+            assert_eq!(script, TOKEN_BUCKET_SCRIPT);
+            self.calls
+                .lock()
+                .unwrap()
+                .push((keys.to_vec(), args.to_vec()));
+            Ok(self.reply.clone())
+        }
+    }
+
+    fn script(reply: Vec<i64>) -> Script {
+        Script {
+            reply,
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn redis_limiter_passes_the_tenant_policy_to_the_script() {
+        let clock = clock();
+        let redis = script(vec![1, 4, 0]);
+        let policy = RateLimitPolicy::new(
+            5,
+            Duration::seconds(2),
+            Duration::seconds(1),
+            Duration::minutes(1),
+        );
+        let limiter = RedisRateLimiter::new(
+            &redis,
+            RateLimitPolicies::default().with_tenant("acme", policy),
+            &clock,
+        );
+
+        assert_eq!(
+            limiter.acquire(&key("ada")).unwrap(),
+            RateLimitDecision::Allowed { remaining: 4 }
+        );
+        limiter.acquire(&key("bob")).unwrap();
+        let calls = redis.calls.lock().unwrap();
+        let (keys, args) = &calls[0];
+        assert_eq!(
+            args[..],
+            [clock.now().timestamp_millis(), 5, 2_000, 1_000, 60_000]
+        );
+        assert!(keys[0].starts_with("iam:rate-limit:"));
+        assert!(!keys[0].contains("ada"), "usernames are hashed");
+        assert_ne!(calls[1].0, *keys);
+    }
+
+    #[test]
+    fn redis_limiter_reports_throttling_and_bad_replies() {
+        let clock = clock();
+        let throttled = script(vec![0, 0, 1_500]);
+        let limiter = RedisRateLimiter::new(&throttled, RateLimitPolicies::default(), &clock);
+        assert_eq!(
+            limiter.acquire(&key("ada")).unwrap(),
+            RateLimitDecision::Throttled {
+                retry_after: Duration::milliseconds(1_500)
+            }
+        );
+
+        let garbled = script(vec![7]);
+        let limiter = RedisRateLimiter::new(&garbled, RateLimitPolicies::default(), &clock);
+        assert!(matches!(
+            limiter.acquire(&key("ada")),
+            Err(RateLimitError::Storage(_))
+        ));
+    }
+}
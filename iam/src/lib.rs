@@ -0,0 +1,11 @@
+pub mod access;
+pub mod common;
+#[cfg(feature = "verifiable-credentials")]
+pub mod credentials;
+pub mod federation;
+pub mod identity;
+pub mod mfa;
+pub mod oauth;
+pub mod prelude;
+pub mod tokens;
+pub mod verify;
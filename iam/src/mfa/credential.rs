@@ -0,0 +1,358 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::RwLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+
+use super::recovery::{self, RecoveryCodes};
+use super::totp::TotpSecret;
+use super::MfaError;
+
+/// Encrypts authenticator secrets before they are stored.
+///
+/// Secrets are sealed with AES-256-GCM, bound to the owning tenant and user
+/// as associated data so a stored secret cannot be moved to another account.
+pub struct SecretCipher {
+    key_id: String,
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    pub fn new(key_id: impl Into<String>, key: &[u8]) -> Result<Self, MfaError> {
+        let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| MfaError::InvalidParameters)?;
+        Ok(Self {
+            key_id: key_id.into(),
+            cipher,
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn encrypt(
+        &self,
+        secret: &TotpSecret,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<EncryptedSecret, MfaError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(tenant_id, username);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: secret.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| MfaError::Encryption)?;
+        Ok(EncryptedSecret {
+            key_id: self.key_id.clone(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn decrypt(
+        &self,
+        secret: &EncryptedSecret,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<TotpSecret, MfaError> {
+        if secret.key_id != self.key_id || secret.nonce.len() != 12 {
+            return Err(MfaError::Decryption);
+        }
+        let aad = associated_data(tenant_id, username);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&secret.nonce),
+                Payload {
+                    msg: &secret.ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| MfaError::Decryption)?;
+        TotpSecret::from_bytes(plaintext)
+    }
+}
+
+impl fmt::Debug for SecretCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretCipher")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+fn associated_data(tenant_id: &str, username: &str) -> String {
+    format!("{}\u{0}{}", tenant_id, username)
+}
+
+/// An authenticator secret as persisted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    key_id: String,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedSecret {
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+}
+
+/// A user's enrolled TOTP authenticator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MfaCredential {
+    tenant_id: String,
+    username: String,
+    secret: EncryptedSecret,
+    enrolled_at: DateTime<Utc>,
+    last_used_step: Option<u64>,
+    #[serde(default)]
+    recovery_codes: Vec<String>,
+}
+
+impl MfaCredential {
+    pub fn new(
+        tenant_id: impl Into<String>,
+        username: impl Into<String>,
+        secret: EncryptedSecret,
+        enrolled_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            username: username.into(),
+            secret,
+            enrolled_at,
+            last_used_step: None,
+            recovery_codes: Vec::new(),
+        }
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn secret(&self) -> &EncryptedSecret {
+        &self.secret
+    }
+
+    pub fn enrolled_at(&self) -> DateTime<Utc> {
+        self.enrolled_at
+    }
+
+    /// The time step of the last accepted code, used to refuse replays.
+    pub fn last_used_step(&self) -> Option<u64> {
+        self.last_used_step
+    }
+
+    pub fn record_use(&mut self, step: u64) {
+        self.last_used_step = Some(step);
+    }
+
+    /// Records the step unless it is not newer than the last accepted one,
+    /// returning whether it was recorded.
+    pub fn record_use_if_newer(&mut self, step: u64) -> bool {
+        if self.last_used_step.is_some_and(|last| step <= last) {
+            return false;
+        }
+        self.last_used_step = Some(step);
+        true
+    }
+
+    /// Number of recovery codes that have not been used yet.
+    pub fn remaining_recovery_codes(&self) -> usize {
+        self.recovery_codes.len()
+    }
+
+    /// Replaces the recovery codes, invalidating any previous set.
+    pub fn replace_recovery_codes(&mut self, codes: &RecoveryCodes) {
+        self.recovery_codes = codes.hashes();
+    }
+
+    /// Uses up a recovery code, returning whether it was valid.
+    pub fn consume_recovery_code(&mut self, code: &str) -> bool {
+        self.consume_recovery_code_hash(&recovery::hash(code))
+    }
+
+    fn consume_recovery_code_hash(&mut self, hash: &str) -> bool {
+        match self.recovery_codes.iter().position(|c| c == hash) {
+            Some(index) => {
+                self.recovery_codes.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stores enrolled MFA credentials.
+pub trait MfaCredentialRepository: Send + Sync {
+    fn credential_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<MfaCredential>, MfaError>;
+
+    /// Inserts or replaces the credential of its user.
+    fn save(&self, credential: &MfaCredential) -> Result<(), MfaError>;
+
+    /// Records an accepted time step, refusing it when the stored credential
+    /// already accepted the same or a later step.
+    ///
+    /// The check and the update must be atomic so that two concurrent
+    /// verifications of one code cannot both succeed.
+    fn record_use_if_newer(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        step: u64,
+    ) -> Result<bool, MfaError>;
+
+    /// Removes the recovery code with the digest, returning whether it was
+    /// present; atomic, so a code is only ever accepted once.
+    fn consume_recovery_code(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<bool, MfaError>;
+
+    /// Replaces the recovery code digests without touching the rest of the
+    /// credential.
+    fn replace_recovery_codes(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        codes: &RecoveryCodes,
+    ) -> Result<(), MfaError>;
+
+    fn remove(&self, tenant_id: &str, username: &str) -> Result<(), MfaError>;
+
+    /// Whether the user must sign in with a second factor.
+    fn is_required(&self, tenant_id: &str, username: &str) -> Result<bool, MfaError>;
+
+    fn set_required(&self, tenant_id: &str, username: &str, required: bool)
+        -> Result<(), MfaError>;
+}
+
+/// Repository keeping credentials in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryMfaCredentialRepository {
+    credentials: RwLock<HashMap<(String, String), MfaCredential>>,
+    required: RwLock<HashSet<(String, String)>>,
+}
+
+impl InMemoryMfaCredentialRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MfaCredentialRepository for InMemoryMfaCredentialRepository {
+    fn credential_of(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<Option<MfaCredential>, MfaError> {
+        let credentials = self.credentials.read().expect("credentials lock poisoned");
+        Ok(credentials
+            .get(&(tenant_id.to_owned(), username.to_owned()))
+            .cloned())
+    }
+
+    fn save(&self, credential: &MfaCredential) -> Result<(), MfaError> {
+        let mut credentials = self.credentials.write().expect("credentials lock poisoned");
+        credentials.insert(
+            (credential.tenant_id.clone(), credential.username.clone()),
+            credential.clone(),
+        );
+        Ok(())
+    }
+
+    fn record_use_if_newer(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        step: u64,
+    ) -> Result<bool, MfaError> {
+        let mut credentials = self.credentials.write().expect("credentials lock poisoned");
+        let credential = credentials
+            .get_mut(&(tenant_id.to_owned(), username.to_owned()))
+            .ok_or_else(|| MfaError::NotEnrolled(username.to_owned()))?;
+        Ok(credential.record_use_if_newer(step))
+    }
+
+    fn consume_recovery_code(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<bool, MfaError> {
+        let mut credentials = self.credentials.write().expect("credentials lock poisoned");
+        let credential = credentials
+            .get_mut(&(tenant_id.to_owned(), username.to_owned()))
+            .ok_or_else(|| MfaError::NotEnrolled(username.to_owned()))?;
+        Ok(credential.consume_recovery_code_hash(code_hash))
+    }
+
+    fn replace_recovery_codes(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        codes: &RecoveryCodes,
+    ) -> Result<(), MfaError> {
+        let mut credentials = self.credentials.write().expect("credentials lock poisoned");
+        let credential = credentials
+            .get_mut(&(tenant_id.to_owned(), username.to_owned()))
+            .ok_or_else(|| MfaError::NotEnrolled(username.to_owned()))?;
+        credential.replace_recovery_codes(codes);
+        Ok(())
+    }
+
+    fn remove(&self, tenant_id: &str, username: &str) -> Result<(), MfaError> {
+        let mut credentials = self.credentials.write().expect("credentials lock poisoned");
+        credentials.remove(&(tenant_id.to_owned(), username.to_owned()));
+        Ok(())
+    }
+
+    fn is_required(&self, tenant_id: &str, username: &str) -> Result<bool, MfaError> {
+        let required = self
+            .required
+            .read()
+            .expect("MFA requirements lock poisoned");
+        Ok(required.contains(&(tenant_id.to_owned(), username.to_owned())))
+    }
+
+    fn set_required(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        required: bool,
+    ) -> Result<(), MfaError> {
+        let mut users = self
+            .required
+            .write()
+            .expect("MFA requirements lock poisoned");
+        let key = (tenant_id.to_owned(), username.to_owned());
+        if required {
+            users.insert(key);
+        } else {
+            users.remove(&key);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use super::MfaError;
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+
+/// A device a user has signed in from.
+///
+/// Only digests are kept of the client-supplied fingerprint and of the
+/// secret handed to the device when it was trusted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    device_id: String,
+    tenant_id: String,
+    username: String,
+    fingerprint_hash: String,
+    name: String,
+    first_seen_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    trusted_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    secret_hash: Option<String>,
+}
+
+impl Device {
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn first_seen_at(&self) -> DateTime<Utc> {
+        self.first_seen_at
+    }
+
+    pub fn last_seen_at(&self) -> DateTime<Utc> {
+        self.last_seen_at
+    }
+
+    pub fn trusted_until(&self) -> Option<DateTime<Utc>> {
+        self.trusted_until
+    }
+
+    pub fn is_trusted_at(&self, at: DateTime<Utc>) -> bool {
+        self.trusted_until.is_some_and(|until| at < until)
+    }
+
+    fn holds_secret(&self, secret: &str) -> bool {
+        self.secret_hash
+            .as_ref()
+            .is_some_and(|hash| bool::from(digest(secret).as_bytes().ct_eq(hash.as_bytes())))
+    }
+}
+
+/// Stores the devices seen per user.
+pub trait DeviceRepository: Send + Sync {
+    fn devices_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Device>, MfaError>;
+
+    /// Inserts or replaces the device with the same id.
+    fn save(&self, device: &Device) -> Result<(), MfaError>;
+
+    /// Marks the user's device with the candidate's fingerprint as seen at
+    /// the candidate's `last_seen_at`, or inserts the candidate if the user
+    /// has no such device, returning the stored device. The lookup and the
+    /// write must be atomic so that concurrent sign-ins from a new device
+    /// register it once.
+    fn record_seen(&self, candidate: &Device) -> Result<Device, MfaError>;
+
+    /// Trusts the user's device until the given instant with a new secret,
+    /// returning `false` if the device does not exist.
+    fn trust(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        device_id: &str,
+        secret_hash: &str,
+        until: DateTime<Utc>,
+    ) -> Result<bool, MfaError>;
+
+    fn remove(&self, tenant_id: &str, username: &str, device_id: &str) -> Result<(), MfaError>;
+}
+
+/// Repository keeping devices in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryDeviceRepository {
+    devices: RwLock<HashMap<String, Device>>,
+}
+
+impl InMemoryDeviceRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DeviceRepository for InMemoryDeviceRepository {
+    fn devices_of(&self, tenant_id: &str, username: &str) -> Result<Vec<Device>, MfaError> {
+        let devices = self.devices.read().expect("devices lock poisoned");
+        Ok(devices
+            .values()
+            .filter(|d| d.tenant_id == tenant_id && d.username == username)
+            .cloned()
+            .collect())
+    }
+
+    fn save(&self, device: &Device) -> Result<(), MfaError> {
+        let mut devices = self.devices.write().expect("devices lock poisoned");
+        devices.insert(device.device_id.clone(), device.clone());
+        Ok(())
+    }
+
+    fn record_seen(&self, candidate: &Device) -> Result<Device, MfaError> {
+        let mut devices = self.devices.write().expect("devices lock poisoned");
+        let known = devices.values_mut().find(|d| {
+            d.tenant_id == candidate.tenant_id
+                && d.username == candidate.username
+                && d.fingerprint_hash == candidate.fingerprint_hash
+        });
+        if let Some(device) = known {
+            device.last_seen_at = candidate.last_seen_at;
+            return Ok(device.clone());
+        }
+        devices.insert(candidate.device_id.clone(), candidate.clone());
+        Ok(candidate.clone())
+    }
+
+    fn trust(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        device_id: &str,
+        secret_hash: &str,
+        until: DateTime<Utc>,
+    ) -> Result<bool, MfaError> {
+        let mut devices = self.devices.write().expect("devices lock poisoned");
+        match devices
+            .get_mut(device_id)
+            .filter(|d| d.tenant_id == tenant_id && d.username == username)
+        {
+            Some(device) => {
+                device.trusted_until = Some(until);
+                device.secret_hash = Some(secret_hash.to_owned());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove(&self, tenant_id: &str, username: &str, device_id: &str) -> Result<(), MfaError> {
+        let mut devices = self.devices.write().expect("devices lock poisoned");
+        if devices
+            .get(device_id)
+            .is_some_and(|d| d.tenant_id == tenant_id && d.username == username)
+        {
+            devices.remove(device_id);
+        }
+        Ok(())
+    }
+}
+
+/// Tracks user devices and lets trusted ones skip MFA for a while.
+///
+/// A fingerprint is supplied by the client and easily copied, so it only
+/// recognizes a device. Trust is proven by the random secret issued when
+/// the device is trusted, typically kept in a long-lived cookie.
+pub struct DeviceService<R, C> {
+    repository: R,
+    trust_period: Duration,
+    clock: C,
+}
+
+impl<R: DeviceRepository, C: Clock> DeviceService<R, C> {
+    pub fn new(repository: R, trust_period: Duration, clock: C) -> Self {
+        Self {
+            repository,
+            trust_period,
+            clock,
+        }
+    }
+
+    /// Records a sign-in from the device, registering it on first sight.
+    pub fn record_sign_in(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        fingerprint: &str,
+        name: &str,
+    ) -> Result<Device, MfaError> {
+        let now = self.clock.now();
+        self.repository.record_seen(&Device {
+            device_id: device_id(),
+            tenant_id: tenant_id.to_owned(),
+            username: username.to_owned(),
+            fingerprint_hash: digest(fingerprint),
+            name: name.to_owned(),
+            first_seen_at: now,
+            last_seen_at: now,
+            trusted_until: None,
+            secret_hash: None,
+        })
+    }
+
+    /// Trusts the device for the configured period, e.g. after the user
+    /// completes MFA and ticks "remember this device".
+    ///
+    /// Returns the device secret to hand to the client; only its digest is
+    /// stored, and trusting the device again replaces it.
+    pub fn trust(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        device_id: &str,
+    ) -> Result<String, MfaError> {
+        let secret = random_token();
+        let until = self.clock.now() + self.trust_period;
+        if !self
+            .repository
+            .trust(tenant_id, username, device_id, &digest(&secret), until)?
+        {
+            return Err(MfaError::UnknownDevice(device_id.to_owned()));
+        }
+        Ok(secret)
+    }
+
+    /// Whether MFA can be skipped for a sign-in from the device, which must
+    /// present the secret it was given when trusted.
+    pub fn is_trusted(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        fingerprint: &str,
+        device_secret: &str,
+    ) -> Result<bool, MfaError> {
+        let now = self.clock.now();
+        let fingerprint_hash = digest(fingerprint);
+        Ok(self
+            .repository
+            .devices_of(tenant_id, username)?
+            .into_iter()
+            .find(|d| d.fingerprint_hash == fingerprint_hash)
+            .is_some_and(|device| device.is_trusted_at(now) && device.holds_secret(device_secret)))
+    }
+
+    /// The user's devices, most recently seen first.
+    pub fn devices(&self, tenant_id: &str, username: &str) -> Result<Vec<Device>, MfaError> {
+        let mut devices = self.repository.devices_of(tenant_id, username)?;
+        devices.sort_by_key(|d| std::cmp::Reverse(d.last_seen_at));
+        Ok(devices)
+    }
+
+    pub fn revoke(&self, tenant_id: &str, username: &str, device_id: &str) -> Result<(), MfaError> {
+        self.repository.remove(tenant_id, username, device_id)
+    }
+
+    pub fn revoke_all(&self, tenant_id: &str, username: &str) -> Result<(), MfaError> {
+        for device in self.repository.devices_of(tenant_id, username)? {
+            self.repository
+                .remove(tenant_id, username, &device.device_id)?;
+        }
+        Ok(())
+    }
+}
+
+fn device_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn service() -> DeviceService<InMemoryDeviceRepository, FixedClock> {
+        DeviceService::new(
+            InMemoryDeviceRepository::new(),
+            Duration::days(30),
+            FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        )
+    }
+
+    #[test]
+    fn trust_requires_the_device_secret() {
+        let service = service();
+        let device = service
+            .record_sign_in("acme", "ada", "fingerprint", "Laptop")
+            .unwrap();
+        assert!(!service
+            .is_trusted("acme", "ada", "fingerprint", "")
+            .unwrap());
+
+        let secret = service.trust("acme", "ada", device.device_id()).unwrap();
+        assert!(service
+            .is_trusted("acme", "ada", "fingerprint", &secret)
+            .unwrap());
+        assert!(!service
+            .is_trusted("acme", "ada", "fingerprint", "guessed")
+            .unwrap());
+        assert!(!service
+            .is_trusted("acme", "ada", "copied fingerprint", &secret)
+            .unwrap());
+        assert!(!service
+            .is_trusted("acme", "eve", "fingerprint", &secret)
+            .unwrap());
+    }
+
+    #[test]
+    fn trust_expires_and_is_replaced() {
+        let service = service();
+        let device = service
+            .record_sign_in("acme", "ada", "fingerprint", "Laptop")
+            .unwrap();
+        let first = service.trust("acme", "ada", device.device_id()).unwrap();
+        let second = service.trust("acme", "ada", device.device_id()).unwrap();
+        assert!(!service
+            .is_trusted("acme", "ada", "fingerprint", &first)
+            .unwrap());
+        assert!(service
+            .is_trusted("acme", "ada", "fingerprint", &second)
+            .unwrap());
+
+        service.clock.advance(Duration::days(31));
+        assert!(!service
+            .is_trusted("acme", "ada", "fingerprint", &second)
+            .unwrap());
+        assert!(matches!(
+            service.trust("acme", "eve", device.device_id()),
+            Err(MfaError::UnknownDevice(_))
+        ));
+    }
+
+    #[test]
+    fn concurrent_sign_ins_register_a_device_once() {
+        let service = Arc::new(service());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let service = Arc::clone(&service);
+                thread::spawn(move || {
+                    service
+                        .record_sign_in("acme", "ada", "fingerprint", "Laptop")
+                        .unwrap()
+                })
+            })
+            .collect();
+        let ids: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().device_id)
+            .collect();
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        assert_eq!(service.devices("acme", "ada").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sign_in_updates_last_seen() {
+        let service = service();
+        let first = service
+            .record_sign_in("acme", "ada", "fingerprint", "Laptop")
+            .unwrap();
+        service.clock.advance(Duration::hours(1));
+        let again = service
+            .record_sign_in("acme", "ada", "fingerprint", "Laptop")
+            .unwrap();
+        assert_eq!(again.device_id(), first.device_id());
+        assert_eq!(again.first_seen_at(), first.first_seen_at());
+        assert_eq!(
+            again.last_seen_at(),
+            first.last_seen_at() + Duration::hours(1)
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use thiserror::Error;
+
+use crate::common::clock::Clock;
+
+mod credential;
+mod device;
+mod recovery;
+mod totp;
+
+pub use credential::{
+    EncryptedSecret, InMemoryMfaCredentialRepository, MfaCredential, MfaCredentialRepository,
+    SecretCipher,
+};
+pub use device::{Device, DeviceRepository, DeviceService, InMemoryDeviceRepository};
+pub use recovery::{RecoveryCodes, RECOVERY_CODE_COUNT};
+pub use totp::{Totp, TotpSecret};
+
+/// Errors raised by the MFA subsystem.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MfaError {
+    #[error("invalid authenticator secret")]
+    InvalidSecret,
+    #[error("invalid MFA parameters")]
+    InvalidParameters,
+    #[error("cannot encrypt authenticator secret")]
+    Encryption,
+    #[error("cannot decrypt authenticator secret")]
+    Decryption,
+    #[error("invalid verification code")]
+    InvalidCode,
+    #[error("user {0} is not enrolled in MFA")]
+    NotEnrolled(String),
+    #[error("MFA is required for user {0}")]
+    Required(String),
+    #[error("unknown device: {0}")]
+    UnknownDevice(String),
+    #[error("MFA storage failure: {0}")]
+    Storage(String),
+}
+
+/// A TOTP enrollment waiting for the user to confirm a first code.
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    secret: TotpSecret,
+    provisioning_uri: String,
+}
+
+impl TotpEnrollment {
+    pub fn secret(&self) -> &TotpSecret {
+        &self.secret
+    }
+
+    /// The `otpauth://` URI to show as a QR code.
+    pub fn provisioning_uri(&self) -> &str {
+        &self.provisioning_uri
+    }
+}
+
+/// Application service for enrolling and verifying TOTP authenticators.
+pub struct MfaService<R, C> {
+    repository: R,
+    cipher: SecretCipher,
+    totp: Totp,
+    issuer: String,
+    clock: C,
+}
+
+impl<R: MfaCredentialRepository, C: Clock> MfaService<R, C> {
+    pub fn new(
+        repository: R,
+        cipher: SecretCipher,
+        totp: Totp,
+        issuer: impl Into<String>,
+        clock: C,
+    ) -> Self {
+        Self {
+            repository,
+            cipher,
+            totp,
+            issuer: issuer.into(),
+            clock,
+        }
+    }
+
+    /// Generates a secret for the user; nothing is stored until the
+    /// enrollment is confirmed.
+    pub fn begin_enrollment(&self, username: &str) -> TotpEnrollment {
+        let secret = TotpSecret::generate();
+        let provisioning_uri = self.totp.provisioning_uri(&secret, &self.issuer, username);
+        TotpEnrollment {
+            secret,
+            provisioning_uri,
+        }
+    }
+
+    /// Stores the enrollment once the user proves their authenticator works,
+    /// returning the initial set of recovery codes.
+    pub fn confirm_enrollment(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        enrollment: &TotpEnrollment,
+        code: &str,
+    ) -> Result<RecoveryCodes, MfaError> {
+        let step = self
+            .totp
+            .verify(&enrollment.secret, code, None, &self.clock)
+            .ok_or(MfaError::InvalidCode)?;
+        let secret = self
+            .cipher
+            .encrypt(&enrollment.secret, tenant_id, username)?;
+        let mut credential = MfaCredential::new(tenant_id, username, secret, self.clock.now());
+        credential.record_use(step);
+        let recovery_codes = RecoveryCodes::generate();
+        credential.replace_recovery_codes(&recovery_codes);
+        self.repository.save(&credential)?;
+        Ok(recovery_codes)
+    }
+
+    pub fn is_enrolled(&self, tenant_id: &str, username: &str) -> Result<bool, MfaError> {
+        Ok(self
+            .repository
+            .credential_of(tenant_id, username)?
+            .is_some())
+    }
+
+    /// Verifies a code during sign-in, refusing replays of accepted codes.
+    pub fn verify(&self, tenant_id: &str, username: &str, code: &str) -> Result<bool, MfaError> {
+        let credential = self.enrolled_credential(tenant_id, username)?;
+        let secret = self
+            .cipher
+            .decrypt(credential.secret(), tenant_id, username)?;
+        match self
+            .totp
+            .verify(&secret, code, credential.last_used_step(), &self.clock)
+        {
+            Some(step) => self
+                .repository
+                .record_use_if_newer(tenant_id, username, step),
+            None => Ok(false),
+        }
+    }
+
+    /// Signs in with a recovery code when the authenticator is unavailable;
+    /// each code works only once.
+    pub fn redeem_recovery_code(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        code: &str,
+    ) -> Result<bool, MfaError> {
+        self.repository
+            .consume_recovery_code(tenant_id, username, &recovery::hash(code))
+    }
+
+    /// Replaces the user's recovery codes with a fresh set.
+    pub fn regenerate_recovery_codes(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<RecoveryCodes, MfaError> {
+        let recovery_codes = RecoveryCodes::generate();
+        self.repository
+            .replace_recovery_codes(tenant_id, username, &recovery_codes)?;
+        Ok(recovery_codes)
+    }
+
+    pub fn remaining_recovery_codes(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<usize, MfaError> {
+        Ok(self
+            .enrolled_credential(tenant_id, username)?
+            .remaining_recovery_codes())
+    }
+
+    /// Refuses to disable MFA for users who are required to use it.
+    pub fn disable(&self, tenant_id: &str, username: &str) -> Result<(), MfaError> {
+        if self.repository.is_required(tenant_id, username)? {
+            return Err(MfaError::Required(username.to_owned()));
+        }
+        self.repository.remove(tenant_id, username)
+    }
+
+    /// Makes MFA mandatory for the user, or optional again.
+    pub fn set_required(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        required: bool,
+    ) -> Result<(), MfaError> {
+        self.repository.set_required(tenant_id, username, required)
+    }
+
+    pub fn is_required(&self, tenant_id: &str, username: &str) -> Result<bool, MfaError> {
+        self.repository.is_required(tenant_id, username)
+    }
+
+    /// Whether the user must enroll an authenticator before a sign-in can
+    /// complete.
+    pub fn requires_enrollment(&self, tenant_id: &str, username: &str) -> Result<bool, MfaError> {
+        Ok(self.is_required(tenant_id, username)? && !self.is_enrolled(tenant_id, username)?)
+    }
+
+    fn enrolled_credential(
+        &self,
+        tenant_id: &str,
+        username: &str,
+    ) -> Result<MfaCredential, MfaError> {
+        self.repository
+            .credential_of(tenant_id, username)?
+            .ok_or_else(|| MfaError::NotEnrolled(username.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()
+    }
+
+    fn service() -> MfaService<InMemoryMfaCredentialRepository, FixedClock> {
+        MfaService::new(
+            InMemoryMfaCredentialRepository::new(),
+            SecretCipher::new("k1", &[7; 32]).unwrap(),
+            Totp::default(),
+            "Acme",
+            FixedClock::new(now()),
+        )
+    }
+
+    fn enroll(
+        service: &MfaService<InMemoryMfaCredentialRepository, FixedClock>,
+    ) -> (TotpSecret, RecoveryCodes) {
+        let enrollment = service.begin_enrollment("ada");
+        let code = Totp::default().code_at(enrollment.secret(), now() - Duration::seconds(30));
+        let codes = service
+            .confirm_enrollment("acme", "ada", &enrollment, &code)
+            .unwrap();
+        (enrollment.secret().clone(), codes)
+    }
+
+    #[test]
+    fn accepted_code_cannot_be_replayed() {
+        let service = service();
+        let (secret, _) = enroll(&service);
+        let code = Totp::default().code_at(&secret, now());
+        assert!(service.verify("acme", "ada", &code).unwrap());
+        assert!(!service.verify("acme", "ada", &code).unwrap());
+    }
+
+    #[test]
+    fn code_older_than_the_enrollment_code_is_refused() {
+        let service = service();
+        let (secret, _) = enroll(&service);
+        let code = Totp::default().code_at(&secret, now() - Duration::seconds(30));
+        assert!(!service.verify("acme", "ada", &code).unwrap());
+    }
+
+    #[test]
+    fn stale_step_is_refused_by_the_repository() {
+        let repository = InMemoryMfaCredentialRepository::new();
+        let cipher = SecretCipher::new("k1", &[7; 32]).unwrap();
+        let secret = cipher
+            .encrypt(&TotpSecret::generate(), "acme", "ada")
+            .unwrap();
+        repository
+            .save(&MfaCredential::new("acme", "ada", secret, now()))
+            .unwrap();
+        assert!(repository.record_use_if_newer("acme", "ada", 5).unwrap());
+        assert!(!repository.record_use_if_newer("acme", "ada", 5).unwrap());
+        assert!(!repository.record_use_if_newer("acme", "ada", 4).unwrap());
+        assert!(repository.record_use_if_newer("acme", "ada", 6).unwrap());
+    }
+
+    #[test]
+    fn required_users_must_enroll_and_cannot_disable() {
+        let service = service();
+        service.set_required("acme", "ada", true).unwrap();
+        assert!(service.requires_enrollment("acme", "ada").unwrap());
+        enroll(&service);
+        assert!(!service.requires_enrollment("acme", "ada").unwrap());
+        assert!(matches!(
+            service.disable("acme", "ada"),
+            Err(MfaError::Required(_))
+        ));
+
+        service.set_required("acme", "ada", false).unwrap();
+        service.disable("acme", "ada").unwrap();
+        assert!(!service.is_enrolled("acme", "ada").unwrap());
+    }
+
+    #[test]
+    fn recovery_code_works_once() {
+        let service = service();
+        let (_, codes) = enroll(&service);
+        let code = codes.codes()[3].clone();
+        assert!(service.redeem_recovery_code("acme", "ada", &code).unwrap());
+        assert!(!service.redeem_recovery_code("acme", "ada", &code).unwrap());
+        assert_eq!(
+            service.remaining_recovery_codes("acme", "ada").unwrap(),
+            RECOVERY_CODE_COUNT - 1
+        );
+    }
+
+    #[test]
+    fn recovery_codes_ignore_separators_and_case() {
+        let service = service();
+        let (_, codes) = enroll(&service);
+        let retyped = codes.codes()[0].replace('-', " ").to_uppercase();
+        assert!(service
+            .redeem_recovery_code("acme", "ada", &retyped)
+            .unwrap());
+        assert!(!service
+            .redeem_recovery_code("acme", "ada", &codes.codes()[0])
+            .unwrap());
+    }
+
+    #[test]
+    fn regenerating_invalidates_previous_codes_but_keeps_the_step() {
+        let service = service();
+        let (secret, old) = enroll(&service);
+        let code = Totp::default().code_at(&secret, now());
+        assert!(service.verify("acme", "ada", &code).unwrap());
+
+        let new = service.regenerate_recovery_codes("acme", "ada").unwrap();
+        assert!(!service
+            .redeem_recovery_code("acme", "ada", &old.codes()[0])
+            .unwrap());
+        assert!(service
+            .redeem_recovery_code("acme", "ada", &new.codes()[0])
+            .unwrap());
+        assert!(!service.verify("acme", "ada", &code).unwrap());
+    }
+}
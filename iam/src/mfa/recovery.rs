@@ -0,0 +1,75 @@
+use std::fmt;
+
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Number of codes in a freshly generated set.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Crockford base32, so codes survive being read aloud or retyped.
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+const GROUPS: usize = 4;
+const GROUP_LENGTH: usize = 4;
+
+/// A freshly generated set of recovery codes, shown to the user once.
+///
+/// Codes carry 80 bits of entropy, so they are stored as plain SHA-256
+/// digests rather than with a slow password hash.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct RecoveryCodes(Vec<String>);
+
+impl RecoveryCodes {
+    pub fn generate() -> Self {
+        Self((0..RECOVERY_CODE_COUNT).map(|_| generate_code()).collect())
+    }
+
+    pub fn codes(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Digests of the codes, as persisted.
+    pub fn hashes(&self) -> Vec<String> {
+        self.0.iter().map(|code| hash(code)).collect()
+    }
+}
+
+impl fmt::Debug for RecoveryCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RecoveryCodes({} codes)", self.0.len())
+    }
+}
+
+/// Digest of a code as entered by the user.
+///
+/// Separators and case are ignored, and the letters Crockford base32 treats
+/// as look-alikes (`o`, `i`, `l`) are read as digits.
+pub(crate) fn hash(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| match c.to_ascii_lowercase() {
+            'o' => '0',
+            'i' | 'l' => '1',
+            c => c,
+        })
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn generate_code() -> String {
+    let mut bytes = [0u8; GROUPS * GROUP_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    let code = bytes
+        .chunks(GROUP_LENGTH)
+        .map(|group| {
+            group
+                .iter()
+                .map(|b| char::from(ALPHABET[usize::from(b % 32)]))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+    bytes.zeroize();
+    code
+}
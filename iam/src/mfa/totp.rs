@@ -0,0 +1,252 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::MfaError;
+use crate::common::clock::Clock;
+
+/// Shared secret of a TOTP authenticator.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    /// Length of generated secrets, matching the HMAC-SHA1 block output.
+    pub const LENGTH: usize = 20;
+
+    pub fn generate() -> Self {
+        let mut bytes = vec![0; Self::LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Result<Self, MfaError> {
+        let bytes = bytes.into();
+        if bytes.len() < 10 {
+            return Err(MfaError::InvalidSecret);
+        }
+        Ok(Self(bytes))
+    }
+
+    /// Parses the base32 form shown to users during enrollment.
+    pub fn from_base32(encoded: &str) -> Result<Self, MfaError> {
+        let normalized: String = encoded
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>()
+            .trim_end_matches('=')
+            .to_owned();
+        let bytes = BASE32_NOPAD
+            .decode(normalized.as_bytes())
+            .map_err(|_| MfaError::InvalidSecret)?;
+        Self::from_bytes(bytes)
+    }
+
+    pub fn to_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TotpSecret(***)")
+    }
+}
+
+/// Time-based one-time password generator and verifier (RFC 6238, SHA-1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Totp {
+    digits: u32,
+    period: u64,
+    skew: u64,
+}
+
+impl Totp {
+    /// Largest accepted skew; every extra step widens the window an
+    /// observed code stays valid in.
+    pub const MAX_SKEW: u64 = 2;
+
+    /// Creates a generator producing `digits` long codes every `period`
+    /// seconds, accepting codes up to `skew` periods away from the current one.
+    pub fn new(digits: u32, period: u64, skew: u64) -> Result<Self, MfaError> {
+        if !(6..=8).contains(&digits) || period == 0 || skew > Self::MAX_SKEW {
+            return Err(MfaError::InvalidParameters);
+        }
+        Ok(Self {
+            digits,
+            period,
+            skew,
+        })
+    }
+
+    pub fn digits(&self) -> u32 {
+        self.digits
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// The time step a given instant falls into.
+    pub fn step_at(&self, at: DateTime<Utc>) -> u64 {
+        u64::try_from(at.timestamp()).unwrap_or_default() / self.period
+    }
+
+    /// The code valid at the given instant.
+    pub fn code_at(&self, secret: &TotpSecret, at: DateTime<Utc>) -> String {
+        self.code_for_step(secret, self.step_at(at))
+    }
+
+    /// Verifies a code, returning the matched time step.
+    ///
+    /// Steps at or before `last_used_step` are refused so a code cannot be
+    /// replayed; callers persist the returned step for the next check.
+    pub fn verify(
+        &self,
+        secret: &TotpSecret,
+        code: &str,
+        last_used_step: Option<u64>,
+        clock: &impl Clock,
+    ) -> Option<u64> {
+        let code = code.trim();
+        let current = self.step_at(clock.now());
+        let earliest = current.saturating_sub(self.skew);
+        (earliest..=current.saturating_add(self.skew))
+            .filter(|step| last_used_step.is_none_or(|last| *step > last))
+            .find(|step| {
+                constant_time_eq(
+                    self.code_for_step(secret, *step).as_bytes(),
+                    code.as_bytes(),
+                )
+            })
+    }
+
+    /// The `otpauth://` URI encoded in enrollment QR codes.
+    pub fn provisioning_uri(&self, secret: &TotpSecret, issuer: &str, account: &str) -> String {
+        let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC).to_string();
+        let account = utf8_percent_encode(account, NON_ALPHANUMERIC).to_string();
+        format!(
+            "otpauth://totp/{issuer}:{account}?secret={}&issuer={issuer}&algorithm=SHA1&digits={}&period={}",
+            secret.to_base32(),
+            self.digits,
+            self.period,
+        )
+    }
+
+    fn code_for_step(&self, secret: &TotpSecret, step: u64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes(
+            digest[offset..offset + 4]
+                .try_into()
+                .expect("HMAC-SHA1 digest is 20 bytes"),
+        ) & 0x7fff_ffff;
+        let code = binary % 10u32.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            period: 30,
+            skew: 1,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret::from_bytes(b"12345678901234567890".to_vec()).unwrap()
+    }
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn rfc4226_hotp_vectors() {
+        let hotp = Totp::new(6, 1, 0).unwrap();
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp.code_at(&rfc_secret(), at(counter as i64)), *code);
+        }
+    }
+
+    #[test]
+    fn rfc6238_sha1_vectors() {
+        let totp = Totp::new(8, 30, 0).unwrap();
+        let expected = [
+            (59, "94287082"),
+            (1_111_111_109, "07081804"),
+            (1_111_111_111, "14050471"),
+            (1_234_567_890, "89005924"),
+            (2_000_000_000, "69279037"),
+            (20_000_000_000, "65353130"),
+        ];
+        for (timestamp, code) in expected {
+            assert_eq!(totp.code_at(&rfc_secret(), at(timestamp)), code);
+        }
+    }
+
+    #[test]
+    fn verify_accepts_skew_and_refuses_replays() {
+        let totp = Totp::default();
+        let secret = rfc_secret();
+        let clock = FixedClock::new(at(1_111_111_111));
+        let previous = totp.code_at(&secret, at(1_111_111_111 - 30));
+        let step = totp.verify(&secret, &previous, None, &clock).unwrap();
+        assert_eq!(step, totp.step_at(clock.now()) - 1);
+        assert_eq!(totp.verify(&secret, &previous, Some(step), &clock), None);
+
+        let stale = totp.code_at(&secret, at(1_111_111_111 - 60));
+        assert_eq!(totp.verify(&secret, &stale, None, &clock), None);
+    }
+
+    #[test]
+    fn skew_is_bounded() {
+        assert!(Totp::new(6, 30, Totp::MAX_SKEW).is_ok());
+        assert!(matches!(
+            Totp::new(6, 30, Totp::MAX_SKEW + 1),
+            Err(MfaError::InvalidParameters)
+        ));
+    }
+
+    #[test]
+    fn base32_secrets_round_trip() {
+        let secret = TotpSecret::from_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(secret, rfc_secret());
+        assert_eq!(
+            TotpSecret::from_base32(&secret.to_base32()).unwrap(),
+            secret
+        );
+        assert!(TotpSecret::from_base32("GEZDGNBV").is_err());
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use super::client::{Client, ClientRepository, GrantType};
+use super::OAuthError;
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+use crate::identity::authentication::AuthenticationStrength;
+use crate::tokens::TokenSubject;
+
+/// The only PKCE method accepted; `plain` offers no protection.
+pub const PKCE_METHOD: &str = "S256";
+
+/// A request to the authorization endpoint, after the user signed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationCodeRequest {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+}
+
+impl AuthorizationCodeRequest {
+    pub fn new(
+        client_id: impl Into<String>,
+        redirect_uri: impl Into<String>,
+        code_challenge: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            redirect_uri: redirect_uri.into(),
+            scopes: Vec::new(),
+            state: None,
+            nonce: None,
+            code_challenge: code_challenge.into(),
+            code_challenge_method: PKCE_METHOD.to_owned(),
+        }
+    }
+
+    /// Sets the scopes from a space-separated `scope` parameter.
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scopes = super::parse_scope(scope);
+        self
+    }
+
+    pub fn with_state(mut self, state: impl Into<String>) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+
+    pub fn with_nonce(mut self, nonce: impl Into<String>) -> Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+}
+
+/// What the authorization endpoint redirects back with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationResponse {
+    pub redirect_uri: String,
+    pub code: String,
+    pub state: Option<String>,
+}
+
+/// An authorization code as persisted; only its digest is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationCodeRecord {
+    hash: String,
+    client_id: String,
+    tenant_id: String,
+    username: String,
+    roles: Vec<String>,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+    strength: AuthenticationStrength,
+    authenticated_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    redeemed: bool,
+}
+
+impl AuthorizationCodeRecord {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    pub fn is_redeemed(&self) -> bool {
+        self.redeemed
+    }
+}
+
+/// Stores issued authorization codes by digest.
+pub trait AuthorizationCodeRepository: Send + Sync {
+    fn code_of_hash(&self, hash: &str) -> Result<Option<AuthorizationCodeRecord>, OAuthError>;
+
+    /// Inserts or replaces the record with the same digest.
+    fn save(&self, record: &AuthorizationCodeRecord) -> Result<(), OAuthError>;
+
+    /// Marks the code redeemed in a single step, returning it only if this
+    /// call did so, so that concurrent exchanges cannot both succeed.
+    fn redeem(&self, hash: &str) -> Result<Option<AuthorizationCodeRecord>, OAuthError>;
+
+    /// Deletes codes that expired by the given instant, redeemed or not,
+    /// returning how many were removed.
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, OAuthError>;
+}
+
+/// Repository keeping authorization codes in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryAuthorizationCodeRepository {
+    codes: RwLock<HashMap<String, AuthorizationCodeRecord>>,
+}
+
+impl InMemoryAuthorizationCodeRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AuthorizationCodeRepository for InMemoryAuthorizationCodeRepository {
+    fn code_of_hash(&self, hash: &str) -> Result<Option<AuthorizationCodeRecord>, OAuthError> {
+        let codes = self
+            .codes
+            .read()
+            .expect("authorization codes lock poisoned");
+        Ok(codes.get(hash).cloned())
+    }
+
+    fn save(&self, record: &AuthorizationCodeRecord) -> Result<(), OAuthError> {
+        let mut codes = self
+            .codes
+            .write()
+            .expect("authorization codes lock poisoned");
+        codes.insert(record.hash.clone(), record.clone());
+        Ok(())
+    }
+
+    fn redeem(&self, hash: &str) -> Result<Option<AuthorizationCodeRecord>, OAuthError> {
+        let mut codes = self
+            .codes
+            .write()
+            .expect("authorization codes lock poisoned");
+        Ok(codes
+            .get_mut(hash)
+            .filter(|record| !record.redeemed)
+            .map(|record| {
+                record.redeemed = true;
+                record.clone()
+            }))
+    }
+
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, OAuthError> {
+        let mut codes = self
+            .codes
+            .write()
+            .expect("authorization codes lock poisoned");
+        let count = codes.len();
+        codes.retain(|_, record| now < record.expires_at);
+        Ok((count - codes.len()) as u64)
+    }
+}
+
+/// The outcome of redeeming an authorization code at the token endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationGrant {
+    /// Ready to mint the access token, carrying the granted scopes.
+    pub subject: TokenSubject,
+    pub scopes: Vec<String>,
+    pub nonce: Option<String>,
+}
+
+impl AuthorizationGrant {
+    /// Whether an ID token should be issued as well.
+    pub fn is_openid(&self) -> bool {
+        self.scopes.iter().any(|s| s == "openid")
+    }
+}
+
+/// Authorization code flow with mandatory PKCE.
+pub struct AuthorizationCodeService<R, K, C> {
+    codes: R,
+    clients: K,
+    code_ttl: Duration,
+    clock: C,
+}
+
+impl<R: AuthorizationCodeRepository, K: ClientRepository, C: Clock>
+    AuthorizationCodeService<R, K, C>
+{
+    pub const DEFAULT_CODE_TTL_SECONDS: i64 = 60;
+
+    pub fn new(codes: R, clients: K, clock: C) -> Self {
+        Self {
+            codes,
+            clients,
+            code_ttl: Duration::seconds(Self::DEFAULT_CODE_TTL_SECONDS),
+            clock,
+        }
+    }
+
+    pub fn with_code_ttl(mut self, code_ttl: Duration) -> Self {
+        self.code_ttl = code_ttl;
+        self
+    }
+
+    /// Validates the request of the client the user is being sent back to.
+    ///
+    /// Errors before the redirect URI is known to be registered must be shown
+    /// to the user rather than redirected.
+    pub fn validate(&self, request: &AuthorizationCodeRequest) -> Result<Client, OAuthError> {
+        let client = self
+            .clients
+            .client_of(&request.client_id)?
+            .ok_or(OAuthError::InvalidClient)?;
+        if !client.allows_redirect_uri(&request.redirect_uri) {
+            return Err(OAuthError::InvalidRequest(
+                "redirect_uri is not registered".to_owned(),
+            ));
+        }
+        if !client.allows_grant(GrantType::AuthorizationCode) {
+            return Err(OAuthError::UnauthorizedClient(
+                GrantType::AuthorizationCode.to_string(),
+            ));
+        }
+        if request.code_challenge_method != PKCE_METHOD {
+            return Err(OAuthError::InvalidRequest(format!(
+                "code_challenge_method must be {}",
+                PKCE_METHOD
+            )));
+        }
+        if BASE64URL_NOPAD
+            .decode(request.code_challenge.as_bytes())
+            .map_or(true, |challenge| challenge.len() != 32)
+        {
+            return Err(OAuthError::InvalidRequest(
+                "malformed code_challenge".to_owned(),
+            ));
+        }
+        client.check_scopes(&request.scopes)?;
+        Ok(client)
+    }
+
+    /// Issues a code for the signed-in user, who must belong to the client's
+    /// tenant.
+    pub fn authorize(
+        &self,
+        request: &AuthorizationCodeRequest,
+        user: &TokenSubject,
+    ) -> Result<AuthorizationResponse, OAuthError> {
+        let client = self.validate(request)?;
+        if user.tenant_id() != client.tenant_id() {
+            return Err(OAuthError::AccessDenied);
+        }
+        let now = self.clock.now();
+        let code = random_token();
+        self.codes.save(&AuthorizationCodeRecord {
+            hash: digest(&code),
+            client_id: client.client_id().to_owned(),
+            tenant_id: user.tenant_id().to_owned(),
+            username: user.username().to_owned(),
+            roles: user.roles().to_vec(),
+            redirect_uri: request.redirect_uri.clone(),
+            scopes: request.scopes.clone(),
+            nonce: request.nonce.clone(),
+            code_challenge: request.code_challenge.clone(),
+            strength: user.strength(),
+            authenticated_at: user.authenticated_at().unwrap_or(now),
+            expires_at: now + self.code_ttl,
+            redeemed: false,
+        })?;
+        Ok(AuthorizationResponse {
+            redirect_uri: request.redirect_uri.clone(),
+            code,
+            state: request.state.clone(),
+        })
+    }
+
+    /// Redeems a code for the authenticated client; codes are single use.
+    pub fn exchange(
+        &self,
+        client: &Client,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<AuthorizationGrant, OAuthError> {
+        client.ensure_grant(GrantType::AuthorizationCode)?;
+        let invalid = |reason: &str| OAuthError::InvalidGrant(reason.to_owned());
+        let hash = digest(code);
+        let issued_to = self
+            .codes
+            .code_of_hash(&hash)?
+            .ok_or_else(|| invalid("unknown authorization code"))?
+            .client_id;
+        if issued_to != client.client_id() {
+            return Err(invalid("authorization code was issued to another client"));
+        }
+        let record = self
+            .codes
+            .redeem(&hash)?
+            .ok_or_else(|| invalid("authorization code already used"))?;
+        if self.clock.now() >= record.expires_at {
+            return Err(invalid("authorization code expired"));
+        }
+        if record.redirect_uri != redirect_uri {
+            return Err(invalid("redirect_uri does not match"));
+        }
+        if !verifies_challenge(code_verifier, &record.code_challenge) {
+            return Err(invalid("code_verifier does not match"));
+        }
+        let subject = TokenSubject::new(record.tenant_id, record.username)
+            .with_roles(record.roles)
+            .authenticated_with(record.strength, record.authenticated_at)
+            .with_scopes(record.scopes.iter().cloned())
+            .for_client(record.client_id);
+        Ok(AuthorizationGrant {
+            subject,
+            scopes: record.scopes,
+            nonce: record.nonce,
+        })
+    }
+
+    /// Deletes expired codes; meant to be called on a schedule.
+    pub fn purge_expired(&self) -> Result<u64, OAuthError> {
+        self.codes.purge_expired(self.clock.now())
+    }
+}
+
+/// The S256 challenge of a PKCE code verifier.
+pub fn code_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn verifies_challenge(code_verifier: &str, challenge: &str) -> bool {
+    let well_formed = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'));
+    well_formed
+        && bool::from(
+            code_challenge(code_verifier)
+                .as_bytes()
+                .ct_eq(challenge.as_bytes()),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::oauth::InMemoryClientRepository;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+    type Service = AuthorizationCodeService<
+        InMemoryAuthorizationCodeRepository,
+        InMemoryClientRepository,
+        FixedClock,
+    >;
+
+    fn client(client_id: &str) -> Client {
+        Client::public(client_id, "acme", "App").with_redirect_uri(REDIRECT_URI)
+    }
+
+    fn service() -> Service {
+        let clients = InMemoryClientRepository::new();
+        clients.save(&client("app")).unwrap();
+        clients.save(&client("other")).unwrap();
+        AuthorizationCodeService::new(
+            InMemoryAuthorizationCodeRepository::new(),
+            clients,
+            FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        )
+    }
+
+    fn authorize(service: &Service) -> String {
+        let request = AuthorizationCodeRequest::new("app", REDIRECT_URI, CHALLENGE);
+        service
+            .authorize(&request, &TokenSubject::new("acme", "ada"))
+            .unwrap()
+            .code
+    }
+
+    #[test]
+    fn s256_challenge_matches_rfc_7636_appendix_b() {
+        assert_eq!(code_challenge(VERIFIER), CHALLENGE);
+        assert!(verifies_challenge(VERIFIER, CHALLENGE));
+        assert!(!verifies_challenge(&VERIFIER.replace('d', "e"), CHALLENGE));
+        assert!(!verifies_challenge(
+            &VERIFIER[..42],
+            &code_challenge(&VERIFIER[..42])
+        ));
+    }
+
+    #[test]
+    fn code_is_redeemed_once() {
+        let service = service();
+        let code = authorize(&service);
+        let grant = service
+            .exchange(&client("app"), &code, REDIRECT_URI, VERIFIER)
+            .unwrap();
+        assert_eq!(grant.subject.username(), "ada");
+        assert!(matches!(
+            service.exchange(&client("app"), &code, REDIRECT_URI, VERIFIER),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+    }
+
+    #[test]
+    fn concurrent_exchanges_redeem_the_code_once() {
+        let service = service();
+        let code = authorize(&service);
+        let client = client("app");
+        let successes = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| service.exchange(&client, &code, REDIRECT_URI, VERIFIER)))
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap().ok())
+                .count()
+        });
+        assert_eq!(successes, 1);
+    }
+
+    #[test]
+    fn another_client_cannot_burn_the_code() {
+        let service = service();
+        let code = authorize(&service);
+        assert!(service
+            .exchange(&client("other"), &code, REDIRECT_URI, VERIFIER)
+            .is_err());
+        assert!(service
+            .exchange(&client("app"), &code, REDIRECT_URI, VERIFIER)
+            .is_ok());
+    }
+
+    #[test]
+    fn failed_verification_still_consumes_the_code() {
+        let service = service();
+        let code = authorize(&service);
+        let wrong = VERIFIER.replace('d', "e");
+        assert!(service
+            .exchange(&client("app"), &code, REDIRECT_URI, &wrong)
+            .is_err());
+        assert!(service
+            .exchange(&client("app"), &code, REDIRECT_URI, VERIFIER)
+            .is_err());
+    }
+
+    #[test]
+    fn purge_drops_expired_codes() {
+        let service = service();
+        let redeemed = authorize(&service);
+        service
+            .exchange(&client("app"), &redeemed, REDIRECT_URI, VERIFIER)
+            .unwrap();
+        authorize(&service);
+        assert_eq!(service.purge_expired().unwrap(), 0);
+
+        service.clock.advance(service.code_ttl);
+        assert_eq!(service.purge_expired().unwrap(), 2);
+        assert!(matches!(
+            service.exchange(&client("app"), &redeemed, REDIRECT_URI, VERIFIER),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::access::Attributes;
+use crate::tokens::TokenSubject;
+
+/// Claims set by the service itself, which a mapping cannot override.
+const RESERVED_CLAIMS: &[&str] = &[
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "iat",
+    "nbf",
+    "jti",
+    "auth_time",
+    "nonce",
+    "acr",
+    "tenant_id",
+    "roles",
+    "scope",
+    "client_id",
+    "act",
+];
+
+/// Which tokens a claim is added to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimTarget {
+    IdToken,
+    #[default]
+    AccessToken,
+    Both,
+}
+
+impl ClaimTarget {
+    fn id_token(self) -> bool {
+        matches!(self, ClaimTarget::IdToken | ClaimTarget::Both)
+    }
+
+    fn access_token(self) -> bool {
+        matches!(self, ClaimTarget::AccessToken | ClaimTarget::Both)
+    }
+}
+
+/// Where the value of a claim comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "source")]
+pub enum ClaimSource {
+    /// A user or person attribute, e.g. `email` or a custom attribute.
+    Attribute { name: String },
+    /// The same value for every user.
+    Value { value: Value },
+}
+
+/// A claim to add to issued tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimRule {
+    pub claim: String,
+    #[serde(flatten)]
+    pub source: ClaimSource,
+    #[serde(default)]
+    pub target: ClaimTarget,
+}
+
+/// Which claims a client's tokens carry beyond the standard ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimMapping {
+    /// Tokens carrying the user's roles; `None` leaves them out.
+    #[serde(default = "roles_in_access_token")]
+    roles: Option<ClaimTarget>,
+    #[serde(default)]
+    rules: Vec<ClaimRule>,
+}
+
+fn roles_in_access_token() -> Option<ClaimTarget> {
+    Some(ClaimTarget::AccessToken)
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            roles: roles_in_access_token(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl ClaimMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the tokens carrying the user's roles, or none.
+    pub fn with_roles_in(mut self, target: Option<ClaimTarget>) -> Self {
+        self.roles = target;
+        self
+    }
+
+    /// Adds `claim` holding the value of the user attribute.
+    pub fn map_attribute(
+        mut self,
+        claim: impl Into<String>,
+        attribute: impl Into<String>,
+        target: ClaimTarget,
+    ) -> Self {
+        self.rules.push(ClaimRule {
+            claim: claim.into(),
+            source: ClaimSource::Attribute {
+                name: attribute.into(),
+            },
+            target,
+        });
+        self
+    }
+
+    /// Copies a custom attribute into both tokens under its own name.
+    pub fn pass_through(self, attribute: impl Into<String>) -> Self {
+        let attribute = attribute.into();
+        self.map_attribute(attribute.clone(), attribute, ClaimTarget::Both)
+    }
+
+    /// Adds `claim` with the same value for every user.
+    pub fn with_value(
+        mut self,
+        claim: impl Into<String>,
+        value: impl Into<Value>,
+        target: ClaimTarget,
+    ) -> Self {
+        self.rules.push(ClaimRule {
+            claim: claim.into(),
+            source: ClaimSource::Value {
+                value: value.into(),
+            },
+            target,
+        });
+        self
+    }
+
+    pub fn roles_in(&self) -> Option<ClaimTarget> {
+        self.roles
+    }
+
+    pub fn rules(&self) -> &[ClaimRule] {
+        &self.rules
+    }
+
+    /// Adds the mapped claims to the subject tokens are about to be minted
+    /// for.
+    ///
+    /// Rules naming a reserved claim, or an attribute the user lacks, are
+    /// skipped.
+    pub fn apply(&self, mut subject: TokenSubject, attributes: &Attributes) -> TokenSubject {
+        if self.roles.is_some_and(ClaimTarget::id_token) {
+            let roles = Value::from(subject.roles().to_vec());
+            subject = subject.with_id_token_claim("roles", roles);
+        }
+        if !self.roles.is_some_and(ClaimTarget::access_token) {
+            subject = subject.with_roles(Vec::<String>::new());
+        }
+        for rule in &self.rules {
+            if RESERVED_CLAIMS.contains(&rule.claim.as_str()) {
+                continue;
+            }
+            let value = match &rule.source {
+                ClaimSource::Attribute { name } => match attributes.get(name) {
+                    Some(value) => value.clone(),
+                    None => continue,
+                },
+                ClaimSource::Value { value } => value.clone(),
+            };
+            if rule.target.id_token() {
+                subject = subject.with_id_token_claim(rule.claim.clone(), value.clone());
+            }
+            if rule.target.access_token() {
+                subject = subject.with_access_token_claim(rule.claim.clone(), value);
+            }
+        }
+        subject
+    }
+}
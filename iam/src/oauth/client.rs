@@ -0,0 +1,324 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::claims::ClaimMapping;
+use super::token_exchange::TokenExchangePolicy;
+use super::OAuthError;
+use crate::identity::password::{EncryptedPassword, PasswordHashingStrategy, PlainPassword};
+
+/// OAuth 2.0 grant types a client may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum GrantType {
+    #[serde(rename = "authorization_code")]
+    AuthorizationCode,
+    #[serde(rename = "refresh_token")]
+    RefreshToken,
+    #[serde(rename = "client_credentials")]
+    ClientCredentials,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
+    DeviceCode,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:token-exchange")]
+    TokenExchange,
+}
+
+impl GrantType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GrantType::AuthorizationCode => "authorization_code",
+            GrantType::RefreshToken => "refresh_token",
+            GrantType::ClientCredentials => "client_credentials",
+            GrantType::DeviceCode => "urn:ietf:params:oauth:grant-type:device_code",
+            GrantType::TokenExchange => "urn:ietf:params:oauth:grant-type:token-exchange",
+        }
+    }
+}
+
+impl fmt::Display for GrantType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An application registered to obtain tokens on behalf of a tenant's users.
+///
+/// Confidential clients authenticate with a secret, of which only the hash
+/// is kept; public clients (single-page and native apps) have none and rely
+/// on PKCE.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Client {
+    client_id: String,
+    tenant_id: String,
+    name: String,
+    secret: Option<EncryptedPassword>,
+    redirect_uris: Vec<String>,
+    grant_types: BTreeSet<GrantType>,
+    scopes: BTreeSet<String>,
+    #[serde(default)]
+    claim_mapping: ClaimMapping,
+    #[serde(default)]
+    backchannel_logout_uri: Option<String>,
+    #[serde(default)]
+    token_exchange: TokenExchangePolicy,
+}
+
+impl Client {
+    pub fn public(
+        client_id: impl Into<String>,
+        tenant_id: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            tenant_id: tenant_id.into(),
+            name: name.into(),
+            secret: None,
+            redirect_uris: Vec::new(),
+            grant_types: BTreeSet::from([GrantType::AuthorizationCode, GrantType::RefreshToken]),
+            scopes: BTreeSet::from(["openid".to_owned()]),
+            claim_mapping: ClaimMapping::default(),
+            backchannel_logout_uri: None,
+            token_exchange: TokenExchangePolicy::default(),
+        }
+    }
+
+    pub fn confidential(
+        client_id: impl Into<String>,
+        tenant_id: impl Into<String>,
+        name: impl Into<String>,
+        secret: &PlainPassword,
+        strategy: &dyn PasswordHashingStrategy,
+    ) -> Result<Self, OAuthError> {
+        let mut client = Self::public(client_id, tenant_id, name);
+        client.secret = Some(
+            secret
+                .encrypt(strategy)
+                .map_err(|e| OAuthError::Storage(e.to_string()))?,
+        );
+        Ok(client)
+    }
+
+    pub fn with_redirect_uri(mut self, redirect_uri: impl Into<String>) -> Self {
+        self.redirect_uris.push(redirect_uri.into());
+        self
+    }
+
+    /// Replaces the grant types the client may use.
+    pub fn with_grant_types(mut self, grant_types: impl IntoIterator<Item = GrantType>) -> Self {
+        self.grant_types = grant_types.into_iter().collect();
+        self
+    }
+
+    /// Allows the client to request the scope.
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.insert(scope.into());
+        self
+    }
+
+    /// Sets the custom claims of the client's tokens.
+    pub fn with_claim_mapping(mut self, claim_mapping: ClaimMapping) -> Self {
+        self.claim_mapping = claim_mapping;
+        self
+    }
+
+    /// Registers the URI logout tokens are posted to.
+    pub fn with_backchannel_logout_uri(mut self, logout_uri: impl Into<String>) -> Self {
+        self.backchannel_logout_uri = Some(logout_uri.into());
+        self
+    }
+
+    /// Sets which token exchanges the client may perform.
+    pub fn with_token_exchange_policy(mut self, policy: TokenExchangePolicy) -> Self {
+        self.token_exchange = policy;
+        self
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn backchannel_logout_uri(&self) -> Option<&str> {
+        self.backchannel_logout_uri.as_deref()
+    }
+
+    pub fn token_exchange_policy(&self) -> &TokenExchangePolicy {
+        &self.token_exchange
+    }
+
+    pub fn claim_mapping(&self) -> &ClaimMapping {
+        &self.claim_mapping
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    pub fn redirect_uris(&self) -> &[String] {
+        &self.redirect_uris
+    }
+
+    pub fn allows_grant(&self, grant_type: GrantType) -> bool {
+        self.grant_types.contains(&grant_type)
+    }
+
+    pub fn scopes(&self) -> impl Iterator<Item = &str> + '_ {
+        self.scopes.iter().map(String::as_str)
+    }
+
+    /// Redirect URIs are compared exactly, as required by OAuth 2.1.
+    pub fn allows_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|uri| uri == redirect_uri)
+    }
+
+    /// Checks that every requested scope is allowed, returning them.
+    pub fn check_scopes(&self, requested: &[String]) -> Result<Vec<String>, OAuthError> {
+        match requested.iter().find(|s| !self.scopes.contains(*s)) {
+            Some(scope) => Err(OAuthError::InvalidScope(scope.clone())),
+            None => Ok(requested.to_vec()),
+        }
+    }
+
+    pub(crate) fn ensure_grant(&self, grant_type: GrantType) -> Result<(), OAuthError> {
+        if self.allows_grant(grant_type) {
+            Ok(())
+        } else {
+            Err(OAuthError::UnauthorizedClient(grant_type.to_string()))
+        }
+    }
+}
+
+/// Stores registered clients.
+pub trait ClientRepository: Send + Sync {
+    fn client_of(&self, client_id: &str) -> Result<Option<Client>, OAuthError>;
+
+    fn clients_of_tenant(&self, tenant_id: &str) -> Result<Vec<Client>, OAuthError>;
+
+    /// Inserts or replaces the client with the same id.
+    fn save(&self, client: &Client) -> Result<(), OAuthError>;
+
+    fn remove(&self, client_id: &str) -> Result<(), OAuthError>;
+}
+
+impl<R: ClientRepository + ?Sized> ClientRepository for Arc<R> {
+    fn client_of(&self, client_id: &str) -> Result<Option<Client>, OAuthError> {
+        (**self).client_of(client_id)
+    }
+
+    fn clients_of_tenant(&self, tenant_id: &str) -> Result<Vec<Client>, OAuthError> {
+        (**self).clients_of_tenant(tenant_id)
+    }
+
+    fn save(&self, client: &Client) -> Result<(), OAuthError> {
+        (**self).save(client)
+    }
+
+    fn remove(&self, client_id: &str) -> Result<(), OAuthError> {
+        (**self).remove(client_id)
+    }
+}
+
+/// Repository keeping clients in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryClientRepository {
+    clients: RwLock<HashMap<String, Client>>,
+}
+
+impl InMemoryClientRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClientRepository for InMemoryClientRepository {
+    fn client_of(&self, client_id: &str) -> Result<Option<Client>, OAuthError> {
+        let clients = self.clients.read().expect("clients lock poisoned");
+        Ok(clients.get(client_id).cloned())
+    }
+
+    fn clients_of_tenant(&self, tenant_id: &str) -> Result<Vec<Client>, OAuthError> {
+        let clients = self.clients.read().expect("clients lock poisoned");
+        let mut found: Vec<_> = clients
+            .values()
+            .filter(|c| c.tenant_id == tenant_id)
+            .cloned()
+            .collect();
+        found.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        Ok(found)
+    }
+
+    fn save(&self, client: &Client) -> Result<(), OAuthError> {
+        let mut clients = self.clients.write().expect("clients lock poisoned");
+        clients.insert(client.client_id.clone(), client.clone());
+        Ok(())
+    }
+
+    fn remove(&self, client_id: &str) -> Result<(), OAuthError> {
+        let mut clients = self.clients.write().expect("clients lock poisoned");
+        clients.remove(client_id);
+        Ok(())
+    }
+}
+
+/// Authenticates clients at the token endpoint.
+pub struct ClientAuthenticator<R, S> {
+    repository: R,
+    strategy: S,
+}
+
+impl<R: ClientRepository, S: PasswordHashingStrategy> ClientAuthenticator<R, S> {
+    pub fn new(repository: R, strategy: S) -> Self {
+        Self {
+            repository,
+            strategy,
+        }
+    }
+
+    /// Returns the client if it exists and, for confidential clients, the
+    /// secret matches; public clients must not present one.
+    pub fn authenticate(
+        &self,
+        client_id: &str,
+        secret: Option<&PlainPassword>,
+    ) -> Result<Client, OAuthError> {
+        let client = self
+            .repository
+            .client_of(client_id)?
+            .ok_or(OAuthError::InvalidClient)?;
+        let authenticated = match (&client.secret, secret) {
+            (None, None) => true,
+            (Some(hash), Some(secret)) => hash
+                .verify(secret, &self.strategy)
+                .map_err(|e| OAuthError::Storage(e.to_string()))?
+                .is_match(),
+            _ => false,
+        };
+        if authenticated {
+            Ok(client)
+        } else {
+            Err(OAuthError::InvalidClient)
+        }
+    }
+
+    /// Authenticates the client and checks it may use the grant type.
+    pub fn authenticate_for(
+        &self,
+        client_id: &str,
+        secret: Option<&PlainPassword>,
+        grant_type: GrantType,
+    ) -> Result<Client, OAuthError> {
+        let client = self.authenticate(client_id, secret)?;
+        client.ensure_grant(grant_type)?;
+        Ok(client)
+    }
+}
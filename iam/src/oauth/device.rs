@@ -0,0 +1,770 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use super::authorization::AuthorizationGrant;
+use super::client::{Client, GrantType};
+use super::{parse_scope, OAuthError};
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+use crate::identity::authentication::AuthenticationStrength;
+use crate::identity::lockout::{LoginAttemptError, LoginAttemptKey, LoginAttemptTracker};
+use crate::tokens::TokenSubject;
+
+/// Letters of user codes: no vowels, to avoid forming words, and nothing
+/// easily confused, as RFC 8628 suggests.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+/// Returned to the device, which shows the user code and starts polling.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+/// What the user is asked to approve on the verification page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePrompt {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+enum DeviceAuthorizationState {
+    Pending,
+    Approved {
+        tenant_id: String,
+        username: String,
+        roles: Vec<String>,
+        strength: AuthenticationStrength,
+        authenticated_at: DateTime<Utc>,
+    },
+    Denied,
+    Redeemed,
+}
+
+/// A pending device authorization; only the digest of the device code is
+/// kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceAuthorizationRecord {
+    device_code_hash: String,
+    user_code: String,
+    client_id: String,
+    scopes: Vec<String>,
+    state: DeviceAuthorizationState,
+    expires_at: DateTime<Utc>,
+    interval_seconds: i64,
+    last_polled_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceAuthorizationRecord {
+    pub fn device_code_hash(&self) -> &str {
+        &self.device_code_hash
+    }
+
+    pub fn user_code(&self) -> &str {
+        &self.user_code
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+/// Stores device authorizations.
+pub trait DeviceAuthorizationRepository: Send + Sync {
+    fn authorization_of_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError>;
+
+    fn authorization_of_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError>;
+
+    /// Inserts a new authorization unless one that has not expired at `now`
+    /// already holds its user code, returning whether it did. An expired
+    /// authorization holding the code is deleted, so a stale record is
+    /// never found under a reused code.
+    fn insert(
+        &self,
+        record: &DeviceAuthorizationRecord,
+        now: DateTime<Utc>,
+    ) -> Result<bool, OAuthError>;
+
+    /// Replaces the record only while the stored one is still pending,
+    /// returning whether it did, so that an authorization is approved or
+    /// denied once.
+    fn save_if_pending(&self, record: &DeviceAuthorizationRecord) -> Result<bool, OAuthError>;
+
+    /// Notes a poll of the device without touching the authorization state.
+    fn record_poll(
+        &self,
+        device_code_hash: &str,
+        polled_at: DateTime<Utc>,
+        interval_seconds: i64,
+    ) -> Result<(), OAuthError>;
+
+    /// Marks an approved authorization redeemed in a single step, returning
+    /// it as approved only if this call did so.
+    fn redeem(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError>;
+
+    /// Deletes authorizations past their expiry, returning how many were
+    /// removed.
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, OAuthError>;
+}
+
+#[derive(Debug, Default)]
+struct DeviceAuthorizations {
+    records: HashMap<String, DeviceAuthorizationRecord>,
+    /// User code to the device code digest of the authorization holding it.
+    user_codes: HashMap<String, String>,
+}
+
+impl DeviceAuthorizations {
+    fn remove(&mut self, device_code_hash: &str) {
+        if let Some(record) = self.records.remove(device_code_hash) {
+            self.user_codes.remove(&record.user_code);
+        }
+    }
+}
+
+/// Repository keeping device authorizations in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryDeviceAuthorizationRepository {
+    authorizations: RwLock<DeviceAuthorizations>,
+}
+
+impl InMemoryDeviceAuthorizationRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DeviceAuthorizationRepository for InMemoryDeviceAuthorizationRepository {
+    fn authorization_of_device_code(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError> {
+        let authorizations = self
+            .authorizations
+            .read()
+            .expect("device authorizations lock poisoned");
+        Ok(authorizations.records.get(device_code_hash).cloned())
+    }
+
+    fn authorization_of_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError> {
+        let authorizations = self
+            .authorizations
+            .read()
+            .expect("device authorizations lock poisoned");
+        Ok(authorizations
+            .user_codes
+            .get(user_code)
+            .and_then(|hash| authorizations.records.get(hash))
+            .cloned())
+    }
+
+    fn insert(
+        &self,
+        record: &DeviceAuthorizationRecord,
+        now: DateTime<Utc>,
+    ) -> Result<bool, OAuthError> {
+        let mut authorizations = self
+            .authorizations
+            .write()
+            .expect("device authorizations lock poisoned");
+        if let Some(hash) = authorizations.user_codes.get(&record.user_code).cloned() {
+            if authorizations
+                .records
+                .get(&hash)
+                .is_some_and(|holder| now < holder.expires_at)
+            {
+                return Ok(false);
+            }
+            authorizations.remove(&hash);
+        }
+        authorizations
+            .user_codes
+            .insert(record.user_code.clone(), record.device_code_hash.clone());
+        authorizations
+            .records
+            .insert(record.device_code_hash.clone(), record.clone());
+        Ok(true)
+    }
+
+    fn save_if_pending(&self, record: &DeviceAuthorizationRecord) -> Result<bool, OAuthError> {
+        let mut authorizations = self
+            .authorizations
+            .write()
+            .expect("device authorizations lock poisoned");
+        match authorizations.records.get_mut(&record.device_code_hash) {
+            Some(stored)
+                if stored.state == DeviceAuthorizationState::Pending
+                    && stored.user_code == record.user_code =>
+            {
+                *stored = record.clone();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn record_poll(
+        &self,
+        device_code_hash: &str,
+        polled_at: DateTime<Utc>,
+        interval_seconds: i64,
+    ) -> Result<(), OAuthError> {
+        let mut authorizations = self
+            .authorizations
+            .write()
+            .expect("device authorizations lock poisoned");
+        if let Some(record) = authorizations.records.get_mut(device_code_hash) {
+            record.last_polled_at = Some(polled_at);
+            record.interval_seconds = interval_seconds;
+        }
+        Ok(())
+    }
+
+    fn redeem(
+        &self,
+        device_code_hash: &str,
+    ) -> Result<Option<DeviceAuthorizationRecord>, OAuthError> {
+        let mut authorizations = self
+            .authorizations
+            .write()
+            .expect("device authorizations lock poisoned");
+        Ok(authorizations
+            .records
+            .get_mut(device_code_hash)
+            .filter(|record| matches!(record.state, DeviceAuthorizationState::Approved { .. }))
+            .map(|record| {
+                let approved = record.clone();
+                record.state = DeviceAuthorizationState::Redeemed;
+                approved
+            }))
+    }
+
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, OAuthError> {
+        let mut authorizations = self
+            .authorizations
+            .write()
+            .expect("device authorizations lock poisoned");
+        let expired: Vec<_> = authorizations
+            .records
+            .values()
+            .filter(|record| now >= record.expires_at)
+            .map(|record| record.device_code_hash.clone())
+            .collect();
+        for hash in &expired {
+            authorizations.remove(hash);
+        }
+        Ok(expired.len() as u64)
+    }
+}
+
+impl From<LoginAttemptError> for OAuthError {
+    fn from(error: LoginAttemptError) -> Self {
+        OAuthError::Storage(error.to_string())
+    }
+}
+
+/// OAuth 2.0 device authorization grant (RFC 8628) for input-constrained
+/// clients such as CLIs and TVs.
+///
+/// User codes are short enough to guess, so invalid codes entered by a user
+/// are counted and, as RFC 8628 section 5.1 requires, further entries are
+/// refused for a while once there are too many.
+pub struct DeviceAuthorizationService<R, T, C> {
+    repository: R,
+    attempts: T,
+    verification_uri: String,
+    ttl: Duration,
+    interval: Duration,
+    clock: C,
+}
+
+impl<R, T, C> DeviceAuthorizationService<R, T, C>
+where
+    R: DeviceAuthorizationRepository,
+    T: LoginAttemptTracker,
+    C: Clock,
+{
+    pub const DEFAULT_TTL_MINUTES: i64 = 10;
+    pub const DEFAULT_INTERVAL_SECONDS: i64 = 5;
+
+    pub fn new(repository: R, attempts: T, verification_uri: impl Into<String>, clock: C) -> Self {
+        Self {
+            repository,
+            attempts,
+            verification_uri: verification_uri.into(),
+            ttl: Duration::minutes(Self::DEFAULT_TTL_MINUTES),
+            interval: Duration::seconds(Self::DEFAULT_INTERVAL_SECONDS),
+            clock,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Minimum time between polls.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Starts an authorization for the client, which may be public.
+    pub fn start(
+        &self,
+        client: &Client,
+        scope: Option<&str>,
+    ) -> Result<DeviceAuthorizationResponse, OAuthError> {
+        client.ensure_grant(GrantType::DeviceCode)?;
+        let scopes = client.check_scopes(&scope.map(parse_scope).unwrap_or_default())?;
+        let device_code = random_token();
+        let now = self.clock.now();
+        let mut record = DeviceAuthorizationRecord {
+            device_code_hash: digest(&device_code),
+            user_code: user_code(),
+            client_id: client.client_id().to_owned(),
+            scopes,
+            state: DeviceAuthorizationState::Pending,
+            expires_at: now + self.ttl,
+            interval_seconds: self.interval.num_seconds(),
+            last_polled_at: None,
+        };
+        while !self.repository.insert(&record, now)? {
+            record.user_code = user_code();
+        }
+        let user_code = record.user_code;
+        let display = format!("{}-{}", &user_code[..4], &user_code[4..]);
+        let separator = if self.verification_uri.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(DeviceAuthorizationResponse {
+            device_code,
+            verification_uri_complete: format!(
+                "{}{}user_code={}",
+                self.verification_uri, separator, display
+            ),
+            user_code: display,
+            verification_uri: self.verification_uri.clone(),
+            expires_in: self.ttl.num_seconds(),
+            interval: self.interval.num_seconds(),
+        })
+    }
+
+    /// Looks up the pending authorization the signed-in user typed the code
+    /// of.
+    pub fn prompt(&self, user_code: &str, user: &TokenSubject) -> Result<DevicePrompt, OAuthError> {
+        let record = self.pending(user_code, user)?;
+        Ok(DevicePrompt {
+            client_id: record.client_id,
+            scopes: record.scopes,
+        })
+    }
+
+    /// Approves the authorization for the signed-in user, who must belong
+    /// to the client's tenant.
+    pub fn approve(
+        &self,
+        client: &Client,
+        user_code: &str,
+        user: &TokenSubject,
+    ) -> Result<(), OAuthError> {
+        let mut record = self.pending(user_code, user)?;
+        if record.client_id != client.client_id() || user.tenant_id() != client.tenant_id() {
+            return Err(OAuthError::AccessDenied);
+        }
+        record.state = DeviceAuthorizationState::Approved {
+            tenant_id: user.tenant_id().to_owned(),
+            username: user.username().to_owned(),
+            roles: user.roles().to_vec(),
+            strength: user.strength(),
+            authenticated_at: user.authenticated_at().unwrap_or(self.clock.now()),
+        };
+        self.resolve(&record)
+    }
+
+    pub fn deny(&self, user_code: &str, user: &TokenSubject) -> Result<(), OAuthError> {
+        let mut record = self.pending(user_code, user)?;
+        record.state = DeviceAuthorizationState::Denied;
+        self.resolve(&record)
+    }
+
+    fn resolve(&self, record: &DeviceAuthorizationRecord) -> Result<(), OAuthError> {
+        if self.repository.save_if_pending(record)? {
+            Ok(())
+        } else {
+            Err(OAuthError::InvalidGrant(
+                "unknown or expired user code".to_owned(),
+            ))
+        }
+    }
+
+    /// Answers a poll of the device at the token endpoint.
+    pub fn poll(
+        &self,
+        client: &Client,
+        device_code: &str,
+    ) -> Result<AuthorizationGrant, OAuthError> {
+        client.ensure_grant(GrantType::DeviceCode)?;
+        let now = self.clock.now();
+        let hash = digest(device_code);
+        let record = self
+            .repository
+            .authorization_of_device_code(&hash)?
+            .filter(|r| r.client_id == client.client_id())
+            .ok_or_else(|| OAuthError::InvalidGrant("unknown device code".to_owned()))?;
+        if now >= record.expires_at {
+            return Err(OAuthError::ExpiredToken);
+        }
+        let too_soon = record
+            .last_polled_at
+            .is_some_and(|at| now - at < Duration::seconds(record.interval_seconds));
+        if too_soon {
+            let interval = record.interval_seconds + Self::DEFAULT_INTERVAL_SECONDS;
+            self.repository.record_poll(&hash, now, interval)?;
+            return Err(OAuthError::SlowDown);
+        }
+        self.repository
+            .record_poll(&hash, now, record.interval_seconds)?;
+        let used = || OAuthError::InvalidGrant("device code already used".to_owned());
+        match record.state {
+            DeviceAuthorizationState::Pending => return Err(OAuthError::AuthorizationPending),
+            DeviceAuthorizationState::Denied => return Err(OAuthError::AccessDenied),
+            DeviceAuthorizationState::Redeemed => return Err(used()),
+            DeviceAuthorizationState::Approved { .. } => {}
+        }
+        let record = self.repository.redeem(&hash)?.ok_or_else(used)?;
+        let DeviceAuthorizationState::Approved {
+            tenant_id,
+            username,
+            roles,
+            strength,
+            authenticated_at,
+        } = record.state
+        else {
+            return Err(used());
+        };
+        Ok(AuthorizationGrant {
+            subject: TokenSubject::new(tenant_id, username)
+                .with_roles(roles)
+                .authenticated_with(strength, authenticated_at)
+                .with_scopes(record.scopes.iter().cloned())
+                .for_client(record.client_id),
+            scopes: record.scopes,
+            nonce: None,
+        })
+    }
+
+    /// Deletes authorizations past their expiry, freeing their user codes;
+    /// meant to be called on a schedule.
+    pub fn purge_expired(&self) -> Result<u64, OAuthError> {
+        self.repository.purge_expired(self.clock.now())
+    }
+
+    /// The pending authorization of the user code, counting invalid codes
+    /// against the user who entered them.
+    ///
+    /// Valid codes do not clear past failures, or an attacker could
+    /// interleave codes of their own with guesses.
+    fn pending(
+        &self,
+        user_code: &str,
+        user: &TokenSubject,
+    ) -> Result<DeviceAuthorizationRecord, OAuthError> {
+        let key = LoginAttemptKey::User {
+            tenant_id: user.tenant_id().to_owned(),
+            username: user.username().to_owned(),
+        };
+        if self.attempts.status(&key)?.is_locked() {
+            return Err(OAuthError::TooManyAttempts);
+        }
+        let record = self
+            .repository
+            .authorization_of_user_code(&normalize_user_code(user_code))?
+            .filter(|record| {
+                record.state == DeviceAuthorizationState::Pending
+                    && self.clock.now() < record.expires_at
+            });
+        match record {
+            Some(record) => Ok(record),
+            None => {
+                self.attempts.record_failure(&key)?;
+                Err(OAuthError::InvalidGrant(
+                    "unknown or expired user code".to_owned(),
+                ))
+            }
+        }
+    }
+}
+
+fn user_code() -> String {
+    // Bytes past the last multiple of the alphabet size are dropped so that
+    // every letter is equally likely.
+    let limit = 256 - 256 % USER_CODE_ALPHABET.len();
+    let mut code = String::with_capacity(USER_CODE_LENGTH);
+    while code.len() < USER_CODE_LENGTH {
+        let byte = usize::from(OsRng.next_u32() as u8);
+        if byte < limit {
+            code.push(USER_CODE_ALPHABET[byte % USER_CODE_ALPHABET.len()] as char);
+        }
+    }
+    code
+}
+
+/// Users may type codes in any case, with or without the dash.
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use chrono::TimeZone;
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::lockout::{InMemoryLoginAttemptTracker, LockoutPolicy};
+
+    type Service = DeviceAuthorizationService<
+        InMemoryDeviceAuthorizationRepository,
+        InMemoryLoginAttemptTracker<Arc<FixedClock>>,
+        Arc<FixedClock>,
+    >;
+
+    fn client() -> Client {
+        Client::public("tv", "acme", "TV").with_grant_types([GrantType::DeviceCode])
+    }
+
+    fn service() -> Service {
+        let clock = Arc::new(FixedClock::new(
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        ));
+        DeviceAuthorizationService::new(
+            InMemoryDeviceAuthorizationRepository::new(),
+            InMemoryLoginAttemptTracker::new(
+                LockoutPolicy::new(3, Duration::minutes(15), Duration::minutes(15)),
+                clock.clone(),
+            ),
+            "https://example.com/device",
+            clock,
+        )
+        .with_interval(Duration::zero())
+    }
+
+    #[test]
+    fn approved_device_code_is_redeemed_once() {
+        let service = service();
+        let started = service.start(&client(), Some("openid")).unwrap();
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::AuthorizationPending)
+        ));
+
+        let user = TokenSubject::new("acme", "ada");
+        service
+            .approve(&client(), &started.user_code, &user)
+            .unwrap();
+        let grant = service.poll(&client(), &started.device_code).unwrap();
+        assert_eq!(grant.subject.username(), "ada");
+        assert_eq!(grant.scopes, ["openid"]);
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+    }
+
+    #[test]
+    fn concurrent_polls_redeem_the_device_code_once() {
+        let service = service();
+        let client = client();
+        let started = service.start(&client, None).unwrap();
+        service
+            .approve(
+                &client,
+                &started.user_code,
+                &TokenSubject::new("acme", "ada"),
+            )
+            .unwrap();
+        let successes = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| service.poll(&client, &started.device_code)))
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap().ok())
+                .count()
+        });
+        assert_eq!(successes, 1);
+    }
+
+    #[test]
+    fn authorization_is_resolved_once() {
+        let service = service();
+        let started = service.start(&client(), None).unwrap();
+        let record = service
+            .repository
+            .authorization_of_user_code(&normalize_user_code(&started.user_code))
+            .unwrap()
+            .unwrap();
+
+        service
+            .deny(&started.user_code, &TokenSubject::new("acme", "ada"))
+            .unwrap();
+        let mut approved = record.clone();
+        approved.state = DeviceAuthorizationState::Approved {
+            tenant_id: "acme".to_owned(),
+            username: "ada".to_owned(),
+            roles: Vec::new(),
+            strength: AuthenticationStrength::SingleFactor,
+            authenticated_at: record.expires_at,
+        };
+        assert!(!service.repository.save_if_pending(&approved).unwrap());
+        assert!(service.resolve(&approved).is_err());
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::AccessDenied)
+        ));
+    }
+
+    #[test]
+    fn polling_too_fast_slows_the_device_down() {
+        let service = service().with_interval(Duration::seconds(5));
+        let started = service.start(&client(), None).unwrap();
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::AuthorizationPending)
+        ));
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::SlowDown)
+        ));
+        let record = service
+            .repository
+            .authorization_of_device_code(&digest(&started.device_code))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.interval_seconds, 10);
+    }
+
+    fn pending_record(
+        device_code_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> DeviceAuthorizationRecord {
+        DeviceAuthorizationRecord {
+            device_code_hash: device_code_hash.to_owned(),
+            user_code: "BCDFGHJK".to_owned(),
+            client_id: "tv".to_owned(),
+            scopes: Vec::new(),
+            state: DeviceAuthorizationState::Pending,
+            expires_at,
+            interval_seconds: 5,
+            last_polled_at: None,
+        }
+    }
+
+    #[test]
+    fn user_code_is_only_reused_once_expired() {
+        let repository = InMemoryDeviceAuthorizationRepository::new();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let expires_at = now + Duration::minutes(10);
+        assert!(repository
+            .insert(&pending_record("first", expires_at), now)
+            .unwrap());
+        assert!(!repository
+            .insert(&pending_record("second", expires_at), now)
+            .unwrap());
+
+        let later = pending_record("second", expires_at + Duration::minutes(10));
+        assert!(repository.insert(&later, expires_at).unwrap());
+        assert!(repository
+            .authorization_of_device_code("first")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            repository.authorization_of_user_code("BCDFGHJK").unwrap(),
+            Some(later)
+        );
+    }
+
+    #[test]
+    fn purge_frees_expired_user_codes() {
+        let service = service();
+        let started = service.start(&client(), None).unwrap();
+        assert_eq!(service.purge_expired().unwrap(), 0);
+
+        service.clock.advance(Duration::minutes(10));
+        assert_eq!(service.purge_expired().unwrap(), 1);
+        assert!(service
+            .repository
+            .authorization_of_user_code(&normalize_user_code(&started.user_code))
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            service.poll(&client(), &started.device_code),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+    }
+
+    #[test]
+    fn guessing_user_codes_is_throttled() {
+        let service = service();
+        let started = service.start(&client(), None).unwrap();
+        let eve = TokenSubject::new("acme", "eve");
+        for guess in ["BBBB-BBBB", "CCCC-CCCC", "DDDD-DDDD"] {
+            assert!(matches!(
+                service.prompt(guess, &eve),
+                Err(OAuthError::InvalidGrant(_))
+            ));
+        }
+        assert!(matches!(
+            service.prompt(&started.user_code, &eve),
+            Err(OAuthError::TooManyAttempts)
+        ));
+
+        let ada = TokenSubject::new("acme", "ada");
+        assert_eq!(
+            service.prompt(&started.user_code, &ada).unwrap().client_id,
+            "tv"
+        );
+        service.clock.advance(Duration::minutes(15));
+        assert!(matches!(
+            service.prompt(&started.user_code, &eve),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+    }
+}
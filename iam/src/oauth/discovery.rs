@@ -0,0 +1,73 @@
+use serde::Serialize;
+
+use crate::tokens::SigningAlgorithm;
+
+/// The `/.well-known/openid-configuration` document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub device_authorization_endpoint: String,
+    pub response_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+    pub backchannel_logout_supported: bool,
+    pub backchannel_logout_session_supported: bool,
+}
+
+impl ProviderMetadata {
+    /// Metadata with the endpoints at their default paths below the issuer.
+    pub fn new(issuer: &str, algorithm: SigningAlgorithm) -> Self {
+        let issuer = issuer.trim_end_matches('/');
+        let endpoint = |path: &str| format!("{}/{}", issuer, path);
+        let strings = |values: &[&str]| values.iter().map(|v| (*v).to_owned()).collect();
+        Self {
+            issuer: issuer.to_owned(),
+            authorization_endpoint: endpoint("oauth2/authorize"),
+            token_endpoint: endpoint("oauth2/token"),
+            jwks_uri: endpoint(".well-known/jwks.json"),
+            introspection_endpoint: endpoint("oauth2/introspect"),
+            revocation_endpoint: endpoint("oauth2/revoke"),
+            device_authorization_endpoint: endpoint("oauth2/device_authorization"),
+            response_types_supported: strings(&["code"]),
+            subject_types_supported: strings(&["public"]),
+            id_token_signing_alg_values_supported: vec![format!("{:?}", algorithm)],
+            scopes_supported: strings(&["openid"]),
+            grant_types_supported: strings(&[
+                "authorization_code",
+                "refresh_token",
+                "client_credentials",
+                "urn:ietf:params:oauth:grant-type:device_code",
+                "urn:ietf:params:oauth:grant-type:token-exchange",
+            ]),
+            token_endpoint_auth_methods_supported: strings(&[
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ]),
+            code_challenge_methods_supported: strings(&[super::authorization::PKCE_METHOD]),
+            claims_supported: strings(&[
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "auth_time",
+                "nonce",
+                "acr",
+                "tenant_id",
+            ]),
+            backchannel_logout_supported: true,
+            backchannel_logout_session_supported: true,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use jsonwebtoken::{encode, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::OAuthError;
+use crate::common::clock::Clock;
+use crate::identity::authentication::AuthenticationStrength;
+use crate::tokens::{IssuedToken, KeyProvider, TokenConfig, TokenError, TokenSubject};
+
+/// Claims of an OpenID Connect ID token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    /// The client the token is issued to.
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub auth_time: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub acr: AuthenticationStrength,
+    pub tenant_id: String,
+    /// Custom claims added by the client's claim mapping.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Issues ID tokens to clients of the authorization code flow.
+///
+/// Issuer, lifetime and algorithm come from the token configuration; the
+/// audience is always the client.
+pub struct IdTokenService<C, K> {
+    config: TokenConfig,
+    keys: K,
+    clock: C,
+}
+
+impl<C: Clock, K: KeyProvider> IdTokenService<C, K> {
+    pub fn new(config: TokenConfig, keys: K, clock: C) -> Self {
+        Self {
+            config,
+            keys,
+            clock,
+        }
+    }
+
+    pub fn issue(
+        &self,
+        subject: &TokenSubject,
+        client_id: &str,
+        nonce: Option<&str>,
+    ) -> Result<IssuedToken, OAuthError> {
+        let issued_at = self.clock.now();
+        let expires_at = self.config.expires_at(issued_at);
+        let claims = IdTokenClaims {
+            iss: self.config.issuer().to_owned(),
+            sub: subject.username().to_owned(),
+            aud: client_id.to_owned(),
+            exp: expires_at.timestamp(),
+            iat: issued_at.timestamp(),
+            auth_time: subject.authenticated_at().unwrap_or(issued_at).timestamp(),
+            nonce: nonce.map(str::to_owned),
+            acr: subject.strength(),
+            tenant_id: subject.tenant_id().to_owned(),
+            extra: subject.id_token_claims().clone(),
+        };
+        let (key_id, key) = self.keys.signing_key()?;
+        let mut header = Header::new(self.config.algorithm());
+        header.kid = key_id;
+        let token =
+            encode(&header, &claims, &key).map_err(|e| TokenError::Signing(e.to_string()))?;
+        Ok(IssuedToken { token, expires_at })
+    }
+}
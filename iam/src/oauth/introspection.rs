@@ -0,0 +1,389 @@
+use chrono::DateTime;
+use serde::Serialize;
+
+use super::client::Client;
+use super::OAuthError;
+use crate::common::clock::Clock;
+use crate::tokens::{
+    AccessClaims, AccessTokenService, KeyProvider, RefreshTokenRecord, RefreshTokenRepository,
+    RefreshTokenService, RevokedTokenRepository, TokenError,
+};
+
+/// The `token_type_hint` parameter of introspection and revocation requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
+
+impl TokenTypeHint {
+    /// Parses the hint; unknown values are ignored, as RFC 7009 requires.
+    pub fn parse(hint: &str) -> Option<Self> {
+        match hint {
+            "access_token" => Some(TokenTypeHint::AccessToken),
+            "refresh_token" => Some(TokenTypeHint::RefreshToken),
+            _ => None,
+        }
+    }
+}
+
+/// RFC 7662 introspection response.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+impl IntrospectionResponse {
+    /// The response for unknown, expired or revoked tokens, which reveals
+    /// nothing else.
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    fn of_access(claims: AccessClaims) -> Self {
+        Self {
+            active: true,
+            scope: claims.scope,
+            client_id: claims.client_id,
+            username: Some(claims.sub.clone()),
+            token_type: Some("Bearer".to_owned()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            nbf: Some(claims.nbf),
+            sub: Some(claims.sub),
+            aud: Some(claims.aud),
+            iss: Some(claims.iss),
+            jti: Some(claims.jti),
+            tenant_id: Some(claims.tenant_id),
+        }
+    }
+
+    fn of_refresh(record: RefreshTokenRecord) -> Self {
+        Self {
+            active: true,
+            username: Some(record.username().to_owned()),
+            client_id: Some(record.client_id().to_owned()),
+            token_type: Some("refresh_token".to_owned()),
+            exp: Some(record.expires_at().timestamp()),
+            iat: Some(record.issued_at().timestamp()),
+            sub: Some(record.username().to_owned()),
+            tenant_id: Some(record.tenant_id().to_owned()),
+            ..Self::default()
+        }
+    }
+}
+
+/// Introspection (RFC 7662) and revocation (RFC 7009) over access and
+/// refresh tokens.
+pub struct TokenIntrospectionService<C, K, R, D> {
+    access: AccessTokenService<C, K>,
+    refresh: RefreshTokenService<R, C>,
+    revoked: D,
+}
+
+impl<C, K, R, D> TokenIntrospectionService<C, K, R, D>
+where
+    C: Clock,
+    K: KeyProvider,
+    R: RefreshTokenRepository,
+    D: RevokedTokenRepository,
+{
+    pub fn new(
+        access: AccessTokenService<C, K>,
+        refresh: RefreshTokenService<R, C>,
+        revoked: D,
+    ) -> Self {
+        Self {
+            access,
+            refresh,
+            revoked,
+        }
+    }
+
+    pub fn access_tokens(&self) -> &AccessTokenService<C, K> {
+        &self.access
+    }
+
+    pub fn refresh_tokens(&self) -> &RefreshTokenService<R, C> {
+        &self.refresh
+    }
+
+    /// Describes the token for an authenticated confidential client or
+    /// resource server.
+    ///
+    /// Public clients cannot keep a secret, so anyone could introspect in
+    /// their name; they are refused. Access tokens of another tenant and
+    /// refresh tokens of another client are reported inactive.
+    pub fn introspect(
+        &self,
+        client: &Client,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<IntrospectionResponse, OAuthError> {
+        if !client.is_confidential() {
+            return Err(OAuthError::UnauthorizedClient(
+                "token introspection".to_owned(),
+            ));
+        }
+        let response = match hint {
+            Some(TokenTypeHint::RefreshToken) => match self.introspect_refresh(client, token)? {
+                Some(response) => Some(response),
+                None => self.introspect_access(client, token)?,
+            },
+            _ => match self.introspect_access(client, token)? {
+                Some(response) => Some(response),
+                None => self.introspect_refresh(client, token)?,
+            },
+        };
+        Ok(response.unwrap_or_else(IntrospectionResponse::inactive))
+    }
+
+    /// Revokes the token on behalf of the client it was issued to.
+    ///
+    /// Unknown and invalid tokens are ignored, as RFC 7009 requires, and so
+    /// are refresh tokens of other clients.
+    pub fn revoke(
+        &self,
+        client: &Client,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<(), OAuthError> {
+        if hint != Some(TokenTypeHint::AccessToken) {
+            if let Some(record) = self.refresh.inspect(token)? {
+                if record.is_issued_to(client.client_id()) {
+                    self.refresh.revoke(token)?;
+                }
+                return Ok(());
+            }
+        }
+        let claims = match self.access.validate(token) {
+            Ok(claims) => claims,
+            Err(TokenError::Storage(message)) => return Err(OAuthError::Storage(message)),
+            Err(_) => return Ok(()),
+        };
+        if claims.client_id.as_deref() != Some(client.client_id()) {
+            return Err(OAuthError::UnauthorizedClient(
+                "revocation of another client's token".to_owned(),
+            ));
+        }
+        let expires_at = DateTime::from_timestamp(claims.exp, 0)
+            .ok_or_else(|| OAuthError::InvalidRequest("invalid token expiry".to_owned()))?;
+        Ok(self.revoked.revoke(&claims.jti, expires_at)?)
+    }
+
+    fn introspect_access(
+        &self,
+        client: &Client,
+        token: &str,
+    ) -> Result<Option<IntrospectionResponse>, OAuthError> {
+        let claims = match self.access.validate(token) {
+            Ok(claims) => claims,
+            Err(TokenError::Storage(message)) => return Err(OAuthError::Storage(message)),
+            Err(_) => return Ok(None),
+        };
+        if claims.tenant_id != client.tenant_id() || self.revoked.is_revoked(&claims.jti)? {
+            return Ok(None);
+        }
+        Ok(Some(IntrospectionResponse::of_access(claims)))
+    }
+
+    fn introspect_refresh(
+        &self,
+        client: &Client,
+        token: &str,
+    ) -> Result<Option<IntrospectionResponse>, OAuthError> {
+        Ok(self
+            .refresh
+            .inspect(token)?
+            .filter(|record| {
+                record.is_issued_to(client.client_id()) && record.tenant_id() == client.tenant_id()
+            })
+            .map(IntrospectionResponse::of_refresh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::password::{Argon2Strategy, PlainPassword};
+    use crate::tokens::{
+        InMemoryRefreshTokenRepository, InMemoryRevokedTokenRepository, SigningKeys, TokenConfig,
+        TokenSubject,
+    };
+
+    type Service = TokenIntrospectionService<
+        Arc<FixedClock>,
+        SigningKeys,
+        InMemoryRefreshTokenRepository,
+        InMemoryRevokedTokenRepository,
+    >;
+
+    fn service() -> (Service, Arc<FixedClock>) {
+        let clock = Arc::new(FixedClock::new(
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        ));
+        let service = TokenIntrospectionService::new(
+            AccessTokenService::new(
+                TokenConfig::new("https://iam.example.com", "api"),
+                SigningKeys::hmac(b"introspection test secret"),
+                clock.clone(),
+            ),
+            RefreshTokenService::new(
+                InMemoryRefreshTokenRepository::new(),
+                Duration::days(30),
+                clock.clone(),
+            ),
+            InMemoryRevokedTokenRepository::new(),
+        );
+        (service, clock)
+    }
+
+    fn confidential(client_id: &str, tenant_id: &str) -> Client {
+        Client::confidential(
+            client_id,
+            tenant_id,
+            client_id,
+            &PlainPassword::new("client secret"),
+            &Argon2Strategy::new(8, 1, 1).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn access_token(service: &Service, tenant_id: &str) -> String {
+        service
+            .access_tokens()
+            .issue(&TokenSubject::new(tenant_id, "ada"))
+            .unwrap()
+            .token
+    }
+
+    #[test]
+    fn public_clients_cannot_introspect() {
+        let (service, _) = service();
+        let token = access_token(&service, "acme");
+        assert!(matches!(
+            service.introspect(&Client::public("app", "acme", "App"), &token, None),
+            Err(OAuthError::UnauthorizedClient(_))
+        ));
+    }
+
+    #[test]
+    fn access_token_is_only_active_within_its_tenant() {
+        let (service, _) = service();
+        let token = access_token(&service, "acme");
+
+        let response = service
+            .introspect(&confidential("api", "acme"), &token, None)
+            .unwrap();
+        assert!(response.active);
+        assert_eq!(response.tenant_id.as_deref(), Some("acme"));
+
+        assert_eq!(
+            service
+                .introspect(&confidential("api", "globex"), &token, None)
+                .unwrap(),
+            IntrospectionResponse::inactive()
+        );
+    }
+
+    #[test]
+    fn revoked_and_expired_access_tokens_are_inactive() {
+        let (service, clock) = service();
+        let resource_server = confidential("api", "acme");
+        let revoked = access_token(&service, "acme");
+        let expiring = access_token(&service, "acme");
+
+        let claims = service.access_tokens().validate(&revoked).unwrap();
+        service
+            .revoked
+            .revoke(&claims.jti, clock.now() + Duration::hours(1))
+            .unwrap();
+        assert!(
+            !service
+                .introspect(&resource_server, &revoked, None)
+                .unwrap()
+                .active
+        );
+
+        clock.advance(Duration::days(1));
+        assert_eq!(
+            service
+                .introspect(&resource_server, &expiring, None)
+                .unwrap(),
+            IntrospectionResponse::inactive()
+        );
+    }
+
+    #[test]
+    fn refresh_token_is_only_active_for_its_client() {
+        let (service, clock) = service();
+        let token = service
+            .refresh_tokens()
+            .issue("acme", "ada", "app")
+            .unwrap()
+            .token;
+        let hint = Some(TokenTypeHint::RefreshToken);
+
+        let app = confidential("app", "acme");
+        let response = service.introspect(&app, &token, hint).unwrap();
+        assert!(response.active);
+        assert_eq!(response.client_id.as_deref(), Some("app"));
+
+        let other = confidential("other", "acme");
+        assert_eq!(
+            service.introspect(&other, &token, hint).unwrap(),
+            IntrospectionResponse::inactive()
+        );
+
+        clock.advance(Duration::days(31));
+        assert!(!service.introspect(&app, &token, hint).unwrap().active);
+    }
+
+    #[test]
+    fn other_clients_cannot_revoke_a_refresh_token() {
+        let (service, _) = service();
+        let app = confidential("app", "acme");
+        let token = service
+            .refresh_tokens()
+            .issue("acme", "ada", "app")
+            .unwrap()
+            .token;
+
+        service
+            .revoke(&Client::public("other", "acme", "Other"), &token, None)
+            .unwrap();
+        assert!(service.introspect(&app, &token, None).unwrap().active);
+
+        service.revoke(&app, &token, None).unwrap();
+        assert!(!service.introspect(&app, &token, None).unwrap().active);
+    }
+}
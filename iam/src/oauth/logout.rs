@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::time::Duration as Timeout;
+
+use chrono::Duration;
+use jsonwebtoken::{encode, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::client::ClientRepository;
+use super::OAuthError;
+use crate::common::clock::Clock;
+use crate::common::secret::random_token;
+use crate::tokens::{KeyProvider, TokenConfig, TokenError};
+
+/// Event identifying a logout token, per OpenID Connect Back-Channel Logout.
+pub const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// Claims of a logout token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogoutTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    /// The client notified.
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    /// The session ended, or absent when every session of the user ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    pub events: BTreeMap<String, Map<String, Value>>,
+    pub tenant_id: String,
+}
+
+/// Delivers logout tokens to the back-channel logout URI of clients.
+pub trait LogoutNotifier: Send + Sync {
+    fn notify(&self, logout_uri: &str, logout_token: &str) -> Result<(), OAuthError>;
+}
+
+/// Notifier posting logout tokens over HTTPS.
+#[derive(Debug, Clone)]
+pub struct HttpLogoutNotifier {
+    agent: ureq::Agent,
+}
+
+impl HttpLogoutNotifier {
+    pub fn new(timeout: Timeout) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+}
+
+impl LogoutNotifier for HttpLogoutNotifier {
+    fn notify(&self, logout_uri: &str, logout_token: &str) -> Result<(), OAuthError> {
+        self.agent
+            .post(logout_uri)
+            .send_form(&[("logout_token", logout_token)])
+            .map(|_| ())
+            .map_err(|e| OAuthError::Notification(e.to_string()))
+    }
+}
+
+/// Clients notified of a logout, and those that could not be reached.
+#[derive(Debug, Default)]
+pub struct LogoutReport {
+    pub notified: Vec<String>,
+    pub failed: Vec<(String, OAuthError)>,
+}
+
+/// Tells clients that a user's session ended, e.g. on sign-out, session
+/// revocation or when the user is disabled.
+///
+/// Delivery is best effort: failures are reported rather than retried.
+pub struct BackchannelLogoutService<R, N, C, K> {
+    clients: R,
+    notifier: N,
+    config: TokenConfig,
+    keys: K,
+    clock: C,
+}
+
+impl<R, N, C, K> BackchannelLogoutService<R, N, C, K>
+where
+    R: ClientRepository,
+    N: LogoutNotifier,
+    C: Clock,
+    K: KeyProvider,
+{
+    /// Logout tokens are short-lived, whatever the configured lifetime.
+    pub const LOGOUT_TOKEN_TTL_SECONDS: i64 = 120;
+
+    pub fn new(clients: R, notifier: N, config: TokenConfig, keys: K, clock: C) -> Self {
+        Self {
+            clients,
+            notifier,
+            config,
+            keys,
+            clock,
+        }
+    }
+
+    /// Notifies every client of the tenant registered for back-channel
+    /// logout; `session_id` narrows the logout to one session.
+    pub fn logout(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        session_id: Option<&str>,
+    ) -> Result<LogoutReport, OAuthError> {
+        let mut report = LogoutReport::default();
+        for client in self.clients.clients_of_tenant(tenant_id)? {
+            let Some(logout_uri) = client.backchannel_logout_uri() else {
+                continue;
+            };
+            let token = self.logout_token(tenant_id, username, client.client_id(), session_id)?;
+            match self.notifier.notify(logout_uri, &token) {
+                Ok(()) => report.notified.push(client.client_id().to_owned()),
+                Err(e) => report.failed.push((client.client_id().to_owned(), e)),
+            }
+        }
+        Ok(report)
+    }
+
+    fn logout_token(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        client_id: &str,
+        session_id: Option<&str>,
+    ) -> Result<String, OAuthError> {
+        let issued_at = self.clock.now();
+        let claims = LogoutTokenClaims {
+            iss: self.config.issuer().to_owned(),
+            sub: username.to_owned(),
+            aud: client_id.to_owned(),
+            iat: issued_at.timestamp(),
+            exp: (issued_at + Duration::seconds(Self::LOGOUT_TOKEN_TTL_SECONDS)).timestamp(),
+            jti: random_token(),
+            sid: session_id.map(str::to_owned),
+            events: BTreeMap::from([(BACKCHANNEL_LOGOUT_EVENT.to_owned(), Map::new())]),
+            tenant_id: tenant_id.to_owned(),
+        };
+        let (key_id, key) = self.keys.signing_key()?;
+        let mut header = Header::new(self.config.algorithm());
+        header.kid = key_id;
+        header.typ = Some("logout+jwt".to_owned());
+        Ok(encode(&header, &claims, &key).map_err(|e| TokenError::Signing(e.to_string()))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use chrono::{TimeZone, Utc};
+    use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::oauth::client::{Client, InMemoryClientRepository};
+    use crate::tokens::SigningKeys;
+
+    const SECRET: &[u8] = b"logout test secret";
+
+    /// Records deliveries, failing those to unreachable URIs.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, String)>>);
+
+    impl LogoutNotifier for &Recorder {
+        fn notify(&self, logout_uri: &str, logout_token: &str) -> Result<(), OAuthError> {
+            if logout_uri.contains("unreachable") {
+                return Err(OAuthError::Notification("connection refused".to_owned()));
+            }
+            let mut sent = self.0.lock().unwrap();
+            sent.push((logout_uri.to_owned(), logout_token.to_owned()));
+            Ok(())
+        }
+    }
+
+    type Service<'a> =
+        BackchannelLogoutService<InMemoryClientRepository, &'a Recorder, FixedClock, SigningKeys>;
+
+    fn service(recorder: &Recorder) -> Service<'_> {
+        let clients = InMemoryClientRepository::new();
+        for client in [
+            Client::public("app", "acme", "App")
+                .with_backchannel_logout_uri("https://app.example.com/logout"),
+            Client::public("down", "acme", "Down")
+                .with_backchannel_logout_uri("https://unreachable.example.com/logout"),
+            Client::public("silent", "acme", "Silent"),
+            Client::public("other", "globex", "Other")
+                .with_backchannel_logout_uri("https://other.example.com/logout"),
+        ] {
+            clients.save(&client).unwrap();
+        }
+        BackchannelLogoutService::new(
+            clients,
+            recorder,
+            TokenConfig::new("https://iam.example.com", "api"),
+            SigningKeys::hmac(SECRET),
+            FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        )
+    }
+
+    #[test]
+    fn registered_clients_of_the_tenant_are_notified() {
+        let recorder = Recorder::default();
+        let report = service(&recorder).logout("acme", "ada", None).unwrap();
+
+        assert_eq!(report.notified, ["app"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "down");
+        let sent = recorder.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "https://app.example.com/logout");
+    }
+
+    #[test]
+    fn logout_token_is_short_lived_and_unique() {
+        let recorder = Recorder::default();
+        let service = service(&recorder);
+        service.logout("acme", "ada", Some("session-1")).unwrap();
+        service.logout("acme", "ada", Some("session-1")).unwrap();
+
+        let sent = recorder.0.lock().unwrap();
+        let token = &sent[0].1;
+        let header = decode_header(token).unwrap();
+        assert_eq!(header.typ.as_deref(), Some("logout+jwt"));
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&["app"]);
+        validation.validate_exp = false;
+        let claims =
+            decode::<LogoutTokenClaims>(token, &DecodingKey::from_secret(SECRET), &validation)
+                .unwrap()
+                .claims;
+        assert_eq!(claims.iss, "https://iam.example.com");
+        assert_eq!(claims.sub, "ada");
+        assert_eq!(claims.sid.as_deref(), Some("session-1"));
+        assert_eq!(claims.tenant_id, "acme");
+        assert!(claims.events.contains_key(BACKCHANNEL_LOGOUT_EVENT));
+        assert_eq!(claims.exp - claims.iat, Service::LOGOUT_TOKEN_TTL_SECONDS);
+
+        let second =
+            decode::<LogoutTokenClaims>(&sent[1].1, &DecodingKey::from_secret(SECRET), &validation)
+                .unwrap()
+                .claims;
+        assert_ne!(claims.jti, second.jti);
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::tokens::TokenError;
+
+mod authorization;
+mod claims;
+mod client;
+mod client_credentials;
+mod device;
+mod discovery;
+mod id_token;
+mod introspection;
+mod logout;
+mod token_exchange;
+
+pub use authorization::{
+    code_challenge, AuthorizationCodeRecord, AuthorizationCodeRepository, AuthorizationCodeRequest,
+    AuthorizationCodeService, AuthorizationGrant, AuthorizationResponse,
+    InMemoryAuthorizationCodeRepository, PKCE_METHOD,
+};
+pub use claims::{ClaimMapping, ClaimRule, ClaimSource, ClaimTarget};
+pub use client::{
+    Client, ClientAuthenticator, ClientRepository, GrantType, InMemoryClientRepository,
+};
+pub use client_credentials::{client_subject, ClientCredentialsGrant, CLIENT_SUBJECT_PREFIX};
+pub use device::{
+    DeviceAuthorizationRecord, DeviceAuthorizationRepository, DeviceAuthorizationResponse,
+    DeviceAuthorizationService, DevicePrompt, InMemoryDeviceAuthorizationRepository,
+};
+pub use discovery::ProviderMetadata;
+pub use id_token::{IdTokenClaims, IdTokenService};
+pub use introspection::{IntrospectionResponse, TokenIntrospectionService, TokenTypeHint};
+pub use logout::{
+    BackchannelLogoutService, HttpLogoutNotifier, LogoutNotifier, LogoutReport, LogoutTokenClaims,
+    BACKCHANNEL_LOGOUT_EVENT,
+};
+pub use token_exchange::{
+    TokenExchangeGrant, TokenExchangePolicy, TokenExchangeRequest, ACCESS_TOKEN_TYPE,
+};
+
+/// Errors of the OAuth 2.0 and OpenID Connect endpoints.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OAuthError {
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("invalid grant: {0}")]
+    InvalidGrant(String),
+    #[error("client is not allowed to use {0}")]
+    UnauthorizedClient(String),
+    #[error("unsupported grant type: {0}")]
+    UnsupportedGrantType(String),
+    #[error("scope not allowed: {0}")]
+    InvalidScope(String),
+    #[error("audience not served: {0}")]
+    InvalidTarget(String),
+    #[error("access denied")]
+    AccessDenied,
+    #[error("authorization pending")]
+    AuthorizationPending,
+    #[error("polling too fast")]
+    SlowDown,
+    #[error("device code expired")]
+    ExpiredToken,
+    #[error("too many invalid user codes; retry later")]
+    TooManyAttempts,
+    #[error("cannot notify client: {0}")]
+    Notification(String),
+    #[error(transparent)]
+    Token(#[from] TokenError),
+    #[error("OAuth storage failure: {0}")]
+    Storage(String),
+}
+
+impl OAuthError {
+    /// The `error` code of the RFC 6749 error response.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnauthorizedClient(_) => "unauthorized_client",
+            OAuthError::UnsupportedGrantType(_) => "unsupported_grant_type",
+            OAuthError::InvalidScope(_) => "invalid_scope",
+            OAuthError::InvalidTarget(_) => "invalid_target",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::AuthorizationPending => "authorization_pending",
+            OAuthError::SlowDown => "slow_down",
+            OAuthError::ExpiredToken => "expired_token",
+            OAuthError::TooManyAttempts => "temporarily_unavailable",
+            OAuthError::Notification(_) | OAuthError::Token(_) | OAuthError::Storage(_) => {
+                "server_error"
+            }
+        }
+    }
+}
+
+/// Successful token endpoint response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Set for token exchange responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<&'static str>,
+}
+
+impl TokenResponse {
+    pub fn bearer(access_token: impl Into<String>, expires_in: i64) -> Self {
+        Self {
+            access_token: access_token.into(),
+            token_type: "Bearer",
+            expires_in,
+            refresh_token: None,
+            id_token: None,
+            scope: None,
+            issued_token_type: None,
+        }
+    }
+
+    pub fn with_refresh_token(mut self, refresh_token: impl Into<String>) -> Self {
+        self.refresh_token = Some(refresh_token.into());
+        self
+    }
+
+    pub fn with_id_token(mut self, id_token: impl Into<String>) -> Self {
+        self.id_token = Some(id_token.into());
+        self
+    }
+
+    pub fn with_issued_token_type(mut self, token_type: &'static str) -> Self {
+        self.issued_token_type = Some(token_type);
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: &[String]) -> Self {
+        self.scope = (!scopes.is_empty()).then(|| scopes.join(" "));
+        self
+    }
+}
+
+/// Splits a space-separated `scope` parameter, dropping duplicates.
+pub(crate) fn parse_scope(scope: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for s in scope.split_whitespace() {
+        if !scopes.iter().any(|existing| existing == s) {
+            scopes.push(s.to_owned());
+        }
+    }
+    scopes
+}
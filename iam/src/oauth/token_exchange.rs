@@ -0,0 +1,419 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::client::{Client, GrantType};
+use super::client_credentials::client_subject;
+use super::{parse_scope, OAuthError};
+use crate::common::clock::Clock;
+use crate::tokens::{
+    AccessClaims, AccessTokenService, KeyProvider, RevokedTokenRepository, TokenSubject,
+};
+
+/// The only token type exchanged and issued.
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Which exchanges a client may perform.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenExchangePolicy {
+    /// Clients whose tokens may be exchanged; empty allows none, so every
+    /// client has to be allowed explicitly.
+    #[serde(default)]
+    subject_clients: BTreeSet<String>,
+    /// Clients whose tokens may act for the subject; empty refuses every
+    /// actor token.
+    #[serde(default)]
+    actor_clients: BTreeSet<String>,
+    /// Whether issued tokens omit the `act` claim naming the client.
+    #[serde(default)]
+    impersonation: bool,
+}
+
+impl TokenExchangePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_subject_client(mut self, client_id: impl Into<String>) -> Self {
+        self.subject_clients.insert(client_id.into());
+        self
+    }
+
+    pub fn allow_actor_client(mut self, client_id: impl Into<String>) -> Self {
+        self.actor_clients.insert(client_id.into());
+        self
+    }
+
+    pub fn allow_impersonation(mut self, impersonation: bool) -> Self {
+        self.impersonation = impersonation;
+        self
+    }
+
+    pub fn allows_impersonation(&self) -> bool {
+        self.impersonation
+    }
+
+    fn permits_subject_client(&self, client_id: Option<&str>) -> bool {
+        client_id.is_some_and(|id| self.subject_clients.contains(id))
+    }
+
+    fn permits_actor_client(&self, client_id: Option<&str>) -> bool {
+        client_id.is_some_and(|id| self.actor_clients.contains(id))
+    }
+}
+
+/// A request to the token endpoint with the token exchange grant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenExchangeRequest {
+    pub subject_token: String,
+    pub subject_token_type: String,
+    pub actor_token: Option<String>,
+    pub actor_token_type: Option<String>,
+    pub scope: Option<String>,
+    pub audience: Option<String>,
+}
+
+impl TokenExchangeRequest {
+    pub fn new(subject_token: impl Into<String>, subject_token_type: impl Into<String>) -> Self {
+        Self {
+            subject_token: subject_token.into(),
+            subject_token_type: subject_token_type.into(),
+            actor_token: None,
+            actor_token_type: None,
+            scope: None,
+            audience: None,
+        }
+    }
+
+    pub fn with_actor_token(
+        mut self,
+        actor_token: impl Into<String>,
+        actor_token_type: impl Into<String>,
+    ) -> Self {
+        self.actor_token = Some(actor_token.into());
+        self.actor_token_type = Some(actor_token_type.into());
+        self
+    }
+
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+}
+
+/// A user token exchanged for a narrower one (RFC 8693).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenExchangeGrant {
+    /// The user of the subject token, carrying the granted scopes and, unless
+    /// the client may impersonate, an `act` claim naming the actor: the
+    /// subject of the actor token, or else the client's `client:` subject.
+    pub subject: TokenSubject,
+    pub scopes: Vec<String>,
+    pub issued_token_type: &'static str,
+}
+
+impl TokenExchangeGrant {
+    /// Validates the tokens with `tokens`, refusing revoked ones, and checks
+    /// the exchange against the policy of the authenticated confidential
+    /// client.
+    ///
+    /// Both tokens must belong to the client's tenant and come from clients
+    /// its policy allows. Scopes can only narrow: they must be held by the
+    /// subject token and allowed to the client. A subject token without
+    /// scopes yields a token without scopes, and the issued token expires
+    /// no later than the subject token.
+    pub fn new<C: Clock, K: KeyProvider, D: RevokedTokenRepository>(
+        client: &Client,
+        request: &TokenExchangeRequest,
+        tokens: &AccessTokenService<C, K>,
+        revoked: &D,
+    ) -> Result<Self, OAuthError> {
+        client.ensure_grant(GrantType::TokenExchange)?;
+        if !client.is_confidential() {
+            return Err(OAuthError::UnauthorizedClient(
+                GrantType::TokenExchange.to_string(),
+            ));
+        }
+        if let Some(audience) = &request.audience {
+            if audience != tokens.config().audience() {
+                return Err(OAuthError::InvalidTarget(audience.clone()));
+            }
+        }
+        let subject = validate(
+            tokens,
+            revoked,
+            &request.subject_token,
+            &request.subject_token_type,
+        )?;
+        let policy = client.token_exchange_policy();
+        if subject.tenant_id != client.tenant_id()
+            || !policy.permits_subject_client(subject.client_id.as_deref())
+        {
+            return Err(OAuthError::InvalidGrant(
+                "subject token may not be exchanged by this client".to_owned(),
+            ));
+        }
+        let actor = match (&request.actor_token, &request.actor_token_type) {
+            (Some(token), Some(token_type)) => {
+                let actor = validate(tokens, revoked, token, token_type)?;
+                if actor.tenant_id != client.tenant_id()
+                    || !policy.permits_actor_client(actor.client_id.as_deref())
+                {
+                    return Err(OAuthError::InvalidGrant(
+                        "actor token may not act for this client".to_owned(),
+                    ));
+                }
+                Some(actor)
+            }
+            (None, None) => None,
+            _ => {
+                return Err(OAuthError::InvalidRequest(
+                    "actor_token and actor_token_type go together".to_owned(),
+                ))
+            }
+        };
+        let held = subject
+            .scope
+            .as_deref()
+            .map(parse_scope)
+            .unwrap_or_default();
+        let scopes = match request.scope.as_deref().map(parse_scope) {
+            Some(requested) if !requested.is_empty() => {
+                if let Some(scope) = requested.iter().find(|s| !held.contains(s)) {
+                    return Err(OAuthError::InvalidScope(scope.clone()));
+                }
+                client.check_scopes(&requested)?
+            }
+            _ => held
+                .into_iter()
+                .filter(|s| client.scopes().any(|allowed| allowed == s))
+                .collect(),
+        };
+        let authenticated_at = chrono::DateTime::from_timestamp(subject.auth_time, 0)
+            .ok_or_else(|| OAuthError::InvalidGrant("invalid auth_time".to_owned()))?;
+        let not_after = chrono::DateTime::from_timestamp(subject.exp, 0)
+            .ok_or_else(|| OAuthError::InvalidGrant("invalid exp".to_owned()))?;
+        let mut token_subject = TokenSubject::new(&subject.tenant_id, &subject.sub)
+            .with_roles(subject.roles.iter().cloned())
+            .authenticated_with(subject.acr, authenticated_at)
+            .with_scopes(scopes.iter().cloned())
+            .for_client(client.client_id())
+            .expiring_by(not_after);
+        if !policy.allows_impersonation() {
+            let actor_id = actor.as_ref().map_or_else(
+                || client_subject(client.client_id()),
+                |actor| actor.sub.clone(),
+            );
+            let mut act = json!({ "sub": actor_id });
+            if let Some(previous) = subject.extra.get("act") {
+                act["act"] = previous.clone();
+            }
+            token_subject = token_subject.with_access_token_claim("act", act);
+        }
+        Ok(Self {
+            subject: token_subject,
+            scopes,
+            issued_token_type: ACCESS_TOKEN_TYPE,
+        })
+    }
+}
+
+fn validate<C: Clock, K: KeyProvider, D: RevokedTokenRepository>(
+    tokens: &AccessTokenService<C, K>,
+    revoked: &D,
+    token: &str,
+    token_type: &str,
+) -> Result<AccessClaims, OAuthError> {
+    if token_type != ACCESS_TOKEN_TYPE {
+        return Err(OAuthError::InvalidRequest(format!(
+            "unsupported token type: {}",
+            token_type
+        )));
+    }
+    let claims = tokens
+        .validate(token)
+        .map_err(|e| OAuthError::InvalidGrant(e.to_string()))?;
+    if revoked.is_revoked(&claims.jti)? {
+        return Err(OAuthError::InvalidGrant(
+            "token has been revoked".to_owned(),
+        ));
+    }
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::identity::password::{Pbkdf2Strategy, PlainPassword};
+    use crate::tokens::{InMemoryRevokedTokenRepository, SigningKeys, TokenConfig};
+
+    type Tokens = AccessTokenService<FixedClock, SigningKeys>;
+
+    fn tokens() -> Tokens {
+        AccessTokenService::new(
+            TokenConfig::new("https://iam.example.com", "api"),
+            SigningKeys::hmac(b"token exchange test secret"),
+            FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+        )
+    }
+
+    fn gateway(policy: TokenExchangePolicy) -> Client {
+        Client::confidential(
+            "gateway",
+            "acme",
+            "Gateway",
+            &PlainPassword::new("s3cret-s3cret"),
+            &Pbkdf2Strategy::new(1).unwrap(),
+        )
+        .unwrap()
+        .with_grant_types([GrantType::TokenExchange])
+        .with_scope("orders:read")
+        .with_token_exchange_policy(policy)
+    }
+
+    fn user_token(tokens: &Tokens, scopes: &[&str]) -> String {
+        let subject = TokenSubject::new("acme", "ada")
+            .with_scopes(scopes.iter().copied())
+            .for_client("app");
+        tokens.issue(&subject).unwrap().token
+    }
+
+    fn service_token(tokens: &Tokens, tenant_id: &str, client_id: &str) -> String {
+        let subject = TokenSubject::new(tenant_id, client_subject(client_id)).for_client(client_id);
+        tokens.issue(&subject).unwrap().token
+    }
+
+    fn allow_app() -> TokenExchangePolicy {
+        TokenExchangePolicy::new()
+            .allow_subject_client("app")
+            .allow_actor_client("billing")
+    }
+
+    #[test]
+    fn default_policy_denies_every_subject_client() {
+        let tokens = tokens();
+        let request = TokenExchangeRequest::new(user_token(&tokens, &[]), ACCESS_TOKEN_TYPE);
+        let revoked = InMemoryRevokedTokenRepository::new();
+        assert!(matches!(
+            TokenExchangeGrant::new(
+                &gateway(TokenExchangePolicy::default()),
+                &request,
+                &tokens,
+                &revoked
+            ),
+            Err(OAuthError::InvalidGrant(_))
+        ));
+        assert!(
+            TokenExchangeGrant::new(&gateway(allow_app()), &request, &tokens, &revoked).is_ok()
+        );
+    }
+
+    #[test]
+    fn revoked_subject_and_actor_tokens_are_refused() {
+        let tokens = tokens();
+        let revoked = InMemoryRevokedTokenRepository::new();
+        let client = gateway(allow_app());
+        let subject = user_token(&tokens, &["orders:read"]);
+        let actor = service_token(&tokens, "acme", "billing");
+        let request = TokenExchangeRequest::new(subject.clone(), ACCESS_TOKEN_TYPE)
+            .with_actor_token(actor.clone(), ACCESS_TOKEN_TYPE);
+        assert!(TokenExchangeGrant::new(&client, &request, &tokens, &revoked).is_ok());
+
+        for token in [&actor, &subject] {
+            let claims = tokens.validate(token).unwrap();
+            revoked
+                .revoke(
+                    &claims.jti,
+                    chrono::DateTime::from_timestamp(claims.exp, 0).unwrap(),
+                )
+                .unwrap();
+            assert!(matches!(
+                TokenExchangeGrant::new(&client, &request, &tokens, &revoked),
+                Err(OAuthError::InvalidGrant(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn subject_token_without_scopes_yields_no_scopes() {
+        let tokens = tokens();
+        let revoked = InMemoryRevokedTokenRepository::new();
+        let client = gateway(allow_app());
+        let request = TokenExchangeRequest::new(user_token(&tokens, &[]), ACCESS_TOKEN_TYPE);
+        let grant = TokenExchangeGrant::new(&client, &request, &tokens, &revoked).unwrap();
+        assert!(grant.scopes.is_empty());
+
+        let request = request.with_scope("orders:read");
+        assert!(matches!(
+            TokenExchangeGrant::new(&client, &request, &tokens, &revoked),
+            Err(OAuthError::InvalidScope(_))
+        ));
+    }
+
+    #[test]
+    fn scopes_narrow_to_those_held_and_allowed() {
+        let tokens = tokens();
+        let revoked = InMemoryRevokedTokenRepository::new();
+        let request = TokenExchangeRequest::new(
+            user_token(&tokens, &["orders:read", "orders:write"]),
+            ACCESS_TOKEN_TYPE,
+        );
+        let grant =
+            TokenExchangeGrant::new(&gateway(allow_app()), &request, &tokens, &revoked).unwrap();
+        assert_eq!(grant.scopes, ["orders:read"]);
+    }
+
+    #[test]
+    fn actor_tokens_are_bound_to_the_tenant_and_policy() {
+        let tokens = tokens();
+        let revoked = InMemoryRevokedTokenRepository::new();
+        let client = gateway(allow_app());
+        let exchange = |actor: String| {
+            let request = TokenExchangeRequest::new(user_token(&tokens, &[]), ACCESS_TOKEN_TYPE)
+                .with_actor_token(actor, ACCESS_TOKEN_TYPE);
+            TokenExchangeGrant::new(&client, &request, &tokens, &revoked)
+        };
+
+        let grant = exchange(service_token(&tokens, "acme", "billing")).unwrap();
+        assert_eq!(
+            grant.subject.access_token_claims()["act"]["sub"],
+            client_subject("billing")
+        );
+        for actor in [
+            service_token(&tokens, "acme", "reports"),
+            service_token(&tokens, "globex", "billing"),
+            user_token(&tokens, &[]),
+        ] {
+            assert!(matches!(exchange(actor), Err(OAuthError::InvalidGrant(_))));
+        }
+    }
+
+    #[test]
+    fn exchanged_tokens_expire_with_the_subject_token() {
+        let tokens = tokens();
+        let revoked = InMemoryRevokedTokenRepository::new();
+        let subject_token = user_token(&tokens, &[]);
+        let subject_exp = tokens.validate(&subject_token).unwrap().exp;
+
+        let later = AccessTokenService::new(
+            tokens.config().clone(),
+            SigningKeys::hmac(b"token exchange test secret"),
+            FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 10, 0).unwrap()),
+        );
+        let request = TokenExchangeRequest::new(subject_token, ACCESS_TOKEN_TYPE);
+        let grant =
+            TokenExchangeGrant::new(&gateway(allow_app()), &request, &later, &revoked).unwrap();
+        let issued = later.issue(&grant.subject).unwrap();
+        assert_eq!(issued.expires_at.timestamp(), subject_exp);
+        assert_eq!(later.validate(&issued.token).unwrap().exp, subject_exp);
+    }
+}
@@ -0,0 +1,109 @@
+//! The supported public API of the crate.
+//!
+//! Consumers should import from here rather than from individual modules.
+//! Everything the modules export is re-exported, including adapters; items
+//! behind a cargo feature appear only when that feature is enabled.
+
+pub use crate::access::{
+    AccessApplicationService, AccessError, AssignmentOutcome, AttributeRef, AttributeSource,
+    Attributes, AuthorizationRequest, CachingAccessApplicationService, CachingPolicyDecisionPoint,
+    Condition, Decision, Effect, InMemoryPolicyRepository, InMemoryRoleRepository, Permission,
+    Policy, PolicyDecisionPoint, PolicyRepository, Role, RoleAssignmentReport, RoleDescriptor,
+    RoleEvent, RoleRepository,
+};
+pub use crate::common::canonicalization::{
+    Canonical, Canonicalizer, DefaultCanonicalizer, EmailCanonicalizer,
+};
+pub use crate::common::clock::{Clock, FixedClock, SystemClock};
+pub use crate::common::page::{Page, PagePosition, PageRequest, Sort, SortDirection};
+pub use crate::common::serialization::{
+    CborCodec, CodecError, JsonCodec, Payload, PayloadCodec, PayloadFormat, ProtobufCodec,
+};
+#[cfg(feature = "verifiable-credentials")]
+pub use crate::credentials::{
+    CredentialClaims, CredentialIssuer, VerifiableCredential, CREDENTIALS_CONTEXT,
+    EMAIL_VERIFIED_TYPE, ROLE_MEMBERSHIP_TYPE, VERIFIABLE_CREDENTIAL_TYPE,
+};
+pub use crate::federation::{
+    federated_username, AccountLinkingService, ExternalIdentity, FederatedIdentityRepository,
+    FederatedLoginService, FederatedSignIn, FederationError, FederationRedirect,
+    FederationStateRecord, FederationStateRepository, HttpUpstreamClient,
+    InMemoryFederatedIdentityRepository, InMemoryFederationStateRepository,
+    InMemoryLogoutReplayRepository, InMemoryOidcProviderRepository, JitProvisioningPolicy,
+    LinkedIdentity, LocalSignInMethods, LogoutReplayRepository, OidcProvider,
+    OidcProviderRepository, ProvisioningProfile, SignInMethod, UpstreamClient, UpstreamLogout,
+    UserProvisioner,
+};
+pub use crate::identity::audit::{
+    AuditError, AuthenticationAttempt, AuthenticationAuditQuery, AuthenticationAuditRepository,
+    AuthenticationFailure, AuthenticationOutcome, InMemoryAuthenticationAuditRepository,
+};
+pub use crate::identity::authentication::{
+    AuthenticationError, AuthenticationService, AuthenticationStrength, SignInAccounts,
+    SignInOutcome, StepUpRequirement,
+};
+pub use crate::identity::captcha::{HumanVerification, HumanVerificationError, SiteVerifyClient};
+pub use crate::identity::discovery::{
+    DiscoveryError, InMemoryTenantEmailDomainRepository, TenantDiscoveryService, TenantEmailDomain,
+    TenantEmailDomainRepository, TenantRoute,
+};
+pub use crate::identity::lockout::{
+    InMemoryLoginAttemptTracker, LockoutPolicy, LockoutStatus, LoginAttemptError, LoginAttemptKey,
+    LoginAttemptTracker,
+};
+pub use crate::identity::magic_link::{
+    MagicLinkAccounts, MagicLinkError, MagicLinkService, MagicLinkSignIn,
+};
+pub use crate::identity::password::{
+    Argon2Strategy, BcryptStrategy, BloomFilterChecker, CharacterClass, CompositeStrategy,
+    CompromisedCheckError, CompromisedPasswordChecker, EncryptedPassword, HibpChecker,
+    LdapSshaStrategy, PasswordAuthentication, PasswordChangeReason, PasswordCredential,
+    PasswordError, PasswordHashingStrategy, PasswordPolicy, PasswordPolicyError,
+    PasswordPolicyViolation, PasswordVerification, Pbkdf2Strategy, Pepper, PepperedStrategy,
+    PlainPassword, SaltPosition, SaltedSha1Strategy, ScryptStrategy, StrengthReport,
+};
+pub use crate::identity::recovery::{
+    InMemoryRecoveryChannelRepository, MessageSender, RecoveryChannel, RecoveryChannelKind,
+    RecoveryChannelRepository, RecoveryChannelService, RecoveryDestination, RecoveryError,
+};
+pub use crate::identity::reset::{PasswordResetAccounts, PasswordResetError, PasswordResetService};
+pub use crate::identity::risk::{
+    CompositeRiskEvaluator, GeoLocation, GeoVelocityEvaluator, RiskDecision, RiskError,
+    SignInContext, SignInRiskEvaluator,
+};
+pub use crate::identity::single_use::{
+    InMemorySingleUseTokenRepository, SingleUseToken, SingleUseTokenError,
+    SingleUseTokenRepository, TokenPurpose,
+};
+pub use crate::identity::throttling::{
+    InMemoryRateLimiter, RateLimitDecision, RateLimitError, RateLimitKey, RateLimitPolicies,
+    RateLimitPolicy, RateLimitSubject, RateLimiter, RedisRateLimiter, RedisScripting,
+    ThrottledOperation,
+};
+pub use crate::mfa::{
+    Device, DeviceRepository, DeviceService, EncryptedSecret, InMemoryDeviceRepository,
+    InMemoryMfaCredentialRepository, MfaCredential, MfaCredentialRepository, MfaError, MfaService,
+    RecoveryCodes, SecretCipher, Totp, TotpEnrollment, TotpSecret, RECOVERY_CODE_COUNT,
+};
+pub use crate::oauth::{
+    client_subject, code_challenge, AuthorizationCodeRecord, AuthorizationCodeRepository,
+    AuthorizationCodeRequest, AuthorizationCodeService, AuthorizationGrant, AuthorizationResponse,
+    BackchannelLogoutService, ClaimMapping, ClaimRule, ClaimSource, ClaimTarget, Client,
+    ClientAuthenticator, ClientCredentialsGrant, ClientRepository, DeviceAuthorizationRecord,
+    DeviceAuthorizationRepository, DeviceAuthorizationResponse, DeviceAuthorizationService,
+    DevicePrompt, GrantType, HttpLogoutNotifier, IdTokenClaims, IdTokenService,
+    InMemoryAuthorizationCodeRepository, InMemoryClientRepository,
+    InMemoryDeviceAuthorizationRepository, IntrospectionResponse, LogoutNotifier, LogoutReport,
+    LogoutTokenClaims, OAuthError, ProviderMetadata, TokenExchangeGrant, TokenExchangePolicy,
+    TokenExchangeRequest, TokenIntrospectionService, TokenResponse, TokenTypeHint,
+    ACCESS_TOKEN_TYPE, BACKCHANNEL_LOGOUT_EVENT, CLIENT_SUBJECT_PREFIX, PKCE_METHOD,
+};
+pub use crate::tokens::{
+    AccessClaims, AccessTokenService, InMemoryRefreshTokenRepository,
+    InMemoryRevokedTokenRepository, InMemorySigningKeyRepository, IssuedRefreshToken, IssuedToken,
+    Jwk, Jwks, KeyManager, KeyProvider, KeyRotationPolicy, RefreshGrant, RefreshTokenRecord,
+    RefreshTokenRepository, RefreshTokenService, RefreshTokenStatus, RevokedTokenRepository,
+    SigningAlgorithm, SigningKeyRecord, SigningKeyRepository, SigningKeys, TokenConfig, TokenError,
+    TokenSubject,
+};
+pub use crate::verify::{JwksKeyProvider, OfflineVerifier, RoleSnapshot};
@@ -0,0 +1,499 @@
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{TokenConfig, TokenError};
+use crate::common::clock::Clock;
+use crate::identity::authentication::{AuthenticationStrength, StepUpRequirement};
+
+/// Keys used to sign and verify access tokens.
+#[derive(Clone)]
+pub struct SigningKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl SigningKeys {
+    /// Shared secret for the HMAC algorithms.
+    pub fn hmac(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// PEM-encoded key pair for the RSA and RSA-PSS algorithms.
+    pub fn rsa_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, TokenError> {
+        Ok(Self {
+            encoding: EncodingKey::from_rsa_pem(private_key).map_err(invalid_key)?,
+            decoding: DecodingKey::from_rsa_pem(public_key).map_err(invalid_key)?,
+        })
+    }
+
+    /// PEM-encoded key pair for the ECDSA algorithms.
+    pub fn ec_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, TokenError> {
+        Ok(Self {
+            encoding: EncodingKey::from_ec_pem(private_key).map_err(invalid_key)?,
+            decoding: DecodingKey::from_ec_pem(public_key).map_err(invalid_key)?,
+        })
+    }
+
+    /// PEM-encoded key pair for EdDSA.
+    pub fn ed_pem(private_key: &[u8], public_key: &[u8]) -> Result<Self, TokenError> {
+        Ok(Self {
+            encoding: EncodingKey::from_ed_pem(private_key).map_err(invalid_key)?,
+            decoding: DecodingKey::from_ed_pem(public_key).map_err(invalid_key)?,
+        })
+    }
+}
+
+impl fmt::Debug for SigningKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SigningKeys(***)")
+    }
+}
+
+/// Supplies the keys used to sign new tokens and to verify presented ones.
+pub trait KeyProvider: Send + Sync {
+    /// The key to sign with, along with the key id to put in the header.
+    fn signing_key(&self) -> Result<(Option<String>, EncodingKey), TokenError>;
+
+    /// The key verifying tokens whose header carries the given key id.
+    fn verification_key(&self, key_id: Option<&str>) -> Result<DecodingKey, TokenError>;
+}
+
+impl<K: KeyProvider + ?Sized> KeyProvider for Arc<K> {
+    fn signing_key(&self) -> Result<(Option<String>, EncodingKey), TokenError> {
+        (**self).signing_key()
+    }
+
+    fn verification_key(&self, key_id: Option<&str>) -> Result<DecodingKey, TokenError> {
+        (**self).verification_key(key_id)
+    }
+}
+
+impl KeyProvider for SigningKeys {
+    fn signing_key(&self) -> Result<(Option<String>, EncodingKey), TokenError> {
+        Ok((None, self.encoding.clone()))
+    }
+
+    fn verification_key(&self, _key_id: Option<&str>) -> Result<DecodingKey, TokenError> {
+        Ok(self.decoding.clone())
+    }
+}
+
+pub(crate) fn invalid_key(error: jsonwebtoken::errors::Error) -> TokenError {
+    TokenError::InvalidKey(error.to_string())
+}
+
+/// The authenticated user a token is issued for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSubject {
+    tenant_id: String,
+    username: String,
+    roles: Vec<String>,
+    strength: AuthenticationStrength,
+    authenticated_at: Option<DateTime<Utc>>,
+    scopes: Vec<String>,
+    client_id: Option<String>,
+    not_after: Option<DateTime<Utc>>,
+    access_token_claims: Map<String, Value>,
+    id_token_claims: Map<String, Value>,
+}
+
+impl TokenSubject {
+    pub fn new(tenant_id: impl Into<String>, username: impl Into<String>) -> Self {
+        Self {
+            tenant_id: tenant_id.into(),
+            username: username.into(),
+            roles: Vec::new(),
+            strength: AuthenticationStrength::default(),
+            authenticated_at: None,
+            scopes: Vec::new(),
+            client_id: None,
+            not_after: None,
+            access_token_claims: Map::new(),
+            id_token_claims: Map::new(),
+        }
+    }
+
+    pub fn with_roles(mut self, roles: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.roles = roles.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Records how and when the user authenticated; tokens default to the
+    /// time of issue otherwise.
+    pub fn authenticated_with(
+        mut self,
+        strength: AuthenticationStrength,
+        authenticated_at: DateTime<Utc>,
+    ) -> Self {
+        self.strength = strength;
+        self.authenticated_at = Some(authenticated_at);
+        self
+    }
+
+    /// Limits the token to the OAuth scopes granted to a client.
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Records the OAuth client the token is issued to.
+    pub fn for_client(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Caps the expiry of tokens issued for the subject, which otherwise
+    /// follows the configured lifetime.
+    pub fn expiring_by(mut self, not_after: DateTime<Utc>) -> Self {
+        self.not_after = Some(not_after);
+        self
+    }
+
+    /// Adds a custom claim to access tokens.
+    pub fn with_access_token_claim(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.access_token_claims.insert(name.into(), value);
+        self
+    }
+
+    /// Adds a custom claim to ID tokens.
+    pub fn with_id_token_claim(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.id_token_claims.insert(name.into(), value);
+        self
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn roles(&self) -> &[String] {
+        &self.roles
+    }
+
+    pub fn strength(&self) -> AuthenticationStrength {
+        self.strength
+    }
+
+    pub fn authenticated_at(&self) -> Option<DateTime<Utc>> {
+        self.authenticated_at
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    pub fn not_after(&self) -> Option<DateTime<Utc>> {
+        self.not_after
+    }
+
+    pub fn access_token_claims(&self) -> &Map<String, Value> {
+        &self.access_token_claims
+    }
+
+    pub fn id_token_claims(&self) -> &Map<String, Value> {
+        &self.id_token_claims
+    }
+}
+
+/// Claims carried by an access token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub tenant_id: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub iat: i64,
+    pub nbf: i64,
+    pub exp: i64,
+    pub jti: String,
+    /// Strength of the authentication behind the token.
+    #[serde(default)]
+    pub acr: AuthenticationStrength,
+    /// When the user authenticated, as a Unix timestamp.
+    #[serde(default)]
+    pub auth_time: i64,
+    /// Space-separated OAuth scopes, for tokens issued to clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Custom claims added by the client's claim mapping.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl AccessClaims {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_deref()
+            .is_some_and(|granted| granted.split_whitespace().any(|s| s == scope))
+    }
+
+    /// Who acts on behalf of the subject, for tokens obtained by token
+    /// exchange.
+    pub fn actor(&self) -> Option<&str> {
+        self.extra
+            .get("act")
+            .and_then(|act| act.get("sub"))
+            .and_then(Value::as_str)
+    }
+
+    /// Whether the token's authentication is strong and recent enough for an
+    /// operation requiring step-up.
+    pub fn satisfies(&self, requirement: &StepUpRequirement, clock: &impl Clock) -> bool {
+        DateTime::from_timestamp(self.auth_time, 0).is_some_and(|authenticated_at| {
+            requirement.is_satisfied_by(self.acr, authenticated_at, clock)
+        })
+    }
+}
+
+/// A freshly signed access token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Mints access tokens after authentication and validates them on requests.
+pub struct AccessTokenService<C, K = SigningKeys> {
+    config: TokenConfig,
+    keys: K,
+    clock: C,
+}
+
+impl<C: Clock, K: KeyProvider> AccessTokenService<C, K> {
+    pub fn new(config: TokenConfig, keys: K, clock: C) -> Self {
+        Self {
+            config,
+            keys,
+            clock,
+        }
+    }
+
+    pub fn config(&self) -> &TokenConfig {
+        &self.config
+    }
+
+    pub fn issue(&self, subject: &TokenSubject) -> Result<IssuedToken, TokenError> {
+        let issued_at = self.clock.now();
+        let expires_at = self
+            .config
+            .expires_at(issued_at)
+            .min(subject.not_after.unwrap_or(DateTime::<Utc>::MAX_UTC));
+        let claims = AccessClaims {
+            iss: self.config.issuer.clone(),
+            sub: subject.username.clone(),
+            aud: self.config.audience.clone(),
+            tenant_id: subject.tenant_id.clone(),
+            roles: subject.roles.clone(),
+            iat: issued_at.timestamp(),
+            nbf: issued_at.timestamp(),
+            exp: expires_at.timestamp(),
+            jti: token_id(),
+            acr: subject.strength,
+            auth_time: subject.authenticated_at.unwrap_or(issued_at).timestamp(),
+            scope: (!subject.scopes.is_empty()).then(|| subject.scopes.join(" ")),
+            client_id: subject.client_id.clone(),
+            extra: subject.access_token_claims.clone(),
+        };
+        let (key_id, key) = self.keys.signing_key()?;
+        let mut header = Header::new(self.config.algorithm);
+        header.kid = key_id;
+        let token =
+            encode(&header, &claims, &key).map_err(|e| TokenError::Signing(e.to_string()))?;
+        Ok(IssuedToken { token, expires_at })
+    }
+
+    /// Checks signature, issuer, audience and validity window, returning the
+    /// claims of a valid token.
+    pub fn validate(&self, token: &str) -> Result<AccessClaims, TokenError> {
+        let mut validation = Validation::new(self.config.algorithm);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+        // Time checks use the injected clock instead of the system time.
+        validation.validate_exp = false;
+        validation.validate_nbf = false;
+        let header = decode_header(token).map_err(|e| TokenError::Invalid(e.to_string()))?;
+        let key = self.keys.verification_key(header.kid.as_deref())?;
+        let claims = decode::<AccessClaims>(token, &key, &validation)
+            .map_err(|e| TokenError::Invalid(e.to_string()))?
+            .claims;
+        let now = self.clock.now().timestamp();
+        let leeway = self.config.leeway.num_seconds();
+        if now > claims.exp + leeway {
+            return Err(TokenError::Expired);
+        }
+        if now + leeway < claims.nbf {
+            return Err(TokenError::NotYetValid);
+        }
+        Ok(claims)
+    }
+}
+
+fn token_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use data_encoding::BASE64URL_NOPAD;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::tokens::SigningAlgorithm;
+
+    const SECRET: &[u8] = b"access token test secret";
+
+    fn service_with(config: TokenConfig, clock: &FixedClock) -> AccessTokenService<&FixedClock> {
+        AccessTokenService::new(config, SigningKeys::hmac(SECRET), clock)
+    }
+
+    fn service(clock: &FixedClock) -> AccessTokenService<&FixedClock> {
+        service_with(TokenConfig::new("https://iam.example.com", "api"), clock)
+    }
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn token(clock: &FixedClock) -> String {
+        let subject = TokenSubject::new("acme", "ada").with_roles(["admin"]);
+        service(clock).issue(&subject).unwrap().token
+    }
+
+    #[test]
+    fn issued_token_validates() {
+        let clock = clock();
+        let claims = service(&clock).validate(&token(&clock)).unwrap();
+        assert_eq!(
+            (claims.tenant_id.as_str(), claims.sub.as_str()),
+            ("acme", "ada")
+        );
+        assert!(claims.has_role("admin"));
+    }
+
+    #[test]
+    fn other_algorithm_is_rejected() {
+        let clock = clock();
+        let config = TokenConfig::new("https://iam.example.com", "api")
+            .with_algorithm(SigningAlgorithm::HS384);
+        let token = service_with(config, &clock)
+            .issue(&TokenSubject::new("acme", "ada"))
+            .unwrap()
+            .token;
+        assert!(matches!(
+            service(&clock).validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn unsigned_token_is_rejected() {
+        let clock = clock();
+        let signed = token(&clock);
+        let payload = signed.split('.').nth(1).unwrap();
+        let header = BASE64URL_NOPAD.encode(br#"{"alg":"none","typ":"JWT"}"#);
+        for token in [
+            format!("{header}.{payload}."),
+            format!("{header}.{payload}"),
+        ] {
+            assert!(matches!(
+                service(&clock).validate(&token),
+                Err(TokenError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tampered_or_foreign_signature_is_rejected() {
+        let clock = clock();
+        let foreign = AccessTokenService::new(
+            TokenConfig::new("https://iam.example.com", "api"),
+            SigningKeys::hmac(b"some other secret"),
+            &clock,
+        )
+        .issue(&TokenSubject::new("acme", "ada"))
+        .unwrap()
+        .token;
+        assert!(matches!(
+            service(&clock).validate(&foreign),
+            Err(TokenError::Invalid(_))
+        ));
+
+        let mut tampered = token(&clock);
+        tampered.push('A');
+        assert!(service(&clock).validate(&tampered).is_err());
+    }
+
+    #[test]
+    fn wrong_audience_or_issuer_is_rejected() {
+        let clock = clock();
+        let token = token(&clock);
+        for config in [
+            TokenConfig::new("https://iam.example.com", "billing"),
+            TokenConfig::new("https://evil.example.com", "api"),
+        ] {
+            assert!(matches!(
+                service_with(config, &clock).validate(&token),
+                Err(TokenError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn expiry_allows_the_leeway_only() {
+        let clock = clock();
+        let token = token(&clock);
+        let ttl = Duration::minutes(TokenConfig::DEFAULT_TTL_MINUTES);
+        let leeway = Duration::seconds(TokenConfig::DEFAULT_LEEWAY_SECONDS);
+
+        clock.advance(ttl + leeway);
+        assert!(service(&clock).validate(&token).is_ok());
+        clock.advance(Duration::seconds(1));
+        assert!(matches!(
+            service(&clock).validate(&token),
+            Err(TokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn token_from_the_future_is_rejected_beyond_the_leeway() {
+        let clock = clock();
+        let token = token(&clock);
+        let leeway = Duration::seconds(TokenConfig::DEFAULT_LEEWAY_SECONDS);
+
+        clock.advance(-leeway);
+        assert!(service(&clock).validate(&token).is_ok());
+        clock.advance(Duration::seconds(-1));
+        assert!(matches!(
+            service(&clock).validate(&token),
+            Err(TokenError::NotYetValid)
+        ));
+    }
+}
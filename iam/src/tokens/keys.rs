@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use super::access::KeyProvider;
+use super::TokenError;
+use crate::common::clock::Clock;
+
+/// An Ed25519 token signing key pair with its validity periods.
+///
+/// A key is published before it starts signing, signs until it retires, and
+/// stays published until tokens it signed have expired. The private key is
+/// kept as PKCS#8; repositories are expected to encrypt it at rest.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SigningKeyRecord {
+    key_id: String,
+    private_key: Vec<u8>,
+    #[zeroize(skip)]
+    public_key: Vec<u8>,
+    #[zeroize(skip)]
+    activates_at: DateTime<Utc>,
+    #[zeroize(skip)]
+    retires_at: DateTime<Utc>,
+    #[zeroize(skip)]
+    expires_at: DateTime<Utc>,
+}
+
+impl SigningKeyRecord {
+    fn generate(
+        activates_at: DateTime<Utc>,
+        retires_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, TokenError> {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| TokenError::InvalidKey("cannot generate signing key".to_owned()))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8(document.as_ref())
+            .map_err(|e| TokenError::InvalidKey(e.to_string()))?;
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        Ok(Self {
+            key_id: thumbprint(&public_key),
+            private_key: document.as_ref().to_vec(),
+            public_key,
+            activates_at,
+            retires_at,
+            expires_at,
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn activates_at(&self) -> DateTime<Utc> {
+        self.activates_at
+    }
+
+    pub fn retires_at(&self) -> DateTime<Utc> {
+        self.retires_at
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    /// Whether the key signs new tokens at the given instant.
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.activates_at <= at && at < self.retires_at
+    }
+
+    /// Whether the key is published for verification at the given instant.
+    pub fn is_published_at(&self, at: DateTime<Utc>) -> bool {
+        at < self.expires_at
+    }
+
+    pub fn to_jwk(&self) -> Jwk {
+        Jwk {
+            kty: "OKP".to_owned(),
+            crv: "Ed25519".to_owned(),
+            x: BASE64URL_NOPAD.encode(&self.public_key),
+            kid: self.key_id.clone(),
+            alg: "EdDSA".to_owned(),
+            key_use: "sig".to_owned(),
+        }
+    }
+}
+
+impl fmt::Debug for SigningKeyRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKeyRecord")
+            .field("key_id", &self.key_id)
+            .field("activates_at", &self.activates_at)
+            .field("retires_at", &self.retires_at)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// RFC 7638 thumbprint of an Ed25519 public key, used as its key id.
+fn thumbprint(public_key: &[u8]) -> String {
+    let canonical = format!(
+        r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+        BASE64URL_NOPAD.encode(public_key)
+    );
+    BASE64URL_NOPAD.encode(&Sha256::digest(canonical.as_bytes()))
+}
+
+/// A public key in JWK form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+}
+
+/// The JWKS document served to resource servers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Stores signing keys.
+pub trait SigningKeyRepository: Send + Sync {
+    fn all(&self) -> Result<Vec<SigningKeyRecord>, TokenError>;
+
+    /// Inserts or replaces the key with the same id.
+    fn save(&self, key: &SigningKeyRecord) -> Result<(), TokenError>;
+
+    fn remove(&self, key_id: &str) -> Result<(), TokenError>;
+}
+
+/// Repository keeping signing keys in process memory.
+#[derive(Debug, Default)]
+pub struct InMemorySigningKeyRepository {
+    keys: RwLock<HashMap<String, SigningKeyRecord>>,
+}
+
+impl InMemorySigningKeyRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SigningKeyRepository for InMemorySigningKeyRepository {
+    fn all(&self) -> Result<Vec<SigningKeyRecord>, TokenError> {
+        let keys = self.keys.read().expect("signing keys lock poisoned");
+        Ok(keys.values().cloned().collect())
+    }
+
+    fn save(&self, key: &SigningKeyRecord) -> Result<(), TokenError> {
+        let mut keys = self.keys.write().expect("signing keys lock poisoned");
+        keys.insert(key.key_id.clone(), key.clone());
+        Ok(())
+    }
+
+    fn remove(&self, key_id: &str) -> Result<(), TokenError> {
+        self.keys
+            .write()
+            .expect("signing keys lock poisoned")
+            .remove(key_id);
+        Ok(())
+    }
+}
+
+/// How long keys sign, and how long they are published around that period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRotationPolicy {
+    lifetime: Duration,
+    publish_ahead: Duration,
+    verification_grace: Duration,
+}
+
+impl KeyRotationPolicy {
+    /// Keys sign for `lifetime`, are published `publish_ahead` before they
+    /// start signing, and remain published `verification_grace` after they
+    /// retire; the grace period must cover the access token TTL.
+    pub fn new(lifetime: Duration, publish_ahead: Duration, verification_grace: Duration) -> Self {
+        Self {
+            lifetime,
+            publish_ahead,
+            verification_grace,
+        }
+    }
+}
+
+impl Default for KeyRotationPolicy {
+    fn default() -> Self {
+        Self::new(Duration::days(30), Duration::days(1), Duration::days(1))
+    }
+}
+
+/// Generates, rotates and publishes EdDSA token signing keys.
+///
+/// Used as the key provider of an access token service configured with
+/// `SigningAlgorithm::EdDSA`; `rotate_if_due` is meant to be called on a
+/// schedule.
+pub struct KeyManager<R, C> {
+    repository: R,
+    policy: KeyRotationPolicy,
+    clock: C,
+}
+
+impl<R: SigningKeyRepository, C: Clock> KeyManager<R, C> {
+    pub fn new(repository: R, policy: KeyRotationPolicy, clock: C) -> Self {
+        Self {
+            repository,
+            policy,
+            clock,
+        }
+    }
+
+    /// Drops expired keys and generates a successor when the current key is
+    /// about to retire, returning the id of the generated key.
+    pub fn rotate_if_due(&self) -> Result<Option<String>, TokenError> {
+        let now = self.clock.now();
+        let mut keys = Vec::new();
+        for key in self.repository.all()? {
+            if key.is_published_at(now) {
+                keys.push(key);
+            } else {
+                self.repository.remove(&key.key_id)?;
+            }
+        }
+        let last_retirement = keys.iter().map(|key| key.retires_at).max();
+        if last_retirement.is_some_and(|retires_at| retires_at - self.policy.publish_ahead > now) {
+            return Ok(None);
+        }
+        let activates_at = last_retirement.filter(|at| *at > now).unwrap_or(now);
+        let retires_at = activates_at + self.policy.lifetime;
+        let key = SigningKeyRecord::generate(
+            activates_at,
+            retires_at,
+            retires_at + self.policy.verification_grace,
+        )?;
+        self.repository.save(&key)?;
+        Ok(Some(key.key_id.clone()))
+    }
+
+    /// The key currently signing new tokens.
+    pub fn active_key(&self) -> Result<Option<SigningKeyRecord>, TokenError> {
+        let now = self.clock.now();
+        Ok(self
+            .repository
+            .all()?
+            .into_iter()
+            .filter(|key| key.is_active_at(now))
+            .max_by_key(|key| key.activates_at))
+    }
+
+    /// Public keys of every published key, including upcoming ones.
+    pub fn jwks(&self) -> Result<Jwks, TokenError> {
+        let now = self.clock.now();
+        let mut keys: Vec<_> = self
+            .repository
+            .all()?
+            .into_iter()
+            .filter(|key| key.is_published_at(now))
+            .collect();
+        keys.sort_by_key(|key| key.activates_at);
+        Ok(Jwks {
+            keys: keys.iter().map(SigningKeyRecord::to_jwk).collect(),
+        })
+    }
+}
+
+impl<R: SigningKeyRepository, C: Clock> KeyProvider for KeyManager<R, C> {
+    fn signing_key(&self) -> Result<(Option<String>, EncodingKey), TokenError> {
+        let key = self
+            .active_key()?
+            .ok_or_else(|| TokenError::InvalidKey("no active signing key".to_owned()))?;
+        Ok((
+            Some(key.key_id.clone()),
+            EncodingKey::from_ed_der(&key.private_key),
+        ))
+    }
+
+    fn verification_key(&self, key_id: Option<&str>) -> Result<DecodingKey, TokenError> {
+        let key_id = key_id.ok_or_else(|| TokenError::Invalid("token has no key id".to_owned()))?;
+        let now = self.clock.now();
+        let key = self
+            .repository
+            .all()?
+            .into_iter()
+            .find(|key| key.key_id == key_id && key.is_published_at(now))
+            .ok_or_else(|| TokenError::Invalid(format!("unknown key id: {}", key_id)))?;
+        Ok(DecodingKey::from_ed_der(&key.public_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+    use crate::tokens::{AccessTokenService, SigningAlgorithm, TokenConfig, TokenSubject};
+
+    type Manager<'a> = KeyManager<InMemorySigningKeyRepository, &'a FixedClock>;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn manager(clock: &FixedClock) -> Manager<'_> {
+        KeyManager::new(
+            InMemorySigningKeyRepository::new(),
+            KeyRotationPolicy::default(),
+            clock,
+        )
+    }
+
+    fn tokens<'a>(
+        manager: &Arc<Manager<'a>>,
+        clock: &'a FixedClock,
+    ) -> AccessTokenService<&'a FixedClock, Arc<Manager<'a>>> {
+        let config = TokenConfig::new("https://iam.example.com", "api")
+            .with_algorithm(SigningAlgorithm::EdDSA)
+            .with_ttl(Duration::days(2));
+        AccessTokenService::new(config, Arc::clone(manager), clock)
+    }
+
+    #[test]
+    fn first_key_is_generated_once_and_published() {
+        let clock = clock();
+        let manager = manager(&clock);
+        let key_id = manager.rotate_if_due().unwrap().unwrap();
+        assert_eq!(manager.rotate_if_due().unwrap(), None);
+
+        assert_eq!(manager.active_key().unwrap().unwrap().key_id(), key_id);
+        let jwks = manager.jwks().unwrap();
+        assert_eq!(jwks.keys.len(), 1);
+        let jwk = &jwks.keys[0];
+        assert_eq!(jwk.kid, key_id);
+        assert_eq!(
+            (jwk.kty.as_str(), jwk.crv.as_str(), jwk.alg.as_str()),
+            ("OKP", "Ed25519", "EdDSA")
+        );
+        assert!(manager.verification_key(Some(&key_id)).is_ok());
+        assert!(manager.verification_key(Some("unknown")).is_err());
+        assert!(manager.verification_key(None).is_err());
+    }
+
+    #[test]
+    fn successor_is_published_ahead_and_takes_over_at_retirement() {
+        let clock = clock();
+        let manager = manager(&clock);
+        let first = manager.rotate_if_due().unwrap().unwrap();
+
+        clock.advance(Duration::days(29));
+        let second = manager.rotate_if_due().unwrap().unwrap();
+        assert_eq!(manager.rotate_if_due().unwrap(), None);
+        assert_eq!(manager.active_key().unwrap().unwrap().key_id(), first);
+        let published: Vec<_> = manager
+            .jwks()
+            .unwrap()
+            .keys
+            .into_iter()
+            .map(|k| k.kid)
+            .collect();
+        assert_eq!(published, [first.clone(), second.clone()]);
+
+        clock.advance(Duration::days(1));
+        assert_eq!(manager.active_key().unwrap().unwrap().key_id(), second);
+    }
+
+    #[test]
+    fn retired_key_verifies_until_it_expires() {
+        let clock = clock();
+        let manager = Arc::new(manager(&clock));
+        let first = manager.rotate_if_due().unwrap().unwrap();
+        clock.advance(Duration::days(29));
+        manager.rotate_if_due().unwrap();
+
+        clock.advance(Duration::hours(23));
+        let tokens = tokens(&manager, &clock);
+        let token = tokens
+            .issue(&TokenSubject::new("acme", "ada"))
+            .unwrap()
+            .token;
+        assert_eq!(
+            jsonwebtoken::decode_header(&token).unwrap().kid.as_deref(),
+            Some(first.as_str())
+        );
+
+        // Retired at day 30, published until day 31.
+        clock.advance(Duration::hours(2));
+        assert!(tokens.validate(&token).is_ok());
+        clock.advance(Duration::hours(22));
+        assert!(tokens.validate(&token).is_ok());
+
+        clock.advance(Duration::hours(1));
+        assert!(matches!(
+            tokens.validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+        manager.rotate_if_due().unwrap();
+        assert!(manager.jwks().unwrap().keys.iter().all(|k| k.kid != first));
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Duration, Utc};
+use thiserror::Error;
+
+mod access;
+mod keys;
+mod refresh;
+mod revocation;
+
+pub use access::{
+    AccessClaims, AccessTokenService, IssuedToken, KeyProvider, SigningKeys, TokenSubject,
+};
+pub use jsonwebtoken::Algorithm as SigningAlgorithm;
+pub use keys::{
+    InMemorySigningKeyRepository, Jwk, Jwks, KeyManager, KeyRotationPolicy, SigningKeyRecord,
+    SigningKeyRepository,
+};
+pub use refresh::{
+    InMemoryRefreshTokenRepository, IssuedRefreshToken, RefreshGrant, RefreshTokenRecord,
+    RefreshTokenRepository, RefreshTokenService, RefreshTokenStatus,
+};
+pub use revocation::{InMemoryRevokedTokenRepository, RevokedTokenRepository};
+
+/// Errors raised while issuing or validating tokens.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum TokenError {
+    #[error("invalid signing key: {0}")]
+    InvalidKey(String),
+    #[error("cannot sign token: {0}")]
+    Signing(String),
+    #[error("invalid token: {0}")]
+    Invalid(String),
+    #[error("token expired")]
+    Expired,
+    #[error("token not yet valid")]
+    NotYetValid,
+    #[error("token revoked")]
+    Revoked,
+    #[error("refresh token reused; token family revoked")]
+    Reused,
+    #[error("token storage failure: {0}")]
+    Storage(String),
+}
+
+/// Settings shared by issued access tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenConfig {
+    issuer: String,
+    audience: String,
+    ttl: Duration,
+    leeway: Duration,
+    algorithm: SigningAlgorithm,
+}
+
+impl TokenConfig {
+    pub const DEFAULT_TTL_MINUTES: i64 = 15;
+    pub const DEFAULT_LEEWAY_SECONDS: i64 = 30;
+
+    pub fn new(issuer: impl Into<String>, audience: impl Into<String>) -> Self {
+        Self {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            ttl: Duration::minutes(Self::DEFAULT_TTL_MINUTES),
+            leeway: Duration::seconds(Self::DEFAULT_LEEWAY_SECONDS),
+            algorithm: SigningAlgorithm::HS256,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Tolerance for clock differences between issuer and validators.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    pub fn with_algorithm(mut self, algorithm: SigningAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn leeway(&self) -> Duration {
+        self.leeway
+    }
+
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn expires_at(&self, issued_at: DateTime<Utc>) -> DateTime<Utc> {
+        issued_at + self.ttl
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::TokenError;
+use crate::common::clock::Clock;
+use crate::common::secret::{digest, random_token};
+
+/// Lifecycle state of a stored refresh token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefreshTokenStatus {
+    Active,
+    /// Exchanged for a successor; presenting it again signals theft.
+    Rotated,
+    Revoked,
+}
+
+/// A refresh token as persisted; only the digest of the token is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefreshTokenRecord {
+    hash: String,
+    family_id: String,
+    tenant_id: String,
+    username: String,
+    /// The client the token was issued to; records stored before tokens
+    /// were bound have none and are refused everywhere.
+    #[serde(default)]
+    client_id: String,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    status: RefreshTokenStatus,
+}
+
+impl RefreshTokenRecord {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Identifier shared by all tokens descending from the same sign-in.
+    pub fn family_id(&self) -> &str {
+        &self.family_id
+    }
+
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Whether the token was issued to the client.
+    pub fn is_issued_to(&self, client_id: &str) -> bool {
+        !self.client_id.is_empty() && self.client_id == client_id
+    }
+
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        self.issued_at
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    pub fn status(&self) -> RefreshTokenStatus {
+        self.status
+    }
+}
+
+/// Stores refresh tokens by digest.
+pub trait RefreshTokenRepository: Send + Sync {
+    fn token_of_hash(&self, hash: &str) -> Result<Option<RefreshTokenRecord>, TokenError>;
+
+    /// Inserts or replaces the record with the same digest.
+    fn save(&self, record: &RefreshTokenRecord) -> Result<(), TokenError>;
+
+    /// Marks the token rotated if it is still active, returning whether it
+    /// was; the check and the update must be atomic so that a token can only
+    /// be exchanged once.
+    fn mark_rotated_if_active(&self, hash: &str) -> Result<bool, TokenError>;
+
+    fn revoke_family(&self, family_id: &str) -> Result<(), TokenError>;
+
+    fn revoke_user(&self, tenant_id: &str, username: &str) -> Result<(), TokenError>;
+
+    fn revoke_tenant(&self, tenant_id: &str) -> Result<(), TokenError>;
+
+    /// Deletes tokens that expired by the given instant, whatever their
+    /// status, returning how many were removed.
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, TokenError>;
+}
+
+/// Repository keeping refresh tokens in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryRefreshTokenRepository {
+    tokens: RwLock<HashMap<String, RefreshTokenRecord>>,
+}
+
+impl InMemoryRefreshTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn revoke_where(&self, predicate: impl Fn(&RefreshTokenRecord) -> bool) {
+        let mut tokens = self.tokens.write().expect("refresh tokens lock poisoned");
+        tokens
+            .values_mut()
+            .filter(|record| predicate(record))
+            .for_each(|record| record.status = RefreshTokenStatus::Revoked);
+    }
+}
+
+impl RefreshTokenRepository for InMemoryRefreshTokenRepository {
+    fn token_of_hash(&self, hash: &str) -> Result<Option<RefreshTokenRecord>, TokenError> {
+        let tokens = self.tokens.read().expect("refresh tokens lock poisoned");
+        Ok(tokens.get(hash).cloned())
+    }
+
+    fn save(&self, record: &RefreshTokenRecord) -> Result<(), TokenError> {
+        let mut tokens = self.tokens.write().expect("refresh tokens lock poisoned");
+        tokens.insert(record.hash.clone(), record.clone());
+        Ok(())
+    }
+
+    fn mark_rotated_if_active(&self, hash: &str) -> Result<bool, TokenError> {
+        let mut tokens = self.tokens.write().expect("refresh tokens lock poisoned");
+        Ok(match tokens.get_mut(hash) {
+            Some(record) if record.status == RefreshTokenStatus::Active => {
+                record.status = RefreshTokenStatus::Rotated;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    fn revoke_family(&self, family_id: &str) -> Result<(), TokenError> {
+        self.revoke_where(|record| record.family_id == family_id);
+        Ok(())
+    }
+
+    fn revoke_user(&self, tenant_id: &str, username: &str) -> Result<(), TokenError> {
+        self.revoke_where(|record| record.tenant_id == tenant_id && record.username == username);
+        Ok(())
+    }
+
+    fn revoke_tenant(&self, tenant_id: &str) -> Result<(), TokenError> {
+        self.revoke_where(|record| record.tenant_id == tenant_id);
+        Ok(())
+    }
+
+    fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, TokenError> {
+        let mut tokens = self.tokens.write().expect("refresh tokens lock poisoned");
+        let count = tokens.len();
+        tokens.retain(|_, record| now < record.expires_at);
+        Ok((count - tokens.len()) as u64)
+    }
+}
+
+/// An opaque refresh token handed to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The user a refresh token was exchanged for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshGrant {
+    pub tenant_id: String,
+    pub username: String,
+    pub client_id: String,
+    pub refresh_token: IssuedRefreshToken,
+}
+
+/// Issues, rotates and revokes refresh tokens.
+///
+/// Tokens are bound to the client they were issued to. Every use rotates the
+/// token. Presenting an already rotated token means it leaked, so the whole
+/// family is revoked and the caller must sign in again.
+pub struct RefreshTokenService<R, C> {
+    repository: R,
+    ttl: Duration,
+    clock: C,
+}
+
+impl<R: RefreshTokenRepository, C: Clock> RefreshTokenService<R, C> {
+    pub fn new(repository: R, ttl: Duration, clock: C) -> Self {
+        Self {
+            repository,
+            ttl,
+            clock,
+        }
+    }
+
+    /// Starts a new token family for the client after a successful sign-in.
+    pub fn issue(
+        &self,
+        tenant_id: &str,
+        username: &str,
+        client_id: &str,
+    ) -> Result<IssuedRefreshToken, TokenError> {
+        self.issue_in_family(random_token(), tenant_id, username, client_id)
+    }
+
+    /// Exchanges a refresh token presented by the client for its successor.
+    ///
+    /// A token of another client is refused without being used up.
+    pub fn rotate(&self, client_id: &str, token: &str) -> Result<RefreshGrant, TokenError> {
+        let hash = digest(token);
+        let record = self
+            .repository
+            .token_of_hash(&hash)?
+            .ok_or_else(|| TokenError::Invalid("unknown refresh token".to_owned()))?;
+        if !record.is_issued_to(client_id) {
+            return Err(TokenError::Invalid(
+                "refresh token was issued to another client".to_owned(),
+            ));
+        }
+        match record.status {
+            RefreshTokenStatus::Revoked => return Err(TokenError::Revoked),
+            RefreshTokenStatus::Rotated => return self.reused(&record),
+            RefreshTokenStatus::Active => {}
+        }
+        if self.clock.now() >= record.expires_at {
+            return Err(TokenError::Expired);
+        }
+        if !self.repository.mark_rotated_if_active(&hash)? {
+            // Another request exchanged or revoked the token since it was read.
+            return self.reused(&record);
+        }
+        let refresh_token = self.issue_in_family(
+            record.family_id.clone(),
+            &record.tenant_id,
+            &record.username,
+            &record.client_id,
+        )?;
+        Ok(RefreshGrant {
+            tenant_id: record.tenant_id,
+            username: record.username,
+            client_id: record.client_id,
+            refresh_token,
+        })
+    }
+
+    /// The record of the token if it is active and unexpired.
+    pub fn inspect(&self, token: &str) -> Result<Option<RefreshTokenRecord>, TokenError> {
+        let now = self.clock.now();
+        Ok(self
+            .repository
+            .token_of_hash(&digest(token))?
+            .filter(|record| {
+                record.status == RefreshTokenStatus::Active && now < record.expires_at
+            }))
+    }
+
+    /// Revokes the family of the given token, e.g. on sign-out.
+    pub fn revoke(&self, token: &str) -> Result<(), TokenError> {
+        match self.repository.token_of_hash(&digest(token))? {
+            Some(record) => self.repository.revoke_family(&record.family_id),
+            None => Ok(()),
+        }
+    }
+
+    pub fn revoke_all_for_user(&self, tenant_id: &str, username: &str) -> Result<(), TokenError> {
+        self.repository.revoke_user(tenant_id, username)
+    }
+
+    pub fn revoke_all_for_tenant(&self, tenant_id: &str) -> Result<(), TokenError> {
+        self.repository.revoke_tenant(tenant_id)
+    }
+
+    /// Deletes expired tokens; rotated tokens are kept until they expire so
+    /// that their reuse is still detected. Meant to be called on a schedule.
+    pub fn purge_expired(&self) -> Result<u64, TokenError> {
+        self.repository.purge_expired(self.clock.now())
+    }
+
+    fn reused(&self, record: &RefreshTokenRecord) -> Result<RefreshGrant, TokenError> {
+        self.repository.revoke_family(&record.family_id)?;
+        Err(TokenError::Reused)
+    }
+
+    fn issue_in_family(
+        &self,
+        family_id: String,
+        tenant_id: &str,
+        username: &str,
+        client_id: &str,
+    ) -> Result<IssuedRefreshToken, TokenError> {
+        let token = random_token();
+        let issued_at = self.clock.now();
+        let expires_at = issued_at + self.ttl;
+        self.repository.save(&RefreshTokenRecord {
+            hash: digest(&token),
+            family_id,
+            tenant_id: tenant_id.to_owned(),
+            username: username.to_owned(),
+            client_id: client_id.to_owned(),
+            issued_at,
+            expires_at,
+            status: RefreshTokenStatus::Active,
+        })?;
+        Ok(IssuedRefreshToken { token, expires_at })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::common::clock::FixedClock;
+
+    fn service() -> RefreshTokenService<InMemoryRefreshTokenRepository, FixedClock> {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        RefreshTokenService::new(
+            InMemoryRefreshTokenRepository::new(),
+            Duration::days(30),
+            FixedClock::new(now),
+        )
+    }
+
+    #[test]
+    fn rotation_issues_a_successor_in_the_family() {
+        let service = service();
+        let first = service.issue("acme", "ada", "app").unwrap();
+        let grant = service.rotate("app", &first.token).unwrap();
+        assert_eq!(
+            (grant.tenant_id.as_str(), grant.username.as_str()),
+            ("acme", "ada")
+        );
+        assert!(service.inspect(&first.token).unwrap().is_none());
+
+        let successor = service
+            .inspect(&grant.refresh_token.token)
+            .unwrap()
+            .unwrap();
+        let original = service
+            .repository
+            .token_of_hash(&digest(&first.token))
+            .unwrap();
+        assert_eq!(successor.family_id(), original.unwrap().family_id());
+    }
+
+    #[test]
+    fn reuse_revokes_the_whole_family() {
+        let service = service();
+        let first = service.issue("acme", "ada", "app").unwrap();
+        let second = service.rotate("app", &first.token).unwrap().refresh_token;
+
+        assert!(matches!(
+            service.rotate("app", &first.token),
+            Err(TokenError::Reused)
+        ));
+        assert!(matches!(
+            service.rotate("app", &second.token),
+            Err(TokenError::Revoked)
+        ));
+    }
+
+    /// Serves reads from a snapshot taken before a concurrent rotation.
+    struct StaleReads {
+        inner: InMemoryRefreshTokenRepository,
+        snapshot: RwLock<Option<RefreshTokenRecord>>,
+    }
+
+    impl RefreshTokenRepository for StaleReads {
+        fn token_of_hash(&self, hash: &str) -> Result<Option<RefreshTokenRecord>, TokenError> {
+            match self.snapshot.read().unwrap().clone() {
+                Some(record) => Ok(Some(record)),
+                None => self.inner.token_of_hash(hash),
+            }
+        }
+
+        fn save(&self, record: &RefreshTokenRecord) -> Result<(), TokenError> {
+            self.inner.save(record)
+        }
+
+        fn mark_rotated_if_active(&self, hash: &str) -> Result<bool, TokenError> {
+            self.inner.mark_rotated_if_active(hash)
+        }
+
+        fn revoke_family(&self, family_id: &str) -> Result<(), TokenError> {
+            self.inner.revoke_family(family_id)
+        }
+
+        fn revoke_user(&self, tenant_id: &str, username: &str) -> Result<(), TokenError> {
+            self.inner.revoke_user(tenant_id, username)
+        }
+
+        fn revoke_tenant(&self, tenant_id: &str) -> Result<(), TokenError> {
+            self.inner.revoke_tenant(tenant_id)
+        }
+
+        fn purge_expired(&self, now: DateTime<Utc>) -> Result<u64, TokenError> {
+            self.inner.purge_expired(now)
+        }
+    }
+
+    #[test]
+    fn lost_rotation_race_counts_as_reuse() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let repository = StaleReads {
+            inner: InMemoryRefreshTokenRepository::new(),
+            snapshot: RwLock::new(None),
+        };
+        let service =
+            RefreshTokenService::new(repository, Duration::days(30), FixedClock::new(now));
+        let first = service.issue("acme", "ada", "app").unwrap();
+        let winner = service.rotate("app", &first.token).unwrap().refresh_token;
+
+        // The loser read the token while it was still active.
+        let mut stale = service
+            .repository
+            .inner
+            .token_of_hash(&digest(&first.token))
+            .unwrap()
+            .unwrap();
+        stale.status = RefreshTokenStatus::Active;
+        *service.repository.snapshot.write().unwrap() = Some(stale);
+        assert!(matches!(
+            service.rotate("app", &first.token),
+            Err(TokenError::Reused)
+        ));
+
+        *service.repository.snapshot.write().unwrap() = None;
+        assert!(matches!(
+            service.rotate("app", &winner.token),
+            Err(TokenError::Revoked)
+        ));
+    }
+
+    #[test]
+    fn expired_and_unknown_tokens_are_refused() {
+        let service = service();
+        let token = service.issue("acme", "ada", "app").unwrap();
+        service.clock.advance(Duration::days(30));
+        assert!(matches!(
+            service.rotate("app", &token.token),
+            Err(TokenError::Expired)
+        ));
+        assert!(matches!(
+            service.rotate("app", "bogus"),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn revocation_covers_the_family() {
+        let service = service();
+        let first = service.issue("acme", "ada", "app").unwrap();
+        let second = service.rotate("app", &first.token).unwrap().refresh_token;
+        service.revoke(&first.token).unwrap();
+        assert!(matches!(
+            service.rotate("app", &second.token),
+            Err(TokenError::Revoked)
+        ));
+    }
+
+    #[test]
+    fn token_is_bound_to_its_client() {
+        let service = service();
+        let token = service.issue("acme", "ada", "app").unwrap();
+        assert!(matches!(
+            service.rotate("other", &token.token),
+            Err(TokenError::Invalid(_))
+        ));
+        let grant = service.rotate("app", &token.token).unwrap();
+        assert_eq!(grant.client_id, "app");
+    }
+
+    #[test]
+    fn purge_drops_expired_tokens_only() {
+        let service = service();
+        let first = service.issue("acme", "ada", "app").unwrap();
+        let successor = service.rotate("app", &first.token).unwrap().refresh_token;
+        service.clock.advance(Duration::days(1));
+        let later = service.issue("acme", "bob", "app").unwrap();
+
+        assert_eq!(service.purge_expired().unwrap(), 0);
+        assert!(matches!(
+            service.rotate("app", &first.token),
+            Err(TokenError::Reused)
+        ));
+
+        service.clock.advance(Duration::days(29));
+        assert_eq!(service.purge_expired().unwrap(), 2);
+        assert!(service.inspect(&successor.token).unwrap().is_none());
+        assert!(service.inspect(&later.token).unwrap().is_some());
+    }
+}
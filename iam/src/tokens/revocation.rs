@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+
+use super::TokenError;
+
+/// Remembers revoked access tokens by `jti` until they would expire anyway.
+///
+/// Access tokens are self-contained, so revocation only takes effect where
+/// this list is consulted, such as the introspection endpoint.
+pub trait RevokedTokenRepository: Send + Sync {
+    fn revoke(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), TokenError>;
+
+    fn is_revoked(&self, jti: &str) -> Result<bool, TokenError>;
+
+    /// Forgets tokens that expired before the given instant.
+    fn purge_expired(&self, before: DateTime<Utc>) -> Result<(), TokenError>;
+}
+
+/// Revocation list kept in process memory.
+#[derive(Debug, Default)]
+pub struct InMemoryRevokedTokenRepository {
+    revoked: RwLock<HashMap<String, DateTime<Utc>>>,
+}
+
+impl InMemoryRevokedTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RevokedTokenRepository for InMemoryRevokedTokenRepository {
+    fn revoke(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), TokenError> {
+        let mut revoked = self.revoked.write().expect("revoked tokens lock poisoned");
+        revoked.insert(jti.to_owned(), expires_at);
+        Ok(())
+    }
+
+    fn is_revoked(&self, jti: &str) -> Result<bool, TokenError> {
+        let revoked = self.revoked.read().expect("revoked tokens lock poisoned");
+        Ok(revoked.contains_key(jti))
+    }
+
+    fn purge_expired(&self, before: DateTime<Utc>) -> Result<(), TokenError> {
+        let mut revoked = self.revoked.write().expect("revoked tokens lock poisoned");
+        revoked.retain(|_, expires_at| *expires_at >= before);
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{DecodingKey, EncodingKey};
+
+use crate::tokens::{Jwks, KeyProvider, TokenError};
+
+/// Verification keys taken from a copy of the IAM's JWKS document.
+///
+/// Only the Ed25519 keys published by [`KeyManager`] are understood; other
+/// entries are skipped. The provider cannot sign.
+///
+/// [`KeyManager`]: crate::tokens::KeyManager
+#[derive(Debug, Default)]
+pub struct JwksKeyProvider {
+    keys: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl JwksKeyProvider {
+    pub fn new(jwks: &Jwks) -> Self {
+        let provider = Self::default();
+        provider.replace(jwks);
+        provider
+    }
+
+    /// Swaps in a freshly fetched JWKS document.
+    pub fn replace(&self, jwks: &Jwks) {
+        let keys = jwks
+            .keys
+            .iter()
+            .filter(|jwk| jwk.kty == "OKP" && jwk.crv == "Ed25519" && jwk.key_use == "sig")
+            .filter_map(|jwk| {
+                let public_key = BASE64URL_NOPAD.decode(jwk.x.as_bytes()).ok()?;
+                Some((jwk.kid.clone(), public_key))
+            })
+            .collect();
+        *self.keys.write().expect("JWKS keys lock poisoned") = keys;
+    }
+
+    pub fn contains(&self, key_id: &str) -> bool {
+        self.keys
+            .read()
+            .expect("JWKS keys lock poisoned")
+            .contains_key(key_id)
+    }
+}
+
+impl KeyProvider for JwksKeyProvider {
+    fn signing_key(&self) -> Result<(Option<String>, EncodingKey), TokenError> {
+        Err(TokenError::InvalidKey(
+            "JWKS keys only verify tokens".to_owned(),
+        ))
+    }
+
+    fn verification_key(&self, key_id: Option<&str>) -> Result<DecodingKey, TokenError> {
+        let key_id = key_id.ok_or_else(|| TokenError::Invalid("token has no key id".to_owned()))?;
+        let keys = self.keys.read().expect("JWKS keys lock poisoned");
+        let public_key = keys
+            .get(key_id)
+            .ok_or_else(|| TokenError::Invalid(format!("unknown key id: {}", key_id)))?;
+        Ok(DecodingKey::from_ed_der(public_key))
+    }
+}
@@ -0,0 +1,195 @@
+//! Token verification for resource servers that cannot reach the IAM store.
+//!
+//! Resource servers keep a copy of the IAM's JWKS document and of its role
+//! permissions, refresh both periodically, and check tokens and
+//! permissions against those copies. Revocations are not seen offline, so
+//! access tokens should be short-lived.
+
+use std::sync::{Arc, RwLock};
+
+use crate::access::Permission;
+use crate::common::clock::Clock;
+use crate::tokens::{
+    AccessClaims, AccessTokenService, Jwks, SigningAlgorithm, TokenConfig, TokenError,
+};
+
+mod keys;
+mod roles;
+
+pub use keys::JwksKeyProvider;
+pub use roles::RoleSnapshot;
+
+/// Validates access tokens and checks permissions against cached JWKS and
+/// role snapshots.
+pub struct OfflineVerifier<C> {
+    keys: Arc<JwksKeyProvider>,
+    tokens: AccessTokenService<C, Arc<JwksKeyProvider>>,
+    roles: RwLock<RoleSnapshot>,
+}
+
+impl<C: Clock> OfflineVerifier<C> {
+    /// Tokens must be issued by and for the configured issuer and audience
+    /// and signed with EdDSA by one of the JWKS keys.
+    pub fn new(config: TokenConfig, jwks: &Jwks, roles: RoleSnapshot, clock: C) -> Self {
+        let keys = Arc::new(JwksKeyProvider::new(jwks));
+        let config = config.with_algorithm(SigningAlgorithm::EdDSA);
+        Self {
+            tokens: AccessTokenService::new(config, Arc::clone(&keys), clock),
+            keys,
+            roles: RwLock::new(roles),
+        }
+    }
+
+    pub fn refresh_keys(&self, jwks: &Jwks) {
+        self.keys.replace(jwks);
+    }
+
+    pub fn refresh_roles(&self, roles: RoleSnapshot) {
+        *self.roles.write().expect("role snapshot lock poisoned") = roles;
+    }
+
+    /// Checks signature, issuer, audience and validity window, returning the
+    /// claims of a valid token.
+    pub fn verify(&self, token: &str) -> Result<AccessClaims, TokenError> {
+        self.tokens.validate(token)
+    }
+
+    /// Whether the roles the token was issued with allow the permission.
+    pub fn is_authorized(&self, claims: &AccessClaims, permission: &Permission) -> bool {
+        self.roles
+            .read()
+            .expect("role snapshot lock poisoned")
+            .allows(&claims.tenant_id, &claims.roles, permission)
+    }
+
+    /// Verifies the token and checks the permission in one go, returning the
+    /// claims and whether the permission is held.
+    pub fn authorize(
+        &self,
+        token: &str,
+        permission: &Permission,
+    ) -> Result<(AccessClaims, bool), TokenError> {
+        let claims = self.verify(token)?;
+        let allowed = self.is_authorized(&claims, permission);
+        Ok((claims, allowed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::access::{AccessApplicationService, InMemoryRoleRepository, Role};
+    use crate::common::clock::FixedClock;
+    use crate::tokens::{
+        InMemorySigningKeyRepository, KeyManager, KeyRotationPolicy, TokenSubject,
+    };
+
+    type Manager<'a> = KeyManager<InMemorySigningKeyRepository, &'a FixedClock>;
+
+    fn clock() -> FixedClock {
+        FixedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+    }
+
+    fn config() -> TokenConfig {
+        TokenConfig::new("https://iam.example.com", "api").with_algorithm(SigningAlgorithm::EdDSA)
+    }
+
+    fn issuer(clock: &FixedClock) -> Arc<Manager<'_>> {
+        let manager = KeyManager::new(
+            InMemorySigningKeyRepository::new(),
+            KeyRotationPolicy::default(),
+            clock,
+        );
+        manager.rotate_if_due().unwrap();
+        Arc::new(manager)
+    }
+
+    fn issue(manager: &Arc<Manager<'_>>, clock: &FixedClock, subject: &TokenSubject) -> String {
+        AccessTokenService::new(config(), Arc::clone(manager), clock)
+            .issue(subject)
+            .unwrap()
+            .token
+    }
+
+    fn snapshot() -> RoleSnapshot {
+        let access = AccessApplicationService::new(InMemoryRoleRepository::new());
+        access.define_role(&Role::new("acme", "editor")).unwrap();
+        access
+            .grant("acme", "editor", "invitation:*".parse().unwrap())
+            .unwrap();
+        RoleSnapshot::from_roles(&access.roles_in("acme").unwrap())
+    }
+
+    fn permission(value: &str) -> Permission {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn tokens_are_checked_against_the_cached_keys_and_roles() {
+        let clock = clock();
+        let manager = issuer(&clock);
+        let verifier = OfflineVerifier::new(config(), &manager.jwks().unwrap(), snapshot(), &clock);
+
+        let token = issue(
+            &manager,
+            &clock,
+            &TokenSubject::new("acme", "ada").with_roles(["editor"]),
+        );
+        let (claims, allowed) = verifier
+            .authorize(&token, &permission("invitation:offer"))
+            .unwrap();
+        assert_eq!(claims.sub, "ada");
+        assert!(allowed);
+        assert!(!verifier.is_authorized(&claims, &permission("tenant:delete")));
+
+        let globex = issue(
+            &manager,
+            &clock,
+            &TokenSubject::new("globex", "bob").with_roles(["editor"]),
+        );
+        let (_, allowed) = verifier
+            .authorize(&globex, &permission("invitation:offer"))
+            .unwrap();
+        assert!(!allowed, "roles are looked up in the token's tenant");
+    }
+
+    #[test]
+    fn tokens_signed_with_unpublished_keys_are_rejected() {
+        let clock = clock();
+        let manager = issuer(&clock);
+        let verifier = OfflineVerifier::new(config(), &manager.jwks().unwrap(), snapshot(), &clock);
+
+        let other = issuer(&clock);
+        let foreign = issue(&other, &clock, &TokenSubject::new("acme", "ada"));
+        assert!(matches!(
+            verifier.verify(&foreign),
+            Err(TokenError::Invalid(_))
+        ));
+
+        verifier.refresh_keys(&other.jwks().unwrap());
+        assert!(verifier.verify(&foreign).is_ok());
+    }
+
+    #[test]
+    fn expired_tokens_and_refreshed_roles_take_effect() {
+        let clock = clock();
+        let manager = issuer(&clock);
+        let verifier = OfflineVerifier::new(config(), &manager.jwks().unwrap(), snapshot(), &clock);
+        let token = issue(
+            &manager,
+            &clock,
+            &TokenSubject::new("acme", "ada").with_roles(["editor"]),
+        );
+
+        verifier.refresh_roles(RoleSnapshot::new());
+        let (_, allowed) = verifier
+            .authorize(&token, &permission("invitation:offer"))
+            .unwrap();
+        assert!(!allowed);
+
+        clock.advance(Duration::hours(1));
+        assert!(matches!(verifier.verify(&token), Err(TokenError::Expired)));
+    }
+}
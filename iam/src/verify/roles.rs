@@ -0,0 +1,49 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::access::{Permission, Role};
+
+/// The permissions of each tenant's roles at some instant, exported by the
+/// IAM for resource servers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleSnapshot {
+    tenants: HashMap<String, HashMap<String, BTreeSet<Permission>>>,
+}
+
+impl RoleSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Captures the roles, e.g. those returned by
+    /// [`AccessApplicationService::roles_in`] for each tenant.
+    ///
+    /// [`AccessApplicationService::roles_in`]: crate::access::AccessApplicationService::roles_in
+    pub fn from_roles<'a>(roles: impl IntoIterator<Item = &'a Role>) -> Self {
+        let mut snapshot = Self::new();
+        for role in roles {
+            snapshot
+                .tenants
+                .entry(role.tenant_id().to_owned())
+                .or_default()
+                .insert(
+                    role.name().to_owned(),
+                    role.permissions().cloned().collect(),
+                );
+        }
+        snapshot
+    }
+
+    /// Whether one of the named roles of the tenant allows the permission.
+    /// Roles missing from the snapshot allow nothing.
+    pub fn allows(&self, tenant_id: &str, roles: &[String], requested: &Permission) -> bool {
+        self.tenants.get(tenant_id).is_some_and(|tenant| {
+            roles
+                .iter()
+                .filter_map(|role| tenant.get(role))
+                .flatten()
+                .any(|permission| permission.implies(requested))
+        })
+    }
+}